@@ -0,0 +1,125 @@
+//! Which ACP agent server owns an AcpThread.
+//!
+//! `agents` in the config holds per-agent overrides (`config::Overrides`):
+//!
+//! ```json
+//! "agents": {
+//!   "claude":  { "plan_option": "acceptEdits" },
+//!   "codex":   { "tool_option": "allow_always", "policy": { "rules": [...] } },
+//!   "my-tool": { "mode": "observe" },
+//!   "default": { "mode": "observe" }
+//! }
+//! ```
+//!
+//! The agent is read from the thread itself: the name of the agent server
+//! behind `AcpThread.connection` (`layouts::ThreadLayout::agent_name`, from
+//! the layout database). Nothing the agent sends (tool call ids, prompts)
+//! decides it, so an agent can't pass itself off as a more trusted one. An
+//! `agents` entry named like the server applies; otherwise servers whose
+//! name contains `claude`, `codex` or `gemini` use that entry.
+//!
+//! The stale scanner reads the name once per thread (`thread_context`).
+//! Threads whose agent isn't known, because the layout has no `agent_name`
+//! or it can't be read, use `agents.default`, as do known agents without an
+//! entry. The name is also the policy's `agent` fact. Built-in tools of
+//! Zed's own agent are always `zed` (`hooks::native_tool`).
+
+use std::collections::BTreeMap;
+
+use crate::config::Overrides;
+use crate::layouts::ThreadLayout;
+use crate::mem::SafeMem;
+
+/// Built-in agent names.
+pub(crate) const CLAUDE: &str = "claude";
+pub(crate) const CODEX: &str = "codex";
+pub(crate) const GEMINI: &str = "gemini";
+
+/// Agent server name of the AcpThread at `thread`.
+pub(crate) fn read_name(mem: &impl SafeMem, thread: u64, layout: &ThreadLayout) -> Option<String> {
+    layout
+        .agent_name?
+        .read(mem, thread)
+        .filter(|name| !name.trim().is_empty())
+}
+
+/// The agent name for server `server`: its own `agents` entry's name, else
+/// the built-in agent it is a build of, else the server name.
+pub(crate) fn identify(agents: &BTreeMap<String, Overrides>, server: &str) -> String {
+    if agents.contains_key(server) {
+        return server.to_string();
+    }
+    let lower = server.to_ascii_lowercase();
+    [CLAUDE, CODEX, GEMINI]
+        .into_iter()
+        .find(|builtin| lower.contains(builtin))
+        .unwrap_or(server)
+        .to_string()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::tool_call_snapshot::StrField;
+    use crate::layouts::ChainedStr;
+    use crate::mem::SliceMemory;
+
+    const THREAD: u64 = 0x1_1000_0000;
+    const CONNECTION: u64 = 0x1_2000_0000;
+    const NAME: u64 = 0x1_3000_0000;
+
+    fn agents(json: &str) -> BTreeMap<String, Overrides> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_read_name() {
+        let mut mem = SliceMemory::default();
+        let mut thread = vec![0u64; 8];
+        thread[4] = CONNECTION;
+        mem.add_words(THREAD, &thread);
+        mem.add_words(CONNECTION, &[1, 1, 0, NAME, 11]);
+        mem.add(NAME, b"claude-code".to_vec());
+
+        let layout = ThreadLayout {
+            agent_name: Some(ChainedStr {
+                deref: &[0x20],
+                field: StrField {
+                    ptr_offset: 0x18,
+                    len_offset: 0x20,
+                    data_offset: 0,
+                    none: None,
+                },
+            }),
+            ..ThreadLayout::UNKNOWN
+        };
+        assert_eq!(
+            read_name(&mem, THREAD, &layout).as_deref(),
+            Some("claude-code")
+        );
+        assert_eq!(read_name(&mem, THREAD, &ThreadLayout::UNKNOWN), None);
+
+        // A connection pointer that isn't one reads nothing.
+        mem.remove(THREAD);
+        mem.add_words(THREAD, &[0; 8]);
+        assert_eq!(read_name(&mem, THREAD, &layout), None);
+    }
+
+    #[test]
+    fn test_identify() {
+        let none = BTreeMap::new();
+        assert_eq!(identify(&none, "claude-code"), CLAUDE);
+        assert_eq!(identify(&none, "Codex"), CODEX);
+        assert_eq!(identify(&none, "gemini"), GEMINI);
+        assert_eq!(identify(&none, "my-tool"), "my-tool");
+
+        // An entry for the server name itself wins.
+        let config = agents(r#"{ "claude-code": { "mode": "observe" } }"#);
+        assert_eq!(identify(&config, "claude-code"), "claude-code");
+        assert_eq!(identify(&config, "claude-acp"), CLAUDE);
+    }
+}
@@ -0,0 +1,381 @@
+//! Append-only JSONL audit log of approval decisions.
+//!
+//! Every decision the hooks act on (or, in observe mode, would act on) is
+//! written as one JSON object per line to
+//! `~/Library/Logs/Zed/zed-yolo-audit.jsonl` (or `audit_log` in the config):
+//!
+//! ```json
+//! {"ts":"2026-05-02T10:15:03.120Z","seq":12,"pid":4242,"session":"ab12",
+//!  "tool_call_id":"toolu_01…","title":"Run `ls`","kind":"execute",
+//!  "path":"hook","action":"approved","source":"rule[0]",
+//!  "option_id":"allow","option_kind":"allow_once","layout":"v0.233.x",
+//!  "latency_us":84}
+//! ```
+//!
+//! Unlike the tracing log this file is never rotated or filtered by
+//! `log_level`. It is created `0600` and only ever opened for append.
+//!
+//! Hooks run on Zed's threads, so `record` only clones the record and sends it
+//! over a channel; the file write happens on the writer thread. The writer
+//! thread also keeps the last `RECENT_CAPACITY` records for the control
+//! socket's `recent` command, even with `audit_log: "off"`. That ring's Mutex
+//! is shared by the writer and the control socket only, never a hook.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, OnceLock};
+
+use crate::config::OptionKind;
+
+/// File name in Zed's log directory when `audit_log` is unset.
+pub(crate) const DEFAULT_FILE: &str = "zed-yolo-audit.jsonl";
+
+/// Records kept in memory for `recent`.
+pub(crate) const RECENT_CAPACITY: usize = 64;
+
+static SENDER: OnceLock<Sender<Record>> = OnceLock::new();
+
+/// Most recent records, oldest first. Locked only by the writer thread and
+/// the control socket thread.
+static RECENT: Mutex<VecDeque<Record>> = Mutex::new(VecDeque::new());
+
+// ---------------------------------------------------------------------------
+// Records
+// ---------------------------------------------------------------------------
+
+/// Which code path reached the decision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ApprovalPath {
+    /// `request_tool_call_authorization` hook, first attempt.
+    Hook,
+    /// Same hook, after the `retry_delay_us` retry.
+    Retry,
+    /// Periodic entry scanner (dialogs the hook missed).
+    StaleScanner,
+    /// `ToolPermissionDecision::from_input` (built-in tools).
+    Native,
+}
+
+/// What happened to the dialog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Action {
+    /// Allow option sent.
+    Approved,
+    /// Reject option sent.
+    Rejected,
+    /// Observe mode: logged what would have been sent, sent nothing.
+    Observed,
+    /// Policy said ask, or no acceptable option; left for the user.
+    LeftForUser,
+    /// The option could not be delivered (receiver gone).
+    SendFailed,
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Record {
+    /// RFC 3339, UTC.
+    pub(crate) ts: String,
+    /// 1-based sequence number within this process, assigned by the writer.
+    pub(crate) seq: u64,
+    pub(crate) pid: u32,
+    /// Session tag (low 16 bits of the AcpThread pointer, as in the log).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) session: Option<String>,
+    pub(crate) tool_call_id: Option<String>,
+    pub(crate) title: Option<String>,
+    pub(crate) kind: Option<&'static str>,
+    pub(crate) path: ApprovalPath,
+    pub(crate) action: Action,
+    /// Policy verdict source (`rule[2]`, `default`, ...).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) source: Option<String>,
+    pub(crate) option_id: Option<String>,
+    pub(crate) option_kind: Option<OptionKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) layout: Option<&'static str>,
+    pub(crate) latency_us: u64,
+}
+
+impl Record {
+    /// A record stamped with the current time and pid; fill in the rest.
+    pub(crate) fn new(path: ApprovalPath, action: Action) -> Self {
+        Record {
+            ts: now_rfc3339(),
+            seq: 0,
+            pid: std::process::id(),
+            session: None,
+            tool_call_id: None,
+            title: None,
+            kind: None,
+            path,
+            action,
+            source: None,
+            option_id: None,
+            option_kind: None,
+            layout: None,
+            latency_us: 0,
+        }
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap_or_default();
+        line.push('\n');
+        line
+    }
+}
+
+fn now_rfc3339() -> String {
+    time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default()
+}
+
+// ---------------------------------------------------------------------------
+// Writer
+// ---------------------------------------------------------------------------
+
+/// Audit file for the `audit_log` setting: empty = default location in Zed's
+/// log directory, `off` = disabled, otherwise a path (`~/` expanded).
+pub(crate) fn resolve_path(setting: &str, home: Option<&Path>) -> Option<PathBuf> {
+    let setting = setting.trim();
+    if setting.eq_ignore_ascii_case("off") {
+        return None;
+    }
+    if setting.is_empty() {
+        return Some(home?.join("Library/Logs/Zed").join(DEFAULT_FILE));
+    }
+    match setting.strip_prefix("~/") {
+        Some(rest) => Some(home?.join(rest)),
+        None => Some(PathBuf::from(setting)),
+    }
+}
+
+/// Open `path` for append (created `0600`).
+fn open(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// Start the writer thread: append each record to `file` (if any) and keep
+/// it in `recent`.
+fn spawn(
+    mut file: Option<std::fs::File>,
+    recent: &'static Mutex<VecDeque<Record>>,
+) -> std::io::Result<(Sender<Record>, std::thread::JoinHandle<()>)> {
+    let (tx, rx) = mpsc::channel::<Record>();
+    let handle = std::thread::Builder::new()
+        .name("zed-yolo-audit".to_string())
+        .spawn(move || {
+            let mut seq = 0;
+            for mut record in rx {
+                seq += 1;
+                record.seq = seq;
+                // One write per record: O_APPEND keeps lines whole even if
+                // another Zed process appends to the same file.
+                if let Some(file) = file.as_mut()
+                    && let Err(e) = file.write_all(record.to_line().as_bytes())
+                {
+                    tracing::error!("audit: write failed: {e}");
+                }
+                let mut recent = recent.lock().unwrap_or_else(|e| e.into_inner());
+                if recent.len() == RECENT_CAPACITY {
+                    recent.pop_front();
+                }
+                recent.push_back(record);
+            }
+        })?;
+    Ok((tx, handle))
+}
+
+/// Start the audit writer. Called once at init; a file that can't be opened
+/// is logged and leaves the file off rather than blocking startup.
+pub(crate) fn init(setting: &str) {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let file = match resolve_path(setting, home.as_deref()) {
+        None => {
+            tracing::warn!("audit: file disabled (audit_log=off)");
+            None
+        }
+        Some(path) => match open(&path) {
+            Ok(file) => {
+                tracing::info!("audit: {}", path.display());
+                Some(file)
+            }
+            Err(e) => {
+                tracing::error!("audit: cannot open {}: {e}", path.display());
+                None
+            }
+        },
+    };
+    match spawn(file, &RECENT) {
+        Ok((tx, _handle)) => {
+            let _ = SENDER.set(tx);
+        }
+        Err(e) => tracing::error!("audit: cannot start writer thread: {e}"),
+    }
+}
+
+/// Queue `record` for the audit file. Never blocks.
+pub(crate) fn record(record: &Record) {
+    if let Some(tx) = SENDER.get() {
+        let _ = tx.send(record.clone());
+    }
+}
+
+/// Up to `limit` most recent records with `seq > since`, newest first.
+pub(crate) fn recent(limit: usize, since: u64) -> Vec<Record> {
+    let recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+    recent
+        .iter()
+        .rev()
+        .take_while(|record| record.seq > since)
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Record {
+        Record {
+            ts: "2026-05-02T10:15:03.120Z".to_string(),
+            seq: 7,
+            pid: 4242,
+            session: Some("ab12".to_string()),
+            tool_call_id: Some("toolu_01".to_string()),
+            title: Some("Run `ls`".to_string()),
+            kind: Some("execute"),
+            path: ApprovalPath::StaleScanner,
+            action: Action::Approved,
+            source: Some("rule[0]".to_string()),
+            option_id: Some("allow".to_string()),
+            option_kind: Some(OptionKind::AllowOnce),
+            layout: Some("v0.233.x"),
+            latency_us: 84,
+        }
+    }
+
+    #[test]
+    fn test_record_json() {
+        let line = sample().to_line();
+        assert!(line.ends_with('\n'));
+        assert_eq!(line.matches('\n').count(), 1);
+
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["pid"], 4242);
+        assert_eq!(json["session"], "ab12");
+        assert_eq!(json["path"], "stale_scanner");
+        assert_eq!(json["action"], "approved");
+        assert_eq!(json["option_kind"], "allow_once");
+        assert_eq!(json["latency_us"], 84);
+
+        let native = Record::new(ApprovalPath::Native, Action::Observed);
+        let json: serde_json::Value = serde_json::from_str(&native.to_line()).unwrap();
+        assert_eq!(json["path"], "native");
+        assert_eq!(json["action"], "observed");
+        assert!(json.get("session").is_none());
+        assert!(json["tool_call_id"].is_null());
+        assert!(json["ts"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let home = Path::new("/Users/me");
+        assert_eq!(
+            resolve_path("", Some(home)),
+            Some(PathBuf::from(
+                "/Users/me/Library/Logs/Zed/zed-yolo-audit.jsonl"
+            ))
+        );
+        assert_eq!(resolve_path("OFF", Some(home)), None);
+        assert_eq!(
+            resolve_path("~/audit/yolo.jsonl", Some(home)),
+            Some(PathBuf::from("/Users/me/audit/yolo.jsonl"))
+        );
+        assert_eq!(
+            resolve_path("/var/log/yolo.jsonl", None),
+            Some(PathBuf::from("/var/log/yolo.jsonl"))
+        );
+        assert_eq!(resolve_path("", None), None);
+    }
+
+    #[test]
+    fn test_writer_appends_lines() {
+        static RING: Mutex<VecDeque<Record>> = Mutex::new(VecDeque::new());
+
+        let dir = std::env::temp_dir().join(format!("zed-yolo-audit-test-{}", std::process::id()));
+        let path = dir.join("audit.jsonl");
+        let _ = std::fs::remove_file(&path);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, "{\"existing\":true}\n").unwrap();
+
+        let (tx, handle) = spawn(Some(open(&path).unwrap()), &RING).unwrap();
+        tx.send(sample()).unwrap();
+        tx.send(Record::new(ApprovalPath::Hook, Action::Rejected))
+            .unwrap();
+        drop(tx);
+        handle.join().unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "{\"existing\":true}");
+        assert!(lines[2].contains("\"action\":\"rejected\""));
+        assert_eq!(RING.lock().unwrap().len(), 2);
+
+        // A fresh file is created 0600.
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::remove_file(&path).unwrap();
+            drop(open(&path).unwrap());
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_recent_ring_without_file() {
+        static RING: Mutex<VecDeque<Record>> = Mutex::new(VecDeque::new());
+
+        let (tx, handle) = spawn(None, &RING).unwrap();
+        for i in 0..RECENT_CAPACITY + 3 {
+            tx.send(Record {
+                latency_us: i as u64,
+                ..Record::new(ApprovalPath::Hook, Action::Approved)
+            })
+            .unwrap();
+        }
+        drop(tx);
+        handle.join().unwrap();
+
+        let ring = RING.lock().unwrap();
+        assert_eq!(ring.len(), RECENT_CAPACITY);
+        assert_eq!(ring.front().unwrap().latency_us, 3);
+        assert_eq!(ring.front().unwrap().seq, 4);
+        assert_eq!(
+            ring.back().unwrap().latency_us,
+            (RECENT_CAPACITY + 2) as u64
+        );
+    }
+}
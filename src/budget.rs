@@ -0,0 +1,434 @@
+//! Time- and count-limited YOLO sessions.
+//!
+//! `"budget": { "minutes": 30, "approvals": 50 }` in the config approves for
+//! the next 30 minutes or 50 approvals, whichever runs out first, then hands
+//! every dialog back to the user — as if paused. `0` means no limit on that
+//! axis; both `0` (the default) is an unlimited session.
+//!
+//! The budget is armed the first time it is set and re-armed whenever the
+//! `budget` setting changes (config reload or `set` on the control socket),
+//! so editing the numbers starts a fresh session. Other reloads, and `set`
+//! to the same value, leave it running.
+//!
+//! The session survives restarts: the stale scanner saves when it was armed
+//! and the approvals used, with the budget they count against, to
+//! `zed-yolo-budget.json` next to the config file (`Saver`), and startup
+//! resumes it when the budget is unchanged (`resume`). The time limit is an
+//! absolute expiry, so it keeps running while Zed is closed. Approvals made
+//! since the scanner's last save (at most one sweep interval) are not
+//! counted after a crash.
+//!
+//! Only approvals are counted: policy rejections and observe-only decisions
+//! don't use up the budget, but they stop as well once it is exhausted.
+//!
+//! State is a handful of atomics so listeners can check it from interceptor
+//! context without locking.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `budget` config section.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Budget {
+    /// Minutes after arming before approvals stop. 0 = no time limit.
+    pub minutes: u64,
+    /// Approvals before they stop. 0 = no count limit.
+    pub approvals: u64,
+}
+
+impl Budget {
+    pub fn is_limited(&self) -> bool {
+        self.minutes != 0 || self.approvals != 0
+    }
+}
+
+/// What is left of a budget. `None` fields are unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Remaining {
+    pub approvals: Option<u64>,
+    pub secs: Option<u64>,
+}
+
+impl Remaining {
+    pub fn is_exhausted(&self) -> bool {
+        self.approvals == Some(0) || self.secs == Some(0)
+    }
+}
+
+impl std::fmt::Display for Remaining {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.approvals, self.secs) {
+            (None, None) => write!(f, "unlimited"),
+            (Some(n), None) => write!(f, "{n} approvals left"),
+            (None, Some(s)) => write!(f, "{}m{:02}s left", s / 60, s % 60),
+            (Some(n), Some(s)) => write!(f, "{n} approvals / {}m{:02}s left", s / 60, s % 60),
+        }
+    }
+}
+
+/// A session as saved across restarts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct State {
+    /// The budget the session counts against.
+    budget: Budget,
+    armed_at_ms: u64,
+    used: u64,
+}
+
+/// Budget usage since the last `arm`. Times are Unix milliseconds, passed in
+/// so the logic is testable.
+pub(crate) struct Tracker {
+    armed_at_ms: AtomicU64,
+    used: AtomicU64,
+    /// Whether exhaustion was logged since the last `arm`.
+    announced: AtomicBool,
+}
+
+impl Tracker {
+    pub(crate) const fn new() -> Self {
+        Tracker {
+            armed_at_ms: AtomicU64::new(0),
+            used: AtomicU64::new(0),
+            announced: AtomicBool::new(false),
+        }
+    }
+
+    /// Start a fresh session at `now_ms`.
+    pub(crate) fn arm(&self, now_ms: u64) {
+        self.used.store(0, Ordering::Relaxed);
+        self.announced.store(false, Ordering::Relaxed);
+        self.armed_at_ms.store(now_ms, Ordering::Release);
+    }
+
+    /// Continue the session saved in `state`.
+    fn restore(&self, state: &State) {
+        self.used.store(state.used, Ordering::Relaxed);
+        self.announced.store(false, Ordering::Relaxed);
+        self.armed_at_ms.store(state.armed_at_ms, Ordering::Release);
+    }
+
+    fn state(&self, budget: &Budget) -> State {
+        State {
+            budget: *budget,
+            armed_at_ms: self.armed_at_ms.load(Ordering::Acquire),
+            used: self.used(),
+        }
+    }
+
+    pub(crate) fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn remaining(&self, budget: &Budget, now_ms: u64) -> Remaining {
+        let approvals =
+            (budget.approvals != 0).then(|| budget.approvals.saturating_sub(self.used()));
+        let secs = (budget.minutes != 0).then(|| {
+            let elapsed_ms = now_ms.saturating_sub(self.armed_at_ms.load(Ordering::Acquire));
+            (budget.minutes * 60).saturating_sub(elapsed_ms.div_ceil(1000))
+        });
+        Remaining { approvals, secs }
+    }
+
+    /// Use one approval. `Err` (nothing used) when the budget is exhausted.
+    pub(crate) fn take(&self, budget: &Budget, now_ms: u64) -> Result<Remaining, Remaining> {
+        let before = self.remaining(budget, now_ms);
+        if before.is_exhausted() {
+            return Err(before);
+        }
+        if budget.approvals == 0 {
+            self.used.fetch_add(1, Ordering::Relaxed);
+            return Ok(before);
+        }
+        // Two listeners may race for the last approval; only one gets it.
+        match self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used < budget.approvals).then_some(used + 1)
+            }) {
+            Ok(used) => Ok(Remaining {
+                approvals: Some(budget.approvals - used - 1),
+                ..before
+            }),
+            Err(_) => Err(Remaining {
+                approvals: Some(0),
+                ..before
+            }),
+        }
+    }
+
+    /// Give back an approval taken for a dialog that was not answered after all.
+    pub(crate) fn refund(&self) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_sub(1)
+            });
+    }
+
+    /// `true` the first time it is called after exhaustion (per `arm`).
+    fn announce(&self) -> bool {
+        !self.announced.swap(true, Ordering::Relaxed)
+    }
+}
+
+static TRACKER: Tracker = Tracker::new();
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Start a new session for `budget`, logging it when limited.
+pub(crate) fn arm(budget: &Budget) {
+    TRACKER.arm(now_ms());
+    if budget.is_limited() {
+        tracing::info!("budget: armed — {}", remaining(budget));
+    }
+}
+
+/// At startup: resume the session saved at `path` if it was for `budget`,
+/// else start a new one.
+pub(crate) fn resume(budget: &Budget, path: Option<&Path>) {
+    match path.and_then(|path| saved_for(path, budget)) {
+        Some(state) => {
+            TRACKER.restore(&state);
+            if budget.is_limited() {
+                tracing::info!("budget: resumed — {}", remaining(budget));
+            }
+        }
+        None => arm(budget),
+    }
+}
+
+/// The session saved at `path`, if it was for `budget`.
+fn saved_for(path: &Path, budget: &Budget) -> Option<State> {
+    let text = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<State>(&text) {
+        Ok(state) => (state.budget == *budget).then_some(state),
+        Err(e) => {
+            tracing::warn!("budget: ignoring {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Saved session path: `~/.config/dylib-hooks/{app_id}/zed-yolo-budget.json`
+pub fn state_path(app_id: &str) -> Option<PathBuf> {
+    crate::config::config_path(app_id).map(|path| path.with_file_name("zed-yolo-budget.json"))
+}
+
+/// Saves the session when it changed. Lives on the stale scanner thread.
+pub(crate) struct Saver {
+    path: PathBuf,
+    saved: Option<State>,
+}
+
+impl Saver {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Saver { path, saved: None }
+    }
+
+    /// Save the current session for `budget` if it changed since the last
+    /// save.
+    pub(crate) fn save(&mut self, budget: &Budget) {
+        let state = TRACKER.state(budget);
+        if self.saved == Some(state) {
+            return;
+        }
+        if let Err(e) = write_state(&self.path, &state) {
+            // Not retried until the session changes again.
+            tracing::warn!("budget: cannot save {}: {e}", self.path.display());
+        }
+        self.saved = Some(state);
+    }
+}
+
+/// Write `state` to `path` via a temp file, so a crash never leaves half a
+/// file.
+fn write_state(path: &Path, state: &State) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let text = serde_json::to_string(state).map_err(std::io::Error::other)?;
+    std::fs::write(&tmp, text)?;
+    std::fs::rename(&tmp, path)
+}
+
+pub(crate) fn remaining(budget: &Budget) -> Remaining {
+    TRACKER.remaining(budget, now_ms())
+}
+
+/// Approvals made since the budget was armed.
+pub(crate) fn used() -> u64 {
+    TRACKER.used()
+}
+
+/// Whether the budget has run out. Logs the transition once.
+pub(crate) fn exhausted(budget: &Budget) -> bool {
+    let left = remaining(budget);
+    if !left.is_exhausted() {
+        return false;
+    }
+    announce(budget);
+    true
+}
+
+/// Use one approval; `Err` when the budget has run out.
+pub(crate) fn take(budget: &Budget) -> Result<Remaining, Remaining> {
+    TRACKER
+        .take(budget, now_ms())
+        .inspect_err(|_| announce(budget))
+}
+
+pub(crate) fn refund() {
+    TRACKER.refund();
+}
+
+fn announce(budget: &Budget) {
+    if TRACKER.announce() {
+        tracing::warn!(
+            "budget: exhausted after {} approvals (minutes={}, approvals={}) — dialogs are left for the user until the budget is changed",
+            TRACKER.used(),
+            budget.minutes,
+            budget.approvals
+        );
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: u64 = 60_000;
+
+    #[test]
+    fn test_unlimited() {
+        let tracker = Tracker::new();
+        tracker.arm(0);
+        let budget = Budget::default();
+        assert!(!budget.is_limited());
+        for _ in 0..1000 {
+            assert!(tracker.take(&budget, 10_000 * MIN).is_ok());
+        }
+        assert_eq!(tracker.used(), 1000);
+        assert_eq!(tracker.remaining(&budget, 0).to_string(), "unlimited");
+    }
+
+    #[test]
+    fn test_approval_limit() {
+        let tracker = Tracker::new();
+        tracker.arm(0);
+        let budget = Budget {
+            approvals: 2,
+            ..Budget::default()
+        };
+        assert_eq!(tracker.take(&budget, 0).unwrap().approvals, Some(1));
+        assert_eq!(tracker.take(&budget, 0).unwrap().approvals, Some(0));
+        assert_eq!(tracker.take(&budget, 0).unwrap_err().approvals, Some(0));
+        assert_eq!(tracker.used(), 2);
+
+        tracker.refund();
+        assert_eq!(tracker.remaining(&budget, 0).approvals, Some(1));
+        assert!(tracker.take(&budget, 0).is_ok());
+
+        tracker.arm(5);
+        assert_eq!(tracker.remaining(&budget, 5).approvals, Some(2));
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let tracker = Tracker::new();
+        tracker.arm(1_000 * MIN);
+        let budget = Budget {
+            minutes: 30,
+            ..Budget::default()
+        };
+        assert_eq!(tracker.remaining(&budget, 1_000 * MIN).secs, Some(1800));
+        assert!(tracker.take(&budget, 1_029 * MIN).is_ok());
+        assert_eq!(
+            tracker.remaining(&budget, 1_029 * MIN + 500).to_string(),
+            "0m59s left"
+        );
+        assert!(tracker.take(&budget, 1_030 * MIN).is_err());
+        assert!(tracker.remaining(&budget, 2_000 * MIN).is_exhausted());
+    }
+
+    #[test]
+    fn test_both_limits() {
+        let tracker = Tracker::new();
+        tracker.arm(0);
+        let budget = Budget {
+            minutes: 10,
+            approvals: 5,
+        };
+        let left = tracker.take(&budget, MIN).unwrap();
+        assert_eq!(left.to_string(), "4 approvals / 9m00s left");
+        assert!(tracker.take(&budget, 11 * MIN).is_err());
+        assert_eq!(tracker.used(), 1);
+    }
+
+    #[test]
+    fn test_announce_once_per_arm() {
+        let tracker = Tracker::new();
+        assert!(tracker.announce());
+        assert!(!tracker.announce());
+        tracker.arm(0);
+        assert!(tracker.announce());
+    }
+
+    #[test]
+    fn test_session_survives_restart() {
+        let path =
+            std::env::temp_dir().join(format!("zed-yolo-budget-test-{}.json", std::process::id()));
+        let budget = Budget {
+            minutes: 30,
+            approvals: 5,
+        };
+        let before = Tracker::new();
+        before.arm(1_000 * MIN);
+        for _ in 0..3 {
+            before.take(&budget, 1_001 * MIN).unwrap();
+        }
+        write_state(&path, &before.state(&budget)).unwrap();
+
+        // Restarted ten minutes later: the clock kept running.
+        let after = Tracker::new();
+        after.restore(&saved_for(&path, &budget).unwrap());
+        assert_eq!(
+            after.remaining(&budget, 1_010 * MIN).to_string(),
+            "2 approvals / 20m00s left"
+        );
+        assert!(after.remaining(&budget, 1_030 * MIN).is_exhausted());
+
+        // A changed budget is a new session.
+        let changed = Budget {
+            minutes: 60,
+            ..budget
+        };
+        assert_eq!(saved_for(&path, &changed), None);
+        std::fs::write(&path, "not json").unwrap();
+        assert_eq!(saved_for(&path, &budget), None);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(saved_for(&path, &budget), None);
+    }
+
+    #[test]
+    fn test_budget_json() {
+        let budget: Budget = serde_json::from_str(r#"{"minutes": 30}"#).unwrap();
+        assert_eq!(
+            budget,
+            Budget {
+                minutes: 30,
+                approvals: 0
+            }
+        );
+        assert!(budget.is_limited());
+    }
+}
@@ -0,0 +1,944 @@
+//! Layout self-calibration from live AcpThread memory.
+//!
+//! When a Zed release moves our offsets (v0.233.0's `cost: Option<SessionCost>`
+//! shifted `entries` from 0x90 to 0xb0) every approval misses until someone
+//! disassembles the new binary. This module proposes a new `EntryLayout` from
+//! memory instead, using the two pointers the hook already has on a miss:
+//!
+//! 1. `ToolCallUpdate` (2nd argument of `request_tool_call_authorization`):
+//!    find its `tool_call_id`, an `Arc<str>` — (ptr, len) pair whose pointer
+//!    has a plausible ArcInner header and ASCII text.
+//! 2. `AcpThread` (`self`): find a (ptr, len) pair that looks like a Vec whose
+//!    buffer contains that same `Arc<str>` (same pointer, or equal text).
+//! 3. Entry stride: the (entry_size, index) combination for which every element
+//!    has a small enum discriminant at +0 and every element with the matched
+//!    element's discriminant has a valid id at the same offset.
+//! 4. `respond_tx`: an Arc-looking word in the matched entry, nearest to where
+//!    the current layout would put it after shifting by the id's drift; the
+//!    status head sits at the current layout's distance before it.
+//!
+//! The heuristic only reads through `mem::SafeMem`, so it runs against
+//! synthetic fixtures (`SliceMemory`) in tests and live memory in Zed. It
+//! copies the structs and the entries buffer with a few large reads
+//! (`Scan`) and reads at most `MAX_SCAN_BYTES` in all, so a calibration
+//! costs a bounded number of reads. The hook only captures the two pointers
+//! and the update's bytes (`Request::capture`); the stale scanner thread
+//! runs the heuristic. The result is a proposal to review, never applied
+//! automatically.
+//!
+//! Calibration mode (`ZED_YOLO_CALIBRATE=<report path>`, set by
+//! `cargo patch calibrate`) runs the heuristic on the first tool call whether
+//! or not the layout missed, writes the proposal as a layout database entry to
+//! that path, and keeps the hooks observe-only for the session.
+
+use crate::fingerprint::AppFingerprint;
+use crate::layouts::{self, EntryLayout, MatchStyle};
+use crate::mem::{SafeMem, plausible_ptr};
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Bytes of AcpThread searched for the entries Vec.
+const THREAD_SCAN_LEN: usize = 0x400;
+/// Bytes of ToolCallUpdate searched for the tool_call_id.
+const UPDATE_SCAN_LEN: usize = 0x200;
+const MIN_ENTRY_SIZE: usize = 0x40;
+const MAX_ENTRY_SIZE: usize = 0x800;
+const MAX_ENTRIES: u64 = 4096;
+/// AgentThreadEntry has a handful of variants; anything larger isn't a tag.
+const MAX_DISCRIMINANT: u64 = 0x20;
+/// tool_call_ids are short ASCII tokens (`toolu_…`, UUIDs).
+const MAX_ID_LEN: u64 = 256;
+const ARC_INNER_DATA_OFFSET: u64 = 0x10;
+/// Bytes copied per read when copying a struct or buffer.
+const CHUNK: usize = 0x1_0000;
+/// Most bytes one calibration reads, copies included.
+const MAX_SCAN_BYTES: usize = 0x40_0000;
+
+// ---------------------------------------------------------------------------
+// Scan memory
+// ---------------------------------------------------------------------------
+
+/// Memory for one calibration: copied regions are read locally, anything
+/// else from `inner` until `MAX_SCAN_BYTES` have been read in all.
+struct Scan<'a, M> {
+    inner: &'a M,
+    copies: Vec<(u64, Vec<u8>)>,
+    read: Cell<usize>,
+}
+
+impl<'a, M: SafeMem> Scan<'a, M> {
+    fn new(inner: &'a M) -> Self {
+        Scan {
+            inner,
+            copies: Vec::new(),
+            read: Cell::new(0),
+        }
+    }
+
+    fn left(&self) -> usize {
+        MAX_SCAN_BYTES.saturating_sub(self.read.get())
+    }
+
+    fn copied(&self, addr: u64, len: usize) -> Option<&[u8]> {
+        self.copies.iter().find_map(|(base, bytes)| {
+            let start = usize::try_from(addr.checked_sub(*base)?).ok()?;
+            bytes.get(start..start.checked_add(len)?)
+        })
+    }
+
+    /// Copy up to `len` bytes at `base` in reads of up to `CHUNK` bytes,
+    /// halving a read that fails, until nothing more is readable or the scan
+    /// budget is spent. Once per `base`.
+    fn copy(&mut self, base: u64, len: usize) {
+        if self.copied(base, len).is_some() || self.copies.iter().any(|(b, _)| *b == base) {
+            return;
+        }
+        let mut bytes = Vec::new();
+        let mut chunk = CHUNK;
+        while bytes.len() < len {
+            let size = chunk.min(len - bytes.len()).min(self.left());
+            if size < 8 {
+                break;
+            }
+            let start = bytes.len();
+            bytes.resize(start + size, 0);
+            self.read.set(self.read.get() + size);
+            if !self.inner.read(base + start as u64, &mut bytes[start..]) {
+                bytes.truncate(start);
+                chunk = size / 2;
+            }
+        }
+        if !bytes.is_empty() {
+            self.copies.push((base, bytes));
+        }
+    }
+}
+
+impl<M: SafeMem> SafeMem for Scan<'_, M> {
+    fn read(&self, addr: u64, buf: &mut [u8]) -> bool {
+        if let Some(bytes) = self.copied(addr, buf.len()) {
+            buf.copy_from_slice(bytes);
+            return true;
+        }
+        if buf.len() > self.left() {
+            return false;
+        }
+        self.read.set(self.read.get() + buf.len());
+        self.inner.read(addr, buf)
+    }
+
+    fn write_u64(&self, _addr: u64, _value: u64) -> bool {
+        false
+    }
+}
+
+/// A calibration to run on the stale scanner thread: the pointers a hook
+/// had on a miss, plus a copy of the `ToolCallUpdate`, which lives in the
+/// hook caller's frame and is gone by then.
+pub(crate) struct Request {
+    pub(crate) thread: u64,
+    pub(crate) update: u64,
+    update_bytes: Vec<u8>,
+    /// The hook call it came from, for log lines.
+    pub(crate) count: u64,
+}
+
+impl Request {
+    /// Copy what calibration needs from the hook: one read of the update.
+    pub(crate) fn capture(mem: &impl SafeMem, thread: u64, update: u64, count: u64) -> Self {
+        Request {
+            thread,
+            update,
+            update_bytes: mem
+                .read_bytes(update, UPDATE_SCAN_LEN + 16)
+                .unwrap_or_default(),
+            count,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Heap values
+// ---------------------------------------------------------------------------
+
+fn looks_like_arc(mem: &impl SafeMem, ptr: u64) -> bool {
+    if !plausible_ptr(ptr) {
+        return false;
+    }
+    let (Some(strong), Some(weak)) = (mem.read_u64(ptr), mem.read_u64(ptr + 8)) else {
+        return false;
+    };
+    (1..=64).contains(&strong) && (1..=64).contains(&weak)
+}
+
+/// Text of an `Arc<str>` (ptr, len) if it looks like a tool_call_id.
+fn read_id(mem: &impl SafeMem, ptr: u64, len: u64) -> Option<String> {
+    if len == 0 || len > MAX_ID_LEN || !looks_like_arc(mem, ptr) {
+        return None;
+    }
+    let mut bytes = vec![0u8; len as usize];
+    if !mem.read(ptr + ARC_INNER_DATA_OFFSET, &mut bytes) {
+        return None;
+    }
+    if !bytes.iter().all(|b| b.is_ascii_graphic()) {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+// ---------------------------------------------------------------------------
+// Proposal
+// ---------------------------------------------------------------------------
+
+/// Offsets recovered from memory. Everything is relative to the same struct
+/// as the `EntryLayout` field of the same name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Proposal {
+    pub(crate) tool_call_id: String,
+    pub(crate) update_id_ptr_offset: usize,
+    pub(crate) entries_ptr_offset: usize,
+    pub(crate) entries_len_offset: usize,
+    pub(crate) entries_len: u64,
+    pub(crate) entry_size: usize,
+    pub(crate) entry_index: u64,
+    pub(crate) toolcall_variant: u64,
+    pub(crate) id_ptr_offset: usize,
+    pub(crate) status_offset: Option<usize>,
+    pub(crate) respond_tx_offset: Option<usize>,
+    /// Several entry sizes fit equally well (e.g. a single-entry Vec); the
+    /// current layout's size was kept or the smallest was chosen.
+    pub(crate) ambiguous: bool,
+}
+
+impl Proposal {
+    /// The proposal as a layout, keeping everything calibration doesn't
+    /// recover (send style, niche start, snapshot) from `base`. `None` when
+    /// respond_tx / status weren't found or `base` isn't a Preview230 layout.
+    pub(crate) fn to_layout(&self, base: &EntryLayout, name: &'static str) -> Option<EntryLayout> {
+        let MatchStyle::Preview230 {
+            waiting_payload_niche_start,
+            ..
+        } = base.match_style
+        else {
+            return None;
+        };
+        Some(EntryLayout {
+            name,
+            entries_ptr_offset: self.entries_ptr_offset,
+            entries_len_offset: self.entries_len_offset,
+            entry_size: self.entry_size,
+            status_offset: self.status_offset?,
+            respond_tx_offset: self.respond_tx_offset?,
+            update_id_ptr_offset: self.update_id_ptr_offset,
+            update_id_len_offset: self.update_id_ptr_offset + 8,
+            send_style: base.send_style,
+            match_style: MatchStyle::Preview230 {
+                toolcall_variant: self.toolcall_variant,
+                id_ptr_offset: self.id_ptr_offset,
+                id_len_offset: self.id_ptr_offset + 8,
+                waiting_payload_niche_start,
+            },
+            snapshot: base.snapshot,
+            thread: base.thread,
+        })
+    }
+
+    /// One-line summary for logs.
+    pub(crate) fn summary(&self) -> String {
+        let opt = |v: Option<usize>| v.map_or("?".to_string(), |v| format!("{v:#x}"));
+        format!(
+            "entries={:#x}/{:#x} (len={}) entry_size={:#x}{} variant={:#x} id={:#x} status={} respond_tx={} update_id={:#x} (entry[{}] id=\"{}\")",
+            self.entries_ptr_offset,
+            self.entries_len_offset,
+            self.entries_len,
+            self.entry_size,
+            if self.ambiguous { " (ambiguous)" } else { "" },
+            self.toolcall_variant,
+            self.id_ptr_offset,
+            opt(self.status_offset),
+            opt(self.respond_tx_offset),
+            self.update_id_ptr_offset,
+            self.entry_index,
+            self.tool_call_id
+        )
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Heuristic
+// ---------------------------------------------------------------------------
+
+struct IdCandidate {
+    offset: usize,
+    ptr: u64,
+    len: u64,
+    text: String,
+}
+
+/// `Arc<str>` fields in `[base, base + scan_len)`, the hint's offset first.
+fn id_candidates(
+    mem: &impl SafeMem,
+    base: u64,
+    scan_len: usize,
+    preferred: usize,
+) -> Vec<IdCandidate> {
+    let mut offsets: Vec<usize> = vec![preferred];
+    offsets.extend((0..scan_len).step_by(8).filter(|o| *o != preferred));
+
+    offsets
+        .into_iter()
+        .filter_map(|offset| {
+            let ptr = mem.read_u64(base + offset as u64)?;
+            let len = mem.read_u64(base + offset as u64 + 8)?;
+            let text = read_id(mem, ptr, len)?;
+            Some(IdCandidate {
+                offset,
+                ptr,
+                len,
+                text,
+            })
+        })
+        .collect()
+}
+
+/// (ptr_offset, len_offset, buffer, len) of Vec-looking pairs in the thread,
+/// the hint's offsets first.
+fn vec_candidates(
+    mem: &impl SafeMem,
+    thread: u64,
+    hint: &EntryLayout,
+) -> Vec<(usize, usize, u64, u64)> {
+    let mut pairs = vec![(hint.entries_ptr_offset, hint.entries_len_offset)];
+    for ptr_off in (0..THREAD_SCAN_LEN).step_by(8) {
+        pairs.push((ptr_off, ptr_off + 8));
+        if ptr_off >= 8 {
+            pairs.push((ptr_off, ptr_off - 8));
+        }
+    }
+
+    let mut seen = Vec::new();
+    pairs
+        .into_iter()
+        .filter(|pair| {
+            let new = !seen.contains(pair);
+            seen.push(*pair);
+            new
+        })
+        .filter_map(|(ptr_off, len_off)| {
+            let ptr = mem.read_u64(thread + ptr_off as u64)?;
+            let len = mem.read_u64(thread + len_off as u64)?;
+            (plausible_ptr(ptr) && (1..=MAX_ENTRIES).contains(&len))
+                .then_some((ptr_off, len_off, ptr, len))
+        })
+        .collect()
+}
+
+/// Byte positions in the Vec buffer holding `id` (same Arc, or equal text).
+fn id_hits(mem: &impl SafeMem, buffer: u64, len: u64, id: &IdCandidate) -> Vec<usize> {
+    let limit = (len as usize).saturating_mul(MAX_ENTRY_SIZE);
+    let mut same_arc = Vec::new();
+    let mut same_text = Vec::new();
+    for pos in (0..limit).step_by(8) {
+        let Some(word) = mem.read_u64(buffer + pos as u64) else {
+            break;
+        };
+        let Some(next) = mem.read_u64(buffer + pos as u64 + 8) else {
+            break;
+        };
+        if next != id.len {
+            continue;
+        }
+        if word == id.ptr {
+            same_arc.push(pos);
+        } else if read_id(mem, word, next).as_deref() == Some(id.text.as_str()) {
+            same_text.push(pos);
+        }
+    }
+    if same_arc.is_empty() {
+        same_text
+    } else {
+        same_arc
+    }
+}
+
+struct Stride {
+    entry_size: usize,
+    entry_index: u64,
+    id_offset: usize,
+    variant: u64,
+    /// Entries sharing the matched entry's variant, all with a valid id.
+    evidence: u64,
+}
+
+/// Check one (entry_size, index) explanation of an id hit at `pos`.
+fn check_stride(
+    mem: &impl SafeMem,
+    buffer: u64,
+    len: u64,
+    pos: usize,
+    size: usize,
+    index: u64,
+) -> Option<Stride> {
+    let id_offset = pos - index as usize * size;
+    let variant = mem.read_u64(buffer + index * size as u64)?;
+    if variant > MAX_DISCRIMINANT {
+        return None;
+    }
+
+    let mut evidence = 0;
+    for j in 0..len {
+        let entry = buffer + j * size as u64;
+        let discriminant = mem.read_u64(entry)?;
+        if discriminant > MAX_DISCRIMINANT {
+            return None;
+        }
+        if discriminant == variant {
+            let ptr = mem.read_u64(entry + id_offset as u64)?;
+            let id_len = mem.read_u64(entry + id_offset as u64 + 8)?;
+            read_id(mem, ptr, id_len)?;
+            evidence += 1;
+        }
+    }
+
+    Some(Stride {
+        entry_size: size,
+        entry_index: index,
+        id_offset,
+        variant,
+        evidence,
+    })
+}
+
+/// Best stride for an id hit: most evidence, then the hint's size, then smallest.
+fn best_stride(
+    mem: &impl SafeMem,
+    buffer: u64,
+    len: u64,
+    pos: usize,
+    hint: &EntryLayout,
+) -> Option<(Stride, bool)> {
+    let mut fits: Vec<Stride> = Vec::new();
+    for size in (MIN_ENTRY_SIZE..=MAX_ENTRY_SIZE).step_by(8) {
+        // The hit lies in exactly one element per stride; its len word must too.
+        let index = (pos / size) as u64;
+        if index >= len || pos % size + 16 > size {
+            continue;
+        }
+        if let Some(stride) = check_stride(mem, buffer, len, pos, size, index) {
+            fits.push(stride);
+        }
+    }
+
+    let best_evidence = fits.iter().map(|s| s.evidence).max()?;
+    fits.retain(|s| s.evidence == best_evidence);
+    let ambiguous = fits.iter().any(|s| s.entry_size != fits[0].entry_size);
+    let chosen = match fits.iter().position(|s| s.entry_size == hint.entry_size) {
+        Some(i) => fits.swap_remove(i),
+        None => fits.swap_remove(0),
+    };
+    Some((chosen, ambiguous))
+}
+
+/// (status_offset, respond_tx_offset) inside the matched entry.
+fn find_respond_tx(
+    mem: &impl SafeMem,
+    entry: u64,
+    stride: &Stride,
+    id_ptr: u64,
+    hint: &EntryLayout,
+) -> (Option<usize>, Option<usize>) {
+    let MatchStyle::Preview230 {
+        id_ptr_offset: hint_id_offset,
+        waiting_payload_niche_start,
+        ..
+    } = hint.match_style
+    else {
+        return (None, None);
+    };
+
+    let shift = stride.id_offset as i64 - hint_id_offset as i64;
+    let expected = hint.respond_tx_offset as i64 + shift;
+    let status_distance = hint.respond_tx_offset as i64 - hint.status_offset as i64;
+
+    let tx_offset = (0..stride.entry_size)
+        .step_by(8)
+        .filter(|offset| *offset != stride.id_offset)
+        .filter(|offset| {
+            let status = *offset as i64 - status_distance;
+            if status < 0 {
+                return false;
+            }
+            let (Some(tx), Some(head)) = (
+                mem.read_u64(entry + *offset as u64),
+                mem.read_u64(entry + status as u64),
+            ) else {
+                return false;
+            };
+            tx != id_ptr && looks_like_arc(mem, tx) && head < waiting_payload_niche_start
+        })
+        .min_by_key(|offset| (*offset as i64 - expected).abs());
+
+    match tx_offset {
+        Some(tx) => (Some((tx as i64 - status_distance) as usize), Some(tx)),
+        None => (None, None),
+    }
+}
+
+/// `calibrate_request` reading the update from memory too (tests).
+#[cfg(test)]
+pub(crate) fn calibrate(
+    mem: &impl SafeMem,
+    thread: u64,
+    update: u64,
+    hint: &EntryLayout,
+) -> Option<Proposal> {
+    scan(Scan::new(mem), thread, update, hint)
+}
+
+/// Propose a layout for the thread of `request` given its copy of the
+/// `ToolCallUpdate`, using `hint` (the layout that just missed) to break ties.
+pub(crate) fn calibrate_request(
+    mem: &impl SafeMem,
+    request: &Request,
+    hint: &EntryLayout,
+) -> Option<Proposal> {
+    let mut memory = Scan::new(mem);
+    memory
+        .copies
+        .push((request.update, request.update_bytes.clone()));
+    scan(memory, request.thread, request.update, hint)
+}
+
+fn scan<M: SafeMem>(
+    mut mem: Scan<'_, M>,
+    thread: u64,
+    update: u64,
+    hint: &EntryLayout,
+) -> Option<Proposal> {
+    mem.copy(update, UPDATE_SCAN_LEN + 16);
+    mem.copy(thread, THREAD_SCAN_LEN + 16);
+    let ids = id_candidates(&mem, update, UPDATE_SCAN_LEN, hint.update_id_ptr_offset);
+    let vecs = vec_candidates(&mem, thread, hint);
+
+    for id in &ids {
+        for &(entries_ptr_offset, entries_len_offset, buffer, len) in &vecs {
+            mem.copy(buffer, (len as usize).saturating_mul(MAX_ENTRY_SIZE));
+            let mem = &mem;
+            for pos in id_hits(mem, buffer, len, id) {
+                let Some((stride, ambiguous)) = best_stride(mem, buffer, len, pos, hint) else {
+                    continue;
+                };
+                let entry = buffer + stride.entry_index * stride.entry_size as u64;
+                let (status_offset, respond_tx_offset) =
+                    find_respond_tx(mem, entry, &stride, id.ptr, hint);
+
+                return Some(Proposal {
+                    tool_call_id: id.text.clone(),
+                    update_id_ptr_offset: id.offset,
+                    entries_ptr_offset,
+                    entries_len_offset,
+                    entries_len: len,
+                    entry_size: stride.entry_size,
+                    entry_index: stride.entry_index,
+                    toolcall_variant: stride.variant,
+                    id_ptr_offset: stride.id_offset,
+                    status_offset,
+                    respond_tx_offset,
+                    ambiguous,
+                });
+            }
+        }
+    }
+    None
+}
+
+// ---------------------------------------------------------------------------
+// Calibration mode
+// ---------------------------------------------------------------------------
+
+/// Environment variable that turns on calibration mode; its value is the path
+/// the report is written to.
+pub(crate) const CALIBRATE_ENV: &str = "ZED_YOLO_CALIBRATE";
+
+static OUTPUT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Read `ZED_YOLO_CALIBRATE` once at startup. Returns the report path when
+/// calibration mode is on.
+pub(crate) fn init_from_env() -> Option<&'static Path> {
+    let path = std::env::var_os(CALIBRATE_ENV).filter(|v| !v.is_empty())?;
+    Some(OUTPUT.get_or_init(|| PathBuf::from(path)).as_path())
+}
+
+/// Report path if calibration mode is on.
+pub(crate) fn output_path() -> Option<&'static Path> {
+    OUTPUT.get().map(PathBuf::as_path)
+}
+
+/// Name given to a calibrated layout entry.
+pub(crate) fn layout_name(app: &AppFingerprint) -> String {
+    format!(
+        "v{}-calibrated",
+        app.version.as_deref().unwrap_or("unknown")
+    )
+}
+
+/// Calibration report read by `cargo patch calibrate`:
+///
+/// ```json
+/// { "app": { "bundle_id", "version", "build", "binary_hash" },
+///   "hint": "v0.233.x",
+///   "proposal": { "summary", "ambiguous", "complete" } | null,
+///   "layout": <layout database entry> | null }
+/// ```
+///
+/// `layout` is keyed to this exact version and binary hash; it is null when
+/// nothing was found or respond_tx / status couldn't be located.
+pub(crate) fn report(
+    app: &AppFingerprint,
+    proposal: Option<&Proposal>,
+    hint: &EntryLayout,
+) -> serde_json::Value {
+    use serde_json::json;
+
+    let versions: Vec<String> = app.version.iter().cloned().collect();
+    let binary_hashes: Vec<String> = app.binary_hash().into_iter().collect();
+    let name = layout_name(app);
+    let layout = proposal
+        .and_then(|p| p.to_layout(hint, "calibrated"))
+        .map(|layout| {
+            let mut entry = layouts::to_json(&layout, &versions, &binary_hashes);
+            entry["name"] = json!(name);
+            entry
+        });
+
+    json!({
+        "app": {
+            "bundle_id": app.bundle_id,
+            "version": app.version,
+            "build": app.build,
+            "binary_hash": app.binary_hash(),
+        },
+        "hint": hint.name,
+        "proposal": proposal.map(|p| json!({
+            "summary": p.summary(),
+            "ambiguous": p.ambiguous,
+            "complete": layout.is_some(),
+        })),
+        "layout": layout,
+    })
+}
+
+/// Write `report` to `path` (via a temp file, so the reader never sees a
+/// partial report).
+pub(crate) fn write_report(path: &Path, report: &serde_json::Value) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let text = serde_json::to_string_pretty(report).map_err(std::io::Error::other)?;
+    std::fs::write(&tmp, text)?;
+    std::fs::rename(&tmp, path)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layouts::ENTRY_LAYOUTS;
+    use crate::mem::SliceMemory;
+
+    const THREAD: u64 = 0x1_1000_0000;
+    const UPDATE: u64 = 0x1_2000_0000;
+    const ENTRIES: u64 = 0x1_3000_0000;
+    const HEAP: u64 = 0x1_4000_0000;
+
+    fn put(bytes: &mut [u8], offset: usize, value: u64) {
+        bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Synthetic process memory: an AcpThread with an entries Vec laid out per
+    /// the given offsets, ToolCall entries with Arc<str> ids and a waiting
+    /// entry holding a respond_tx Arc.
+    struct Fixture {
+        mem: SliceMemory,
+        next_heap: u64,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Fixture {
+                mem: SliceMemory::default(),
+                next_heap: HEAP,
+            }
+        }
+
+        /// Allocate an ArcInner with `payload` after the strong/weak header.
+        fn arc(&mut self, payload: &[u8]) -> u64 {
+            let ptr = self.next_heap;
+            let mut bytes = vec![0u8; 16 + payload.len()];
+            put(&mut bytes, 0, 1);
+            put(&mut bytes, 8, 1);
+            bytes[16..].copy_from_slice(payload);
+            self.mem.add(ptr, bytes);
+            self.next_heap += 0x1000;
+            ptr
+        }
+    }
+
+    struct Shape {
+        entries_ptr: usize,
+        entries_len: usize,
+        entry_size: usize,
+        id_offset: usize,
+        status_offset: usize,
+        respond_tx_offset: usize,
+        update_id_offset: usize,
+    }
+
+    const CURRENT: Shape = Shape {
+        entries_ptr: 0xb0,
+        entries_len: 0xb8,
+        entry_size: 0x1c0,
+        id_offset: 0x168,
+        status_offset: 0x118,
+        respond_tx_offset: 0x160,
+        update_id_offset: 0x128,
+    };
+
+    /// Everything moved: entries Vec (len before ptr), stride, id, status,
+    /// respond_tx and the update's id.
+    const DRIFTED: Shape = Shape {
+        entries_ptr: 0xd8,
+        entries_len: 0xd0,
+        entry_size: 0x1e0,
+        id_offset: 0x188,
+        status_offset: 0x138,
+        respond_tx_offset: 0x180,
+        update_id_offset: 0x140,
+    };
+
+    /// `kinds`: discriminant per entry; ToolCalls (2) get ids `call_<i>`.
+    /// The last ToolCall is the waiting one and the update's id.
+    fn build(shape: &Shape, kinds: &[u64], same_arc: bool) -> Fixture {
+        let mut f = Fixture::new();
+        let mut entries = vec![0u8; shape.entry_size * kinds.len()];
+        let waiting = kinds.iter().rposition(|k| *k == 2).unwrap();
+        let mut waiting_id = 0;
+
+        for (i, kind) in kinds.iter().enumerate() {
+            let base = i * shape.entry_size;
+            put(&mut entries, base, *kind);
+            // Some noise that must not be taken for ids or senders.
+            put(&mut entries, base + 0x08, 0xdead_beef);
+            if *kind == 2 {
+                let text = format!("toolu_call_{i}");
+                let id = f.arc(text.as_bytes());
+                put(&mut entries, base + shape.id_offset, id);
+                put(&mut entries, base + shape.id_offset + 8, text.len() as u64);
+                put(
+                    &mut entries,
+                    base + shape.status_offset,
+                    0x8000_0000_0000_0004,
+                );
+                if i == waiting {
+                    waiting_id = id;
+                    let tx = f.arc(&[0u8; 32]);
+                    put(&mut entries, base + shape.status_offset, 0x10);
+                    put(&mut entries, base + shape.respond_tx_offset, tx);
+                }
+            }
+        }
+        f.mem.add(ENTRIES, entries);
+
+        let mut thread = vec![0u8; THREAD_SCAN_LEN + 16];
+        put(&mut thread, 0x10, HEAP + 0x100_0000); // unrelated pointer
+        put(&mut thread, shape.entries_ptr, ENTRIES);
+        put(&mut thread, shape.entries_len, kinds.len() as u64);
+        f.mem.add(THREAD, thread);
+
+        let text = format!("toolu_call_{waiting}");
+        let update_id = if same_arc {
+            waiting_id
+        } else {
+            f.arc(text.as_bytes())
+        };
+        let mut update = vec![0u8; UPDATE_SCAN_LEN + 16];
+        put(&mut update, shape.update_id_offset, update_id);
+        put(&mut update, shape.update_id_offset + 8, text.len() as u64);
+        f.mem.add(UPDATE, update);
+        f
+    }
+
+    #[test]
+    fn test_calibrate_current_layout() {
+        let f = build(&CURRENT, &[0, 2, 1, 2, 0, 2], true);
+        let proposal = calibrate(&f.mem, THREAD, UPDATE, &ENTRY_LAYOUTS[0]).unwrap();
+
+        assert_eq!(proposal.tool_call_id, "toolu_call_5");
+        assert_eq!(proposal.entries_ptr_offset, 0xb0);
+        assert_eq!(proposal.entries_len_offset, 0xb8);
+        assert_eq!(proposal.entries_len, 6);
+        assert_eq!(proposal.entry_size, 0x1c0);
+        assert_eq!(proposal.entry_index, 5);
+        assert_eq!(proposal.toolcall_variant, 2);
+        assert_eq!(proposal.id_ptr_offset, 0x168);
+        assert_eq!(proposal.status_offset, Some(0x118));
+        assert_eq!(proposal.respond_tx_offset, Some(0x160));
+        assert_eq!(proposal.update_id_ptr_offset, 0x128);
+        assert!(!proposal.ambiguous);
+
+        let layout = proposal.to_layout(&ENTRY_LAYOUTS[0], "calibrated").unwrap();
+        assert_eq!(
+            layout.entries_ptr_offset,
+            ENTRY_LAYOUTS[0].entries_ptr_offset
+        );
+        assert_eq!(layout.match_style, ENTRY_LAYOUTS[0].match_style);
+    }
+
+    #[test]
+    fn test_calibrate_drifted_layout() {
+        let f = build(&DRIFTED, &[0, 2, 2, 1, 2], false);
+        let proposal = calibrate(&f.mem, THREAD, UPDATE, &ENTRY_LAYOUTS[0]).unwrap();
+
+        assert_eq!(proposal.entries_ptr_offset, 0xd8);
+        assert_eq!(proposal.entries_len_offset, 0xd0);
+        assert_eq!(proposal.entry_size, 0x1e0);
+        assert_eq!(proposal.entry_index, 4);
+        assert_eq!(proposal.id_ptr_offset, 0x188);
+        assert_eq!(proposal.status_offset, Some(0x138));
+        assert_eq!(proposal.respond_tx_offset, Some(0x180));
+        assert_eq!(proposal.update_id_ptr_offset, 0x140);
+        assert!(!proposal.ambiguous);
+        assert!(proposal.summary().contains("entry_size=0x1e0"));
+    }
+
+    /// Counts what is read from `mem`.
+    struct Counting<'a> {
+        mem: &'a SliceMemory,
+        reads: Cell<usize>,
+        bytes: Cell<usize>,
+    }
+
+    impl<'a> Counting<'a> {
+        fn new(mem: &'a SliceMemory) -> Self {
+            Counting {
+                mem,
+                reads: Cell::new(0),
+                bytes: Cell::new(0),
+            }
+        }
+    }
+
+    impl SafeMem for Counting<'_> {
+        fn read(&self, addr: u64, buf: &mut [u8]) -> bool {
+            self.reads.set(self.reads.get() + 1);
+            self.bytes.set(self.bytes.get() + buf.len());
+            self.mem.read(addr, buf)
+        }
+
+        fn write_u64(&self, _addr: u64, _value: u64) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_calibrate_reads_in_bulk_with_a_cap() {
+        let f = build(&DRIFTED, &[0, 2, 2, 1, 2], false);
+        let counting = Counting::new(&f.mem);
+        let proposal = calibrate(&counting, THREAD, UPDATE, &ENTRY_LAYOUTS[0]).unwrap();
+        assert_eq!(proposal.entry_size, 0x1e0);
+        assert!(counting.reads.get() < 100, "{} reads", counting.reads.get());
+
+        // A Vec claiming 4096 entries over 8 MiB of zeros: the scan stops
+        // at the cap, in large reads.
+        let mut f = build(&CURRENT, &[0, 2], true);
+        let mut thread = vec![0u8; THREAD_SCAN_LEN + 16];
+        put(&mut thread, 0x10, HEAP + 0x100_0000);
+        put(&mut thread, 0x18, MAX_ENTRIES);
+        f.mem.remove(THREAD);
+        f.mem.add(THREAD, thread);
+        f.mem
+            .add(HEAP + 0x100_0000, vec![0u8; MAX_ENTRY_SIZE * 4096]);
+        let counting = Counting::new(&f.mem);
+        assert!(calibrate(&counting, THREAD, UPDATE, &ENTRY_LAYOUTS[0]).is_none());
+        assert!(counting.bytes.get() <= MAX_SCAN_BYTES);
+        assert!(
+            counting.reads.get() < 1000,
+            "{} reads",
+            counting.reads.get()
+        );
+    }
+
+    #[test]
+    fn test_calibrate_request_after_the_hook_returned() {
+        let mut f = build(&DRIFTED, &[0, 2, 2, 1, 2], false);
+        let request = Request::capture(&f.mem, THREAD, UPDATE, 7);
+        // The caller's frame holding the ToolCallUpdate is gone.
+        f.mem.remove(UPDATE);
+        assert!(calibrate(&f.mem, THREAD, UPDATE, &ENTRY_LAYOUTS[0]).is_none());
+        let proposal = calibrate_request(&f.mem, &request, &ENTRY_LAYOUTS[0]).unwrap();
+        assert_eq!(proposal.update_id_ptr_offset, 0x140);
+        assert_eq!(proposal.respond_tx_offset, Some(0x180));
+    }
+
+    #[test]
+    fn test_calibrate_single_entry_is_ambiguous() {
+        let f = build(&CURRENT, &[2], true);
+        let proposal = calibrate(&f.mem, THREAD, UPDATE, &ENTRY_LAYOUTS[0]).unwrap();
+
+        // Any stride fits one entry; the hint's size is kept.
+        assert!(proposal.ambiguous);
+        assert_eq!(proposal.entry_size, 0x1c0);
+        assert_eq!(proposal.id_ptr_offset, 0x168);
+        assert_eq!(proposal.respond_tx_offset, Some(0x160));
+    }
+
+    #[test]
+    fn test_calibrate_without_match() {
+        let mut f = build(&CURRENT, &[0, 2], true);
+        // The update's id is nowhere in the entries.
+        let other = f.arc(b"toolu_unrelated");
+        let mut update = vec![0u8; UPDATE_SCAN_LEN + 16];
+        put(&mut update, 0x128, other);
+        put(&mut update, 0x130, 15);
+        let mut mem = SliceMemory::default();
+        std::mem::swap(&mut mem, &mut f.mem);
+        mem.remove(UPDATE);
+        mem.add(UPDATE, update);
+
+        assert!(calibrate(&mem, THREAD, UPDATE, &ENTRY_LAYOUTS[0]).is_none());
+        assert!(calibrate(&SliceMemory::default(), THREAD, UPDATE, &ENTRY_LAYOUTS[0]).is_none());
+    }
+
+    #[test]
+    fn test_report_is_a_layout_entry() {
+        let app = AppFingerprint {
+            bundle_id: Some("dev.zed.Zed-Preview".to_string()),
+            version: Some("0.240.0".to_string()),
+            build: Some("20260901.120000".to_string()),
+            uuid: Some("0123ABCD-0000-0000-0000-000000000000".to_string()),
+            ..AppFingerprint::default()
+        };
+        let f = build(&DRIFTED, &[0, 2, 2, 1, 2], false);
+        let proposal = calibrate(&f.mem, THREAD, UPDATE, &ENTRY_LAYOUTS[0]).unwrap();
+        let full = report(&app, Some(&proposal), &ENTRY_LAYOUTS[0]);
+
+        assert_eq!(full["app"]["version"], "0.240.0");
+        assert_eq!(full["proposal"]["complete"], true);
+        assert_eq!(full["layout"]["name"], "v0.240.0-calibrated");
+
+        let db = serde_json::json!({ "layouts": [full["layout"].clone()] });
+        let records = layouts::parse(&db.to_string()).unwrap();
+        assert_eq!(records[0].layout.name, "v0.240.0-calibrated");
+        assert_eq!(records[0].versions, ["0.240.0"]);
+        assert_eq!(records[0].binary_hashes, [app.binary_hash().unwrap()]);
+        assert_eq!(records[0].layout.entries_ptr_offset, 0xd8);
+        assert_eq!(records[0].layout.entry_size, 0x1e0);
+        assert_eq!(records[0].layout.respond_tx_offset, 0x180);
+
+        let empty = report(&app, None, &ENTRY_LAYOUTS[0]);
+        assert!(empty["proposal"].is_null());
+        assert!(empty["layout"].is_null());
+    }
+}
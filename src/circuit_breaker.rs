@@ -0,0 +1,419 @@
+//! Runaway-agent circuit breaker.
+//!
+//! An agent stuck in a loop can have hundreds of tool calls a minute
+//! approved without anyone looking. The breaker counts approvals from every
+//! path (`tool_authorization`, the stale scanner and `permission_decision`)
+//! over a sliding window and trips when there are too many:
+//!
+//! ```json
+//! "circuit_breaker": { "window_secs": 60, "max_approvals": 120, "max_per_session": 60 }
+//! ```
+//!
+//! `max_approvals` limits all sessions together, `max_per_session` one
+//! session tag (one AcpThread); built-in tool calls only count globally. `0`
+//! turns that limit off, as does `window_secs: 0` for the whole breaker.
+//! Limits above `RING` (4096) count as `RING`.
+//!
+//! Once tripped it stays tripped: nothing is auto-approved, on any session,
+//! and every dialog is left for the user, as if the budget ran out. It is
+//! reset when a config reload changes the `circuit_breaker` setting, on any
+//! `set` of a `circuit_breaker_*` key on the control socket (even to the same
+//! value), or when Zed restarts.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+/// `circuit_breaker` config section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreaker {
+    /// Sliding window length. 0 = breaker off.
+    pub window_secs: u64,
+    /// Approvals across all sessions within the window. 0 = no global limit.
+    pub max_approvals: u64,
+    /// Approvals for one session tag within the window. 0 = no per-session
+    /// limit.
+    pub max_per_session: u64,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker {
+            window_secs: 60,
+            max_approvals: 120,
+            max_per_session: 60,
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn is_enabled(&self) -> bool {
+        self.window_secs != 0 && (self.max_approvals != 0 || self.max_per_session != 0)
+    }
+}
+
+/// Why the breaker tripped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Trip {
+    /// `max_approvals` reached across all sessions.
+    Global { approvals: u64, window_secs: u64 },
+    /// `max_per_session` reached by one session tag.
+    Session {
+        session: String,
+        approvals: u64,
+        window_secs: u64,
+    },
+}
+
+impl std::fmt::Display for Trip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trip::Global {
+                approvals,
+                window_secs,
+            } => write!(f, "{approvals} approvals in {window_secs}s (max_approvals)"),
+            Trip::Session {
+                session,
+                approvals,
+                window_secs,
+            } => write!(
+                f,
+                "{approvals} approvals in {window_secs}s for session {session} (max_per_session)"
+            ),
+        }
+    }
+}
+
+/// Approvals remembered; limits above it count as `RING`.
+pub(crate) const RING: usize = 4096;
+
+// Approval word: counted (1) | has session (1) | session tag (16) | Unix ms
+// (44). Zero is an empty slot.
+const COUNTED: u64 = 1 << 63;
+const HAS_SESSION: u64 = 1 << 62;
+const SESSION_SHIFT: u32 = 44;
+const TIME_MASK: u64 = (1 << SESSION_SHIFT) - 1;
+
+/// What an approval word says about its session: the flag and the tag.
+fn session_bits(session: Option<&str>) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    session.map_or(0, |session| {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        session.hash(&mut hasher);
+        HAS_SESSION | ((hasher.finish() & 0xffff) << SESSION_SHIFT)
+    })
+}
+
+/// Approvals within the window and the trip, if any. Times are Unix
+/// milliseconds, passed in so the logic is testable.
+///
+/// Approvals are taken from Frida interceptor context, so this is all
+/// atomics: a ring of `RING` approval words and the trip behind a pointer.
+/// Two approvals taken at once can both pass the last free place under a
+/// limit, so the window may end up one or two over it. Sessions are counted
+/// by a 16-bit tag, so two sessions sharing one add up (tighter, never
+/// looser).
+pub(crate) struct Tracker {
+    recent: [AtomicU64; RING],
+    next: AtomicUsize,
+    /// Leaked `Trip`, null while closed. Trips are rare and a hook may still
+    /// be reading the previous one, so a reset never frees it.
+    tripped: AtomicPtr<Trip>,
+}
+
+impl Tracker {
+    pub(crate) const fn new() -> Self {
+        Tracker {
+            recent: [const { AtomicU64::new(0) }; RING],
+            next: AtomicUsize::new(0),
+            tripped: AtomicPtr::new(std::ptr::null_mut()),
+        }
+    }
+
+    /// Forget every approval and close the breaker.
+    pub(crate) fn reset(&self) {
+        for slot in &self.recent {
+            slot.store(0, Ordering::SeqCst);
+        }
+        self.tripped.store(std::ptr::null_mut(), Ordering::SeqCst);
+    }
+
+    pub(crate) fn tripped(&self) -> Option<Trip> {
+        let trip = self.tripped.load(Ordering::SeqCst);
+        // SAFETY: non-null pointers come from `Box::leak` and are never freed.
+        (!trip.is_null()).then(|| unsafe { (*trip).clone() })
+    }
+
+    /// Approvals within the window ending at `now_ms` whose session bits
+    /// match `session`, or all of them.
+    fn count(&self, breaker: &CircuitBreaker, now_ms: u64, session: Option<u64>) -> u64 {
+        let window_ms = breaker.window_secs.saturating_mul(1000);
+        self.recent
+            .iter()
+            .map(|slot| slot.load(Ordering::SeqCst))
+            .filter(|&word| {
+                word & COUNTED != 0
+                    && now_ms.saturating_sub(word & TIME_MASK) < window_ms
+                    && session.is_none_or(|bits| word & !(COUNTED | TIME_MASK) == bits)
+            })
+            .count() as u64
+    }
+
+    /// Approvals within the window ending at `now_ms`.
+    pub(crate) fn in_window(&self, breaker: &CircuitBreaker, now_ms: u64) -> u64 {
+        self.count(breaker, now_ms, None)
+    }
+
+    /// Count one approval for `session`. `Err` (nothing counted) when the
+    /// breaker is open or this approval would exceed a limit, which trips it.
+    pub(crate) fn take(
+        &self,
+        breaker: &CircuitBreaker,
+        session: Option<&str>,
+        now_ms: u64,
+    ) -> Result<(), Trip> {
+        if let Some(trip) = self.tripped() {
+            return Err(trip);
+        }
+        if !breaker.is_enabled() {
+            return Ok(());
+        }
+
+        let max_approvals = breaker.max_approvals.min(RING as u64);
+        let max_per_session = breaker.max_per_session.min(RING as u64);
+        let global = self.in_window(breaker, now_ms);
+        let trip = if max_approvals != 0 && global >= max_approvals {
+            Some(Trip::Global {
+                approvals: global,
+                window_secs: breaker.window_secs,
+            })
+        } else {
+            session
+                .filter(|_| max_per_session != 0)
+                .and_then(|session| {
+                    let approvals = self.count(breaker, now_ms, Some(session_bits(Some(session))));
+                    (approvals >= max_per_session).then(|| Trip::Session {
+                        session: session.to_string(),
+                        approvals,
+                        window_secs: breaker.window_secs,
+                    })
+                })
+        };
+        if let Some(trip) = trip {
+            let leaked: *mut Trip = Box::leak(Box::new(trip));
+            return match self.tripped.compare_exchange(
+                std::ptr::null_mut(),
+                leaked,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                // SAFETY: just leaked above and not shared.
+                Ok(_) => Err(unsafe { (*leaked).clone() }),
+                Err(first) => {
+                    // SAFETY: ours lost the race and was never shared; the
+                    // winner's is never freed.
+                    drop(unsafe { Box::from_raw(leaked) });
+                    Err(unsafe { (*first).clone() })
+                }
+            };
+        }
+
+        let i = self.next.fetch_add(1, Ordering::SeqCst) % RING;
+        let word = COUNTED | session_bits(session) | (now_ms & TIME_MASK);
+        self.recent[i].store(word, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Give back the latest approval of `session`, for a dialog that was not
+    /// answered after all.
+    pub(crate) fn refund(&self, session: Option<&str>) {
+        let bits = session_bits(session);
+        let next = self.next.load(Ordering::SeqCst);
+        for back in 1..=RING {
+            let slot = &self.recent[(next + RING - back) % RING];
+            let word = slot.load(Ordering::SeqCst);
+            if word & COUNTED != 0
+                && word & !(COUNTED | TIME_MASK) == bits
+                && slot
+                    .compare_exchange(word, 0, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            {
+                return;
+            }
+        }
+    }
+}
+
+static TRACKER: Tracker = Tracker::new();
+
+/// Close the breaker and start counting afresh.
+pub(crate) fn reset(breaker: &CircuitBreaker) {
+    let was_tripped = TRACKER.tripped().is_some();
+    TRACKER.reset();
+    if was_tripped {
+        tracing::warn!(
+            "circuit_breaker: reset (window_secs={}, max_approvals={}, max_per_session={}) — auto-approving again",
+            breaker.window_secs,
+            breaker.max_approvals,
+            breaker.max_per_session
+        );
+    }
+}
+
+/// Why the breaker is open, if it is.
+pub(crate) fn tripped() -> Option<Trip> {
+    TRACKER.tripped()
+}
+
+/// Approvals counted within the current window.
+pub(crate) fn in_window(breaker: &CircuitBreaker) -> u64 {
+    TRACKER.in_window(breaker, crate::budget::now_ms())
+}
+
+/// Count one approval; `Err` when the breaker is (now) open. Logs the trip.
+pub(crate) fn take(breaker: &CircuitBreaker, session: Option<&str>) -> Result<(), Trip> {
+    let was_tripped = TRACKER.tripped().is_some();
+    TRACKER
+        .take(breaker, session, crate::budget::now_ms())
+        .inspect_err(|trip| {
+            if !was_tripped {
+                tracing::warn!(
+                    "!!! circuit_breaker: TRIPPED after {trip} — a runaway agent? Auto-approval is \
+                     OFF for every session; dialogs are left for the user until `circuit_breaker` \
+                     is changed in the config (or set on the control socket) or Zed restarts !!!"
+                );
+            }
+        })
+}
+
+pub(crate) fn refund(session: Option<&str>) {
+    TRACKER.refund(session);
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: u64 = 1_000;
+
+    fn breaker(max_approvals: u64, max_per_session: u64) -> CircuitBreaker {
+        CircuitBreaker {
+            window_secs: 10,
+            max_approvals,
+            max_per_session,
+        }
+    }
+
+    #[test]
+    fn test_global_limit_trips_and_stays_tripped() {
+        let tracker = Tracker::new();
+        let breaker = breaker(3, 0);
+        for i in 0..3 {
+            assert!(tracker.take(&breaker, Some("ab12"), i * SEC).is_ok());
+        }
+        let trip = tracker.take(&breaker, Some("cd34"), 3 * SEC).unwrap_err();
+        assert_eq!(
+            trip,
+            Trip::Global {
+                approvals: 3,
+                window_secs: 10
+            }
+        );
+        assert_eq!(trip.to_string(), "3 approvals in 10s (max_approvals)");
+        // The window moving on doesn't close it; only a reset does.
+        assert!(tracker.take(&breaker, None, 100 * SEC).is_err());
+        tracker.reset();
+        assert!(tracker.tripped().is_none());
+        assert!(tracker.take(&breaker, None, 100 * SEC).is_ok());
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let tracker = Tracker::new();
+        let breaker = breaker(3, 0);
+        for t in [0, 4, 8] {
+            assert!(tracker.take(&breaker, None, t * SEC).is_ok());
+        }
+        // The approval at 0s has left the 10s window.
+        assert!(tracker.take(&breaker, None, 10 * SEC).is_ok());
+        assert_eq!(tracker.in_window(&breaker, 10 * SEC), 3);
+        assert!(tracker.take(&breaker, None, 11 * SEC).is_err());
+    }
+
+    #[test]
+    fn test_per_session_limit() {
+        let tracker = Tracker::new();
+        let breaker = breaker(0, 2);
+        assert!(tracker.take(&breaker, Some("ab12"), 0).is_ok());
+        assert!(tracker.take(&breaker, Some("ab12"), 0).is_ok());
+        assert!(tracker.take(&breaker, Some("cd34"), 0).is_ok());
+        // Built-in tool calls have no session and only count globally.
+        for _ in 0..10 {
+            assert!(tracker.take(&breaker, None, 0).is_ok());
+        }
+        let trip = tracker.take(&breaker, Some("ab12"), SEC).unwrap_err();
+        assert_eq!(
+            trip.to_string(),
+            "2 approvals in 10s for session ab12 (max_per_session)"
+        );
+        // Tripped by one session, closed for all.
+        assert!(tracker.take(&breaker, Some("cd34"), SEC).is_err());
+    }
+
+    #[test]
+    fn test_refund() {
+        let tracker = Tracker::new();
+        let breaker = breaker(2, 0);
+        assert!(tracker.take(&breaker, Some("ab12"), 0).is_ok());
+        assert!(tracker.take(&breaker, Some("cd34"), 0).is_ok());
+        tracker.refund(Some("ab12"));
+        assert_eq!(tracker.in_window(&breaker, 0), 1);
+        assert!(tracker.take(&breaker, Some("ab12"), 0).is_ok());
+        tracker.refund(Some("ef56"));
+        assert_eq!(tracker.in_window(&breaker, 0), 2);
+    }
+
+    #[test]
+    fn test_limit_capped_at_ring() {
+        let tracker = Tracker::new();
+        let breaker = breaker(RING as u64 * 2, 0);
+        for _ in 0..RING {
+            assert!(tracker.take(&breaker, Some("ab12"), 0).is_ok());
+        }
+        assert_eq!(tracker.in_window(&breaker, 0), RING as u64);
+        assert!(tracker.take(&breaker, Some("ab12"), 0).is_err());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let tracker = Tracker::new();
+        let off = CircuitBreaker {
+            window_secs: 0,
+            ..CircuitBreaker::default()
+        };
+        assert!(!off.is_enabled());
+        assert!(!breaker(0, 0).is_enabled());
+        for _ in 0..1000 {
+            assert!(tracker.take(&off, Some("ab12"), 0).is_ok());
+        }
+        assert_eq!(tracker.in_window(&off, 0), 0);
+    }
+
+    #[test]
+    fn test_circuit_breaker_json() {
+        let breaker: CircuitBreaker = serde_json::from_str(r#"{"max_approvals": 30}"#).unwrap();
+        assert_eq!(
+            breaker,
+            CircuitBreaker {
+                max_approvals: 30,
+                ..CircuitBreaker::default()
+            }
+        );
+        assert!(breaker.is_enabled());
+    }
+}
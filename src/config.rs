@@ -15,6 +15,12 @@
 //! 2. Config file
 //! 3. Built-in defaults
 //!
+//! ## Hot reload
+//!
+//! Listeners read `current()`, an atomically swapped pointer. The stale scanner
+//! thread polls the file (`Watcher`) and installs the reloaded config; the
+//! control socket's `set` changes it with `update`.
+//!
 //! ## Example config file
 //!
 //! ```json
@@ -23,12 +29,41 @@
 //!   "tool_option": "allow",
 //!   "plan_option": "acceptEdits",
 //!   "log_level": "info",
-//!   "retry_delay_us": 1500
+//!   "retry_delay_us": 1500,
+//!   "unknown_version": "probe",
+//!   "audit_log": "",
+//!   "budget": { "minutes": 0, "approvals": 0 },
+//!   "circuit_breaker": { "window_secs": 60, "max_approvals": 120, "max_per_session": 60 },
+//!   "project_overrides": false,
+//!   "path_scope": { "enabled": false },
+//!   "scan_schedule": { "startup_delay_ms": 5000, "min_interval_ms": 100, "max_interval_ms": 10000, "fast_sweeps": 20 },
+//!   "policy": { "default": "allow", "rules": [] },
+//!   "agents": {
+//!     "codex": { "tool_option": "allow_always" },
+//!     "default": { "mode": "observe" }
+//!   }
 //! }
 //! ```
+//!
+//! See `policy.rs` for the rule format, `budget.rs` for time-boxed sessions,
+//! `circuit_breaker.rs` for the runaway-agent limit, `agent.rs` for
+//! per-agent settings, `project.rs` for per-project rules,
+//! `path_scope.rs` for keeping edits inside the workspace and
+//! `scan_schedule.rs` for when the stale scanner sweeps.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::time::SystemTime;
 
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use crate::budget::Budget;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::path_scope::PathScope;
+use crate::policy::{Decision, Policy, Rule};
+use crate::project::ProjectFile;
+use crate::scan_schedule::ScanSchedule;
 
 // ---------------------------------------------------------------------------
 // Config structs
@@ -48,6 +83,115 @@ pub struct YoloConfig {
     pub log_level: String,
     /// Microseconds to wait before single retry on miss.
     pub retry_delay_us: u64,
+    /// Ordered allow/deny/ask rules evaluated before any approval is sent.
+    pub policy: Policy,
+    /// PermissionOptionKind preference when picking an allow option from the
+    /// dialog's option list. Empty = derive from `tool_option`. Allow kinds
+    /// only.
+    #[serde(deserialize_with = "allow_kinds")]
+    pub allow_kinds: Vec<OptionKind>,
+    /// PermissionOptionKind preference when picking a reject option. Reject
+    /// kinds only.
+    #[serde(deserialize_with = "reject_kinds")]
+    pub reject_kinds: Vec<OptionKind>,
+    /// What to do when the running Zed version has no known memory layout.
+    pub unknown_version: UnknownVersion,
+    /// JSONL audit file: empty = `~/Library/Logs/Zed/zed-yolo-audit.jsonl`,
+    /// `off` = disabled, otherwise a path. See `audit.rs`.
+    pub audit_log: String,
+    /// Stop approving after this many minutes / approvals (0 = unlimited).
+    pub budget: Budget,
+    /// Stop approving after too many approvals in a sliding window. See
+    /// `circuit_breaker.rs`.
+    pub circuit_breaker: CircuitBreaker,
+    /// Add the rules of `.zed/yolo.json` in the project of each agent thread
+    /// (tighten only). See `project.rs`.
+    pub project_overrides: bool,
+    /// Only approve edits inside the workspace. See `path_scope.rs`.
+    pub path_scope: PathScope,
+    /// When the stale scanner sweeps. See `scan_schedule.rs`.
+    pub scan_schedule: ScanSchedule,
+    /// Per ACP agent server (`claude`, `codex`, `gemini`, custom names)
+    /// overrides; `default` applies to agents without an entry and threads
+    /// whose agent isn't known. See `agent.rs`.
+    pub agents: BTreeMap<String, Overrides>,
+}
+
+/// `agents` key used for agents without their own entry.
+pub const DEFAULT_AGENT: &str = "default";
+
+/// Settings an `agents` entry can change for the threads of one agent. Unset
+/// fields keep the value underneath. (A project's `.zed/yolo.json` can only
+/// add rules: `project::ProjectFile`.)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Overrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<YoloMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_option: Option<ToolOption>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan_option: Option<PlanOption>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "some_allow_kinds"
+    )]
+    pub allow_kinds: Option<Vec<OptionKind>>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "some_reject_kinds"
+    )]
+    pub reject_kinds: Option<Vec<OptionKind>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PolicyOverrides>,
+}
+
+/// Overrides `policy`: merged into the policy underneath rather than
+/// replacing it, so a global denylist can't be dropped.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyOverrides {
+    /// Replaces `policy.default`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<Decision>,
+    /// Added to `policy.denylist`.
+    pub denylist: Vec<String>,
+    /// Checked before `policy.rules`.
+    pub rules: Vec<Rule>,
+}
+
+impl Overrides {
+    /// Apply these overrides to `config`.
+    pub fn apply(&self, config: &mut YoloConfig) {
+        if let Some(mode) = self.mode {
+            config.mode = mode;
+        }
+        if let Some(option) = self.tool_option {
+            config.tool_option = option;
+        }
+        if let Some(option) = self.plan_option {
+            config.plan_option = option;
+        }
+        if let Some(kinds) = &self.allow_kinds {
+            config.allow_kinds = kinds.clone();
+        }
+        if let Some(kinds) = &self.reject_kinds {
+            config.reject_kinds = kinds.clone();
+        }
+        if let Some(policy) = &self.policy {
+            if let Some(default) = policy.default {
+                config.policy.default = default;
+            }
+            config
+                .policy
+                .denylist
+                .extend(policy.denylist.iter().cloned());
+            config
+                .policy
+                .rules
+                .splice(0..0, policy.rules.iter().cloned());
+        }
+    }
 }
 
 /// Controls which hooks are installed.
@@ -60,6 +204,20 @@ pub enum YoloMode {
     AllowSafe,
     /// Dylib loads but installs no hooks.
     Disabled,
+    /// Dry run: every hook installs, the scanner walks entries and the policy
+    /// is evaluated, but nothing is sent, rewritten or forced. Each decision is
+    /// logged with what would have happened.
+    Observe,
+}
+
+/// Behaviour for a Zed build that matches no layout (see `layouts`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownVersion {
+    /// Probe every known layout, as before version detection existed.
+    Probe,
+    /// Install hooks but only log what would be approved; send nothing.
+    Observe,
 }
 
 /// What to send for regular tool permission dialogs (Scenario C).
@@ -93,6 +251,102 @@ pub enum PlanOption {
     Plan,
 }
 
+/// ACP `PermissionOptionKind`, used to pick an option by kind rather than id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OptionKind {
+    AllowOnce,
+    AllowAlways,
+    RejectOnce,
+    RejectAlways,
+}
+
+impl OptionKind {
+    pub fn is_allow(self) -> bool {
+        matches!(self, OptionKind::AllowOnce | OptionKind::AllowAlways)
+    }
+
+    /// Map an in-memory `PermissionOptionKind` discriminant (declaration order).
+    pub fn from_discriminant(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(OptionKind::AllowOnce),
+            1 => Some(OptionKind::AllowAlways),
+            2 => Some(OptionKind::RejectOnce),
+            3 => Some(OptionKind::RejectAlways),
+            _ => None,
+        }
+    }
+}
+
+/// `allow_kinds` / `reject_kinds` only take kinds of their own side: an
+/// approval that sent a reject option would still mark the entry InProgress
+/// (and a rejection an allow option, Rejected).
+fn check_kinds<E: serde::de::Error>(
+    kinds: Vec<OptionKind>,
+    allow: bool,
+) -> Result<Vec<OptionKind>, E> {
+    match kinds.iter().find(|kind| kind.is_allow() != allow) {
+        Some(kind) => Err(E::custom(format!(
+            "{kind:?} is not {} kind",
+            if allow { "an allow" } else { "a reject" }
+        ))),
+        None => Ok(kinds),
+    }
+}
+
+fn allow_kinds<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<OptionKind>, D::Error> {
+    check_kinds(Vec::deserialize(d)?, true)
+}
+
+fn reject_kinds<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<OptionKind>, D::Error> {
+    check_kinds(Vec::deserialize(d)?, false)
+}
+
+fn some_allow_kinds<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<OptionKind>>, D::Error> {
+    allow_kinds(d).map(Some)
+}
+
+fn some_reject_kinds<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<OptionKind>>, D::Error> {
+    reject_kinds(d).map(Some)
+}
+
+impl ToolOption {
+    /// Fixed option_id, used when the dialog's option list can't be decoded.
+    pub fn option_id(self) -> &'static str {
+        match self {
+            ToolOption::Allow => "allow",
+            ToolOption::AllowAlways => "allow_always",
+        }
+    }
+
+    pub fn option_kind(self) -> OptionKind {
+        match self {
+            ToolOption::Allow => OptionKind::AllowOnce,
+            ToolOption::AllowAlways => OptionKind::AllowAlways,
+        }
+    }
+}
+
+impl PlanOption {
+    /// The option_id (= Claude Code session mode name).
+    pub fn option_id(self) -> &'static str {
+        match self {
+            PlanOption::AcceptEdits => "acceptEdits",
+            PlanOption::BypassPermissions => "bypassPermissions",
+            PlanOption::Default => "default",
+            PlanOption::Plan => "plan",
+        }
+    }
+
+    pub fn option_kind(self) -> OptionKind {
+        match self {
+            PlanOption::AcceptEdits | PlanOption::BypassPermissions => OptionKind::AllowAlways,
+            PlanOption::Default => OptionKind::AllowOnce,
+            PlanOption::Plan => OptionKind::RejectOnce,
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Defaults
 // ---------------------------------------------------------------------------
@@ -105,6 +359,17 @@ impl Default for YoloConfig {
             plan_option: PlanOption::AcceptEdits,
             log_level: "info".to_string(),
             retry_delay_us: 1500,
+            policy: Policy::default(),
+            allow_kinds: Vec::new(),
+            reject_kinds: vec![OptionKind::RejectOnce, OptionKind::RejectAlways],
+            unknown_version: UnknownVersion::Probe,
+            audit_log: String::new(),
+            budget: Budget::default(),
+            circuit_breaker: CircuitBreaker::default(),
+            project_overrides: false,
+            path_scope: PathScope::default(),
+            scan_schedule: ScanSchedule::default(),
+            agents: BTreeMap::new(),
         }
     }
 }
@@ -115,10 +380,47 @@ impl Default for YoloConfig {
 
 impl YoloConfig {
     /// Load config: env vars override config file, which overrides defaults.
-    pub fn load(app_id: &str) -> Self {
-        let mut config = Self::load_from_file(app_id).unwrap_or_default();
+    /// A config file that can't be read or parsed is returned as the error
+    /// next to [`YoloConfig::fallback`], for the caller to log once logging
+    /// is up.
+    pub fn load(app_id: &str) -> (Self, Option<String>) {
+        Self::load_from(config_path(app_id).as_deref())
+    }
+
+    fn load_from(path: Option<&Path>) -> (Self, Option<String>) {
+        let (config, error) = match path.map(Self::read_file) {
+            Some(Ok(Some(config))) => (config, None),
+            Some(Ok(None)) | None => (Self::default(), None),
+            Some(Err(e)) => (Self::fallback(), Some(e)),
+        };
+        (config.with_env_overrides(), error)
+    }
+
+    /// What runs when the config file is broken at startup: observe mode, so
+    /// a typo never turns into "approve everything". The scanner thread
+    /// reloads the file once it's fixed.
+    pub fn fallback() -> Self {
+        Self {
+            mode: YoloMode::Observe,
+            ..Self::default()
+        }
+    }
 
-        // Env var overrides (for terminal testing / cargo patch --verify)
+    /// Parse the config file at `path`. `Ok(None)` if it doesn't exist.
+    fn read_file(path: &Path) -> Result<Option<Self>, String> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Env var overrides (for terminal testing / cargo patch --verify).
+    fn with_env_overrides(mut self) -> Self {
+        let config = &mut self;
         if let Ok(val) = std::env::var("ZED_YOLO_MODE") {
             if let Some(mode) = parse_yolo_mode(&val) {
                 config.mode = mode;
@@ -144,14 +446,26 @@ impl YoloConfig {
                 config.retry_delay_us = us.min(10_000);
             }
         }
+        if let Ok(val) = std::env::var("ZED_YOLO_UNKNOWN_VERSION") {
+            if let Some(policy) = parse_unknown_version(&val) {
+                config.unknown_version = policy;
+            }
+        }
+        if let Ok(val) = std::env::var("ZED_YOLO_AUDIT_LOG") {
+            config.audit_log = val;
+        }
+        if let Ok(val) = std::env::var("ZED_YOLO_BUDGET_MINUTES") {
+            if let Ok(minutes) = val.parse::<u64>() {
+                config.budget.minutes = minutes;
+            }
+        }
+        if let Ok(val) = std::env::var("ZED_YOLO_BUDGET_APPROVALS") {
+            if let Ok(approvals) = val.parse::<u64>() {
+                config.budget.approvals = approvals;
+            }
+        }
 
-        config
-    }
-
-    fn load_from_file(app_id: &str) -> Option<Self> {
-        let path = config_path(app_id)?;
-        let content = std::fs::read_to_string(&path).ok()?;
-        serde_json::from_str(&content).ok()
+        self
     }
 
     /// Save current config to file.
@@ -169,6 +483,48 @@ impl YoloConfig {
     pub fn is_enabled(&self) -> bool {
         self.mode != YoloMode::Disabled
     }
+
+    /// The `agents` entry for `agent`, else `agents.default`.
+    pub fn agent_overrides(&self, agent: Option<&str>) -> Option<&Overrides> {
+        agent
+            .and_then(|agent| self.agents.get(agent))
+            .or_else(|| self.agents.get(DEFAULT_AGENT))
+    }
+
+    /// The config for one agent thread: this one, then the `agents` entry
+    /// for `agent`, then the additions of the project's file.
+    pub fn for_thread(
+        &self,
+        agent: Option<&str>,
+        project: Option<&ProjectFile>,
+    ) -> Cow<'_, YoloConfig> {
+        let agent = self.agent_overrides(agent);
+        if agent.is_none() && project.is_none() {
+            return Cow::Borrowed(self);
+        }
+        let mut config = self.clone();
+        if let Some(agent) = agent {
+            agent.apply(&mut config);
+        }
+        if let Some(project) = project {
+            project.apply(&mut config);
+        }
+        Cow::Owned(config)
+    }
+
+    /// Kind preference for allow options: `allow_kinds`, or derived from
+    /// `tool_option`. `allow_always` falls back to once; `allow` never to
+    /// always, which would grant the tool for good without ever asking the
+    /// policy again.
+    pub fn allow_preference(&self) -> Vec<OptionKind> {
+        if !self.allow_kinds.is_empty() {
+            return self.allow_kinds.clone();
+        }
+        match self.tool_option {
+            ToolOption::Allow => vec![OptionKind::AllowOnce],
+            ToolOption::AllowAlways => vec![OptionKind::AllowAlways, OptionKind::AllowOnce],
+        }
+    }
 }
 
 /// Config file path: `~/.config/dylib-hooks/{app_id}/zed-yolo-hook.json`
@@ -199,6 +555,180 @@ pub fn detect_app_id() -> String {
         .unwrap_or_else(|| "zed".to_string())
 }
 
+// ---------------------------------------------------------------------------
+// Runtime config (hot reload)
+// ---------------------------------------------------------------------------
+
+/// Where a config is installed. Replaced wholesale; never mutated.
+struct Slot(AtomicPtr<Installed>);
+
+/// The config listeners read.
+static CURRENT: Slot = Slot::new();
+
+/// An installed config and its merge for Zed's own agent, swapped together
+/// so they always match.
+struct Installed {
+    config: YoloConfig,
+    /// `agents.zed` over `config`.
+    native: YoloConfig,
+}
+
+fn leak_installed(config: YoloConfig) -> *mut Installed {
+    let native = config
+        .for_thread(Some(crate::hooks::native_tool::NATIVE_AGENT), None)
+        .into_owned();
+    Box::into_raw(Box::new(Installed { config, native }))
+}
+
+/// The active config (defaults before `install`).
+///
+/// The returned reference stays valid forever: replaced configs are leaked
+/// rather than freed, since a listener on another thread may still be reading
+/// one. A reload leaks a few hundred bytes, which is fine for something that
+/// happens when a human edits a file.
+pub(crate) fn current() -> &'static YoloConfig {
+    CURRENT.current()
+}
+
+/// The config for Zed's own agent (`agents.zed` over `current()`), merged
+/// once per `install` rather than per tool call. Leaked like `current()`.
+pub(crate) fn native() -> &'static YoloConfig {
+    CURRENT.native()
+}
+
+/// Make `config` the active config, whatever it replaces; returns it.
+pub(crate) fn install(config: YoloConfig) -> &'static YoloConfig {
+    CURRENT.install(config)
+}
+
+/// Replace the active config with `change` applied to it; returns the
+/// replaced and the new config. If another `update` or `install` replaces
+/// the config while `change` runs, `change` runs again on that one (a
+/// compare-and-swap on the config pointer), so neither change is lost.
+pub(crate) fn update<E>(
+    change: impl FnMut(&YoloConfig) -> Result<YoloConfig, E>,
+) -> Result<(&'static YoloConfig, &'static YoloConfig), E> {
+    CURRENT.update(change)
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Slot(AtomicPtr::new(std::ptr::null_mut()))
+    }
+
+    fn current(&self) -> &'static YoloConfig {
+        current_of(self.0.load(Ordering::Acquire))
+    }
+
+    fn native(&self) -> &'static YoloConfig {
+        // SAFETY: as in `current_of`.
+        match unsafe { self.0.load(Ordering::Acquire).as_ref() } {
+            Some(installed) => &installed.native,
+            None => self.current(),
+        }
+    }
+
+    fn install(&self, config: YoloConfig) -> &'static YoloConfig {
+        let installed = leak_installed(config);
+        self.0.store(installed, Ordering::Release);
+        // SAFETY: just published, never freed.
+        unsafe { &(*installed).config }
+    }
+
+    fn update<E>(
+        &self,
+        mut change: impl FnMut(&YoloConfig) -> Result<YoloConfig, E>,
+    ) -> Result<(&'static YoloConfig, &'static YoloConfig), E> {
+        loop {
+            let old = self.0.load(Ordering::Acquire);
+            let base = current_of(old);
+            let new = leak_installed(change(base)?);
+            match self
+                .0
+                .compare_exchange(old, new, Ordering::AcqRel, Ordering::Acquire)
+            {
+                // SAFETY: just published, never freed.
+                Ok(_) => return Ok((base, unsafe { &(*new).config })),
+                // SAFETY: from `leak_installed` and never published.
+                Err(_) => drop(unsafe { Box::from_raw(new) }),
+            }
+        }
+    }
+}
+
+/// The config `ptr` (a value of a `Slot`) stands for.
+fn current_of(ptr: *mut Installed) -> &'static YoloConfig {
+    static DEFAULT: std::sync::OnceLock<YoloConfig> = std::sync::OnceLock::new();
+    // SAFETY: only ever set from `leak_installed`, never freed once
+    // published.
+    match unsafe { ptr.as_ref() } {
+        Some(installed) => &installed.config,
+        None => DEFAULT.get_or_init(YoloConfig::default),
+    }
+}
+
+/// Polls the config file's modification time so the scanner thread can
+/// reload it. A file that fails to parse is reported and ignored; deleting
+/// the file reverts to defaults (plus env overrides).
+pub(crate) struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Watcher {
+    /// Watch `path`, treating its current contents as already loaded.
+    pub(crate) fn new(path: PathBuf) -> Self {
+        let modified = modified(&path);
+        Watcher { path, modified }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `Some` when the file changed since the last poll: the new config
+    /// (env overrides applied) or the parse error.
+    pub(crate) fn poll(&mut self) -> Option<Result<YoloConfig, String>> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(
+            YoloConfig::read_file(&self.path)
+                .map(|config| config.unwrap_or_default().with_env_overrides()),
+        )
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Settings in `new` that differ from the config Zed was started with
+/// (`startup`) but only take effect at startup, for the reload log line.
+pub(crate) fn restart_required(startup: &YoloConfig, new: &YoloConfig) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if startup.log_level != new.log_level {
+        fields.push("log_level");
+    }
+    if startup.unknown_version != new.unknown_version {
+        fields.push("unknown_version");
+    }
+    if startup.audit_log != new.audit_log {
+        fields.push("audit_log");
+    }
+    if startup.scan_schedule.startup_delay_ms != new.scan_schedule.startup_delay_ms {
+        fields.push("scan_schedule.startup_delay_ms");
+    }
+    // Hooks are installed once, in every mode but `disabled`: leaving
+    // `disabled` needs a relaunch.
+    if startup.mode == YoloMode::Disabled && new.mode != YoloMode::Disabled {
+        fields.push("mode");
+    }
+    fields
+}
+
 // ---------------------------------------------------------------------------
 // Env var parsers (lenient, case-insensitive)
 // ---------------------------------------------------------------------------
@@ -207,12 +737,13 @@ fn parse_yolo_mode(val: &str) -> Option<YoloMode> {
     match val.trim().to_lowercase().as_str() {
         "0" | "off" | "disabled" => Some(YoloMode::Disabled),
         "allow_safe" | "safe" => Some(YoloMode::AllowSafe),
+        "observe" | "dry_run" | "dry-run" => Some(YoloMode::Observe),
         "allow_all" | "1" | "on" | "" => Some(YoloMode::AllowAll),
         _ => Some(YoloMode::AllowAll), // unknown → default
     }
 }
 
-fn parse_tool_option(val: &str) -> Option<ToolOption> {
+pub(crate) fn parse_tool_option(val: &str) -> Option<ToolOption> {
     match val.trim().to_lowercase().as_str() {
         "allow" => Some(ToolOption::Allow),
         "allow_always" => Some(ToolOption::AllowAlways),
@@ -220,7 +751,7 @@ fn parse_tool_option(val: &str) -> Option<ToolOption> {
     }
 }
 
-fn parse_plan_option(val: &str) -> Option<PlanOption> {
+pub(crate) fn parse_plan_option(val: &str) -> Option<PlanOption> {
     match val.trim().to_lowercase().as_str() {
         "acceptedits" | "accept_edits" => Some(PlanOption::AcceptEdits),
         "bypasspermissions" | "bypass_permissions" | "bypass" => {
@@ -232,6 +763,14 @@ fn parse_plan_option(val: &str) -> Option<PlanOption> {
     }
 }
 
+fn parse_unknown_version(val: &str) -> Option<UnknownVersion> {
+    match val.trim().to_lowercase().as_str() {
+        "probe" => Some(UnknownVersion::Probe),
+        "observe" | "observe_only" => Some(UnknownVersion::Observe),
+        _ => None,
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Tests
 // ---------------------------------------------------------------------------
@@ -258,6 +797,7 @@ mod tests {
             plan_option: PlanOption::BypassPermissions,
             log_level: "debug".to_string(),
             retry_delay_us: 2000,
+            ..YoloConfig::default()
         };
         let json = serde_json::to_string_pretty(&config).unwrap();
         let loaded: YoloConfig = serde_json::from_str(&json).unwrap();
@@ -292,6 +832,8 @@ mod tests {
         assert_eq!(parse_yolo_mode("disabled"), Some(YoloMode::Disabled));
         assert_eq!(parse_yolo_mode("allow_safe"), Some(YoloMode::AllowSafe));
         assert_eq!(parse_yolo_mode("safe"), Some(YoloMode::AllowSafe));
+        assert_eq!(parse_yolo_mode("observe"), Some(YoloMode::Observe));
+        assert_eq!(parse_yolo_mode("Dry-Run"), Some(YoloMode::Observe));
         assert_eq!(parse_yolo_mode("allow_all"), Some(YoloMode::AllowAll));
         assert_eq!(parse_yolo_mode(""), Some(YoloMode::AllowAll));
     }
@@ -325,6 +867,24 @@ mod tests {
         assert_eq!(parse_plan_option("unknown"), None);
     }
 
+    #[test]
+    fn test_parse_unknown_version() {
+        assert_eq!(parse_unknown_version("probe"), Some(UnknownVersion::Probe));
+        assert_eq!(
+            parse_unknown_version("Observe"),
+            Some(UnknownVersion::Observe)
+        );
+        assert_eq!(
+            parse_unknown_version("observe_only"),
+            Some(UnknownVersion::Observe)
+        );
+        assert_eq!(parse_unknown_version("bogus"), None);
+
+        let config: YoloConfig = serde_json::from_str(r#"{"unknown_version": "observe"}"#).unwrap();
+        assert_eq!(config.unknown_version, UnknownVersion::Observe);
+        assert_eq!(YoloConfig::default().unknown_version, UnknownVersion::Probe);
+    }
+
     #[test]
     fn test_serde_plan_option_camel_case() {
         // PlanOption uses camelCase for JSON (matching ACP protocol option_ids)
@@ -334,9 +894,303 @@ mod tests {
         assert_eq!(json, r#""bypassPermissions""#);
     }
 
+    #[test]
+    fn test_policy_json() {
+        use crate::policy::{Decision, RuleAction};
+
+        let json = r#"{ "policy": { "rules": [{ "action": "deny", "title": "*rm -rf*" }] } }"#;
+        let config: YoloConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.policy.default, Decision::Allow);
+        assert_eq!(config.policy.rules.len(), 1);
+        assert_eq!(config.policy.rules[0].action, RuleAction::Deny);
+        assert!(YoloConfig::default().policy.rules.is_empty());
+    }
+
+    #[test]
+    fn test_allow_preference() {
+        let mut config = YoloConfig::default();
+        // Never a persistent grant unless asked for.
+        assert_eq!(config.allow_preference(), vec![OptionKind::AllowOnce]);
+        config.tool_option = ToolOption::AllowAlways;
+        assert_eq!(
+            config.allow_preference(),
+            vec![OptionKind::AllowAlways, OptionKind::AllowOnce]
+        );
+        config.allow_kinds = vec![OptionKind::AllowOnce];
+        assert_eq!(config.allow_preference(), vec![OptionKind::AllowOnce]);
+        assert_eq!(
+            config.reject_kinds,
+            vec![OptionKind::RejectOnce, OptionKind::RejectAlways]
+        );
+    }
+
+    #[test]
+    fn test_option_kinds_json() {
+        let json = r#"{ "allow_kinds": ["allow_always"], "reject_kinds": ["reject_always"] }"#;
+        let config: YoloConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.allow_kinds, vec![OptionKind::AllowAlways]);
+        assert_eq!(config.reject_kinds, vec![OptionKind::RejectAlways]);
+
+        for wrong in [
+            r#"{ "allow_kinds": ["allow_once", "reject_once"] }"#,
+            r#"{ "reject_kinds": ["allow_always"] }"#,
+            r#"{ "agents": { "codex": { "allow_kinds": ["reject_always"] } } }"#,
+        ] {
+            let err = serde_json::from_str::<YoloConfig>(wrong).unwrap_err();
+            assert!(err.to_string().contains(" kind"), "{wrong}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_option_ids() {
+        assert_eq!(ToolOption::AllowAlways.option_id(), "allow_always");
+        assert_eq!(
+            PlanOption::BypassPermissions.option_id(),
+            "bypassPermissions"
+        );
+        assert_eq!(PlanOption::Plan.option_kind(), OptionKind::RejectOnce);
+    }
+
     #[test]
     fn test_serde_tool_option_snake_case() {
         let json = serde_json::to_string(&ToolOption::AllowAlways).unwrap();
         assert_eq!(json, r#""allow_always""#);
     }
+
+    #[test]
+    fn test_overrides_apply() {
+        use crate::policy::{RuleAction, ToolCallFacts};
+
+        let mut config = YoloConfig {
+            policy: Policy {
+                default: Decision::Allow,
+                denylist: vec!["rm -rf".to_string()],
+                rules: vec![Rule {
+                    action: RuleAction::Allow,
+                    kind: Some("execute".to_string()),
+                    ..Rule::default()
+                }],
+            },
+            ..YoloConfig::default()
+        };
+        let base = config.clone();
+        let overrides: Overrides = serde_json::from_str(
+            r#"{
+                "mode": "observe",
+                "tool_option": "allow_always",
+                "policy": {
+                    "denylist": ["terraform apply"],
+                    "rules": [{ "action": "ask", "kind": "execute" }]
+                }
+            }"#,
+        )
+        .unwrap();
+        overrides.apply(&mut config);
+
+        assert_eq!(config.mode, YoloMode::Observe);
+        assert_eq!(config.tool_option, ToolOption::AllowAlways);
+        assert_eq!(config.plan_option, base.plan_option);
+        assert_eq!(config.policy.default, Decision::Allow);
+        assert_eq!(config.policy.denylist, ["rm -rf", "terraform apply"]);
+        assert_eq!(config.policy.rules.len(), 2);
+
+        // The override rule comes first.
+        let facts = ToolCallFacts {
+            kind: Some("execute".to_string()),
+            title: Some("ls".to_string()),
+            ..ToolCallFacts::default()
+        };
+        assert_eq!(config.policy.evaluate(&facts), Decision::Ask);
+        assert_eq!(base.policy.evaluate(&facts), Decision::Allow);
+
+        let mut unchanged = base.clone();
+        Overrides::default().apply(&mut unchanged);
+        assert_eq!(unchanged.policy, base.policy);
+    }
+
+    #[test]
+    fn test_for_thread() {
+        use crate::policy::RuleAction;
+
+        let config: YoloConfig = serde_json::from_str(
+            r#"{
+                "plan_option": "acceptEdits",
+                "agents": {
+                    "claude": { "plan_option": "plan" },
+                    "codex": { "tool_option": "allow_always" },
+                    "default": { "mode": "observe" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(config.for_thread(None, None), Cow::Owned(_)));
+        assert_eq!(config.for_thread(None, None).mode, YoloMode::Observe);
+        assert_eq!(
+            config.for_thread(Some("gemini"), None).mode,
+            YoloMode::Observe
+        );
+        let claude = config.for_thread(Some("claude"), None);
+        assert_eq!(claude.plan_option, PlanOption::Plan);
+        assert_eq!(claude.mode, YoloMode::AllowAll);
+        assert_eq!(
+            config.for_thread(Some("codex"), None).tool_option,
+            ToolOption::AllowAlways
+        );
+
+        // Project rules go after the agent's.
+        let project: ProjectFile =
+            serde_json::from_str(r#"{"policy": {"rules": [{"action": "deny"}]}}"#).unwrap();
+        let claude = config.for_thread(Some("claude"), Some(&project));
+        assert_eq!(claude.plan_option, PlanOption::Plan);
+        assert_eq!(claude.policy.rules.last().unwrap().action, RuleAction::Deny);
+
+        let plain = YoloConfig::default();
+        assert!(matches!(
+            plain.for_thread(Some("claude"), None),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_watcher_reloads_on_change() {
+        let dir = std::env::temp_dir().join(format!("zed-yolo-config-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("zed-yolo-hook.json");
+        let write = |json: &str, secs: u64| {
+            std::fs::write(&path, json).unwrap();
+            let mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        };
+
+        write(r#"{"plan_option": "acceptEdits"}"#, 1_000);
+        let mut watcher = Watcher::new(path.clone());
+        assert!(watcher.poll().is_none());
+
+        write(r#"{"plan_option": "plan"}"#, 2_000);
+        let reloaded = watcher.poll().unwrap().unwrap();
+        assert_eq!(reloaded.plan_option, PlanOption::Plan);
+        assert!(watcher.poll().is_none());
+
+        write("{ not json", 3_000);
+        assert!(watcher.poll().unwrap().is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        let reverted = watcher.poll().unwrap().unwrap();
+        assert_eq!(reverted.plan_option, PlanOption::AcceptEdits);
+        assert!(watcher.poll().is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_invalid_file_falls_back_to_observe() {
+        let dir = std::env::temp_dir().join(format!("zed-yolo-load-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("zed-yolo-hook.json");
+
+        let (config, error) = YoloConfig::load_from(Some(&path));
+        assert_eq!((config.mode, error), (YoloMode::AllowAll, None));
+
+        std::fs::write(&path, r#"{"mode": "allow_safe"}"#).unwrap();
+        let (config, error) = YoloConfig::load_from(Some(&path));
+        assert_eq!((config.mode, error), (YoloMode::AllowSafe, None));
+
+        std::fs::write(&path, r#"{"mode": "allow_sfae"}"#).unwrap();
+        let (config, error) = YoloConfig::load_from(Some(&path));
+        assert_eq!(config.mode, YoloMode::Observe);
+        assert!(error.unwrap().contains("allow_sfae"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_install_and_current() {
+        // A slot of its own: other tests read `current()` meanwhile.
+        let slot = Slot::new();
+        assert_eq!(
+            slot.current().retry_delay_us,
+            YoloConfig::default().retry_delay_us
+        );
+        let installed = slot.install(YoloConfig {
+            retry_delay_us: 42,
+            ..YoloConfig::default()
+        });
+        assert_eq!(installed.retry_delay_us, 42);
+        assert_eq!(slot.current().retry_delay_us, 42);
+
+        // A reload installed while `set` builds its config isn't lost: the
+        // change runs again on top of it.
+        let mut runs = 0;
+        let (old, new) = slot
+            .update(|config| {
+                runs += 1;
+                if runs == 1 {
+                    slot.install(YoloConfig {
+                        retry_delay_us: 7,
+                        ..YoloConfig::default()
+                    });
+                }
+                let mut config = config.clone();
+                config.tool_option = ToolOption::AllowAlways;
+                Ok::<_, String>(config)
+            })
+            .unwrap();
+        assert_eq!(runs, 2);
+        assert_eq!(old.retry_delay_us, 7);
+        assert_eq!(
+            (new.retry_delay_us, new.tool_option),
+            (7, ToolOption::AllowAlways)
+        );
+        assert!(std::ptr::eq(slot.current(), new));
+
+        let failed = slot.update(|_| Err("no"));
+        assert_eq!(failed.unwrap_err(), "no");
+        assert!(std::ptr::eq(slot.current(), new));
+    }
+
+    #[test]
+    fn test_restart_required() {
+        let startup = YoloConfig {
+            mode: YoloMode::AllowSafe,
+            ..YoloConfig::default()
+        };
+        let same = |mode| YoloConfig {
+            mode,
+            ..YoloConfig::default()
+        };
+        assert!(restart_required(&startup, &same(YoloMode::AllowSafe)).is_empty());
+        assert!(restart_required(&startup, &same(YoloMode::Disabled)).is_empty());
+        assert!(restart_required(&startup, &same(YoloMode::AllowAll)).is_empty());
+
+        let all = same(YoloMode::AllowAll);
+        assert!(restart_required(&all, &same(YoloMode::AllowSafe)).is_empty());
+        assert!(restart_required(&all, &same(YoloMode::Observe)).is_empty());
+
+        let disabled = same(YoloMode::Disabled);
+        assert_eq!(
+            restart_required(&disabled, &same(YoloMode::AllowSafe)),
+            ["mode"]
+        );
+
+        let changed = YoloConfig {
+            log_level: "debug".to_string(),
+            audit_log: "off".to_string(),
+            scan_schedule: ScanSchedule {
+                startup_delay_ms: 0,
+                max_interval_ms: 30_000,
+                ..ScanSchedule::default()
+            },
+            ..YoloConfig::default()
+        };
+        assert_eq!(
+            restart_required(&YoloConfig::default(), &changed),
+            ["log_level", "audit_log", "scan_schedule.startup_delay_ms"]
+        );
+    }
 }
@@ -0,0 +1,689 @@
+//! Local control socket: pause / resume / inspect YOLO at runtime.
+//!
+//! The dylib listens on a Unix domain socket in its config directory,
+//! `~/.config/dylib-hooks/{app_id}/zed-yolo-hook.{pid}.sock` (`0600`), and
+//! speaks line-delimited JSON: one request object per line, one response
+//! object per line.
+//!
+//! ```text
+//! → {"cmd":"status"}
+//! ← {"ok":true,"pid":4242,"paused":false,"mode":"allow_all",...}
+//! → {"cmd":"pause"}
+//! ← {"ok":true,"paused":true}
+//! → {"cmd":"set","key":"tool_option","value":"allow_always"}
+//! ← {"ok":true,"key":"tool_option","value":"allow_always"}
+//! → {"cmd":"recent","limit":5}
+//! ← {"ok":true,"records":[{"seq":12,...audit record...}]}
+//! → {"cmd":"recent","since":12}
+//! ← {"ok":true,"records":[]}
+//! ```
+//!
+//! Commands: `status`, `pause`, `resume`, `set` (`tool_option`,
+//! `plan_option`, `retry_delay_us`, `budget_minutes`, `budget_approvals`,
+//! `circuit_breaker_window_secs`, `circuit_breaker_max_approvals`,
+//! `circuit_breaker_max_per_session`),
+//! `stats`, `recent` (audit records, newest
+//! first; `since` returns only records with a larger `seq`, for tailing). Errors are `{"ok":false,"error":"..."}`; the connection stays open.
+//!
+//! Request lines are capped at 64 KiB and at most 8 clients are served at
+//! once; a longer line or a ninth client gets an error and is disconnected.
+//!
+//! The server runs on its own thread (one more per client) and only touches
+//! atomics and the config pointer, never Frida interceptor context. `set`
+//! changes the in-memory config only, through `config::update`, so it cannot
+//! lose a concurrent file reload; the next reload replaces it.
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Socket file name prefix; the pid and `.sock` follow.
+pub(crate) const SOCKET_PREFIX: &str = "zed-yolo-hook.";
+
+/// `recent` without a `limit`.
+const DEFAULT_RECENT: usize = 20;
+
+/// Longest request line; a longer one gets an error and the connection is
+/// closed.
+const MAX_LINE: usize = 64 * 1024;
+
+/// Clients served at once; more are turned away with an error.
+const MAX_CLIENTS: usize = 8;
+
+// ---------------------------------------------------------------------------
+// Protocol
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case", deny_unknown_fields)]
+enum Request {
+    Status,
+    Pause,
+    Resume,
+    Set {
+        key: String,
+        value: Value,
+    },
+    Stats,
+    Recent {
+        limit: Option<usize>,
+        since: Option<u64>,
+    },
+}
+
+/// What the socket controls. `LiveHost` is the running hook; tests use a fake.
+pub(crate) trait Host: Send + Sync {
+    /// Status fields (merged into the response).
+    fn status(&self) -> Value;
+    fn set_paused(&self, paused: bool);
+    /// Change one setting; `Err` is a message for the client.
+    fn set(&self, key: &str, value: &Value) -> Result<(), String>;
+    /// Counter fields (merged into the response).
+    fn stats(&self) -> Value;
+    /// Up to `limit` audit records with `seq > since`, newest first.
+    fn recent(&self, limit: usize, since: u64) -> Vec<Value>;
+}
+
+/// Handle one request line; always returns a response object.
+pub(crate) fn handle_line(host: &dyn Host, line: &str) -> Value {
+    let request: Request = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return error(format!("bad request: {e}")),
+    };
+    match request {
+        Request::Status => ok_with(host.status()),
+        Request::Pause => set_paused(host, true),
+        Request::Resume => set_paused(host, false),
+        Request::Set { key, value } => match host.set(&key, &value) {
+            Ok(()) => json!({ "ok": true, "key": key, "value": value }),
+            Err(e) => error(e),
+        },
+        Request::Stats => ok_with(host.stats()),
+        Request::Recent { limit, since } => {
+            let records = host.recent(limit.unwrap_or(DEFAULT_RECENT), since.unwrap_or(0));
+            json!({ "ok": true, "records": records })
+        }
+    }
+}
+
+fn set_paused(host: &dyn Host, paused: bool) -> Value {
+    host.set_paused(paused);
+    json!({ "ok": true, "paused": paused })
+}
+
+fn ok_with(mut fields: Value) -> Value {
+    if let Some(map) = fields.as_object_mut() {
+        map.insert("ok".to_string(), Value::Bool(true));
+    }
+    fields
+}
+
+fn error(message: String) -> Value {
+    json!({ "ok": false, "error": message })
+}
+
+// ---------------------------------------------------------------------------
+// Server
+// ---------------------------------------------------------------------------
+
+/// Socket path for `pid` in the config directory of `app_id`.
+pub(crate) fn socket_path(app_id: &str, pid: u32) -> Option<PathBuf> {
+    let dir = crate::config::config_path(app_id)?.parent()?.to_path_buf();
+    Some(dir.join(format!("{SOCKET_PREFIX}{pid}.sock")))
+}
+
+/// Serve one client until it disconnects or sends a line over `MAX_LINE`.
+fn serve_client(host: &dyn Host, stream: UnixStream) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = (&mut reader)
+            .take(MAX_LINE as u64 + 1)
+            .read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(());
+        }
+        if line.last() != Some(&b'\n') && line.len() > MAX_LINE {
+            let response = error(format!("request line longer than {} KiB", MAX_LINE / 1024));
+            writer.write_all(format!("{response}\n").as_bytes())?;
+            return Ok(());
+        }
+        let response = match std::str::from_utf8(&line) {
+            Ok(text) if text.trim().is_empty() => continue,
+            Ok(text) => handle_line(host, text),
+            Err(e) => error(format!("bad request: {e}")),
+        };
+        writer.write_all(format!("{response}\n").as_bytes())?;
+    }
+}
+
+/// Decrements the client count when a client thread ends.
+struct ClientSlot(Arc<AtomicUsize>);
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Accept clients forever, one thread each, at most `MAX_CLIENTS` at a time.
+/// Clients over the limit get an error and are disconnected.
+fn serve(listener: UnixListener, host: Arc<dyn Host>) {
+    let clients = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("control: accept failed: {e}");
+                continue;
+            }
+        };
+        if clients.fetch_add(1, Ordering::AcqRel) >= MAX_CLIENTS {
+            clients.fetch_sub(1, Ordering::AcqRel);
+            tracing::debug!("control: rejecting client, {MAX_CLIENTS} already connected");
+            let response = error("too many clients".to_string());
+            let _ = stream.write_all(format!("{response}\n").as_bytes());
+            continue;
+        }
+        let slot = ClientSlot(Arc::clone(&clients));
+        let host = Arc::clone(&host);
+        let spawned = std::thread::Builder::new()
+            .name("zed-yolo-control-client".to_string())
+            .spawn(move || {
+                let _slot = slot;
+                if let Err(e) = serve_client(host.as_ref(), stream) {
+                    tracing::debug!("control: client error: {e}");
+                }
+            });
+        if let Err(e) = spawned {
+            tracing::warn!("control: cannot spawn client thread: {e}");
+        }
+    }
+}
+
+/// Bind `path` (replacing a leftover socket file) with mode `0600` and serve
+/// `host` on a background thread.
+///
+/// `bind` creates the socket with the umask's mode, so it is bound inside a
+/// fresh `0700` directory next to `path`, made `0600` there and only then
+/// renamed into place: nobody else can connect before it is private.
+pub(crate) fn bind_and_serve(path: &Path, host: Arc<dyn Host>) -> std::io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(std::io::Error::other("socket path has no file name"));
+    };
+    std::fs::create_dir_all(dir)?;
+    let private = dir.join(format!(".{}", name.to_string_lossy()));
+    let _ = std::fs::remove_dir_all(&private);
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let staged = private.join("s");
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+        let _ = std::fs::remove_file(path);
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_dir_all(&private);
+    let listener = bound?;
+
+    std::thread::Builder::new()
+        .name("zed-yolo-control".to_string())
+        .spawn(move || serve(listener, host))?;
+    Ok(())
+}
+
+/// Remove sockets left behind by Zed processes that are no longer running.
+fn remove_stale_sockets(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name
+            .to_str()
+            .and_then(|n| n.strip_prefix(SOCKET_PREFIX))
+            .and_then(|n| n.strip_suffix(".sock"))
+            .and_then(|n| n.parse::<libc::pid_t>().ok())
+        else {
+            continue;
+        };
+        let alive = unsafe { libc::kill(pid, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+        if !alive {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Start the control socket for this process. Failures are logged; YOLO
+/// works without it.
+pub(crate) fn start(app_id: &str) {
+    let pid = std::process::id();
+    let Some(path) = socket_path(app_id, pid) else {
+        tracing::warn!("control: cannot determine socket path");
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        remove_stale_sockets(dir);
+    }
+    match bind_and_serve(&path, Arc::new(LiveHost)) {
+        Ok(()) => tracing::info!("control: listening on {}", path.display()),
+        Err(e) => tracing::warn!("control: cannot bind {}: {e}", path.display()),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Live host
+// ---------------------------------------------------------------------------
+
+/// The running hook: `hooks::PAUSED`, `config::current()`, hook counters and
+/// the audit ring.
+struct LiveHost;
+
+impl Host for LiveHost {
+    fn status(&self) -> Value {
+        use crate::hooks;
+
+        let config = crate::config::current();
+        json!({
+            "pid": std::process::id(),
+            "version": env!("CARGO_PKG_VERSION"),
+            "app": crate::APP.get().map(|app| app.to_string()),
+            "paused": hooks::PAUSED.load(std::sync::atomic::Ordering::Relaxed),
+            "active": !hooks::inactive(),
+            "observe_only": hooks::observe_only(),
+            "mode": config.mode,
+            "tool_option": config.tool_option,
+            "plan_option": config.plan_option,
+            "retry_delay_us": config.retry_delay_us,
+            "policy_rules": config.policy.rules.len(),
+            "budget": config.budget,
+            "budget_remaining": crate::budget::remaining(&config.budget).to_string(),
+            "circuit_breaker": config.circuit_breaker,
+            "circuit_breaker_tripped": crate::circuit_breaker::tripped().map(|trip| trip.to_string()),
+            "scan_schedule": config.scan_schedule,
+            "layouts": crate::layouts::active().iter().map(|l| l.name).collect::<Vec<_>>(),
+        })
+    }
+
+    fn set_paused(&self, paused: bool) {
+        crate::hooks::PAUSED.store(paused, std::sync::atomic::Ordering::Relaxed);
+        if !paused {
+            crate::hooks::attempts::reset();
+        }
+        tracing::warn!(
+            "control: YOLO {}",
+            if paused { "PAUSED" } else { "resumed" }
+        );
+    }
+
+    fn set(&self, key: &str, value: &Value) -> Result<(), String> {
+        // Applied with `update`, so a config reload at the same time isn't
+        // undone (or this undone by it).
+        let (old, installed) = crate::config::update(|current| {
+            let mut updated = current.clone();
+            apply_setting(&mut updated, key, value)?;
+            Ok::<_, String>(updated)
+        })?;
+        // A changed budget starts a new session, and setting the breaker
+        // resets it.
+        crate::hooks::attempts::reset();
+        if installed.budget != old.budget {
+            crate::budget::arm(&installed.budget);
+        }
+        if key.starts_with("circuit_breaker_") {
+            crate::circuit_breaker::reset(&installed.circuit_breaker);
+        }
+        tracing::info!("control: set {key}={value}");
+        Ok(())
+    }
+
+    fn stats(&self) -> Value {
+        use crate::hooks::{self, entry_scanner};
+        use std::sync::atomic::Ordering::Relaxed;
+
+        json!({
+            "permission_decision": hooks::PERMISSION_DECISION_COUNT.load(Relaxed),
+            "tool_authorization": hooks::TOOL_AUTHORIZATION_COUNT.load(Relaxed),
+            "tool_authorization_misses": hooks::TOOL_AUTHORIZATION_MISS_COUNT.load(Relaxed),
+            "tool_authorization_retry_successes":
+                hooks::TOOL_AUTHORIZATION_RETRY_SUCCESS_COUNT.load(Relaxed),
+            "policy_rejects": hooks::POLICY_REJECT_COUNT.load(Relaxed),
+            "left_for_user": hooks::LEFT_FOR_USER_COUNT.load(Relaxed),
+            "scanner_approvals": entry_scanner::SCANNER_APPROVAL_COUNT.load(Relaxed),
+            "threads": entry_scanner::registered_threads(),
+            "thread_slots": entry_scanner::thread_slots(),
+            "thread_overflows": entry_scanner::thread_overflows(),
+            "thread_evictions": entry_scanner::THREAD_EVICTION_COUNT.load(Relaxed),
+            "attempts": hooks::attempts::len(),
+            "scan_sweeps": hooks::stale_scanner::SWEEP_COUNT.load(Relaxed),
+            "scan_wakes": hooks::stale_scanner::WAKE_COUNT.load(Relaxed),
+            "scan_interval_ms": hooks::stale_scanner::INTERVAL_MS.load(Relaxed),
+            "scan_latency_us": hooks::stale_scanner::latency(),
+            "budget_used": crate::budget::used(),
+            "budget_remaining": crate::budget::remaining(&crate::config::current().budget).to_string(),
+            "circuit_breaker_window": crate::circuit_breaker::in_window(&crate::config::current().circuit_breaker),
+            "circuit_breaker_tripped": crate::circuit_breaker::tripped().is_some(),
+        })
+    }
+
+    fn recent(&self, limit: usize, since: u64) -> Vec<Value> {
+        crate::audit::recent(limit, since)
+            .iter()
+            .filter_map(|record| serde_json::to_value(record).ok())
+            .collect()
+    }
+}
+
+/// Applies a `set` of `key` to `value` on `updated`.
+fn apply_setting(
+    updated: &mut crate::config::YoloConfig,
+    key: &str,
+    value: &Value,
+) -> Result<(), String> {
+    use crate::config;
+
+    let text = value.as_str();
+    match key {
+        "tool_option" => {
+            updated.tool_option = text
+                .and_then(config::parse_tool_option)
+                .ok_or("tool_option must be \"allow\" or \"allow_always\"")?;
+        }
+        "plan_option" => {
+            updated.plan_option = text.and_then(config::parse_plan_option).ok_or(
+                "plan_option must be \"acceptEdits\", \"bypassPermissions\", \"default\" or \"plan\"",
+            )?;
+        }
+        "retry_delay_us" => {
+            updated.retry_delay_us = value
+                .as_u64()
+                .ok_or("retry_delay_us must be a number")?
+                .min(10_000);
+        }
+        "budget_minutes" => {
+            updated.budget.minutes = value.as_u64().ok_or("budget_minutes must be a number")?;
+        }
+        "budget_approvals" => {
+            updated.budget.approvals = value.as_u64().ok_or("budget_approvals must be a number")?;
+        }
+        "circuit_breaker_window_secs" => {
+            updated.circuit_breaker.window_secs = value
+                .as_u64()
+                .ok_or("circuit_breaker_window_secs must be a number")?;
+        }
+        "circuit_breaker_max_approvals" => {
+            updated.circuit_breaker.max_approvals = value
+                .as_u64()
+                .ok_or("circuit_breaker_max_approvals must be a number")?;
+        }
+        "circuit_breaker_max_per_session" => {
+            updated.circuit_breaker.max_per_session = value
+                .as_u64()
+                .ok_or("circuit_breaker_max_per_session must be a number")?;
+        }
+        other => {
+            return Err(format!(
+                "unknown key {other:?} (expected tool_option, plan_option, retry_delay_us, \
+                 budget_minutes, budget_approvals, circuit_breaker_window_secs, \
+                 circuit_breaker_max_approvals, circuit_breaker_max_per_session)"
+            ));
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[derive(Default)]
+    struct FakeHost {
+        paused: AtomicBool,
+        tool_option: Mutex<String>,
+    }
+
+    impl Host for FakeHost {
+        fn status(&self) -> Value {
+            json!({
+                "paused": self.paused.load(Ordering::Relaxed),
+                "tool_option": *self.tool_option.lock().unwrap(),
+            })
+        }
+
+        fn set_paused(&self, paused: bool) {
+            self.paused.store(paused, Ordering::Relaxed);
+        }
+
+        fn set(&self, key: &str, value: &Value) -> Result<(), String> {
+            match (key, value.as_str()) {
+                ("tool_option", Some(v @ ("allow" | "allow_always"))) => {
+                    *self.tool_option.lock().unwrap() = v.to_string();
+                    Ok(())
+                }
+                _ => Err(format!("cannot set {key}")),
+            }
+        }
+
+        fn stats(&self) -> Value {
+            json!({ "tool_authorization": 3 })
+        }
+
+        fn recent(&self, limit: usize, since: u64) -> Vec<Value> {
+            (1..=5u64)
+                .rev()
+                .filter(|seq| *seq > since)
+                .take(limit)
+                .map(|seq| json!({ "seq": seq }))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_handle_line() {
+        let host = FakeHost::default();
+
+        assert_eq!(
+            handle_line(&host, r#"{"cmd":"pause"}"#),
+            json!({ "ok": true, "paused": true })
+        );
+        assert_eq!(handle_line(&host, r#"{"cmd":"status"}"#)["paused"], true);
+        assert_eq!(handle_line(&host, r#"{"cmd":"resume"}"#)["paused"], false);
+
+        let set = handle_line(
+            &host,
+            r#"{"cmd":"set","key":"tool_option","value":"allow_always"}"#,
+        );
+        assert_eq!(set["ok"], true);
+        assert_eq!(
+            handle_line(&host, r#"{"cmd":"status"}"#)["tool_option"],
+            "allow_always"
+        );
+
+        let bad_set = handle_line(&host, r#"{"cmd":"set","key":"mode","value":"x"}"#);
+        assert_eq!(bad_set["ok"], false);
+        assert_eq!(bad_set["error"], "cannot set mode");
+
+        assert_eq!(
+            handle_line(&host, r#"{"cmd":"stats"}"#)["tool_authorization"],
+            3
+        );
+
+        let recent = handle_line(&host, r#"{"cmd":"recent","limit":2}"#);
+        assert_eq!(recent["records"], json!([{ "seq": 5 }, { "seq": 4 }]));
+        let recent = handle_line(&host, r#"{"cmd":"recent"}"#);
+        assert_eq!(recent["records"].as_array().unwrap().len(), 5);
+        let recent = handle_line(&host, r#"{"cmd":"recent","since":3}"#);
+        assert_eq!(recent["records"], json!([{ "seq": 5 }, { "seq": 4 }]));
+
+        for bad in [
+            "",
+            "nope",
+            r#"{"cmd":"reboot"}"#,
+            r#"{"cmd":"recent","x":1}"#,
+        ] {
+            let response = handle_line(&host, bad);
+            assert_eq!(response["ok"], false, "{bad}");
+            assert!(
+                response["error"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("bad request")
+            );
+        }
+    }
+
+    #[test]
+    fn test_socket_round_trip() {
+        let dir = std::env::temp_dir().join(format!("zyc-{}", std::process::id()));
+        let path = dir.join("zed-yolo-hook.1.sock");
+        let host = Arc::new(FakeHost::default());
+        bind_and_serve(&path, host.clone()).unwrap();
+
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+            // The private directory it was bound in is gone.
+            assert!(!dir.join(".zed-yolo-hook.1.sock").exists());
+        }
+
+        let stream = UnixStream::connect(&path).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut ask = |request: &str| {
+            writer.write_all(format!("{request}\n").as_bytes()).unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            serde_json::from_str::<Value>(&line).unwrap()
+        };
+
+        assert_eq!(ask(r#"{"cmd":"pause"}"#)["paused"], true);
+        assert!(host.paused.load(Ordering::Relaxed));
+        assert_eq!(ask("garbage")["ok"], false);
+        assert_eq!(ask(r#"{"cmd":"status"}"#)["paused"], true);
+        assert_eq!(ask(r#"{"cmd":"resume"}"#)["paused"], false);
+        assert!(!host.paused.load(Ordering::Relaxed));
+
+        // A second client is served concurrently.
+        let mut other = UnixStream::connect(&path).unwrap();
+        other.write_all(b"{\"cmd\":\"stats\"}\n").unwrap();
+        let mut line = String::new();
+        BufReader::new(other).read_line(&mut line).unwrap();
+        assert!(line.contains("\"tool_authorization\":3"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn read_response(stream: &UnixStream) -> Value {
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn test_socket_rejects_long_line() {
+        let dir = std::env::temp_dir().join(format!("zyc-long-{}", std::process::id()));
+        let path = dir.join("zed-yolo-hook.1.sock");
+        bind_and_serve(&path, Arc::new(FakeHost::default())).unwrap();
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(&[b' '; MAX_LINE + 1]).unwrap();
+        let response = read_response(&stream);
+        assert_eq!(response["ok"], false);
+        assert_eq!(response["error"], "request line longer than 64 KiB");
+        // The connection is closed after the error.
+        let mut rest = String::new();
+        assert_eq!(stream.read_to_string(&mut rest).unwrap(), 0);
+
+        // A line just under the cap is still parsed.
+        let mut stream = UnixStream::connect(&path).unwrap();
+        let mut request = r#"{"cmd":"stats"}"#.as_bytes().to_vec();
+        request.resize(MAX_LINE - 1, b' ');
+        request.push(b'\n');
+        stream.write_all(&request).unwrap();
+        assert_eq!(read_response(&stream)["tool_authorization"], 3);
+
+        stream.write_all(b"\xff\n").unwrap();
+        let response = read_response(&stream);
+        assert!(
+            response["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("bad request")
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_socket_limits_clients() {
+        let dir = std::env::temp_dir().join(format!("zyc-clients-{}", std::process::id()));
+        let path = dir.join("zed-yolo-hook.1.sock");
+        bind_and_serve(&path, Arc::new(FakeHost::default())).unwrap();
+
+        let ask = |stream: &mut UnixStream| {
+            stream.write_all(b"{\"cmd\":\"stats\"}\n").unwrap();
+            read_response(stream)
+        };
+        let mut clients: Vec<UnixStream> = (0..MAX_CLIENTS)
+            .map(|_| UnixStream::connect(&path).unwrap())
+            .collect();
+        for client in &mut clients {
+            assert_eq!(ask(client)["ok"], true);
+        }
+
+        let extra = UnixStream::connect(&path).unwrap();
+        assert_eq!(read_response(&extra)["error"], "too many clients");
+
+        // Once a client leaves, a new one is served.
+        drop(clients.pop());
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            // A rejected client may already be closed; its error line is
+            // still readable.
+            let mut next = UnixStream::connect(&path).unwrap();
+            let _ = next.write_all(b"{\"cmd\":\"stats\"}\n");
+            if read_response(&next)["ok"] == true {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "slot never freed");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_remove_stale_sockets() {
+        let dir = std::env::temp_dir().join(format!("zyc-stale-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let live = dir.join(format!("{SOCKET_PREFIX}{}.sock", std::process::id()));
+        // pid_t max on macOS / Linux defaults is far below this.
+        let dead = dir.join(format!("{SOCKET_PREFIX}{}.sock", 999_999_999));
+        let other = dir.join("zed-yolo-hook.json");
+        for path in [&live, &dead, &other] {
+            std::fs::write(path, "").unwrap();
+        }
+
+        remove_stale_sockets(&dir);
+        assert!(live.exists());
+        assert!(!dead.exists());
+        assert!(other.exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,92 @@
+//! macOS Mach VM FFI bindings.
+//!
+//! Provides `mach_vm_read_overwrite` (a kernel-checked copy out of our own
+//! address space) and `mach_vm_region` (the mapping and protection around an
+//! address). Used by `mem::LiveMemory` so a stale pointer read from Zed's heap
+//! fails with a return code instead of faulting.
+
+use std::ffi::c_int;
+
+pub type KernReturn = c_int;
+pub type MachPort = u32;
+
+pub const KERN_SUCCESS: KernReturn = 0;
+
+/// `VM_PROT_READ` / `VM_PROT_WRITE` bits of a region's protection.
+pub const VM_PROT_READ: c_int = 0x1;
+pub const VM_PROT_WRITE: c_int = 0x2;
+
+/// `VM_REGION_BASIC_INFO_64` flavor and its size in `natural_t` words
+/// (`VM_REGION_BASIC_INFO_COUNT_64`).
+pub const VM_REGION_BASIC_INFO_64: c_int = 9;
+pub const VM_REGION_BASIC_INFO_COUNT_64: u32 = 9;
+
+/// `vm_region_basic_info_data_64_t` (`#pragma pack(4)`), as raw words;
+/// `protection` is the first one.
+pub type RegionBasicInfo64 = [c_int; VM_REGION_BASIC_INFO_COUNT_64 as usize];
+
+unsafe extern "C" {
+    /// What `mach_task_self()` expands to.
+    static mach_task_self_: MachPort;
+
+    pub fn mach_vm_read_overwrite(
+        target_task: MachPort,
+        address: u64,
+        size: u64,
+        data: u64,
+        outsize: *mut u64,
+    ) -> KernReturn;
+
+    pub fn mach_vm_region(
+        target_task: MachPort,
+        address: *mut u64,
+        size: *mut u64,
+        flavor: c_int,
+        info: *mut c_int,
+        info_count: *mut u32,
+        object_name: *mut MachPort,
+    ) -> KernReturn;
+}
+
+/// The current task's port.
+pub fn task_self() -> MachPort {
+    unsafe { mach_task_self_ }
+}
+
+/// Copy `buf.len()` bytes from `addr` in this task. `false` if any page in
+/// the range is unmapped or unreadable.
+pub fn read(addr: u64, buf: &mut [u8]) -> bool {
+    let mut copied = 0u64;
+    let kr = unsafe {
+        mach_vm_read_overwrite(
+            task_self(),
+            addr,
+            buf.len() as u64,
+            buf.as_mut_ptr() as u64,
+            &mut copied,
+        )
+    };
+    kr == KERN_SUCCESS && copied == buf.len() as u64
+}
+
+/// The mapped region containing `addr`: `(start, end, protection)`. `None`
+/// when `addr` is not mapped (the kernel returns the next region up).
+pub fn region(addr: u64) -> Option<(u64, u64, c_int)> {
+    let mut start = addr;
+    let mut size = 0u64;
+    let mut info: RegionBasicInfo64 = [0; VM_REGION_BASIC_INFO_COUNT_64 as usize];
+    let mut count = VM_REGION_BASIC_INFO_COUNT_64;
+    let mut object_name: MachPort = 0;
+    let kr = unsafe {
+        mach_vm_region(
+            task_self(),
+            &mut start,
+            &mut size,
+            VM_REGION_BASIC_INFO_64,
+            info.as_mut_ptr(),
+            &mut count,
+            &mut object_name,
+        )
+    };
+    (kr == KERN_SUCCESS && start <= addr).then(|| (start, start + size, info[0]))
+}
@@ -0,0 +1,445 @@
+//! Identify the running Zed build.
+//!
+//! Layout selection, the DIAGNOSTIC log line and bug reports all need to know
+//! exactly which binary the hook was injected into:
+//!
+//! - `Info.plist` — `CFBundleIdentifier`, `CFBundleShortVersionString`
+//!   (marketing version, e.g. `1.1.2`) and `CFBundleVersion` (build, e.g.
+//!   `20260429.140800`)
+//! - Mach-O `LC_UUID` of the main executable — the linker derives it from the
+//!   binary's contents, so two builds of the same version still differ
+//!
+//! Hashing the whole executable (~350 MB) on every launch is not an option —
+//! startup time is what the helper-process short-circuit protects. The UUID
+//! costs one 64 KiB read. Binaries linked without `LC_UUID` fall back to an
+//! FNV-1a hash of the Mach-O header and load commands.
+//!
+//! Parsing is pure and works on byte slices, so it is tested on any host.
+
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// How much of the executable to read: Mach-O header + load commands.
+const HEADER_READ_LEN: usize = 64 * 1024;
+
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const FAT_MAGIC: u32 = 0xcafe_babe;
+const LC_UUID: u32 = 0x1b;
+const MACH_HEADER_64_SIZE: usize = 32;
+const FAT_ARCH_SIZE: usize = 20;
+
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+
+/// Identity of the running app binary. Every field is best-effort.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AppFingerprint {
+    pub exe_path: Option<PathBuf>,
+    /// `CFBundleIdentifier`, e.g. `dev.zed.Zed-Preview`.
+    pub bundle_id: Option<String>,
+    /// `CFBundleShortVersionString`, e.g. `1.1.2`.
+    pub version: Option<String>,
+    /// `CFBundleVersion`, e.g. `20260429.140800`.
+    pub build: Option<String>,
+    /// Mach-O `LC_UUID`, uppercase hyphenated.
+    pub uuid: Option<String>,
+    /// FNV-1a 64 of the Mach-O header + load commands.
+    pub header_hash: Option<u64>,
+}
+
+impl AppFingerprint {
+    /// Key used for `binary_hashes` in the layout database: the `LC_UUID`,
+    /// or `fnv:<hex>` when the binary has none.
+    pub fn binary_hash(&self) -> Option<String> {
+        self.uuid
+            .clone()
+            .or_else(|| self.header_hash.map(|h| format!("fnv:{h:016x}")))
+    }
+
+    /// Hook registry / config directory name, from the bundle id when known.
+    pub fn app_id(&self) -> String {
+        match self.bundle_id.as_deref() {
+            Some("dev.zed.Zed-Preview") => "zed-preview".to_string(),
+            Some("dev.zed.Zed") => "zed-stable".to_string(),
+            _ => crate::config::detect_app_id(),
+        }
+    }
+}
+
+impl fmt::Display for AppFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_unknown = |v: &Option<String>| v.clone().unwrap_or_else(|| "?".to_string());
+        write!(
+            f,
+            "bundle={} version={} build={} hash={}",
+            or_unknown(&self.bundle_id),
+            or_unknown(&self.version),
+            or_unknown(&self.build),
+            or_unknown(&self.binary_hash())
+        )
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Detection
+// ---------------------------------------------------------------------------
+
+/// Fingerprint the current executable. Never fails; missing pieces are `None`.
+pub fn detect() -> AppFingerprint {
+    let Ok(exe) = std::env::current_exe() else {
+        return AppFingerprint::default();
+    };
+
+    let mut fingerprint = AppFingerprint {
+        exe_path: Some(exe.clone()),
+        ..AppFingerprint::default()
+    };
+
+    // .../Zed Preview.app/Contents/MacOS/zed → .../Contents/Info.plist
+    if let Some(contents) = exe.parent().and_then(Path::parent)
+        && let Ok(plist) = std::fs::read_to_string(contents.join("Info.plist"))
+    {
+        fingerprint.bundle_id = plist_string(&plist, "CFBundleIdentifier");
+        fingerprint.version = plist_string(&plist, "CFBundleShortVersionString");
+        fingerprint.build = plist_string(&plist, "CFBundleVersion");
+    }
+
+    if let Some(info) = read_macho_info(&exe) {
+        fingerprint.uuid = info.uuid;
+        fingerprint.header_hash = Some(info.header_hash);
+    }
+
+    fingerprint
+}
+
+/// Read the header of `path` (following a fat header to our architecture's
+/// slice) and parse it.
+fn read_macho_info(path: &Path) -> Option<MachOInfo> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let mut buf = vec![0u8; HEADER_READ_LEN];
+    let n = read_up_to(&mut file, &mut buf)?;
+    buf.truncate(n);
+
+    if let Some(offset) = fat_slice_offset(&buf, host_cpu_type()) {
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        buf.resize(HEADER_READ_LEN, 0);
+        let n = read_up_to(&mut file, &mut buf)?;
+        buf.truncate(n);
+    }
+
+    parse_macho(&buf)
+}
+
+fn read_up_to(file: &mut std::fs::File, buf: &mut [u8]) -> Option<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match file.read(&mut buf[total..]) {
+            Ok(0) => break,
+            Ok(n) => total += n,
+            Err(_) => return None,
+        }
+    }
+    Some(total)
+}
+
+fn host_cpu_type() -> u32 {
+    if cfg!(target_arch = "x86_64") {
+        CPU_TYPE_X86_64
+    } else {
+        CPU_TYPE_ARM64
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Info.plist
+// ---------------------------------------------------------------------------
+
+/// Value of `<key>{key}</key><string>…</string>` in an XML plist.
+///
+/// Zed ships an XML `Info.plist`; binary plists are not supported and yield `None`.
+pub(crate) fn plist_string(xml: &str, key: &str) -> Option<String> {
+    let key_tag = format!("<key>{key}</key>");
+    let after_key = &xml[xml.find(&key_tag)? + key_tag.len()..];
+    let value = after_key.trim_start().strip_prefix("<string>")?;
+    let end = value.find("</string>")?;
+    let text = value[..end].trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some(
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&"),
+    )
+}
+
+// ---------------------------------------------------------------------------
+// Mach-O
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct MachOInfo {
+    pub(crate) uuid: Option<String>,
+    pub(crate) header_hash: u64,
+}
+
+fn u32_le(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u32_be(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// File offset of the `cpu_type` slice if `bytes` starts with a fat header.
+pub(crate) fn fat_slice_offset(bytes: &[u8], cpu_type: u32) -> Option<u64> {
+    if u32_be(bytes, 0)? != FAT_MAGIC {
+        return None;
+    }
+    let count = u32_be(bytes, 4)? as usize;
+    (0..count).find_map(|i| {
+        let arch = 8 + i * FAT_ARCH_SIZE;
+        (u32_be(bytes, arch)? == cpu_type).then_some(u32_be(bytes, arch + 8)? as u64)
+    })
+}
+
+/// Parse a thin little-endian 64-bit Mach-O header: `LC_UUID` and a hash of
+/// the header + load commands. `None` if `bytes` isn't one.
+pub(crate) fn parse_macho(bytes: &[u8]) -> Option<MachOInfo> {
+    if u32_le(bytes, 0)? != MH_MAGIC_64 {
+        return None;
+    }
+    let ncmds = u32_le(bytes, 16)? as usize;
+    let sizeofcmds = u32_le(bytes, 20)? as usize;
+    let commands = bytes.get(MACH_HEADER_64_SIZE..MACH_HEADER_64_SIZE + sizeofcmds)?;
+
+    let mut uuid = None;
+    let mut offset = 0;
+    for _ in 0..ncmds {
+        let cmd = u32_le(commands, offset)?;
+        let cmdsize = u32_le(commands, offset + 4)? as usize;
+        if cmdsize < 8 {
+            return None;
+        }
+        if cmd == LC_UUID {
+            uuid = commands.get(offset + 8..offset + 24).map(format_uuid);
+        }
+        offset += cmdsize;
+    }
+
+    Some(MachOInfo {
+        uuid,
+        header_hash: fnv1a64(&bytes[..MACH_HEADER_64_SIZE + sizeofcmds]),
+    })
+}
+
+/// `8-4-4-4-12` uppercase, the format `dwarfdump --uuid` prints.
+fn format_uuid(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+pub(crate) fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: [u8; 16] = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x10, 0x32, 0x54, 0x76, 0x98, 0xba, 0xdc,
+        0xfe,
+    ];
+
+    /// Thin arm64 Mach-O header with a dummy load command and optionally LC_UUID.
+    fn macho(with_uuid: bool) -> Vec<u8> {
+        let mut commands = Vec::new();
+        // LC_SEGMENT_64-ish filler (cmd=0x19, cmdsize=16)
+        commands.extend(0x19u32.to_le_bytes());
+        commands.extend(16u32.to_le_bytes());
+        commands.extend([0u8; 8]);
+        if with_uuid {
+            commands.extend(LC_UUID.to_le_bytes());
+            commands.extend(24u32.to_le_bytes());
+            commands.extend(UUID);
+        }
+        let ncmds: u32 = if with_uuid { 2 } else { 1 };
+
+        let mut bytes = Vec::new();
+        bytes.extend(MH_MAGIC_64.to_le_bytes());
+        bytes.extend(CPU_TYPE_ARM64.to_le_bytes());
+        bytes.extend(0u32.to_le_bytes()); // cpusubtype
+        bytes.extend(2u32.to_le_bytes()); // MH_EXECUTE
+        bytes.extend(ncmds.to_le_bytes());
+        bytes.extend((commands.len() as u32).to_le_bytes());
+        bytes.extend(0u32.to_le_bytes()); // flags
+        bytes.extend(0u32.to_le_bytes()); // reserved
+        bytes.extend(commands);
+        bytes.extend([0xaa; 64]); // section data, not part of the hash
+        bytes
+    }
+
+    #[test]
+    fn test_parse_macho_uuid() {
+        let info = parse_macho(&macho(true)).unwrap();
+        assert_eq!(
+            info.uuid.as_deref(),
+            Some("01234567-89AB-CDEF-1032-547698BADCFE")
+        );
+
+        let fingerprint = AppFingerprint {
+            uuid: info.uuid,
+            header_hash: Some(info.header_hash),
+            ..AppFingerprint::default()
+        };
+        assert_eq!(
+            fingerprint.binary_hash().as_deref(),
+            Some("01234567-89AB-CDEF-1032-547698BADCFE")
+        );
+    }
+
+    #[test]
+    fn test_parse_macho_without_uuid() {
+        let bytes = macho(false);
+        let info = parse_macho(&bytes).unwrap();
+        assert!(info.uuid.is_none());
+
+        // Hash covers header + load commands only.
+        let mut changed_data = bytes.clone();
+        *changed_data.last_mut().unwrap() = 0;
+        assert_eq!(
+            parse_macho(&changed_data).unwrap().header_hash,
+            info.header_hash
+        );
+        let mut changed_header = bytes;
+        changed_header[8] = 1; // cpusubtype
+        assert_ne!(
+            parse_macho(&changed_header).unwrap().header_hash,
+            info.header_hash
+        );
+
+        let fingerprint = AppFingerprint {
+            header_hash: Some(0xdead_beef),
+            ..AppFingerprint::default()
+        };
+        assert_eq!(
+            fingerprint.binary_hash().as_deref(),
+            Some("fnv:00000000deadbeef")
+        );
+    }
+
+    #[test]
+    fn test_parse_macho_rejects_garbage() {
+        assert!(parse_macho(b"").is_none());
+        assert!(parse_macho(b"#!/bin/sh\necho hi\n").is_none());
+
+        // Truncated load commands
+        let bytes = macho(true);
+        assert!(parse_macho(&bytes[..40]).is_none());
+
+        // cmdsize of 0 would loop forever
+        let mut zero_size = macho(true);
+        zero_size[MACH_HEADER_64_SIZE + 4..MACH_HEADER_64_SIZE + 8].fill(0);
+        assert!(parse_macho(&zero_size).is_none());
+    }
+
+    #[test]
+    fn test_fat_slice_offset() {
+        let mut fat = Vec::new();
+        fat.extend(FAT_MAGIC.to_be_bytes());
+        fat.extend(2u32.to_be_bytes());
+        for (cpu, offset) in [(CPU_TYPE_X86_64, 0x4000u32), (CPU_TYPE_ARM64, 0x1_0000)] {
+            fat.extend(cpu.to_be_bytes());
+            fat.extend(0u32.to_be_bytes()); // cpusubtype
+            fat.extend(offset.to_be_bytes());
+            fat.extend(0x1000u32.to_be_bytes()); // size
+            fat.extend(14u32.to_be_bytes()); // align
+        }
+
+        assert_eq!(fat_slice_offset(&fat, CPU_TYPE_ARM64), Some(0x1_0000));
+        assert_eq!(fat_slice_offset(&fat, CPU_TYPE_X86_64), Some(0x4000));
+        assert_eq!(fat_slice_offset(&fat, 0x12), None);
+        assert_eq!(fat_slice_offset(&macho(true), CPU_TYPE_ARM64), None);
+    }
+
+    #[test]
+    fn test_plist_string() {
+        let plist = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+	<key>CFBundleIdentifier</key>
+	<string>dev.zed.Zed-Preview</string>
+	<key>CFBundleShortVersionString</key>
+	<string>1.1.2</string>
+	<key>CFBundleVersion</key>
+	<string>20260429.140800</string>
+	<key>LSApplicationCategoryType</key>
+	<string>public.app-category.developer-tools</string>
+	<key>NSHighResolutionCapable</key>
+	<true/>
+	<key>Copyright</key>
+	<string>Zed Industries &amp; co</string>
+</dict>
+</plist>"#;
+
+        assert_eq!(
+            plist_string(plist, "CFBundleIdentifier").as_deref(),
+            Some("dev.zed.Zed-Preview")
+        );
+        assert_eq!(
+            plist_string(plist, "CFBundleShortVersionString").as_deref(),
+            Some("1.1.2")
+        );
+        assert_eq!(
+            plist_string(plist, "CFBundleVersion").as_deref(),
+            Some("20260429.140800")
+        );
+        assert_eq!(
+            plist_string(plist, "Copyright").as_deref(),
+            Some("Zed Industries & co")
+        );
+        // Not a string value
+        assert_eq!(plist_string(plist, "NSHighResolutionCapable"), None);
+        assert_eq!(plist_string(plist, "CFBundleName"), None);
+    }
+
+    #[test]
+    fn test_display_and_app_id() {
+        let fingerprint = AppFingerprint {
+            bundle_id: Some("dev.zed.Zed-Preview".to_string()),
+            version: Some("1.1.2".to_string()),
+            build: Some("20260429.140800".to_string()),
+            ..AppFingerprint::default()
+        };
+        assert_eq!(
+            fingerprint.to_string(),
+            "bundle=dev.zed.Zed-Preview version=1.1.2 build=20260429.140800 hash=?"
+        );
+        assert_eq!(fingerprint.app_id(), "zed-preview");
+
+        let stable = AppFingerprint {
+            bundle_id: Some("dev.zed.Zed".to_string()),
+            ..AppFingerprint::default()
+        };
+        assert_eq!(stable.app_id(), "zed-stable");
+    }
+}
@@ -0,0 +1,319 @@
+//! Which waiting entries a hook has already acted on.
+//!
+//! `tool_authorization::Listener` and the stale scanner can both find the
+//! same WaitingForConfirmation entry: the scanner sweeps every thread while
+//! the hook is still deciding, or the hook left the dialog for the user and
+//! the scanner would decide it again every sweep. `resolve_entry` claims an
+//! entry here before acting on it, and only the path that claimed it goes on.
+//!
+//! Entries are known by thread pointer and tool call id; ids are unique per
+//! agent session, so they survive the entries Vec being reordered or
+//! truncated. Layouts that don't decode the id fall back to the entry's
+//! address. Claims expire after `TTL_MS` (a dialog left for the user is then
+//! decided again), and `reset` drops them all at once: a config reload, a
+//! `set` or a resume through the control socket can change what the answer
+//! would be. Those of a freed thread are dropped when the scanner evicts it.
+//!
+//! Claims are taken from Frida interceptor context, so nothing here blocks.
+//! The table is a fixed array of `BUCKETS` × `WAYS` atomic words, each
+//! holding one claim: a fingerprint of the key (thread tag + id hash), the
+//! generation it was claimed in and the claim time in seconds. A key hashes
+//! to one bucket; a full bucket gives up its oldest claim. Two paths
+//! claiming the same key at once can both take a slot, so each re-scans the
+//! bucket after its CAS (SeqCst: at least one sees the other). A path that
+//! finds another slot with its key backs out and tries again; after
+//! `MAX_RACES` rounds it gives up, and the entry is left for the next sweep.
+//!
+//! Two keys with the same bucket and fingerprint (42 bits in all) look like
+//! one: the second entry is then treated as handled and left for the user.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Buckets in the table.
+const BUCKETS: usize = 1024;
+/// Claims per bucket.
+const WAYS: usize = 4;
+/// Claims kept at most.
+pub(crate) const MAX_ATTEMPTS: usize = BUCKETS * WAYS;
+/// How long a claim is kept.
+pub(crate) const TTL_MS: u64 = 60 * 60 * 1000;
+/// Times `claim` backs out of a race before giving up.
+const MAX_RACES: usize = 8;
+
+// Claim word: thread tag (12) | id hash (20) | generation (8) | seconds (24).
+// Zero is an empty slot; the id hash is never zero.
+const TIME_BITS: u32 = 24;
+const GENERATION_BITS: u32 = 8;
+const FINGERPRINT_SHIFT: u32 = TIME_BITS + GENERATION_BITS;
+const THREAD_TAG_SHIFT: u32 = 52;
+const TIME_MASK: u64 = (1 << TIME_BITS) - 1;
+const GENERATION_MASK: u64 = (1 << GENERATION_BITS) - 1;
+
+/// What a waiting entry is known by.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum EntryId {
+    ToolCall(String),
+    /// Entry address, for layouts without a decoded tool call id.
+    Entry(u64),
+}
+
+impl EntryId {
+    pub(crate) fn new(tool_call_id: Option<&str>, entry: u64) -> Self {
+        match tool_call_id {
+            Some(id) => EntryId::ToolCall(id.to_string()),
+            None => EntryId::Entry(entry),
+        }
+    }
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn thread_tag(thread: u64) -> u64 {
+    hash(thread) >> THREAD_TAG_SHIFT
+}
+
+/// Bucket index and fingerprint (high 32 bits of a claim word) of a key.
+fn locate(thread: u64, id: &EntryId) -> (usize, u64) {
+    let key = hash((thread, id));
+    let bucket = (key as usize) % BUCKETS;
+    let id_hash = ((key >> 32) & 0xf_ffff).max(1);
+    let fingerprint = (thread_tag(thread) << THREAD_TAG_SHIFT) | (id_hash << FINGERPRINT_SHIFT);
+    (bucket, fingerprint)
+}
+
+/// Claimed entries. Times are Unix milliseconds, passed in so the logic is
+/// testable.
+pub(crate) struct Attempts {
+    slots: [AtomicU64; MAX_ATTEMPTS],
+    generation: AtomicU64,
+}
+
+impl Attempts {
+    pub(crate) const fn new() -> Self {
+        Attempts {
+            slots: [const { AtomicU64::new(0) }; MAX_ATTEMPTS],
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    fn bucket(&self, bucket: usize) -> &[AtomicU64] {
+        &self.slots[bucket * WAYS..(bucket + 1) * WAYS]
+    }
+
+    /// Age of the claim in `word` in seconds, `None` if it no longer counts
+    /// (empty, expired, or from before a `reset`).
+    fn age(&self, word: u64, now_ms: u64) -> Option<u64> {
+        let generation = self.generation.load(Ordering::SeqCst) & GENERATION_MASK;
+        if word == 0 || (word >> TIME_BITS) & GENERATION_MASK != generation {
+            return None;
+        }
+        let age = ((now_ms / 1000) & TIME_MASK).wrapping_sub(word & TIME_MASK) & TIME_MASK;
+        (age < TTL_MS / 1000).then_some(age)
+    }
+
+    fn held(&self, word: u64, fingerprint: u64, now_ms: u64) -> bool {
+        word >> FINGERPRINT_SHIFT << FINGERPRINT_SHIFT == fingerprint
+            && self.age(word, now_ms).is_some()
+    }
+
+    pub(crate) fn contains(&self, thread: u64, id: &EntryId, now_ms: u64) -> bool {
+        let (bucket, fingerprint) = locate(thread, id);
+        self.bucket(bucket)
+            .iter()
+            .any(|slot| self.held(slot.load(Ordering::SeqCst), fingerprint, now_ms))
+    }
+
+    /// Claim the entry `id` of `thread`. `false` if it is already claimed.
+    pub(crate) fn claim(&self, thread: u64, id: EntryId, now_ms: u64) -> bool {
+        let (bucket, fingerprint) = locate(thread, &id);
+        let slots = self.bucket(bucket);
+        let mut races = 0;
+        while races < MAX_RACES {
+            let words: [u64; WAYS] = std::array::from_fn(|i| slots[i].load(Ordering::SeqCst));
+            if words.iter().any(|&w| self.held(w, fingerprint, now_ms)) {
+                return false;
+            }
+
+            // A free slot, or else the oldest claim.
+            let target = (0..WAYS)
+                .max_by_key(|&i| self.age(words[i], now_ms).map_or(u64::MAX, |age| age))
+                .unwrap_or(0);
+            let generation = self.generation.load(Ordering::SeqCst) & GENERATION_MASK;
+            let claim = fingerprint | (generation << TIME_BITS) | ((now_ms / 1000) & TIME_MASK);
+            if slots[target]
+                .compare_exchange(words[target], claim, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+            {
+                continue;
+            }
+
+            // Someone else claiming the same key at once: both may see each
+            // other, so both back out and look again.
+            let raced = (0..WAYS).any(|i| {
+                i != target && self.held(slots[i].load(Ordering::SeqCst), fingerprint, now_ms)
+            });
+            if !raced {
+                return true;
+            }
+            let _ = slots[target].compare_exchange(claim, 0, Ordering::SeqCst, Ordering::SeqCst);
+            races += 1;
+            std::hint::spin_loop();
+        }
+        false
+    }
+
+    /// Drop every claim, so every waiting entry is decided again.
+    pub(crate) fn reset(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Drop every claim of `thread` (and, rarely, of another thread with the
+    /// same tag, which just gets its entries decided again).
+    pub(crate) fn forget(&self, thread: u64) {
+        let tag = thread_tag(thread);
+        for slot in &self.slots {
+            let word = slot.load(Ordering::SeqCst);
+            if word != 0 && word >> THREAD_TAG_SHIFT == tag {
+                let _ = slot.compare_exchange(word, 0, Ordering::SeqCst, Ordering::SeqCst);
+            }
+        }
+    }
+
+    pub(crate) fn len(&self, now_ms: u64) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| self.age(slot.load(Ordering::SeqCst), now_ms).is_some())
+            .count()
+    }
+}
+
+static ATTEMPTS: Attempts = Attempts::new();
+
+/// Whether the entry `id` of `thread` was claimed already.
+pub(crate) fn attempted(thread: u64, id: &EntryId) -> bool {
+    ATTEMPTS.contains(thread, id, crate::budget::now_ms())
+}
+
+/// Claim the entry `id` of `thread`; `false` when another path has it.
+pub(crate) fn claim(thread: u64, id: EntryId) -> bool {
+    ATTEMPTS.claim(thread, id, crate::budget::now_ms())
+}
+
+/// Forget every claim: what was left for the user is decided again.
+pub(crate) fn reset() {
+    ATTEMPTS.reset();
+}
+
+/// Drop the claims of a freed `thread` (its address may be reused).
+pub(crate) fn forget(thread: u64) {
+    ATTEMPTS.forget(thread);
+}
+
+/// Claims currently kept.
+pub(crate) fn len() -> usize {
+    ATTEMPTS.len(crate::budget::now_ms())
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THREAD: u64 = 0x1_1000_0000;
+    const OTHER: u64 = 0x1_2000_0000;
+
+    fn id(id: &str) -> EntryId {
+        EntryId::ToolCall(id.to_string())
+    }
+
+    #[test]
+    fn test_claim_once() {
+        let attempts = Attempts::new();
+        assert!(!attempts.contains(THREAD, &id("toolu_01"), 0));
+        assert!(attempts.claim(THREAD, id("toolu_01"), 0));
+        assert!(!attempts.claim(THREAD, id("toolu_01"), 5));
+        assert!(attempts.contains(THREAD, &id("toolu_01"), 5));
+        // Keyed by thread and id, not by position.
+        assert!(attempts.claim(THREAD, id("toolu_02"), 5));
+        assert!(attempts.claim(OTHER, id("toolu_01"), 5));
+        assert!(attempts.claim(THREAD, EntryId::Entry(0x1_3000_0000), 5));
+        assert!(!attempts.claim(THREAD, EntryId::new(None, 0x1_3000_0000), 5));
+        assert_eq!(attempts.len(5), 4);
+    }
+
+    #[test]
+    fn test_claims_expire() {
+        let attempts = Attempts::new();
+        assert!(attempts.claim(THREAD, id("toolu_01"), 0));
+        assert!(attempts.claim(THREAD, id("toolu_02"), TTL_MS / 2));
+        assert!(!attempts.claim(THREAD, id("toolu_01"), TTL_MS - 1));
+        assert!(!attempts.contains(THREAD, &id("toolu_01"), TTL_MS));
+        assert!(attempts.contains(THREAD, &id("toolu_02"), TTL_MS));
+        assert_eq!(attempts.len(TTL_MS), 1);
+        assert!(attempts.claim(THREAD, id("toolu_01"), TTL_MS));
+    }
+
+    #[test]
+    fn test_reset_drops_claims() {
+        let attempts = Attempts::new();
+        assert!(attempts.claim(THREAD, id("toolu_01"), 0));
+        attempts.reset();
+        assert!(!attempts.contains(THREAD, &id("toolu_01"), 0));
+        assert_eq!(attempts.len(0), 0);
+        assert!(attempts.claim(THREAD, id("toolu_01"), 0));
+        assert!(!attempts.claim(THREAD, id("toolu_01"), 0));
+    }
+
+    #[test]
+    fn test_bounded() {
+        let attempts = Attempts::new();
+        for i in 0..MAX_ATTEMPTS * 2 {
+            assert!(attempts.claim(THREAD, id(&format!("toolu_{i}")), i as u64 * 1000));
+        }
+        let now = MAX_ATTEMPTS as u64 * 2000;
+        assert!(attempts.len(now) <= MAX_ATTEMPTS);
+        // A full bucket gives up its oldest claim, never the newest.
+        let newest = format!("toolu_{}", MAX_ATTEMPTS * 2 - 1);
+        assert!(attempts.contains(THREAD, &id(&newest), now));
+    }
+
+    #[test]
+    fn test_forget_thread() {
+        let attempts = Attempts::new();
+        assert!(attempts.claim(THREAD, id("toolu_01"), 0));
+        assert!(attempts.claim(OTHER, id("toolu_02"), 0));
+        attempts.forget(THREAD);
+        assert_eq!(attempts.len(0), 1);
+        assert!(attempts.claim(THREAD, id("toolu_01"), 0));
+        assert!(attempts.contains(OTHER, &id("toolu_02"), 0));
+    }
+
+    #[test]
+    fn test_concurrent_claims() {
+        let attempts = Attempts::new();
+        for round in 0..200 {
+            let key = format!("toolu_{round}");
+            let won: usize = std::thread::scope(|scope| {
+                let handles: Vec<_> = (0..4)
+                    .map(|_| scope.spawn(|| attempts.claim(THREAD, id(&key), 0)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().unwrap() as usize)
+                    .sum()
+            });
+            // Never two; none only if every round of backing out raced again.
+            assert!(won <= 1, "{key}");
+            assert!(!attempts.claim(THREAD, id(&key), 0) || won == 0, "{key}");
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Hook `drop_in_place::<AcpThread>` to unregister AcpThread pointers.
+//!
+//! Without it a closed thread stays in the scanner registry until the
+//! stale_scanner notices its session id changed or its memory is unmapped
+//! (see `entry_scanner`), and the scanner keeps reading freed memory until
+//! then. The drop glue is not always a separate symbol (it can be inlined
+//! into its callers); the scanner's check covers that case when the layout
+//! has `thread.session_id`.
+//!
+//! NOTE: Only unregisters (lock-free). The caches keyed by the thread pointer
+//! are cleared by the stale_scanner thread on its next sweep.
+
+use super::entry_scanner;
+
+pub struct Listener;
+
+impl frida_gum::interceptor::InvocationListener for Listener {
+    fn on_enter(&mut self, context: frida_gum::interceptor::InvocationContext) {
+        let self_ptr = context.cpu_context().reg(0);
+        entry_scanner::unregister_thread(self_ptr);
+    }
+
+    fn on_leave(&mut self, _context: frida_gum::interceptor::InvocationContext) {}
+}
+
+/// Symbol patterns for `core::ptr::drop_in_place::<acp_thread::AcpThread>`
+/// (legacy mangling: `drop_in_place$LT$acp_thread..AcpThread$GT$`).
+pub const SYMBOL_INCLUDE: &[&str] = &["drop_in_place$LT$acp_thread..AcpThread$GT$"];
+pub const SYMBOL_EXCLUDE: &[&str] = &["closure"];
@@ -7,168 +7,441 @@
 //! Thread safety: all Mutex access happens on the dedicated scanner thread only.
 
 use std::collections::HashSet;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
 
+use super::attempts::{self, EntryId};
+use super::thread_registry::{RegisteredThread, Registration, ThreadRegistry};
 use super::tool_authorization;
+use crate::audit::ApprovalPath;
+use crate::budget;
+use crate::circuit_breaker;
+use crate::config;
+use crate::fingerprint;
+use crate::layouts::{self, MatchStyle, ThreadLayout};
+use crate::mem::{LiveMemory, SafeMem};
 
-// Re-export layout constants from tool_authorization (v0.233.0)
-pub(crate) const ENTRIES_PTR_OFFSET: usize = 0xb0;
-pub(crate) const ENTRIES_LEN_OFFSET: usize = 0xb8;
+/// Tracks the AcpThread pointers we've seen, for periodic scanning.
+/// Written by register_thread / unregister_thread (from interceptor context,
+/// lock-free) and by the stale_scanner thread when it evicts a dead thread.
+///
+/// Each thread is registered with its signature (`thread_signature`), a hash
+/// of its session id. The session id is set when the AcpThread is created
+/// and never changes, and a new thread allocated at a freed one's address
+/// has its own, so a changed signature means the AcpThread behind the
+/// pointer is gone; other fields (the header included) change while the
+/// thread lives.
+static REGISTRY: ThreadRegistry = ThreadRegistry::new();
+
+/// Signature of every readable thread when the layout has no
+/// `thread.session_id`.
+const NO_SESSION_ID: u64 = 1;
+
+/// Set once the missing `thread.session_id` was logged.
+static NO_SESSION_ID_LOGGED: AtomicBool = AtomicBool::new(false);
 
-/// Tracks all AcpThread pointers we've seen, for periodic scanning.
-/// Written by register_thread (from interceptor context via atomic CAS-style),
-/// read by stale_scanner thread.
+/// Signature of the AcpThread at `ptr`: a hash of its session id
+/// (`layout.session_id`). `None` when unreadable; never 0.
 ///
-/// Uses a fixed-size array + atomic length to avoid Mutex in interceptor context.
-const MAX_THREADS: usize = 64;
-static THREAD_PTRS: [AtomicU64; MAX_THREADS] = {
-    // Initialize all to 0
-    const ZERO: AtomicU64 = AtomicU64::new(0);
-    [ZERO; MAX_THREADS]
-};
-static THREAD_COUNT: AtomicU64 = AtomicU64::new(0);
-
-// Tracks entry indices we've already attempted, to avoid infinite retries.
-// Only accessed from the stale_scanner thread via thread_local.
+/// Without `session_id` in the layout, every readable thread has the same
+/// signature: a freed AcpThread is then only noticed through the drop hook
+/// (`unregister_thread`) or once its memory is unmapped.
+pub(crate) fn thread_signature(mem: &impl SafeMem, ptr: u64, layout: &ThreadLayout) -> Option<u64> {
+    let Some(session_id) = layout.session_id else {
+        if !NO_SESSION_ID_LOGGED.swap(true, Ordering::Relaxed) {
+            tracing::warn!(
+                "entry_scanner: the layout has no `thread.session_id` — freed AcpThreads are \
+                 only noticed when Zed drops them"
+            );
+        }
+        return mem.read_u64(ptr).map(|_| NO_SESSION_ID);
+    };
+    let id = session_id.read(mem, ptr).filter(|id| !id.is_empty())?;
+    Some(fingerprint::fnv1a64(id.as_bytes()) | 1)
+}
+
+/// Whether `thread` is still the AcpThread it was registered as.
+pub(crate) fn is_alive(
+    mem: &impl SafeMem,
+    thread: RegisteredThread,
+    layout: &ThreadLayout,
+) -> bool {
+    thread_signature(mem, thread.ptr, layout) == Some(thread.signature)
+}
+
+// Tracks the threads the last sweep scanned, to notice the ones that are
+// gone. Only accessed from the stale_scanner thread via thread_local.
 thread_local! {
-    static SCANNER_ATTEMPTED: std::cell::RefCell<HashSet<(u64, u64)>> =
+    static SCANNER_SEEN: std::cell::RefCell<HashSet<RegisteredThread>> =
         std::cell::RefCell::new(HashSet::new());
 }
 
 /// Counter for approvals made by the stale scanner.
 pub static SCANNER_APPROVAL_COUNT: AtomicU64 = AtomicU64::new(0);
 
-/// Register an AcpThread pointer for periodic scanning.
+/// Counter for registered AcpThreads found freed by the stale scanner.
+pub static THREAD_EVICTION_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Register an AcpThread pointer for periodic scanning, and wake the scanner:
+/// the thread is inserting a tool call that may need confirming.
 /// Lock-free — safe to call from Frida interceptor context.
 pub fn register_thread(self_ptr: u64) {
     if self_ptr == 0 {
         return;
     }
+    super::stale_scanner::wake();
+    let Some(signature) = thread_signature(&LiveMemory, self_ptr, &layouts::primary().thread)
+    else {
+        return;
+    };
 
-    // Check if already registered
-    let count = THREAD_COUNT.load(Ordering::Relaxed) as usize;
-    for i in 0..count.min(MAX_THREADS) {
-        if THREAD_PTRS[i].load(Ordering::Relaxed) == self_ptr {
-            return; // Already registered
+    match REGISTRY.register(self_ptr, signature) {
+        Registration::New { slot } => {
+            tracing::info!("entry_scanner: registered AcpThread {self_ptr:#x} (slot={slot})");
+        }
+        Registration::Renewed { slot } => tracing::info!(
+            "entry_scanner: AcpThread {self_ptr:#x} has a new session id — registered as a new thread (slot={slot})"
+        ),
+        Registration::Known => {}
+        Registration::Full => {
+            // Logged at the 1st, 2nd, 4th, 8th, ... dropped registration.
+            let overflows = REGISTRY.overflows();
+            if overflows.is_power_of_two() {
+                tracing::warn!(
+                    "entry_scanner: thread registry full ({} threads) — AcpThread {self_ptr:#x} \
+                     is not scanned ({overflows} registrations dropped)",
+                    REGISTRY.len()
+                );
+            }
         }
     }
+}
 
-    // Try to add
-    let idx = THREAD_COUNT.fetch_add(1, Ordering::Relaxed) as usize;
-    if idx < MAX_THREADS {
-        THREAD_PTRS[idx].store(self_ptr, Ordering::Relaxed);
-        tracing::info!("entry_scanner: registered AcpThread {self_ptr:#x} (slot={idx})");
+/// Forget an AcpThread that is being dropped.
+/// Lock-free — safe to call from Frida interceptor context.
+pub fn unregister_thread(self_ptr: u64) {
+    if self_ptr != 0 && REGISTRY.unregister(self_ptr) {
+        tracing::info!("entry_scanner: unregistered dropped AcpThread {self_ptr:#x}");
     }
-    // If full, silently ignore — 64 threads is far more than any real scenario
 }
 
-/// Get all known AcpThread pointers. Only call from scanner thread.
-pub fn known_threads() -> Vec<u64> {
-    let count = THREAD_COUNT.load(Ordering::Relaxed) as usize;
-    let mut result = Vec::with_capacity(count.min(MAX_THREADS));
-    for i in 0..count.min(MAX_THREADS) {
-        let ptr = THREAD_PTRS[i].load(Ordering::Relaxed);
-        if ptr != 0 {
-            result.push(ptr);
-        }
-    }
-    result
+/// Number of registered AcpThreads.
+pub fn registered_threads() -> usize {
+    REGISTRY.len()
 }
 
-/// Read entries ptr and len from an AcpThread pointer.
+/// Registry slots allocated so far.
+pub fn thread_slots() -> usize {
+    REGISTRY.capacity()
+}
+
+/// Registrations dropped because the registry was full.
+pub fn thread_overflows() -> u64 {
+    REGISTRY.overflows()
+}
+
+/// The registered AcpThreads that are still alive. Only call from scanner
+/// thread.
 ///
-/// # Safety
-/// `self_ptr` must be a valid AcpThread pointer.
-pub unsafe fn read_entries(self_ptr: u64) -> (u64, u64) {
-    let p = self_ptr as *const u64;
-    let ptr = unsafe { *p.byte_add(ENTRIES_PTR_OFFSET) };
-    let len = unsafe { *p.byte_add(ENTRIES_LEN_OFFSET) };
-    (ptr, len)
+/// Evicts the ones whose session id changed, and forgets the claimed
+/// entries of every thread that left the registry since the last call
+/// (`thread_context` drops its own), so an AcpThread allocated at the same
+/// address starts clean.
+pub fn live_threads() -> Vec<u64> {
+    let mem = &LiveMemory;
+    let layout = &layouts::primary().thread;
+    let mut live = Vec::new();
+    for thread in REGISTRY.threads() {
+        if is_alive(mem, thread, layout) {
+            live.push(thread);
+        } else if REGISTRY.evict(thread) {
+            THREAD_EVICTION_COUNT.fetch_add(1, Ordering::Relaxed);
+            tracing::info!(
+                "entry_scanner: evicted AcpThread {:#x} (freed: session id changed or unmapped)",
+                thread.ptr
+            );
+        }
+    }
+
+    SCANNER_SEEN.with(|seen| {
+        let mut seen = seen.borrow_mut();
+        let gone: Vec<RegisteredThread> = seen
+            .iter()
+            .filter(|thread| !live.contains(thread))
+            .copied()
+            .collect();
+        for thread in gone {
+            seen.remove(&thread);
+            attempts::forget(thread.ptr);
+        }
+        seen.extend(live.iter().copied());
+    });
+
+    live.into_iter().map(|thread| thread.ptr).collect()
 }
 
 /// Scan all entries of an AcpThread for WaitingForConfirmation entries and auto-approve them.
-/// ONLY call from the stale_scanner thread (accesses SCANNER_ATTEMPTED without lock).
+/// ONLY call from the stale_scanner thread.
 ///
-/// Returns the number of entries approved.
+/// Returns the number of entries approved. Entries already claimed in
+/// `attempts` (by the hook or an earlier sweep) are skipped. Does nothing
+/// once the budget is exhausted or the circuit breaker is open, so waiting
+/// entries are not claimed and a new budget (or a reset breaker) picks them
+/// up.
+///
+/// Reads go through `LiveMemory`, so a freed AcpThread just yields nothing.
 ///
 /// # Safety
-/// `self_ptr` must be a valid AcpThread pointer with a live entries Vec.
+/// A waiting entry found at `self_ptr` must hold a live oneshot sender (see
+/// `tool_authorization::resolve_entry`).
 pub unsafe fn scan_and_approve_from_scanner(self_ptr: u64) -> u64 {
-    let (entries_ptr, entries_len) = unsafe { read_entries(self_ptr) };
+    if budget::exhausted(&config::current().budget) || circuit_breaker::tripped().is_some() {
+        return 0;
+    }
+
+    let layout = layouts::primary();
+    let mem = &LiveMemory;
+    let (entries_ptr, entries_len) = tool_authorization::read_entries(mem, self_ptr, layout);
 
     if entries_ptr == 0 || entries_len == 0 {
         return 0;
     }
 
-    let layout = &tool_authorization::ENTRY_LAYOUTS[0]; // v0.230.x
     let mut approved = 0;
 
-    SCANNER_ATTEMPTED.with(|attempted| {
-        let mut attempted = attempted.borrow_mut();
+    for i in 0..entries_len {
+        let entry = entries_ptr + (i * layout.entry_size as u64);
+        let Some(discriminant) = mem.read_u64(entry) else {
+            // Stale Vec: the rest of the buffer is unreadable too.
+            break;
+        };
 
-        for i in 0..entries_len {
-            // Skip already-attempted entries
-            if attempted.contains(&(self_ptr, i)) {
+        if let MatchStyle::Preview230 {
+            toolcall_variant,
+            waiting_payload_niche_start,
+            ..
+        } = layout.match_style
+        {
+            if discriminant != toolcall_variant {
                 continue;
             }
 
-            let entry = entries_ptr + (i * layout.entry_size as u64);
-            let discriminant = unsafe { *(entry as *const u64).byte_add(0) };
-
-            if let tool_authorization::MatchStyle::Preview230 {
-                toolcall_variant,
-                waiting_payload_niche_start,
-                ..
-            } = layout.match_style
-            {
-                if discriminant != toolcall_variant {
-                    continue;
-                }
-
-                let status_head = unsafe { *(entry as *const u64).byte_add(layout.status_offset) };
+            let Some(status_head) = mem.read_u64(entry + layout.status_offset as u64) else {
+                continue;
+            };
 
-                // Check if WaitingForConfirmation (niche-encoded)
-                if status_head >= waiting_payload_niche_start {
-                    continue; // Not waiting
-                }
+            // Check if WaitingForConfirmation (niche-encoded)
+            if status_head >= waiting_payload_niche_start {
+                continue; // Not waiting
+            }
 
-                let tx = unsafe { *(entry as *const u64).byte_add(layout.respond_tx_offset) };
-                if !unsafe { tool_authorization::looks_like_sender_arc_pub(tx) } {
-                    continue; // Invalid sender
-                }
+            let Some(tx) = mem.read_u64(entry + layout.respond_tx_offset as u64) else {
+                continue;
+            };
+            if !tool_authorization::looks_like_sender_arc_pub(mem, tx) {
+                continue; // Invalid sender
+            }
 
-                // Check plan mode
-                let is_plan = unsafe { tool_authorization::detect_plan_mode_pub(entry, layout) };
-                let count_val = SCANNER_APPROVAL_COUNT.load(Ordering::Relaxed) + approved + 1;
+            // Already handled: by the hook, or by an earlier sweep (a policy
+            // "ask" is decided once rather than re-evaluated every sweep)
+            let tool_call_id = tool_authorization::entry_tool_call_id(mem, entry, layout);
+            if attempts::attempted(self_ptr, &EntryId::new(tool_call_id.as_deref(), entry)) {
+                continue;
+            }
 
-                let session_tag = format!("{:04x}", self_ptr & 0xFFFF);
-                tracing::info!(
-                    "stale_scanner [s:{session_tag}]: found WaitingForConfirmation at entry[{i}], approving..."
-                );
+            let started = Instant::now();
+            let session_tag = format!("{:04x}", self_ptr & 0xFFFF);
+            let snap = tool_authorization::snapshot(mem, entry, layout);
 
-                let ok = unsafe { tool_authorization::send_allow_pub(layout.send_style, tx, is_plan, count_val) };
+            // Check plan mode
+            let is_plan = tool_authorization::detect_plan_mode_pub(mem, entry, layout);
+            let count_val = SCANNER_APPROVAL_COUNT.load(Ordering::Relaxed) + approved + 1;
 
-                if ok {
-                    // Force status to InProgress so the UI dismisses the dialog
-                    unsafe { tool_authorization::force_status_in_progress(entry, layout) };
-                }
+            tracing::info!(
+                "stale_scanner [s:{session_tag}]: found WaitingForConfirmation at entry[{i}], resolving..."
+            );
 
-                // Mark as attempted (avoid infinite retry)
-                attempted.insert((self_ptr, i));
+            let origin = tool_authorization::Origin {
+                path: ApprovalPath::StaleScanner,
+                thread: self_ptr,
+                log_prefix: format!("stale_scanner [s:{session_tag}] entry[{i}]"),
+                session_tag: session_tag.clone(),
+                started,
+            };
+            let resolution = unsafe {
+                tool_authorization::resolve_entry(
+                    layout, entry, tx, is_plan, &snap, count_val, &origin,
+                )
+            };
 
-                if ok {
+            match resolution {
+                tool_authorization::Resolution::Approved => {
                     approved += 1;
                     tracing::info!(
-                        "stale_scanner [s:{session_tag}]: approved entry[{i}] (plan_mode={is_plan})"
+                        "stale_scanner [s:{session_tag}]: approved entry[{i}] (plan_mode={is_plan}) {}",
+                        snap.summary()
                     );
-                } else {
+                }
+                tool_authorization::Resolution::Rejected => {
+                    tracing::info!(
+                        "stale_scanner [s:{session_tag}]: rejected entry[{i}] {}",
+                        snap.summary()
+                    );
+                }
+                tool_authorization::Resolution::LeftForUser => {}
+                tool_authorization::Resolution::Duplicate => {
+                    tracing::debug!(
+                        "stale_scanner [s:{session_tag}]: entry[{i}] already handled by the hook"
+                    );
+                }
+                tool_authorization::Resolution::Deferred => {
+                    tracing::debug!(
+                        "stale_scanner [s:{session_tag}]: entry[{i}] deferred (thread context not built)"
+                    );
+                }
+                tool_authorization::Resolution::SendFailed => {
                     tracing::debug!(
                         "stale_scanner [s:{session_tag}]: entry[{i}] send failed (already consumed)"
                     );
                 }
             }
         }
-    });
+    }
 
     approved
 }
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::SliceMemory;
+
+    use crate::hooks::tool_call_snapshot::StrField;
+    use crate::layouts::ChainedStr;
+
+    const THREAD: u64 = 0x1_1000_0000;
+    const SESSION_ID: u64 = 0x1_2000_0000;
+
+    /// `AcpThread.session_id` (an `Arc<str>`) at +0x20.
+    const LAYOUT: ThreadLayout = ThreadLayout {
+        session_id: Some(ChainedStr {
+            deref: &[],
+            field: StrField {
+                ptr_offset: 0x20,
+                len_offset: 0x28,
+                data_offset: 0x10,
+                none: None,
+            },
+        }),
+        ..ThreadLayout::UNKNOWN
+    };
+
+    /// Map an AcpThread-sized block whose header is `header` and whose
+    /// session id is `id`.
+    fn allocate(mem: &mut SliceMemory, header: [u64; 2], id: &str) -> RegisteredThread {
+        let mut words = vec![0u64; 0x40];
+        words[..2].copy_from_slice(&header);
+        words[4] = SESSION_ID;
+        words[5] = id.len() as u64;
+        mem.remove(THREAD);
+        mem.add_words(THREAD, &words);
+        let mut arc = [1u64, 1].map(u64::to_le_bytes).concat();
+        arc.extend_from_slice(id.as_bytes());
+        mem.remove(SESSION_ID);
+        mem.add(SESSION_ID, arc);
+        RegisteredThread {
+            ptr: THREAD,
+            signature: thread_signature(mem, THREAD, &LAYOUT).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_thread_signature() {
+        let mut mem = SliceMemory::default();
+        assert_eq!(thread_signature(&mem, THREAD, &LAYOUT), None);
+        let thread = allocate(&mut mem, [0, 0], "1");
+        assert_ne!(thread.signature, 0);
+        let other = allocate(&mut SliceMemory::default(), [0, 0], "2");
+        assert_ne!(thread.signature, other.signature);
+
+        // Without `session_id`, every readable thread looks the same.
+        assert_eq!(
+            thread_signature(&mem, THREAD, &ThreadLayout::UNKNOWN),
+            Some(NO_SESSION_ID)
+        );
+        assert_eq!(
+            thread_signature(&SliceMemory::default(), THREAD, &ThreadLayout::UNKNOWN),
+            None
+        );
+    }
+
+    #[test]
+    fn test_live_thread_header_changes() {
+        let mut mem = SliceMemory::default();
+        let thread = allocate(&mut mem, [0x1_2345_6780, 3], "a");
+        // Fields at the start of a live AcpThread change (entries growing,
+        // a new title): it is still the same thread.
+        mem.write_u64(THREAD, 0x1_5555_0000);
+        mem.write_u64(THREAD + 8, 9);
+        assert!(is_alive(&mem, thread, &LAYOUT));
+        let unknown = RegisteredThread {
+            ptr: THREAD,
+            signature: NO_SESSION_ID,
+        };
+        assert!(is_alive(&mem, unknown, &ThreadLayout::UNKNOWN));
+    }
+
+    #[test]
+    fn test_allocate_free_reallocate() {
+        let registry = ThreadRegistry::new();
+        let mut mem = SliceMemory::default();
+
+        let first = allocate(&mut mem, [0x1_2345_6780, 3], "first");
+        assert_eq!(
+            registry.register(first.ptr, first.signature),
+            Registration::New { slot: 0 }
+        );
+        assert_eq!(
+            registry.register(first.ptr, first.signature),
+            Registration::Known
+        );
+        assert!(is_alive(&mem, first, &LAYOUT));
+
+        // Freed: the session id's Arc is released and malloc links its block
+        // into a free list over the counts.
+        mem.write_u64(SESSION_ID, 0x1_5555_0000);
+        assert!(!is_alive(&mem, first, &LAYOUT));
+        assert!(registry.evict(first));
+        assert!(registry.threads().is_empty());
+
+        // Reallocated as a new AcpThread: registered afresh in the free slot.
+        let second = allocate(&mut mem, [0x1_2345_9990, 5], "second");
+        assert!(!is_alive(&mem, first, &LAYOUT));
+        assert_eq!(
+            registry.register(second.ptr, second.signature),
+            Registration::New { slot: 0 }
+        );
+        assert_eq!(registry.threads(), vec![second]);
+
+        // Freed and reallocated between two sweeps: the new thread's hooks
+        // register it before the scanner notices, and the stale entry can no
+        // longer be evicted over it.
+        let third = allocate(&mut mem, [0x1_2345_9990, 5], "third");
+        assert_eq!(
+            registry.register(third.ptr, third.signature),
+            Registration::Renewed { slot: 0 }
+        );
+        assert!(!registry.evict(second));
+        assert_eq!(registry.threads(), vec![third]);
+
+        // Unmapped entirely.
+        mem.remove(THREAD);
+        assert!(!is_alive(&mem, third, &LAYOUT));
+        assert!(registry.evict(third));
+        assert_eq!(registry.len(), 0);
+    }
+}
@@ -2,22 +2,58 @@
 //!
 //! Modules:
 //!   - `permission_decision`   — hooks `ToolPermissionDecision::from_input` (native tools)
+//!   - `native_tool`           — policy facts from `from_input`'s arguments
 //!   - `tool_authorization`    — hooks `request_tool_call_authorization` (ACP agents, primary)
 //!   - `upsert_hook`           — hooks `upsert_tool_call_inner` (approach 1: catch all insertions)
 //!   - `session_update_hook`   — hooks `handle_session_update` (approach 2: catch session restore)
 //!   - `push_entry_hook`       — hooks `push_entry` (catch-all: every entry insertion path)
+//!   - `drop_hook`             — hooks `drop_in_place::<AcpThread>` (unregisters freed threads)
 //!   - `stale_scanner`         — periodic background scan (approach 3: catch stale entries)
 //!   - `entry_scanner`         — shared scanning logic used by approaches 1-3
+//!   - `thread_registry`       — lock-free set of known AcpThread pointers
+//!   - `attempts`              — waiting entries already claimed (hook / stale scanner dedup)
+//!   - `tool_call_snapshot`    — decodes title/kind/locations/raw_input of a pending ToolCall
+//!   - `permission_options`    — picks the PermissionOption to send from the offered list
 
+pub mod attempts;
+pub mod drop_hook;
 pub mod entry_scanner;
+pub mod native_tool;
 pub mod permission_decision;
+pub mod permission_options;
 pub mod push_entry_hook;
 pub mod session_update_hook;
 pub mod stale_scanner;
+pub mod thread_registry;
 pub mod tool_authorization;
+pub mod tool_call_snapshot;
 pub mod upsert_hook;
 
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::config::{self, YoloMode};
+
+/// Observe-only: hooks run and log, but nothing is approved or rejected.
+/// Set at init for calibration mode and when the Zed build is unknown and
+/// `unknown_version` is `observe`. See `observe_only` for `mode: observe`.
+pub static OBSERVE_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Whether hooks should only log: forced at init (`OBSERVE_ONLY`) or the
+/// current config's `mode` is `observe` (which can change on reload).
+pub fn observe_only() -> bool {
+    OBSERVE_ONLY.load(Ordering::Relaxed) || config::current().mode == YoloMode::Observe
+}
+
+/// Paused through the control socket (`control.rs`): like `disabled`, but
+/// without touching the config.
+pub static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Whether listeners should do nothing: paused, or the current config has
+/// the hooks switched off. Hooks stay installed after a reload to `disabled`;
+/// their listeners return early.
+pub fn inactive() -> bool {
+    PAUSED.load(Ordering::Relaxed) || config::current().mode == YoloMode::Disabled
+}
 
 /// Counter for permission_decision hook invocations (PATH 1).
 pub static PERMISSION_DECISION_COUNT: AtomicU64 = AtomicU64::new(0);
@@ -28,5 +64,12 @@ pub static TOOL_AUTHORIZATION_COUNT: AtomicU64 = AtomicU64::new(0);
 /// Counter for tool_authorization misses (no WaitingForConfirmation found).
 pub static TOOL_AUTHORIZATION_MISS_COUNT: AtomicU64 = AtomicU64::new(0);
 
-/// Counter for misses recovered by retry.
+/// Counter for tool calls rejected by the approval policy (all paths).
+pub static POLICY_REJECT_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Counter for tool_authorization calls left for the user (policy `ask`,
+/// observe, budget, circuit breaker, no acceptable option).
+pub static LEFT_FOR_USER_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Counter for misses recovered by retry: approvals sent on the retry.
 pub static TOOL_AUTHORIZATION_RETRY_SUCCESS_COUNT: AtomicU64 = AtomicU64::new(0);
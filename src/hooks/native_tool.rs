@@ -0,0 +1,153 @@
+//! Arguments of `ToolPermissionDecision::from_input`, as policy facts.
+//!
+//! Zed's built-in agent asks `from_input(tool_name, input, ...)` whether a
+//! tool call needs confirmation. `permission_decision` reads the two string
+//! arguments in `on_enter`; this module turns them into [`ToolCallFacts`] so
+//! native tools go through the same policy as ACP tool calls.
+//!
+//! `input` is the tool's primary argument as Zed's own permission rules see
+//! it: the command line for `terminal`, the path for file tools, the URL for
+//! `fetch`. It becomes the `title` fact (and `command` for `terminal`), the
+//! tool name maps to an ACP kind, and the agent is always `zed`.
+
+use crate::policy::ToolCallFacts;
+
+/// Agent name for Zed's built-in agent (an `agents` key and `agent` fact).
+pub(crate) const NATIVE_AGENT: &str = "zed";
+
+/// Built-in tool names → ACP tool kind.
+const TOOL_KINDS: &[(&str, &str)] = &[
+    ("terminal", "execute"),
+    ("edit_file", "edit"),
+    ("streaming_edit_file", "edit"),
+    ("save_file", "edit"),
+    ("restore_file_from_disk", "edit"),
+    ("create_directory", "edit"),
+    ("copy_path", "edit"),
+    ("read_file", "read"),
+    ("list_directory", "read"),
+    ("diagnostics", "read"),
+    ("delete_path", "delete"),
+    ("move_path", "move"),
+    ("find_path", "search"),
+    ("grep", "search"),
+    ("fetch", "fetch"),
+    ("web_search", "fetch"),
+    ("thinking", "think"),
+];
+
+/// A native tool call as seen by `from_input`. `None` = not readable.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct NativeToolCall {
+    pub(crate) tool: Option<String>,
+    pub(crate) input: Option<String>,
+}
+
+impl NativeToolCall {
+    /// ACP kind for the tool; unknown tools are `other`.
+    pub(crate) fn kind(&self) -> Option<&'static str> {
+        let tool = self.tool.as_deref()?;
+        Some(
+            TOOL_KINDS
+                .iter()
+                .find(|(name, _)| *name == tool)
+                .map_or("other", |(_, kind)| kind),
+        )
+    }
+
+    /// Facts for the approval policy.
+    pub(crate) fn facts(&self) -> ToolCallFacts {
+        let is_terminal = self.tool.as_deref() == Some("terminal");
+        ToolCallFacts {
+            kind: self.kind().map(str::to_string),
+            title: self.input.clone(),
+            command: self.input.clone().filter(|_| is_terminal),
+            agent: Some(NATIVE_AGENT.to_string()),
+            workspace: None,
+        }
+    }
+
+    /// One-line summary for log lines.
+    pub(crate) fn summary(&self) -> String {
+        format!(
+            "tool={} input={:?}",
+            self.tool.as_deref().unwrap_or("<undecoded>"),
+            self.input.as_deref().unwrap_or("<undecoded>")
+        )
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Decision, Policy, Rule, RuleAction};
+
+    fn call(tool: &str, input: &str) -> NativeToolCall {
+        NativeToolCall {
+            tool: Some(tool.to_string()),
+            input: Some(input.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_facts() {
+        let terminal = call("terminal", "cargo test").facts();
+        assert_eq!(terminal.kind.as_deref(), Some("execute"));
+        assert_eq!(terminal.command.as_deref(), Some("cargo test"));
+        assert_eq!(terminal.agent.as_deref(), Some(NATIVE_AGENT));
+
+        let edit = call("edit_file", "src/main.rs").facts();
+        assert_eq!(edit.kind.as_deref(), Some("edit"));
+        assert_eq!(edit.title.as_deref(), Some("src/main.rs"));
+        assert_eq!(edit.command, None);
+
+        assert_eq!(call("mcp_thing", "x").kind(), Some("other"));
+        assert_eq!(NativeToolCall::default().kind(), None);
+    }
+
+    #[test]
+    fn test_policy_on_native_call() {
+        let policy = Policy {
+            default: Decision::Allow,
+            denylist: vec!["rm -rf".to_string()],
+            rules: vec![Rule {
+                action: RuleAction::Ask,
+                kind: Some("delete".to_string()),
+                ..Rule::default()
+            }],
+        };
+        assert_eq!(
+            policy.evaluate(&call("terminal", "rm -rf target").facts()),
+            Decision::Deny
+        );
+        assert_eq!(
+            policy.evaluate(&call("delete_path", "src").facts()),
+            Decision::Ask
+        );
+        assert_eq!(
+            policy.evaluate(&call("read_file", "Cargo.toml").facts()),
+            Decision::Allow
+        );
+        // Nothing readable: the denylist can't be checked, so ask.
+        assert_eq!(
+            policy.evaluate(&NativeToolCall::default().facts()),
+            Decision::Ask
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(
+            call("terminal", "ls").summary(),
+            r#"tool=terminal input="ls""#
+        );
+        assert_eq!(
+            NativeToolCall::default().summary(),
+            r#"tool=<undecoded> input="<undecoded>""#
+        );
+    }
+}
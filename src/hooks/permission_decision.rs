@@ -1,41 +1,363 @@
 //! Hook for `ToolPermissionDecision::from_input` (built-in tools).
 //!
-//! Intercepts the return value of `from_input` and forces it to `Allow`,
-//! bypassing the confirmation dialog for built-in tool calls.
+//! Reads the tool name and input text in `on_enter`, runs them through the
+//! approval policy (`native_tool`), and in `on_leave` turns a `Confirm`
+//! return value into `Allow` only when the policy allows. Zed's own `Allow`
+//! and `Deny` are never touched, whatever the policy says.
 //!
-//! ARM64 ABI: the return struct is written via x8 (indirect return pointer).
-//! We zero the first 32 bytes at x8 to produce the `Allow` variant (discriminant 0).
+//! ARM64 ABI: `tool_name: &str` and `input: &str` arrive as (ptr, len) pairs
+//! in x0/x1 and x2/x3. `ToolPermissionDecision` is larger than 16 bytes, so
+//! it is returned through x8 (indirect return pointer), which `on_enter`
+//! saves since the callee needn't preserve it. Both facts are checked at
+//! compile time against a mirror of the enum, and [`check_layout`] derives
+//! how `Allow` and `Confirm` are encoded before the hook is installed;
+//! without a usable encoding the hook isn't installed.
+//! Arguments and the return value are read and written through
+//! `mem::SafeMem`; arguments that fail validation are treated as unknown
+//! facts, which the policy never allows past a deny/ask rule.
+//!
+//! Modes: `allow_all` applies the policy (its `default` included);
+//! `allow_safe` only rewrites when a policy *rule* explicitly allows the call,
+//! so native tools otherwise follow Zed's settings; `observe` logs. The
+//! `agents.zed` entry applies to these calls.
+//!
+//! Every forced `Allow` uses up one unit of the `budget` and counts towards
+//! the circuit breaker (globally, there is no session tag); once either
+//! stops approvals Zed's own decision stands.
 
+use std::cell::RefCell;
+use std::sync::OnceLock;
 use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use super::native_tool::NativeToolCall;
+use super::{PERMISSION_DECISION_COUNT, observe_only};
+use crate::audit::{self, Action, ApprovalPath};
+use crate::budget;
+use crate::circuit_breaker;
+use crate::config::{self, YoloMode};
+use crate::mem::{LiveMemory, SafeMem};
+use crate::policy::{Decision, Verdict, VerdictSource};
+
+/// Refuse tool names / inputs longer than this.
+const MAX_ARG_LEN: u64 = 64 * 1024;
+
+/// Mirror of Zed's `agent::tool_permissions::ToolPermissionDecision`.
+#[allow(dead_code)]
+enum ToolPermissionDecision {
+    Allow,
+    Deny(String),
+    Confirm,
+}
+
+// The arguments are two (ptr, len) register pairs and the return value
+// doesn't fit in x0/x1, so it goes through x8.
+const _: () = assert!(std::mem::size_of::<&str>() == 16);
+const _: () = assert!(std::mem::size_of::<ToolPermissionDecision>() > 16);
+const DECISION_WORDS: usize = std::mem::size_of::<ToolPermissionDecision>() / 8;
+
+/// Where the variant of a returned `ToolPermissionDecision` is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Encoding {
+    /// Byte offset of the word that tells the variants apart.
+    offset: u64,
+    allow: u64,
+    confirm: u64,
+}
+
+static ENCODING: OnceLock<Encoding> = OnceLock::new();
+
+/// The words of `value`, written over zeroes. `value` is never dropped.
+fn words(value: ToolPermissionDecision) -> [u64; DECISION_WORDS] {
+    let mut words = [0u64; DECISION_WORDS];
+    // SAFETY: `words` is exactly as large as the enum and u64-aligned.
+    unsafe { std::ptr::write(words.as_mut_ptr().cast::<ToolPermissionDecision>(), value) };
+    words
+}
 
-use super::PERMISSION_DECISION_COUNT;
+/// The first word in which `Allow` and `Confirm` differ, if no `Deny` can
+/// have either value there.
+fn derive_encoding(allow: &[u64], confirm: &[u64], deny: &[&[u64]]) -> Result<Encoding, String> {
+    let index = (0..allow.len())
+        .find(|&i| allow[i] != confirm[i])
+        .ok_or("Allow and Confirm are encoded alike")?;
+    if let Some(clash) = deny
+        .iter()
+        .find(|deny| deny[index] == allow[index] || deny[index] == confirm[index])
+    {
+        return Err(format!(
+            "Deny has {:#x} in word {index}, like Allow or Confirm",
+            clash[index]
+        ));
+    }
+    Ok(Encoding {
+        offset: (index * 8) as u64,
+        allow: allow[index],
+        confirm: confirm[index],
+    })
+}
+
+/// Work out how the mirrored enum encodes its variants. Call before
+/// installing the hook; on `Err` it must not be installed.
+pub fn check_layout() -> Result<(), String> {
+    let allow = words(ToolPermissionDecision::Allow);
+    let confirm = words(ToolPermissionDecision::Confirm);
+    let denies = [
+        words(ToolPermissionDecision::Deny(String::new())),
+        words(ToolPermissionDecision::Deny(
+            "denied by settings".to_string(),
+        )),
+    ];
+    let encoding = derive_encoding(&allow, &confirm, &[&denies[0], &denies[1]])?;
+    tracing::info!(
+        "permission_decision: variant word at +{:#x}: Allow={:#x}, Confirm={:#x}",
+        encoding.offset,
+        encoding.allow,
+        encoding.confirm
+    );
+    ENCODING.get_or_init(|| encoding);
+    Ok(())
+}
+
+/// What `from_input` wrote to `x8`: `Some(true)` for `Confirm`, `Some(false)`
+/// for `Allow` or `Deny`, `None` when it can't be read.
+fn returned_confirm(mem: &impl SafeMem, encoding: &Encoding, x8: u64) -> Option<bool> {
+    mem.read_u64(x8.checked_add(encoding.offset)?)
+        .map(|word| word == encoding.confirm)
+}
+
+/// What `on_enter` saw: the call and `x8`, the slot `from_input` returns
+/// through. x8 isn't preserved across the call, so it can't be read in
+/// `on_leave`.
+struct Entered {
+    call: NativeToolCall,
+    x8: u64,
+}
+
+thread_local! {
+    static ENTERED: RefCell<Option<Entered>> = const { RefCell::new(None) };
+}
+
+/// Read a `&str` argument passed as (ptr, len) registers.
+fn read_str_arg(mem: &impl SafeMem, ptr: u64, len: u64) -> Option<String> {
+    if len > MAX_ARG_LEN {
+        return None;
+    }
+    String::from_utf8(mem.read_bytes(ptr, len as usize)?).ok()
+}
 
 pub struct Listener;
 
 impl frida_gum::interceptor::InvocationListener for Listener {
-    fn on_enter(&mut self, _context: frida_gum::interceptor::InvocationContext) {}
-
-    fn on_leave(&mut self, context: frida_gum::interceptor::InvocationContext) {
-        let count = PERMISSION_DECISION_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+    fn on_enter(&mut self, context: frida_gum::interceptor::InvocationContext) {
+        if super::inactive() {
+            return;
+        }
         let cpu = context.cpu_context();
+        let call = NativeToolCall {
+            tool: read_str_arg(&LiveMemory, cpu.reg(0), cpu.reg(1)),
+            input: read_str_arg(&LiveMemory, cpu.reg(2), cpu.reg(3)),
+        };
         let x8 = cpu.reg(8);
+        ENTERED.with(|entered| *entered.borrow_mut() = Some(Entered { call, x8 }));
+    }
+
+    fn on_leave(&mut self, _context: frida_gum::interceptor::InvocationContext) {
+        let entered = ENTERED.with(|entered| entered.borrow_mut().take());
+        // Paused or `disabled`: Zed's own decision stands.
+        if super::inactive() {
+            return;
+        }
+        // Inactive when the call started: it was never inspected.
+        let Some(Entered { call, x8 }) = entered else {
+            return;
+        };
+        let t0 = Instant::now();
+        let count = PERMISSION_DECISION_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+        let Some(encoding) = ENCODING.get() else {
+            return;
+        };
+        let confirm = returned_confirm(&LiveMemory, encoding, x8);
+
+        let config = config::native();
+        let verdict = config.policy.verdict(&call.facts());
+        let rewrite = match config.mode {
+            YoloMode::Disabled => false,
+            YoloMode::AllowSafe => {
+                verdict.decision == Decision::Allow
+                    && matches!(verdict.source, VerdictSource::Rule(_))
+            }
+            YoloMode::AllowAll | YoloMode::Observe => verdict.decision == Decision::Allow,
+        };
+
+        if observe_only() || config.mode == YoloMode::Observe {
+            // Leave the return value alone; report what Zed decided.
+            let returned = match confirm {
+                Some(true) => "Confirm",
+                Some(false) => "Allow or Deny",
+                None => "an unreadable value",
+            };
+            tracing::info!(
+                "permission_decision #{}: observe-only — from_input returned {} (x8={:#x}), policy decision={:?} via {} — would {} ({})",
+                count,
+                returned,
+                x8,
+                verdict.decision,
+                verdict.source,
+                if rewrite && confirm == Some(true) {
+                    "force Allow"
+                } else {
+                    "leave it"
+                },
+                call.summary()
+            );
+            record(&call, Action::Observed, &verdict, t0);
+            return;
+        }
 
-        if x8 != 0 && (x8 >> 32) < 2 {
-            unsafe {
-                std::ptr::write_bytes(x8 as *mut u8, 0, 32);
+        if confirm != Some(true) {
+            // Zed decided on its own (or the return slot is unreadable):
+            // nothing to approve, and a `Deny` from the user's settings
+            // stands.
+            tracing::debug!(
+                "permission_decision #{}: from_input didn't return Confirm ({:?}) — leaving it ({})",
+                count,
+                confirm,
+                call.summary()
+            );
+            return;
+        }
+
+        if !rewrite {
+            // `allow_safe` without an allow rule is the common, quiet case.
+            if verdict.decision == Decision::Allow {
+                tracing::debug!(
+                    "permission_decision #{}: no allow rule (mode={:?}) — leaving Zed's decision ({})",
+                    count,
+                    config.mode,
+                    call.summary()
+                );
+            } else {
+                tracing::info!(
+                    "permission_decision #{}: policy decision={:?} via {} — leaving Zed's decision ({})",
+                    count,
+                    verdict.decision,
+                    verdict.source,
+                    call.summary()
+                );
             }
+            record(&call, Action::LeftForUser, &verdict, t0);
+            return;
+        }
+
+        let budget = &config.budget;
+        let left = match budget::take(budget) {
+            Ok(left) => left,
+            Err(_) => {
+                tracing::info!(
+                    "permission_decision #{}: budget exhausted — leaving Zed's decision ({})",
+                    count,
+                    call.summary()
+                );
+                record(&call, Action::LeftForUser, &verdict, t0);
+                return;
+            }
+        };
+        if let Err(trip) = circuit_breaker::take(&config.circuit_breaker, None) {
+            budget::refund();
             tracing::info!(
-                "permission_decision #{}: from_input → Allow (x8={:#x})",
+                "permission_decision #{}: circuit breaker open ({}) — leaving Zed's decision ({})",
                 count,
-                x8
+                trip,
+                call.summary()
             );
-        } else {
-            context.set_return_value(0);
-            tracing::info!("permission_decision #{}: from_input → Allow (x0)", count);
+            record(&call, Action::LeftForUser, &verdict, t0);
+            return;
         }
+
+        // `Confirm` carries no data, so the variant word is all there is.
+        if !LiveMemory.write_u64(x8 + encoding.offset, encoding.allow) {
+            budget::refund();
+            circuit_breaker::refund(None);
+            tracing::warn!(
+                "permission_decision #{}: could not write Allow at x8={:#x} — leaving Zed's decision ({})",
+                count,
+                x8,
+                call.summary()
+            );
+            record(&call, Action::LeftForUser, &verdict, t0);
+            return;
+        }
+        tracing::info!(
+            "permission_decision #{}: from_input Confirm → Allow via {} (x8={:#x}) {}",
+            count,
+            verdict.source,
+            x8,
+            call.summary()
+        );
+        if budget.is_limited() {
+            tracing::info!("permission_decision #{}: budget {}", count, left);
+        }
+        record(&call, Action::Approved, &verdict, t0);
     }
 }
 
+/// Audit a `from_input` decision.
+fn record(call: &NativeToolCall, action: Action, verdict: &Verdict, t0: Instant) {
+    audit::record(&audit::Record {
+        title: call.input.clone(),
+        kind: call.kind(),
+        source: Some(verdict.source.to_string()),
+        latency_us: t0.elapsed().as_micros() as u64,
+        ..audit::Record::new(ApprovalPath::Native, action)
+    });
+}
+
 /// Symbol search patterns for locating `ToolPermissionDecision::from_input` in Zed's binary.
 pub const SYMBOL_INCLUDE: &[&str] = &["tool_permissions", "ToolPermissionDecision", "from_input"];
 pub const SYMBOL_EXCLUDE: &[&str] = &["drop_in_place", "closure", "check_commands"];
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::SliceMemory;
+
+    #[test]
+    fn test_encoding_tells_confirm_apart() {
+        check_layout().unwrap();
+        let encoding = *ENCODING.get().unwrap();
+        assert_ne!(encoding.allow, encoding.confirm);
+
+        let mut mem = SliceMemory::default();
+        let x8 = 0x1_0000_1000;
+        let returned = |mem: &SliceMemory| returned_confirm(mem, &encoding, x8);
+        assert_eq!(returned(&mem), None);
+        for (value, confirm) in [
+            (ToolPermissionDecision::Confirm, true),
+            (ToolPermissionDecision::Allow, false),
+            (ToolPermissionDecision::Deny("no".to_string()), false),
+        ] {
+            mem.remove(x8);
+            mem.add_words(x8, &words(value));
+            assert_eq!(returned(&mem), Some(confirm));
+        }
+    }
+
+    #[test]
+    fn test_ambiguous_encoding_is_rejected() {
+        assert!(derive_encoding(&[1, 2, 3], &[1, 2, 3], &[]).is_err());
+        assert!(derive_encoding(&[1, 2, 3], &[1, 5, 3], &[&[7, 5, 0]]).is_err());
+        assert_eq!(
+            derive_encoding(&[1, 2, 3], &[1, 5, 3], &[&[7, 8, 0]]),
+            Ok(Encoding {
+                offset: 8,
+                allow: 2,
+                confirm: 5
+            })
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Choosing which `PermissionOption` to send, from the options the dialog
+//! actually offers.
+//!
+//! Agents don't agree on option ids: Claude Code uses `allow` / `allow_always`
+//! / `reject`, Codex and Gemini use their own ids, and ExitPlanMode prompts use
+//! session-mode names. Every option carries an ACP `PermissionOptionKind`
+//! though, so for regular tool calls we pick by kind (preference order from
+//! config) and send whatever id the agent attached to that kind.
+//!
+//! ExitPlanMode prompts are the exception: their options are distinct modes
+//! that can share a kind, so they are matched by id (`plan_option`).
+//!
+//! Decoding the options from Zed's memory lives in `tool_authorization`; this
+//! module is the pure selection logic.
+
+use crate::config::OptionKind;
+
+/// Option ids that only appear on ExitPlanMode ("Ready to code?") prompts.
+const PLAN_OPTION_IDS: &[&str] = &["bypassPermissions", "acceptEdits", "default", "plan"];
+
+/// Which `PermissionOptions` variant the dialog uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OptionsShape {
+    /// `PermissionOptions::Flat(Vec<PermissionOption>)`
+    Flat,
+    /// `PermissionOptions::Dropdown(Vec<PermissionOptionChoice>)`
+    Dropdown,
+    /// `PermissionOptions::DropdownWithPatterns { choices, .. }`
+    DropdownWithPatterns,
+}
+
+/// One decoded `acp::PermissionOption`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PermissionOptionInfo {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) kind: OptionKind,
+}
+
+/// All options offered by a waiting entry, flattened (dropdown choices
+/// contribute both their allow and deny option, in order).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PermissionOptionList {
+    pub(crate) shape: OptionsShape,
+    pub(crate) options: Vec<PermissionOptionInfo>,
+}
+
+impl PermissionOptionList {
+    /// ExitPlanMode prompts offer session modes instead of allow/reject ids:
+    /// every id is a mode name, and staying in `plan` is one of them. A tool
+    /// prompt that happens to use one of those ids (`default`) is not one.
+    pub(crate) fn is_plan_prompt(&self) -> bool {
+        !self.options.is_empty()
+            && self
+                .options
+                .iter()
+                .all(|option| PLAN_OPTION_IDS.contains(&option.id.as_str()))
+            && self.find_id("plan").is_some()
+    }
+
+    /// The option with exactly this id.
+    pub(crate) fn find_id(&self, id: &str) -> Option<&PermissionOptionInfo> {
+        self.options.iter().find(|option| option.id == id)
+    }
+
+    /// The first option whose kind appears earliest in `preference`.
+    ///
+    /// In a `DropdownWithPatterns` dialog the "always" choices are scoped to
+    /// the command pattern the user picks, which is sent along as params. We
+    /// don't pick a pattern, so only the "once" options are eligible there.
+    pub(crate) fn choose(&self, preference: &[OptionKind]) -> Option<&PermissionOptionInfo> {
+        let eligible = |kind: &OptionKind| {
+            self.shape != OptionsShape::DropdownWithPatterns
+                || matches!(kind, OptionKind::AllowOnce | OptionKind::RejectOnce)
+        };
+        preference
+            .iter()
+            .filter(|kind| eligible(kind))
+            .find_map(|kind| self.options.iter().find(|option| option.kind == *kind))
+    }
+
+    /// Compact `id(kind)` list for log lines.
+    pub(crate) fn summary(&self) -> String {
+        let ids: Vec<String> = self
+            .options
+            .iter()
+            .map(|option| format!("{}({:?})", option.id, option.kind))
+            .collect();
+        format!("{:?}[{}]", self.shape, ids.join(", "))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(id: &str, kind: OptionKind) -> PermissionOptionInfo {
+        PermissionOptionInfo {
+            id: id.to_string(),
+            name: id.to_string(),
+            kind,
+        }
+    }
+
+    fn list(options: Vec<PermissionOptionInfo>) -> PermissionOptionList {
+        PermissionOptionList {
+            shape: OptionsShape::Flat,
+            options,
+        }
+    }
+
+    #[test]
+    fn test_choose_by_kind_preference() {
+        // Claude Code
+        let claude = list(vec![
+            option("allow_always", OptionKind::AllowAlways),
+            option("allow", OptionKind::AllowOnce),
+            option("reject", OptionKind::RejectOnce),
+        ]);
+        let once_first = [OptionKind::AllowOnce, OptionKind::AllowAlways];
+        assert_eq!(claude.choose(&once_first).unwrap().id, "allow");
+        let always_first = [OptionKind::AllowAlways, OptionKind::AllowOnce];
+        assert_eq!(claude.choose(&always_first).unwrap().id, "allow_always");
+        let reject = [OptionKind::RejectOnce, OptionKind::RejectAlways];
+        assert_eq!(claude.choose(&reject).unwrap().id, "reject");
+
+        // Codex-style ids
+        let codex = list(vec![
+            option("approved", OptionKind::AllowOnce),
+            option("approved-for-session", OptionKind::AllowAlways),
+            option("abort", OptionKind::RejectOnce),
+        ]);
+        assert_eq!(codex.choose(&once_first).unwrap().id, "approved");
+        assert_eq!(codex.choose(&reject).unwrap().id, "abort");
+    }
+
+    #[test]
+    fn test_no_acceptable_option() {
+        let only_once = list(vec![
+            option("proceed_once", OptionKind::AllowOnce),
+            option("cancel", OptionKind::RejectOnce),
+        ]);
+        assert!(only_once.choose(&[OptionKind::AllowAlways]).is_none());
+        assert!(
+            list(vec![option("allow", OptionKind::AllowOnce)])
+                .choose(&[OptionKind::RejectOnce, OptionKind::RejectAlways])
+                .is_none()
+        );
+        assert!(list(vec![]).choose(&[OptionKind::AllowOnce]).is_none());
+    }
+
+    #[test]
+    fn test_plan_prompt() {
+        let plan = list(vec![
+            option("bypassPermissions", OptionKind::AllowAlways),
+            option("acceptEdits", OptionKind::AllowAlways),
+            option("default", OptionKind::AllowOnce),
+            option("plan", OptionKind::RejectOnce),
+        ]);
+        assert!(plan.is_plan_prompt());
+        assert_eq!(
+            plan.find_id("acceptEdits").unwrap().kind,
+            OptionKind::AllowAlways
+        );
+        assert!(plan.find_id("allow").is_none());
+
+        let tool = list(vec![option("allow", OptionKind::AllowOnce)]);
+        assert!(!tool.is_plan_prompt());
+
+        // Mode names among regular options don't make a plan prompt.
+        let lookalike = list(vec![
+            option("default", OptionKind::AllowOnce),
+            option("plan", OptionKind::RejectOnce),
+            option("reject", OptionKind::RejectOnce),
+        ]);
+        assert!(!lookalike.is_plan_prompt());
+        let no_plan = list(vec![
+            option("default", OptionKind::AllowOnce),
+            option("acceptEdits", OptionKind::AllowAlways),
+        ]);
+        assert!(!no_plan.is_plan_prompt());
+    }
+
+    #[test]
+    fn test_patterns_dropdown_sends_once_only() {
+        let mut options = list(vec![
+            option("always", OptionKind::AllowAlways),
+            option("once", OptionKind::AllowOnce),
+            option("never", OptionKind::RejectAlways),
+        ]);
+        let always_first = [OptionKind::AllowAlways, OptionKind::AllowOnce];
+        assert_eq!(options.choose(&always_first).unwrap().id, "always");
+
+        options.shape = OptionsShape::DropdownWithPatterns;
+        assert_eq!(options.choose(&always_first).unwrap().id, "once");
+        assert!(options.choose(&[OptionKind::AllowAlways]).is_none());
+        assert!(options.choose(&[OptionKind::RejectAlways]).is_none());
+    }
+
+    #[test]
+    fn test_summary() {
+        let options = PermissionOptionList {
+            shape: OptionsShape::Dropdown,
+            options: vec![
+                option("allow", OptionKind::AllowOnce),
+                option("deny", OptionKind::RejectOnce),
+            ],
+        };
+        assert_eq!(
+            options.summary(),
+            "Dropdown[allow(AllowOnce), deny(RejectOnce)]"
+        );
+    }
+}
@@ -8,39 +8,202 @@
 //! ensure AcpThread pointers are registered; this thread does the actual approval.
 //!
 //! Thread safety: NO Mutex is held during scan. AcpThread registration uses
-//! lock-free atomics. The attempted-entries set is only accessed from this thread.
+//! lock-free atomics, and so do the claimed entries (`attempts`) shared with
+//! the hook.
+//!
+//! Sweeps follow the adaptive `scan_schedule` (see `scan_schedule.rs`): the
+//! hooks call `wake()` when a dialog may be pending, and the thread backs off
+//! while nothing happens. Sweep durations are kept for the `stats` latency
+//! percentiles.
+//!
+//! The same thread polls the config file before each sweep and installs a
+//! reloaded `YoloConfig` when it changes (see `config::Watcher`), saves the
+//! budget session (`budget::Saver`), and works out each thread's agent,
+//! project and config for the hooks (`thread_context`). Layout calibrations
+//! the hooks ask for run here too (`tool_authorization::run_calibration`).
+
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread::Thread;
+use std::time::{Duration, Instant};
+
+use super::{attempts, entry_scanner, tool_authorization};
+use crate::budget;
+use crate::circuit_breaker;
+use crate::config::{self, YoloConfig};
+use crate::layouts;
+use crate::mem::LiveMemory;
+use crate::scan_schedule::{Backoff, Latencies, Percentiles};
+use crate::thread_context::Contexts;
+
+/// The scanner thread, for `wake()` to unpark.
+static SCANNER: OnceLock<Thread> = OnceLock::new();
+
+/// Set by `wake()`, cleared by the scanner when it notices.
+static WAKE: AtomicBool = AtomicBool::new(false);
+
+/// Wake-ups, not counting repeats before the scanner noticed the first.
+pub static WAKE_COUNT: AtomicU64 = AtomicU64::new(0);
 
-use std::sync::atomic::Ordering;
-use std::time::Duration;
+/// Sweeps over at least one AcpThread.
+pub static SWEEP_COUNT: AtomicU64 = AtomicU64::new(0);
 
-use super::entry_scanner;
+/// The interval the scanner is waiting out, for `stats`.
+pub static INTERVAL_MS: AtomicU64 = AtomicU64::new(0);
 
-/// Start the periodic scanner thread.
-pub fn start(scan_interval_ms: u64) {
-    let interval = Duration::from_millis(scan_interval_ms.max(500));
+static LATENCIES: Mutex<Latencies> = Mutex::new(Latencies::new());
 
+/// Ask for a sweep soon (after at most `min_interval_ms`) and fast polling
+/// after it: a dialog may be pending.
+/// Lock-free — safe to call from Frida interceptor context (an atomic swap,
+/// plus an unpark when the scanner is waiting).
+pub fn wake() {
+    if !WAKE.swap(true, Ordering::AcqRel) {
+        WAKE_COUNT.fetch_add(1, Ordering::Relaxed);
+        if let Some(scanner) = SCANNER.get() {
+            scanner.unpark();
+        }
+    }
+}
+
+/// Percentiles of the latest sweep durations; `None` before the first sweep.
+pub(crate) fn latency() -> Option<Percentiles> {
+    LATENCIES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .percentiles()
+}
+
+/// Start the periodic scanner thread. `watcher` is polled for config changes;
+/// `budget_saver` keeps the budget session across restarts; `startup` is the
+/// config the hooks were installed with.
+pub fn start(
+    mut watcher: Option<config::Watcher>,
+    mut budget_saver: Option<budget::Saver>,
+    startup: YoloConfig,
+) {
     std::thread::Builder::new()
         .name("yolo-stale-scanner".to_string())
         .spawn(move || {
+            let schedule = startup.scan_schedule;
             tracing::info!(
-                "stale_scanner: started (interval={}ms)",
-                interval.as_millis()
+                "stale_scanner: started (startup_delay={}ms, interval={}..{}ms, fast_sweeps={})",
+                schedule.startup_delay_ms,
+                schedule.min_interval_ms(),
+                schedule.max_interval_ms(),
+                schedule.fast_sweeps
             );
-            // Initial delay — wait for Zed to fully initialize
-            std::thread::sleep(Duration::from_secs(5));
+            let _ = SCANNER.set(std::thread::current());
+            // Initial delay — wait for Zed to fully initialize. Wake-ups in
+            // the meantime are kept for the first sweep.
+            std::thread::sleep(Duration::from_millis(schedule.startup_delay_ms));
 
+            let mut backoff = Backoff::new(&schedule);
+            let mut contexts = Contexts::new();
             loop {
-                std::thread::sleep(interval);
-                scan_all_threads();
+                let schedule = config::current().scan_schedule;
+                let interval = backoff.interval(&schedule);
+                INTERVAL_MS.store(interval.as_millis() as u64, Ordering::Relaxed);
+                if wait(Duration::from_millis(schedule.min_interval_ms()), interval) {
+                    backoff.hurry(&schedule);
+                }
+
+                if let Some(watcher) = watcher.as_mut() {
+                    reload_config(watcher, &startup);
+                }
+                if let Some(saver) = budget_saver.as_mut() {
+                    saver.save(&config::current().budget);
+                }
+                tool_authorization::run_calibration();
+                if super::inactive() {
+                    backoff.idle(&schedule);
+                    continue;
+                }
+                match scan_all_threads(&mut contexts) {
+                    Some(approved) if approved > 0 => backoff.hurry(&schedule),
+                    _ => backoff.idle(&schedule),
+                }
             }
         })
         .expect("failed to spawn stale scanner thread");
 }
 
-fn scan_all_threads() {
-    let threads = entry_scanner::known_threads();
-    if threads.is_empty() {
+/// Sleep `min`, then until `interval` is over or `wake()` is called.
+/// Whether the scanner was woken.
+fn wait(min: Duration, interval: Duration) -> bool {
+    std::thread::sleep(min);
+    let deadline = Instant::now() + interval.saturating_sub(min);
+    loop {
+        if WAKE.swap(false, Ordering::AcqRel) {
+            return true;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return false;
+        }
+        std::thread::park_timeout(left);
+    }
+}
+
+fn reload_config(watcher: &mut config::Watcher, startup: &YoloConfig) {
+    let Some(result) = watcher.poll() else {
         return;
+    };
+    let cfg = match result {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            tracing::warn!(
+                "config: {} changed but is invalid, keeping current config: {e}",
+                watcher.path().display()
+            );
+            return;
+        }
+    };
+
+    tracing::info!(
+        "config: reloaded {} — mode={:?} tool_option={:?} plan_option={:?} retry_delay_us={} policy_rules={} policy_default={:?}",
+        watcher.path().display(),
+        cfg.mode,
+        cfg.tool_option,
+        cfg.plan_option,
+        cfg.retry_delay_us,
+        cfg.policy.rules.len(),
+        cfg.policy.default
+    );
+    let pending = config::restart_required(startup, &cfg);
+    if !pending.is_empty() {
+        tracing::warn!(
+            "config: {} only take effect after restarting Zed",
+            pending.join(", ")
+        );
+    }
+    // Compared with the config actually replaced, even if a control socket
+    // `set` got in between.
+    let Ok((old, cfg)) = config::update(|_| Ok::<_, Infallible>(cfg.clone()));
+    // Entries left for the user under the old config are decided again.
+    attempts::reset();
+    if cfg.budget != old.budget {
+        budget::arm(&cfg.budget);
+    }
+    if cfg.circuit_breaker != old.circuit_breaker {
+        circuit_breaker::reset(&cfg.circuit_breaker);
+    }
+}
+
+/// Sweep every live AcpThread. The number of entries approved, `None` when
+/// no thread is registered.
+fn scan_all_threads(contexts: &mut Contexts) -> Option<u64> {
+    let started = Instant::now();
+    let threads = entry_scanner::live_threads();
+    contexts.refresh(
+        &LiveMemory,
+        &layouts::primary().thread,
+        config::current(),
+        &threads,
+    );
+    if threads.is_empty() {
+        return None;
     }
 
     let mut total_approved: u64 = 0;
@@ -58,4 +221,11 @@ fn scan_all_threads() {
             threads.len()
         );
     }
+
+    SWEEP_COUNT.fetch_add(1, Ordering::Relaxed);
+    LATENCIES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .record(started.elapsed().as_micros() as u64);
+    Some(total_approved)
 }
@@ -0,0 +1,457 @@
+//! Lock-free registry of the AcpThread pointers the stale scanner visits.
+//!
+//! Hooks register the AcpThread they see from Frida interceptor context, so
+//! nothing here blocks: slots are `AtomicPtr`s updated with CAS, and the
+//! registry grows by appending segments instead of reallocating. Slots freed
+//! by `unregister` / `evict` are reused. Only with `MAX_SEGMENTS *
+//! SEGMENT_SLOTS` threads registered at once is a registration dropped, and
+//! that is counted (`overflows`).
+//!
+//! A slot points at an immutable record (pointer + signature).
+//! Records are never freed: a removed one may still be read by a concurrent
+//! scan. That costs 16 bytes per registered thread over the life of the
+//! process, and makes every CAS on a slot ABA-free.
+//!
+//! Two hooks registering the same new pointer at once can both claim a slot.
+//! Each re-scans after claiming (SeqCst, so at least one of them sees the
+//! other) and removes the later slot of the pair. No duplicate survives the
+//! two `register` calls, and the earliest slot, which this never removes,
+//! keeps the thread registered.
+
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+/// Slots per segment.
+pub(crate) const SEGMENT_SLOTS: usize = 64;
+/// Segments the registry grows to at most (16384 threads).
+pub(crate) const MAX_SEGMENTS: usize = 256;
+
+/// One registered AcpThread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct RegisteredThread {
+    pub(crate) ptr: u64,
+    /// Session id signature (`entry_scanner::thread_signature`).
+    pub(crate) signature: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Registration {
+    /// First seen; took a free slot.
+    New { slot: usize },
+    /// Already registered with this signature.
+    Known,
+    /// Registered, but the signature changed: a new AcpThread at a freed one's
+    /// address. The slot now holds the new signature.
+    Renewed { slot: usize },
+    /// Every slot of every segment is taken.
+    Full,
+}
+
+type Slot = AtomicPtr<RegisteredThread>;
+
+struct Segment {
+    slots: [Slot; SEGMENT_SLOTS],
+    next: AtomicPtr<Segment>,
+}
+
+impl Segment {
+    const fn new() -> Self {
+        Segment {
+            slots: [const { AtomicPtr::new(ptr::null_mut()) }; SEGMENT_SLOTS],
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+pub(crate) struct ThreadRegistry {
+    head: Segment,
+    segments: AtomicUsize,
+    max_segments: usize,
+    overflows: AtomicU64,
+}
+
+impl ThreadRegistry {
+    pub(crate) const fn new() -> Self {
+        Self::with_max_segments(MAX_SEGMENTS)
+    }
+
+    pub(crate) const fn with_max_segments(max_segments: usize) -> Self {
+        ThreadRegistry {
+            head: Segment::new(),
+            segments: AtomicUsize::new(1),
+            max_segments,
+            overflows: AtomicU64::new(0),
+        }
+    }
+
+    fn segments(&self) -> impl Iterator<Item = &Segment> {
+        std::iter::successors(Some(&self.head), |segment| {
+            // Published segments are never freed.
+            unsafe { segment.next.load(Ordering::Acquire).as_ref() }
+        })
+    }
+
+    /// Every slot with its registry-wide index.
+    fn slots(&self) -> impl Iterator<Item = (usize, &Slot)> {
+        self.segments()
+            .flat_map(|segment| segment.slots.iter())
+            .enumerate()
+    }
+
+    fn load(slot: &Slot) -> Option<RegisteredThread> {
+        // Records are never freed.
+        unsafe { slot.load(Ordering::SeqCst).as_ref() }.copied()
+    }
+
+    /// Record `ptr` with its current `signature`.
+    pub(crate) fn register(&self, ptr: u64, signature: u64) -> Registration {
+        let thread = RegisteredThread { ptr, signature };
+        'retry: loop {
+            // Known pointer: keep it, or swap in the new signature.
+            for (index, slot) in self.slots() {
+                let current = slot.load(Ordering::SeqCst);
+                let Some(known) = (unsafe { current.as_ref() }) else {
+                    continue;
+                };
+                if known.ptr != ptr {
+                    continue;
+                }
+                if known.signature == signature {
+                    return Registration::Known;
+                }
+                let record = Box::into_raw(Box::new(thread));
+                if slot
+                    .compare_exchange(current, record, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    return Registration::Renewed { slot: index };
+                }
+                // Never published.
+                drop(unsafe { Box::from_raw(record) });
+                continue 'retry;
+            }
+
+            // New pointer: the first free slot, growing when there is none.
+            let record = Box::into_raw(Box::new(thread));
+            loop {
+                let claimed = self.slots().find(|(_, slot)| {
+                    slot.compare_exchange(
+                        ptr::null_mut(),
+                        record,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    )
+                    .is_ok()
+                });
+                if let Some((index, slot)) = claimed {
+                    return if self.dedupe(index, slot, record) {
+                        Registration::New { slot: index }
+                    } else {
+                        Registration::Known
+                    };
+                }
+                if !self.grow() {
+                    drop(unsafe { Box::from_raw(record) });
+                    self.overflows.fetch_add(1, Ordering::Relaxed);
+                    return Registration::Full;
+                }
+            }
+        }
+    }
+
+    /// Remove the later slot of every pair holding `record`'s pointer. Whether
+    /// `mine` (holding `record`) survived.
+    fn dedupe(&self, mine: usize, my_slot: &Slot, record: *mut RegisteredThread) -> bool {
+        let ptr = unsafe { (*record).ptr };
+        for (index, slot) in self.slots() {
+            if index == mine || Self::load(slot).is_none_or(|other| other.ptr != ptr) {
+                continue;
+            }
+            if index < mine {
+                Self::remove_if(my_slot, |thread| thread.ptr == ptr);
+                break;
+            }
+            Self::remove_if(slot, |thread| thread.ptr == ptr);
+        }
+        my_slot.load(Ordering::SeqCst) == record
+    }
+
+    /// Empty `slot` while it holds a record matching `matches`.
+    fn remove_if(slot: &Slot, matches: impl Fn(&RegisteredThread) -> bool) -> bool {
+        loop {
+            let current = slot.load(Ordering::SeqCst);
+            match unsafe { current.as_ref() } {
+                Some(thread) if matches(thread) => {}
+                _ => return false,
+            }
+            if slot
+                .compare_exchange(current, ptr::null_mut(), Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                return true;
+            }
+        }
+    }
+
+    /// Append a segment. `false` at `max_segments`.
+    fn grow(&self) -> bool {
+        if self.segments.load(Ordering::Acquire) >= self.max_segments {
+            return false;
+        }
+        let last = self.segments().last().expect("head segment");
+        let segment = Box::into_raw(Box::new(Segment::new()));
+        match last.next.compare_exchange(
+            ptr::null_mut(),
+            segment,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => {
+                let segments = self.segments.fetch_add(1, Ordering::AcqRel) + 1;
+                tracing::info!(
+                    "thread_registry: all slots taken, grew to {} slots",
+                    segments * SEGMENT_SLOTS
+                );
+            }
+            // Another registration grew it first.
+            Err(_) => drop(unsafe { Box::from_raw(segment) }),
+        }
+        true
+    }
+
+    /// Remove `ptr` whatever its signature. `false` if it wasn't registered.
+    pub(crate) fn unregister(&self, ptr: u64) -> bool {
+        self.slots().fold(false, |removed, (_, slot)| {
+            Self::remove_if(slot, |thread| thread.ptr == ptr) || removed
+        })
+    }
+
+    /// Remove `thread` if it is still registered with the same signature (a
+    /// concurrent `Renewed` registration wins).
+    pub(crate) fn evict(&self, thread: RegisteredThread) -> bool {
+        self.slots().fold(false, |removed, (_, slot)| {
+            Self::remove_if(slot, |current| *current == thread) || removed
+        })
+    }
+
+    /// Every registered thread.
+    pub(crate) fn threads(&self) -> Vec<RegisteredThread> {
+        self.slots()
+            .filter_map(|(_, slot)| Self::load(slot))
+            .collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.slots()
+            .filter(|(_, slot)| !slot.load(Ordering::Relaxed).is_null())
+            .count()
+    }
+
+    /// Slots allocated so far.
+    pub(crate) fn capacity(&self) -> usize {
+        self.segments.load(Ordering::Relaxed) * SEGMENT_SLOTS
+    }
+
+    /// Registrations dropped because the registry was at `max_segments`.
+    pub(crate) fn overflows(&self) -> u64 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Barrier};
+
+    const THREAD: u64 = 0x1_1000_0000;
+
+    fn ptr(i: u64) -> u64 {
+        THREAD + i * 0x1000
+    }
+
+    /// Every registered pointer, asserting none is registered twice.
+    fn registered(registry: &ThreadRegistry) -> Vec<u64> {
+        let mut ptrs: Vec<u64> = registry.threads().iter().map(|t| t.ptr).collect();
+        ptrs.sort_unstable();
+        let len = ptrs.len();
+        ptrs.dedup();
+        assert_eq!(ptrs.len(), len, "a pointer is registered twice");
+        ptrs
+    }
+
+    #[test]
+    fn test_slots_are_reused() {
+        let registry = ThreadRegistry::new();
+        for i in 0..SEGMENT_SLOTS as u64 {
+            assert_eq!(
+                registry.register(ptr(i), 1),
+                Registration::New { slot: i as usize }
+            );
+        }
+        assert_eq!(registry.register(ptr(3), 1), Registration::Known);
+        assert_eq!(
+            registry.register(ptr(3), 2),
+            Registration::Renewed { slot: 3 }
+        );
+
+        assert!(registry.unregister(ptr(7)));
+        assert!(!registry.unregister(ptr(7)));
+        assert!(registry.evict(RegisteredThread {
+            ptr: ptr(9),
+            signature: 1
+        }));
+        // Renewed since: not evicted.
+        assert!(!registry.evict(RegisteredThread {
+            ptr: ptr(3),
+            signature: 1
+        }));
+        assert_eq!(registry.len(), SEGMENT_SLOTS - 2);
+
+        assert_eq!(
+            registry.register(ptr(100), 1),
+            Registration::New { slot: 7 }
+        );
+        assert_eq!(
+            registry.register(ptr(101), 1),
+            Registration::New { slot: 9 }
+        );
+        assert_eq!(registry.capacity(), SEGMENT_SLOTS);
+    }
+
+    #[test]
+    fn test_grows_past_a_segment() {
+        let registry = ThreadRegistry::new();
+        let count = SEGMENT_SLOTS as u64 * 3 + 5;
+        for i in 0..count {
+            assert_eq!(
+                registry.register(ptr(i), 1),
+                Registration::New { slot: i as usize }
+            );
+        }
+        assert_eq!(registry.capacity(), SEGMENT_SLOTS * 4);
+        assert_eq!(
+            registered(&registry),
+            (0..count).map(ptr).collect::<Vec<_>>()
+        );
+
+        // A slot freed in a later segment is found again.
+        assert!(registry.unregister(ptr(130)));
+        assert_eq!(
+            registry.register(ptr(1000), 1),
+            Registration::New { slot: 130 }
+        );
+        assert_eq!(registry.overflows(), 0);
+    }
+
+    #[test]
+    fn test_overflow_is_counted() {
+        let registry = ThreadRegistry::with_max_segments(2);
+        let max = (SEGMENT_SLOTS * 2) as u64;
+        for i in 0..max {
+            assert_ne!(registry.register(ptr(i), 1), Registration::Full);
+        }
+        assert_eq!(registry.register(ptr(max), 1), Registration::Full);
+        assert_eq!(registry.register(ptr(max + 1), 1), Registration::Full);
+        assert_eq!(registry.overflows(), 2);
+
+        assert!(registry.unregister(ptr(0)));
+        assert_eq!(
+            registry.register(ptr(max), 1),
+            Registration::New { slot: 0 }
+        );
+    }
+
+    /// Many hooks registering the same new pointers at once: each ends up
+    /// registered exactly once.
+    #[test]
+    fn test_concurrent_registration_never_duplicates() {
+        const WORKERS: usize = 8;
+        const PTRS: u64 = 300;
+
+        for _ in 0..20 {
+            let registry = Arc::new(ThreadRegistry::new());
+            let barrier = Arc::new(Barrier::new(WORKERS));
+            let workers: Vec<_> = (0..WORKERS)
+                .map(|worker| {
+                    let registry = Arc::clone(&registry);
+                    let barrier = Arc::clone(&barrier);
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        // Half the workers go in reverse, so claims interleave.
+                        for i in 0..PTRS {
+                            let i = if worker % 2 == 0 { i } else { PTRS - 1 - i };
+                            assert_ne!(registry.register(ptr(i), 1), Registration::Full);
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap();
+            }
+            assert_eq!(
+                registered(&registry),
+                (0..PTRS).map(ptr).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    /// Registration, renewal, unregistration and eviction racing on shared
+    /// and private pointers: nothing registered at the end is lost, nothing
+    /// removed comes back, and no pointer is in two slots.
+    #[test]
+    fn test_concurrent_churn_never_loses() {
+        const WORKERS: u64 = 8;
+        const ROUNDS: u64 = 2_000;
+        const SHARED: u64 = 40;
+
+        let registry = Arc::new(ThreadRegistry::new());
+        let barrier = Arc::new(Barrier::new(WORKERS as usize));
+        let workers: Vec<_> = (0..WORKERS)
+            .map(|worker| {
+                let registry = Arc::clone(&registry);
+                let barrier = Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    // Private pointers: allocate / free / reallocate cycles,
+                    // each pointer at its own phase.
+                    let mut alive = HashMap::new();
+                    for round in 0..ROUNDS {
+                        let p = ptr(1_000 + worker * 100 + round % 100);
+                        match (round / 100 + round) % 4 {
+                            0 | 1 => {
+                                let signature = round | 1;
+                                registry.register(p, signature);
+                                alive.insert(p, signature);
+                            }
+                            2 => {
+                                if let Some(signature) = alive.remove(&p) {
+                                    registry.evict(RegisteredThread { ptr: p, signature });
+                                }
+                            }
+                            _ => {
+                                registry.unregister(p);
+                                alive.remove(&p);
+                            }
+                        }
+                        // Shared pointers: everyone registers them.
+                        registry.register(ptr(round % SHARED), 1);
+                    }
+                    alive
+                })
+            })
+            .collect();
+
+        let mut expected: Vec<u64> = (0..SHARED).map(ptr).collect();
+        for worker in workers {
+            expected.extend(worker.join().unwrap().into_keys());
+        }
+        expected.sort_unstable();
+        assert_eq!(registered(&registry), expected);
+        assert_eq!(registry.len(), expected.len());
+    }
+}
@@ -16,9 +16,16 @@
 //! - Regular tools: option_id="allow" or "allow_always"
 //! - ExitPlanMode: option_id="acceptEdits", "bypassPermissions", "default", or "plan"
 //!
-//! We attempt to detect ExitPlanMode by reading the first PermissionOption's
-//! option_id from the WaitingForConfirmation entry. If it looks like a mode name
-//! (not "allow"/"allow_always"/"reject"), we send the configured `plan_option`.
+//! We decode the entry's full `PermissionOptions` list (Flat, Dropdown and
+//! DropdownWithPatterns). If every option_id is a mode name ("plan" among
+//! them), it is an ExitPlanMode prompt and we send the configured
+//! `plan_option`. Otherwise we pick an option by `PermissionOptionKind`
+//! (preference order from config) and send whatever id the agent attached to
+//! it, so Codex / Gemini / custom ACP servers with their own ids work too.
+//! We never send pattern params, so in a DropdownWithPatterns dialog only the
+//! "once" options are picked. If the list decodes but has no
+//! acceptable option, the dialog is left for the user; if it can't be decoded,
+//! the fixed ids above are sent.
 //!
 //! ## Memory layout (from disassembly of Zed Preview v0.233.0 aarch64):
 //!   AcpThread + 0xb0 = entries.ptr
@@ -33,95 +40,71 @@
 //!                payload head < 0x8000_0000_0000_0002
 //!   entry[0x160] = respond_tx (pointer to oneshot::Sender on heap)
 //!
-//! The v0.228.x layout is retained as a fallback because the exported symbol
-//! stayed stable across the upgrade.
+//! Title / kind / locations / raw_input are decoded in `on_enter` from the
+//! `acp::ToolCallUpdate` argument, before the function consumes it (see
+//! `tool_call_snapshot::read_update`), and completed from the entry's
+//! `EntryLayout::snapshot` offsets where a layout has them.
+//!
+//! The layout is chosen once at init from the running build's version / binary
+//! hash (`crate::fingerprint`, `crate::layouts`); only an unrecognised build
+//! probes every known layout. Layouts for newer versions can be added at runtime
+//! through the layout database.
+//!
+//! Every read of Zed's memory goes through `crate::mem::SafeMem`
+//! (`LiveMemory` in the hook), so a stale AcpThread or entries pointer makes
+//! the walk come up empty instead of crashing the editor. What stays
+//! unchecked is what we hand back to Zed's own code: the oneshot sender we
+//! send through.
+//!
+//! ## Policy gate
+//!
+//! Once the waiting entry is found, its facts are run through the configured
+//! `policy` before anything is sent (`resolve_entry`):
+//!
+//! - `allow` — send the allow outcome, force status to InProgress
+//! - `deny`  — send the reject outcome, force status to Rejected
+//! - `ask`   — leave the entry alone; Zed keeps showing the permission dialog
+//!
+//! The config is the global one with the thread's `agents` entry
+//! (`crate::agent`) and the rules of its project's `.zed/yolo.json`
+//! (`crate::project`) applied, merged by the stale scanner
+//! (`crate::thread_context`). The agent name and project root are also the
+//! policy's `agent` and `workspace` facts.
+//!
+//! Each approval uses up one unit of the configured `budget`; once it is
+//! exhausted every entry is left for the user (`crate::budget`).
 
 use agent_client_protocol as acp;
-use std::cell::Cell;
-use std::slice;
+use std::cell::{Cell, RefCell};
+use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
 use std::time::Instant;
 
 use super::{
-    TOOL_AUTHORIZATION_COUNT, TOOL_AUTHORIZATION_MISS_COUNT, TOOL_AUTHORIZATION_RETRY_SUCCESS_COUNT,
+    LEFT_FOR_USER_COUNT, POLICY_REJECT_COUNT, TOOL_AUTHORIZATION_COUNT,
+    TOOL_AUTHORIZATION_MISS_COUNT, TOOL_AUTHORIZATION_RETRY_SUCCESS_COUNT,
 };
-use crate::CONFIG;
-use crate::config::{PlanOption, ToolOption};
+use crate::audit::{self, Action, ApprovalPath};
+use crate::budget;
+use crate::calibrate;
+use crate::circuit_breaker;
+use crate::config::{self, OptionKind, PlanOption, YoloConfig, YoloMode};
+use crate::layouts::{self, EntryLayout, MatchStyle, SendStyle};
+use crate::mem::{LiveMemory, SafeMem, plausible_ptr};
+use crate::path_scope;
+use crate::policy::{Decision, Verdict, VerdictSource};
+use crate::thread_context;
+
+use super::attempts::{self, EntryId};
+use super::permission_options::{OptionsShape, PermissionOptionInfo, PermissionOptionList};
+use super::tool_call_snapshot::{self, ToolCallSnapshot};
 
 // ---- AcpThread offsets ----
-// v0.233.0: 0xb0/0xb8 (repr(Rust) reordering from new `cost` field)
-// v0.228.x–v0.232.0: was 0x90/0x98
-const ENTRIES_PTR_OFFSET: usize = 0xb0; // Vec<AgentThreadEntry>.ptr
-const ENTRIES_LEN_OFFSET: usize = 0xb8; // Vec<AgentThreadEntry>.len
+// Per-version offsets (entries Vec, entry stride, status, respond_tx,
+// tool_call_id) live in `crate::layouts`, compiled table + layout database.
 const ENTRY_DISCRIMINANT_OFFSET: usize = 0x00; // AgentThreadEntry variant tag
 const ARC_INNER_DATA_OFFSET: usize = 0x10; // ArcInner<T> header = strong + weak
-const TOOL_CALL_UPDATE_ID_PTR_OFFSET_V230: usize = 0x128; // ToolCallUpdate.tool_call_id.ptr
-const TOOL_CALL_UPDATE_ID_LEN_OFFSET_V230: usize = 0x130; // ToolCallUpdate.tool_call_id.len
-
-#[derive(Clone, Copy, Debug)]
-pub(crate) enum SendStyle {
-    LegacyOptionId,
-    SelectedOutcome,
-}
-
-#[derive(Clone, Copy, Debug)]
-pub(crate) enum MatchStyle {
-    // Preview 0.230.x:
-    // - AgentThreadEntry::ToolCall discriminant = 0x2
-    // - ToolCall.id at entry+0x168 / +0x170
-    // - ToolCallStatus::WaitingForConfirmation is niche-encoded:
-    //   any payload head < 0x8000_0000_0000_0002
-    Preview230 {
-        toolcall_variant: u64,
-        id_ptr_offset: usize,
-        id_len_offset: usize,
-        waiting_payload_niche_start: u64,
-    },
-    LegacyExact {
-        toolcall_variant: u64,
-        waiting_variant: u64,
-    },
-}
-
-#[derive(Clone, Copy, Debug)]
-pub(crate) struct EntryLayout {
-    pub(crate) name: &'static str,
-    pub(crate) entry_size: usize,
-    pub(crate) status_offset: usize,
-    pub(crate) respond_tx_offset: usize,
-    pub(crate) send_style: SendStyle,
-    pub(crate) match_style: MatchStyle,
-}
-
-pub(crate) const ENTRY_LAYOUTS: &[EntryLayout] = &[
-    // Zed Preview 0.230.0 9437a84390a396d666f04b38db87d89bb07284c1
-    EntryLayout {
-        name: "v0.230.x",
-        entry_size: 0x1c0,
-        status_offset: 0x118,
-        respond_tx_offset: 0x160,
-        send_style: SendStyle::SelectedOutcome,
-        match_style: MatchStyle::Preview230 {
-            toolcall_variant: 0x02,
-            id_ptr_offset: 0x168,
-            id_len_offset: 0x170,
-            waiting_payload_niche_start: 0x8000_0000_0000_0002,
-        },
-    },
-    // Zed Preview 0.228.x / 0.229.x
-    EntryLayout {
-        name: "v0.228.x",
-        entry_size: 0x1b0,
-        status_offset: 0x48,
-        respond_tx_offset: 0x68,
-        send_style: SendStyle::LegacyOptionId,
-        match_style: MatchStyle::LegacyExact {
-            toolcall_variant: 0x07,
-            waiting_variant: 0x00,
-        },
-    },
-];
 
 // ---- Thread-local for capturing `self` pointer ----
 #[derive(Clone, Copy, Debug, Default)]
@@ -130,9 +113,19 @@ struct ArcStrRef {
     len: u64,
 }
 
+/// `ToolCallUpdate.tool_call_id` as read with one layout's offsets.
+#[derive(Clone, Copy, Debug)]
+struct SavedCallId {
+    ptr_offset: usize,
+    len_offset: usize,
+    id: ArcStrRef,
+}
+
 thread_local! {
     static SAVED_SELF: Cell<u64> = const { Cell::new(0) };
-    static SAVED_TOOL_CALL_ID: Cell<ArcStrRef> = const { Cell::new(ArcStrRef { ptr: 0, len: 0 }) };
+    static SAVED_UPDATE: Cell<u64> = const { Cell::new(0) };
+    static SAVED_TOOL_CALL_IDS: RefCell<Vec<SavedCallId>> = const { RefCell::new(Vec::new()) };
+    static SAVED_SNAPSHOT: RefCell<Option<ToolCallSnapshot>> = const { RefCell::new(None) };
 }
 
 // ---- ACP outcome shim for Zed Preview 0.230.x ----
@@ -167,118 +160,322 @@ unsafe fn bump_sender_refcount(sender_arc_ptr: u64) {
     unsafe { (*strong).fetch_add(1, Ordering::Relaxed) };
 }
 
-unsafe fn looks_like_arc_allocation(arc_ptr: u64) -> bool {
-    if arc_ptr <= 0x1_0000_0000 {
+fn looks_like_arc_allocation(mem: &impl SafeMem, arc_ptr: u64) -> bool {
+    if !plausible_ptr(arc_ptr) {
         return false;
     }
-
-    let p = arc_ptr as *const usize;
-    let strong = unsafe { *p };
-    let weak = unsafe { *p.add(1) };
+    let (Some(strong), Some(weak)) = (mem.read_u64(arc_ptr), mem.read_u64(arc_ptr + 8)) else {
+        return false;
+    };
 
     (1..=64).contains(&strong) && (1..=64).contains(&weak)
 }
 
-unsafe fn looks_like_sender_arc(sender_arc_ptr: u64) -> bool {
-    unsafe { looks_like_arc_allocation(sender_arc_ptr) }
+fn looks_like_sender_arc(mem: &impl SafeMem, sender_arc_ptr: u64) -> bool {
+    looks_like_arc_allocation(mem, sender_arc_ptr)
 }
 
-unsafe fn looks_like_arc_str(value: ArcStrRef) -> bool {
-    value.len != 0 && value.len <= 4096 && unsafe { looks_like_arc_allocation(value.ptr) }
+fn looks_like_arc_str(mem: &impl SafeMem, value: ArcStrRef) -> bool {
+    value.len != 0 && value.len <= 4096 && looks_like_arc_allocation(mem, value.ptr)
 }
 
-unsafe fn arc_str_eq(a: ArcStrRef, b: ArcStrRef) -> bool {
-    if a.len != b.len || !unsafe { looks_like_arc_str(a) } || !unsafe { looks_like_arc_str(b) } {
-        return false;
+/// Text bytes of an `Arc<str>` that passes `looks_like_arc_str`.
+fn arc_str_bytes(mem: &impl SafeMem, value: ArcStrRef) -> Option<Vec<u8>> {
+    if !looks_like_arc_str(mem, value) {
+        return None;
     }
+    mem.read_bytes(value.ptr + ARC_INNER_DATA_OFFSET as u64, value.len as usize)
+}
 
-    let len = a.len as usize;
-    let a_bytes =
-        unsafe { slice::from_raw_parts((a.ptr + ARC_INNER_DATA_OFFSET as u64) as *const u8, len) };
-    let b_bytes =
-        unsafe { slice::from_raw_parts((b.ptr + ARC_INNER_DATA_OFFSET as u64) as *const u8, len) };
-    a_bytes == b_bytes
+fn arc_str_eq(mem: &impl SafeMem, a: ArcStrRef, b: ArcStrRef) -> bool {
+    if a.len != b.len {
+        return false;
+    }
+    match (arc_str_bytes(mem, a), arc_str_bytes(mem, b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
 }
 
-unsafe fn read_arc_str(ptr: u64, ptr_offset: usize, len_offset: usize) -> ArcStrRef {
+fn read_arc_str(mem: &impl SafeMem, ptr: u64, ptr_offset: usize, len_offset: usize) -> ArcStrRef {
     if ptr == 0 {
         return ArcStrRef::default();
     }
 
-    let p = ptr as *const u64;
-    ArcStrRef {
-        ptr: unsafe { *p.byte_add(ptr_offset) },
-        len: unsafe { *p.byte_add(len_offset) },
+    match (
+        mem.read_u64(ptr + ptr_offset as u64),
+        mem.read_u64(ptr + len_offset as u64),
+    ) {
+        (Some(ptr), Some(len)) => ArcStrRef { ptr, len },
+        _ => ArcStrRef::default(),
     }
 }
 
-unsafe fn read_tool_call_id_v230(tool_call_update_ptr: u64) -> ArcStrRef {
-    unsafe {
-        read_arc_str(
-            tool_call_update_ptr,
-            TOOL_CALL_UPDATE_ID_PTR_OFFSET_V230,
-            TOOL_CALL_UPDATE_ID_LEN_OFFSET_V230,
-        )
+fn read_tool_call_id(
+    mem: &impl SafeMem,
+    tool_call_update_ptr: u64,
+    layout: &EntryLayout,
+) -> ArcStrRef {
+    read_arc_str(
+        mem,
+        tool_call_update_ptr,
+        layout.update_id_ptr_offset,
+        layout.update_id_len_offset,
+    )
+}
+
+/// Read the call id once per distinct update offset pair in `layouts`. The
+/// update is consumed by the hooked function, so this happens in `on_enter`,
+/// before it is known which layout the entry will match.
+fn read_tool_call_ids(
+    mem: &impl SafeMem,
+    tool_call_update_ptr: u64,
+    layouts: &[EntryLayout],
+) -> Vec<SavedCallId> {
+    let mut ids: Vec<SavedCallId> = Vec::new();
+    for layout in layouts {
+        if ids.iter().any(|saved| saved.matches(layout)) {
+            continue;
+        }
+        ids.push(SavedCallId {
+            ptr_offset: layout.update_id_ptr_offset,
+            len_offset: layout.update_id_len_offset,
+            id: read_tool_call_id(mem, tool_call_update_ptr, layout),
+        });
+    }
+    ids
+}
+
+impl SavedCallId {
+    fn matches(&self, layout: &EntryLayout) -> bool {
+        self.ptr_offset == layout.update_id_ptr_offset
+            && self.len_offset == layout.update_id_len_offset
     }
 }
 
+/// The call id as read with `layout`'s update offsets.
+fn call_id_for(ids: &[SavedCallId], layout: &EntryLayout) -> ArcStrRef {
+    ids.iter()
+        .find(|saved| saved.matches(layout))
+        .map(|saved| saved.id)
+        .unwrap_or_default()
+}
+
 /// Try to read the Arc<str> content as a UTF-8 string (for diagnostics).
-unsafe fn arc_str_to_string(value: ArcStrRef) -> Option<String> {
-    if !unsafe { looks_like_arc_str(value) } {
+fn arc_str_to_string(mem: &impl SafeMem, value: ArcStrRef) -> Option<String> {
+    String::from_utf8(arc_str_bytes(mem, value)?).ok()
+}
+
+// ---- PermissionOptions decoding ----
+//
+// WaitingForConfirmation payload layout (entry + status_offset). Speculative,
+// like the rest of the status payload; every read is validated and a mismatch
+// makes `read_permission_options` return None (→ fixed option ids).
+//   +0x08: PermissionOptions discriminant (0 = Flat, 1 = Dropdown,
+//          2 = DropdownWithPatterns)
+//   +0x10: Vec ptr (Flat: PermissionOption, Dropdown*: PermissionOptionChoice)
+//   +0x18: Vec len
+//
+// Elements use our own `acp::PermissionOption` layout — same pinned crate
+// version as Zed, the same assumption `SelectedPermissionOutcome` relies on.
+
+const OPTIONS_DISCRIMINANT_OFFSET: u64 = 0x08;
+const OPTIONS_VEC_PTR_OFFSET: u64 = 0x10;
+const OPTIONS_VEC_LEN_OFFSET: u64 = 0x18;
+const OPTIONS_FLAT: u64 = 0;
+const OPTIONS_DROPDOWN: u64 = 1;
+const OPTIONS_DROPDOWN_WITH_PATTERNS: u64 = 2;
+const MAX_OPTIONS: u64 = 16;
+
+const OPTION_SIZE: u64 = std::mem::size_of::<acp::PermissionOption>() as u64;
+const OPTION_ID_OFFSET: usize = std::mem::offset_of!(acp::PermissionOption, option_id);
+const OPTION_NAME_OFFSET: usize = std::mem::offset_of!(acp::PermissionOption, name);
+const OPTION_KIND_OFFSET: usize = std::mem::offset_of!(acp::PermissionOption, kind);
+
+/// Mirror of Zed's `PermissionOptionChoice` (one dropdown row) for size/offset math.
+#[allow(dead_code)]
+struct PermissionOptionChoice {
+    allow: acp::PermissionOption,
+    deny: acp::PermissionOption,
+}
+
+const CHOICE_SIZE: u64 = std::mem::size_of::<PermissionOptionChoice>() as u64;
+const CHOICE_ALLOW_OFFSET: u64 = std::mem::offset_of!(PermissionOptionChoice, allow) as u64;
+const CHOICE_DENY_OFFSET: u64 = std::mem::offset_of!(PermissionOptionChoice, deny) as u64;
+
+// `read_permission_options` walks a row as the allow option followed by the
+// deny option, nothing else; fail the build if the mirror stops being that.
+const _: [(); 2 * OPTION_SIZE as usize] = [(); CHOICE_SIZE as usize];
+const _: [(); 0] = [(); CHOICE_ALLOW_OFFSET as usize];
+const _: [(); OPTION_SIZE as usize] = [(); CHOICE_DENY_OFFSET as usize];
+
+/// Decode one `acp::PermissionOption` at `ptr`.
+fn read_permission_option(mem: &impl SafeMem, ptr: u64) -> Option<PermissionOptionInfo> {
+    let id = read_arc_str(mem, ptr, OPTION_ID_OFFSET, OPTION_ID_OFFSET + 8);
+    let id = arc_str_to_string(mem, id)?;
+
+    let kind_byte = mem.read_u8(ptr + OPTION_KIND_OFFSET as u64)?;
+    let kind = OptionKind::from_discriminant(kind_byte)?;
+
+    // Only the copy's pointer and length are used; the text is read checked.
+    let name = unsafe { mem.read_copy::<String>(ptr + OPTION_NAME_OFFSET as u64) }?;
+    if name.len() > 4096 {
         return None;
     }
-    let len = value.len as usize;
-    let bytes = unsafe {
-        slice::from_raw_parts((value.ptr + ARC_INNER_DATA_OFFSET as u64) as *const u8, len)
+    let name = if name.is_empty() {
+        String::new()
+    } else {
+        String::from_utf8(mem.read_bytes(name.as_ptr() as u64, name.len())?).ok()?
     };
-    std::str::from_utf8(bytes).ok().map(|s| s.to_string())
+
+    Some(PermissionOptionInfo { id, name, kind })
 }
 
-// ---- Option-aware outcome builders ----
+/// Decode the `PermissionOptions` of a WaitingForConfirmation entry.
+///
+/// Returns `None` for layouts without a known status payload or when any
+/// element fails validation.
+fn read_permission_options(
+    mem: &impl SafeMem,
+    entry: u64,
+    layout: &EntryLayout,
+) -> Option<PermissionOptionList> {
+    if !matches!(layout.match_style, MatchStyle::Preview230 { .. }) {
+        return None;
+    }
 
-/// Build the outcome for a regular tool permission (Scenario C).
-fn build_tool_outcome(tool_option: ToolOption) -> SelectedPermissionOutcome {
-    match tool_option {
-        ToolOption::Allow => SelectedPermissionOutcome {
-            option_id: acp::PermissionOptionId::new("allow"),
-            option_kind: acp::PermissionOptionKind::AllowOnce,
-            params: None,
-        },
-        ToolOption::AllowAlways => SelectedPermissionOutcome {
-            option_id: acp::PermissionOptionId::new("allow_always"),
-            option_kind: acp::PermissionOptionKind::AllowAlways,
-            params: None,
-        },
+    let status_base = entry + layout.status_offset as u64;
+    let discriminant = mem.read_u64(status_base + OPTIONS_DISCRIMINANT_OFFSET)?;
+    let vec_ptr = mem.read_u64(status_base + OPTIONS_VEC_PTR_OFFSET)?;
+    let vec_len = mem.read_u64(status_base + OPTIONS_VEC_LEN_OFFSET)?;
+
+    if !plausible_ptr(vec_ptr) || vec_len == 0 || vec_len > MAX_OPTIONS {
+        return None;
+    }
+
+    let shape = match discriminant {
+        OPTIONS_FLAT => OptionsShape::Flat,
+        OPTIONS_DROPDOWN => OptionsShape::Dropdown,
+        OPTIONS_DROPDOWN_WITH_PATTERNS => OptionsShape::DropdownWithPatterns,
+        _ => return None,
+    };
+
+    let mut options = Vec::new();
+    for i in 0..vec_len {
+        match shape {
+            OptionsShape::Flat => {
+                options.push(read_permission_option(mem, vec_ptr + i * OPTION_SIZE)?);
+            }
+            OptionsShape::Dropdown | OptionsShape::DropdownWithPatterns => {
+                let choice = vec_ptr + i * CHOICE_SIZE;
+                options.push(read_permission_option(mem, choice + CHOICE_ALLOW_OFFSET)?);
+                options.push(read_permission_option(mem, choice + CHOICE_DENY_OFFSET)?);
+            }
+        }
     }
+
+    Some(PermissionOptionList { shape, options })
 }
 
-/// Build the outcome for an ExitPlanMode prompt (Scenario A).
-fn build_plan_outcome(plan_option: PlanOption) -> SelectedPermissionOutcome {
-    match plan_option {
-        PlanOption::AcceptEdits => SelectedPermissionOutcome {
-            option_id: acp::PermissionOptionId::new("acceptEdits"),
-            option_kind: acp::PermissionOptionKind::AllowAlways,
-            params: None,
-        },
-        PlanOption::BypassPermissions => SelectedPermissionOutcome {
-            option_id: acp::PermissionOptionId::new("bypassPermissions"),
-            option_kind: acp::PermissionOptionKind::AllowAlways,
-            params: None,
-        },
-        PlanOption::Default => SelectedPermissionOutcome {
-            option_id: acp::PermissionOptionId::new("default"),
-            option_kind: acp::PermissionOptionKind::AllowOnce,
-            params: None,
-        },
-        PlanOption::Plan => SelectedPermissionOutcome {
-            option_id: acp::PermissionOptionId::new("plan"),
-            option_kind: acp::PermissionOptionKind::RejectOnce,
-            params: None,
-        },
+// ---- Option selection ----
+
+fn fixed_option(id: &str, kind: OptionKind) -> PermissionOptionInfo {
+    PermissionOptionInfo {
+        id: id.to_string(),
+        name: id.to_string(),
+        kind,
+    }
+}
+
+fn acp_option_kind(kind: OptionKind) -> acp::PermissionOptionKind {
+    match kind {
+        OptionKind::AllowOnce => acp::PermissionOptionKind::AllowOnce,
+        OptionKind::AllowAlways => acp::PermissionOptionKind::AllowAlways,
+        OptionKind::RejectOnce => acp::PermissionOptionKind::RejectOnce,
+        OptionKind::RejectAlways => acp::PermissionOptionKind::RejectAlways,
     }
 }
 
-/// Reconstruct the legacy `oneshot::Sender<PermissionOptionId>` and send `"allow"`.
+/// Pick the option to send for `decision` (`Allow` or `Deny`).
+///
+/// - ExitPlanMode: the option whose id is `plan_option` (`plan` when denying)
+/// - Regular tools: first option matching the configured kind preference
+///
+/// Returns `None` when the decoded list has nothing acceptable — the dialog is
+/// then left for the user. When the list can't be decoded (legacy layout,
+/// validation failure) the fixed ids from `tool_option` / `plan_option` are used.
+fn choose_option(
+    mem: &impl SafeMem,
+    config: &YoloConfig,
+    layout: &EntryLayout,
+    entry: u64,
+    is_plan_mode: bool,
+    decision: Decision,
+    log_prefix: &str,
+) -> Option<PermissionOptionInfo> {
+    let allow = decision == Decision::Allow;
+
+    if let SendStyle::LegacyOptionId = layout.send_style {
+        return Some(if allow {
+            fixed_option("allow", OptionKind::AllowOnce)
+        } else {
+            fixed_option("reject", OptionKind::RejectOnce)
+        });
+    }
+
+    // ExitPlanMode has no "reject" option; rejecting it means staying in plan mode.
+    let plan_option = if allow {
+        config.plan_option
+    } else {
+        PlanOption::Plan
+    };
+    if is_plan_mode {
+        tracing::info!("{log_prefix}: ExitPlanMode detected, plan_option={plan_option:?}");
+    }
+
+    let Some(options) = read_permission_options(mem, entry, layout) else {
+        tracing::debug!("{log_prefix}: options not decodable, using fixed option_id");
+        return Some(if is_plan_mode {
+            fixed_option(plan_option.option_id(), plan_option.option_kind())
+        } else if allow {
+            fixed_option(
+                config.tool_option.option_id(),
+                config.tool_option.option_kind(),
+            )
+        } else {
+            fixed_option("reject", OptionKind::RejectOnce)
+        });
+    };
+
+    let chosen = if is_plan_mode {
+        options.find_id(plan_option.option_id())
+    } else if allow {
+        options.choose(&config.allow_preference())
+    } else {
+        options.choose(&config.reject_kinds)
+    };
+
+    match chosen {
+        Some(option) => {
+            tracing::debug!(
+                "{log_prefix}: chose option_id=\"{}\" ({:?}) from {}",
+                option.id,
+                option.kind,
+                options.summary()
+            );
+            Some(option.clone())
+        }
+        None => {
+            tracing::info!(
+                "{log_prefix}: no acceptable option in {} — leaving dialog for user",
+                options.summary()
+            );
+            None
+        }
+    }
+}
+
+/// Reconstruct the legacy `oneshot::Sender<PermissionOptionId>` and send `option_id`.
 ///
 /// The respond_tx value at entry+0x68 is the Arc<Inner<T>> pointer inside the
 /// Sender<PermissionOptionId>. We reconstruct a Sender from it and call .send().
@@ -289,10 +486,10 @@ fn build_plan_outcome(plan_option: PlanOption) -> SelectedPermissionOutcome {
 ///
 /// # Safety
 /// `sender_arc_ptr` must point to a valid Arc<Inner<Sender<Arc<str>>>>.
-unsafe fn send_allow_legacy(sender_arc_ptr: u64, count: u64) -> bool {
-    // Build PermissionOptionId("allow") — same type as what Zed uses.
+unsafe fn send_legacy_option_id(sender_arc_ptr: u64, option_id: &str, count: u64) -> bool {
+    // Build PermissionOptionId(option_id) — same type as what Zed uses.
     // PermissionOptionId is #[repr(transparent)] around Arc<str>.
-    let option_id: Arc<str> = Arc::from("allow");
+    let option_id: Arc<str> = Arc::from(option_id);
 
     unsafe { bump_sender_refcount(sender_arc_ptr) };
 
@@ -343,34 +540,38 @@ unsafe fn send_outcome(
     }
 }
 
-unsafe fn send_allow(
-    layout: EntryLayout,
+/// Send `option` through the entry's oneshot, in whatever shape the layout expects.
+unsafe fn send_option(
+    layout: &EntryLayout,
     sender_arc_ptr: u64,
-    is_plan_mode: bool,
+    option: &PermissionOptionInfo,
     count: u64,
 ) -> bool {
     match layout.send_style {
-        SendStyle::LegacyOptionId => unsafe { send_allow_legacy(sender_arc_ptr, count) },
+        SendStyle::LegacyOptionId => unsafe {
+            send_legacy_option_id(sender_arc_ptr, &option.id, count)
+        },
         SendStyle::SelectedOutcome => {
-            let config = CONFIG.get();
-            let outcome = if is_plan_mode {
-                let plan_opt = config
-                    .map(|c| c.plan_option)
-                    .unwrap_or(PlanOption::AcceptEdits);
-                tracing::info!(
-                    "tool_authorization #{count}: ExitPlanMode detected, plan_option={:?}",
-                    plan_opt
-                );
-                build_plan_outcome(plan_opt)
-            } else {
-                let tool_opt = config.map(|c| c.tool_option).unwrap_or(ToolOption::Allow);
-                build_tool_outcome(tool_opt)
+            let outcome = SelectedPermissionOutcome {
+                option_id: acp::PermissionOptionId::new(option.id.clone()),
+                option_kind: acp_option_kind(option.kind),
+                params: None,
             };
             unsafe { send_outcome(sender_arc_ptr, outcome, count) }
         }
     }
 }
 
+/// Niche value we write for `ToolCallStatus::InProgress` (see `force_status_in_progress`).
+const IN_PROGRESS_NICHE: u64 = 0x8000_0000_0000_0004;
+
+/// Niche value we write for `ToolCallStatus::Rejected`.
+///
+/// Same reasoning as `IN_PROGRESS_NICHE`: niche_start + variant index, with
+/// `Rejected` being variant 5 (Pending, WaitingForConfirmation, InProgress,
+/// Completed, Failed, Rejected, Canceled).
+const REJECTED_NICHE: u64 = 0x8000_0000_0000_0007;
+
 /// After a successful send, overwrite the entry's status to InProgress.
 ///
 /// `authorize_tool_call` does `mem::replace(&mut call.status, InProgress)` which
@@ -399,128 +600,98 @@ unsafe fn send_allow(
 /// niche_start + 2). If wrong, at minimum it takes the status OUT of the
 /// WaitingForConfirmation range, which stops the dialog from rendering.
 ///
-/// # Safety
-/// `entry` must be a valid entry pointer and `layout` must match the binary.
-pub(crate) unsafe fn force_status_in_progress(entry: u64, layout: &EntryLayout) {
-    // Write InProgress niche value to status_head
+/// `layout` must match the binary; an entry that is no longer mapped
+/// writable is left alone.
+pub(crate) fn force_status_in_progress(mem: &impl SafeMem, entry: u64, layout: &EntryLayout) {
+    force_status(mem, entry, layout, IN_PROGRESS_NICHE);
+}
+
+/// After a successful reject, overwrite the entry's status to Rejected.
+///
+/// Mirrors what `authorize_tool_call` does for a reject option so the dialog
+/// disappears and the entry renders as rejected.
+pub(crate) fn force_status_rejected(mem: &impl SafeMem, entry: u64, layout: &EntryLayout) {
+    force_status(mem, entry, layout, REJECTED_NICHE);
+}
+
+fn force_status(mem: &impl SafeMem, entry: u64, layout: &EntryLayout, new_head: u64) {
+    // Write the target niche value to status_head.
     // This takes the entry out of WaitingForConfirmation regardless of exact variant mapping
-    let status_ptr = (entry + layout.status_offset as u64) as *mut u64;
+    let status_addr = entry + layout.status_offset as u64;
 
     // Read current value for logging
-    let old_head = unsafe { *status_ptr };
+    let Some(old_head) = mem.read_u64(status_addr) else {
+        tracing::warn!("force_status: entry {entry:#x} is no longer readable, status left alone");
+        return;
+    };
 
-    // Write a value >= niche_start to exit WaitingForConfirmation
-    // We use niche_start + 2 as a guess for InProgress (Pending=+0, WFC=niche, InProgress=+2)
-    // The exact value may not be InProgress, but it will be "not WaitingForConfirmation"
+    // Any value >= niche_start exits WaitingForConfirmation. The exact value may
+    // not be the intended variant, but it will be "not WaitingForConfirmation"
     // which is sufficient to dismiss the dialog.
-    let in_progress_niche: u64 = 0x8000_0000_0000_0004;
-    unsafe { *status_ptr = in_progress_niche };
+    if !mem.write_u64(status_addr, new_head) {
+        tracing::warn!("force_status: entry {entry:#x} is not writable, status left alone");
+        return;
+    }
 
     // Zero the respond_tx pointer so it's not dangling
-    let tx_ptr = (entry + layout.respond_tx_offset as u64) as *mut u64;
-    unsafe { *tx_ptr = 0 };
+    mem.write_u64(entry + layout.respond_tx_offset as u64, 0);
 
-    tracing::debug!(
-        "force_status_in_progress: entry status_head {old_head:#x} → {in_progress_niche:#x}"
-    );
+    tracing::debug!("force_status: entry status_head {old_head:#x} → {new_head:#x}");
 }
 
-/// Attempt to detect if this is an ExitPlanMode prompt by reading the first
-/// PermissionOption's option_id from the WaitingForConfirmation entry.
-///
-/// ExitPlanMode options start with "bypassPermissions" or "acceptEdits" (never "allow").
-/// Regular tool options start with "allow_always" (never "acceptEdits").
-///
-/// Returns `true` if this appears to be an ExitPlanMode prompt.
+/// Detect an ExitPlanMode prompt: the offered option ids are exactly
+/// session-mode names ("bypassPermissions", "acceptEdits", "default", "plan"),
+/// "plan" included, rather than allow/reject ids.
 ///
-/// This is best-effort: if pointer validation fails, returns `false` (conservative,
-/// falls back to regular tool behavior).
-unsafe fn detect_plan_mode(entry: u64, layout: &EntryLayout, count: u64) -> bool {
-    // Only supported for v0.230.x layout with known status structure.
-    // The PermissionOptions enum is at the start of the WaitingForConfirmation payload.
-    // PermissionOptions::Flat(Vec<PermissionOption>) has discriminant 0, followed by Vec.
-    //
-    // Status layout within WaitingForConfirmation (entry + status_offset):
-    //   +0x00: niche/status head (used for waiting detection)
-    //   +0x08: PermissionOptions discriminant (0 = Flat)
-    //   +0x10: Vec<PermissionOption>.ptr
-    //   +0x18: Vec<PermissionOption>.len
-    //   ...
-    //   +0x48: respond_tx
-    //
-    // This is speculative — based on typical Rust enum layout for:
-    //   enum PermissionOptions { Flat(Vec<T>), Dropdown(Vec<U>), DropdownWithPatterns{...} }
-    // where the largest variant determines the enum size.
-    //
-    // If the offsets are wrong, pointer validation will fail and we'll return false.
-
-    let status_base = entry + layout.status_offset as u64;
-
-    // Read what we think is the PermissionOptions discriminant
-    let perm_opts_disc = unsafe { *((status_base + 0x08) as *const u64) };
-
-    // Flat variant should be discriminant 0
-    if perm_opts_disc != 0 {
+/// This is best-effort: if the option list can't be decoded, returns `false`
+/// (conservative, falls back to regular tool behavior).
+fn detect_plan_mode(mem: &impl SafeMem, entry: u64, layout: &EntryLayout, count: u64) -> bool {
+    let Some(options) = read_permission_options(mem, entry, layout) else {
         tracing::debug!(
-            "tool_authorization #{count}: PermissionOptions discriminant={perm_opts_disc:#x} (not Flat), skipping plan detection"
+            "tool_authorization #{count}: could not decode permission options, assuming regular tool"
         );
         return false;
-    }
-
-    // Read Vec<PermissionOption> ptr and len
-    let vec_ptr = unsafe { *((status_base + 0x10) as *const u64) };
-    let vec_len = unsafe { *((status_base + 0x18) as *const u64) };
-
-    if vec_ptr == 0 || vec_len == 0 || vec_len > 10 {
-        tracing::debug!(
-            "tool_authorization #{count}: options vec ptr={vec_ptr:#x} len={vec_len} — invalid, skipping plan detection"
-        );
-        return false;
-    }
-
-    // Read the first PermissionOption's option_id (Arc<str>).
-    // PermissionOption layout (speculative, based on field types):
-    //   +0x00: option_id: PermissionOptionId (= Arc<str>, 16 bytes: ptr + len)
-    //   +0x10: name: String (24 bytes: ptr + len + cap)
-    //   +0x28: kind: PermissionOptionKind (1-4 bytes + padding)
-    //   +0x30: meta: Option<Map<String,Value>> (...)
-    //
-    // We only need the first option_id to distinguish tool vs plan.
-    let first_opt_id = unsafe { read_arc_str(vec_ptr, 0x00, 0x08) };
-
-    if let Some(id_str) = unsafe { arc_str_to_string(first_opt_id) } {
-        tracing::debug!("tool_authorization #{count}: first option_id = \"{id_str}\"");
-        // ExitPlanMode options: "bypassPermissions", "acceptEdits", "default", "plan"
-        // Regular tool options: "allow_always", "allow", "reject"
-        let is_plan = matches!(
-            id_str.as_str(),
-            "bypassPermissions" | "acceptEdits" | "default" | "plan"
-        );
-        if is_plan {
-            tracing::info!(
-                "tool_authorization #{count}: detected ExitPlanMode (first_option=\"{id_str}\")"
-            );
-        }
-        return is_plan;
-    }
+    };
 
     tracing::debug!(
-        "tool_authorization #{count}: could not read first option_id, assuming regular tool"
+        "tool_authorization #{count}: options = {}",
+        options.summary()
     );
-    false
+    let is_plan = options.is_plan_prompt();
+    if is_plan {
+        tracing::info!(
+            "tool_authorization #{count}: detected ExitPlanMode ({})",
+            options.summary()
+        );
+    }
+    is_plan
 }
 
+/// Find the WaitingForConfirmation entry for `current_call_id`, newest first.
+///
+/// Returns (respond_tx, is_plan_mode, entry_ptr). An unreadable entry ends
+/// the walk: the buffer is contiguous, so the Vec itself is stale.
 fn find_waiting_sender(
+    mem: &impl SafeMem,
     entries_ptr: u64,
     entries_len: u64,
     layout: EntryLayout,
     current_call_id: ArcStrRef,
     count: u64,
 ) -> Option<(u64, bool, u64)> {
-    // Returns (respond_tx, is_plan_mode, entry_ptr)
     for i in (0..entries_len).rev() {
         let entry = entries_ptr + (i * layout.entry_size as u64);
-        let discriminant = unsafe { *(entry as *const u64).byte_add(ENTRY_DISCRIMINANT_OFFSET) };
+        let (Some(discriminant), Some(status_head), Some(tx)) = (
+            mem.read_u64(entry + ENTRY_DISCRIMINANT_OFFSET as u64),
+            mem.read_u64(entry + layout.status_offset as u64),
+            mem.read_u64(entry + layout.respond_tx_offset as u64),
+        ) else {
+            tracing::debug!(
+                "tool_authorization #{count}: layout={} entry[{i}] at {entry:#x} is unreadable, stopping",
+                layout.name
+            );
+            return None;
+        };
 
         match layout.match_style {
             MatchStyle::Preview230 {
@@ -533,8 +704,7 @@ fn find_waiting_sender(
                     continue;
                 }
 
-                let status_head = unsafe { *(entry as *const u64).byte_add(layout.status_offset) };
-                let entry_id = unsafe { read_arc_str(entry, id_ptr_offset, id_len_offset) };
+                let entry_id = read_arc_str(mem, entry, id_ptr_offset, id_len_offset);
 
                 tracing::debug!(
                     "tool_authorization #{count}: layout={} entry[{i}] disc={discriminant:#x} status_head={status_head:#x} id_len={}",
@@ -542,7 +712,7 @@ fn find_waiting_sender(
                     entry_id.len
                 );
 
-                if !unsafe { arc_str_eq(entry_id, current_call_id) } {
+                if !arc_str_eq(mem, entry_id, current_call_id) {
                     continue;
                 }
 
@@ -554,10 +724,9 @@ fn find_waiting_sender(
                     continue;
                 }
 
-                let tx = unsafe { *(entry as *const u64).byte_add(layout.respond_tx_offset) };
-                if unsafe { looks_like_sender_arc(tx) } {
+                if looks_like_sender_arc(mem, tx) {
                     // Attempt ExitPlanMode detection
-                    let is_plan = unsafe { detect_plan_mode(entry, &layout, count) };
+                    let is_plan = detect_plan_mode(mem, entry, &layout, count);
 
                     tracing::info!(
                         "tool_authorization #{count}: matched {} entry[{i}] by ToolCallId, respond_tx={tx:#x}, plan_mode={is_plan}",
@@ -575,7 +744,7 @@ fn find_waiting_sender(
                 toolcall_variant,
                 waiting_variant,
             } => {
-                let status = unsafe { *(entry as *const u64).byte_add(layout.status_offset) };
+                let status = status_head;
 
                 tracing::debug!(
                     "tool_authorization #{count}: layout={} entry[{i}] disc={discriminant:#x} status={status:#x}",
@@ -586,8 +755,7 @@ fn find_waiting_sender(
                     continue;
                 }
 
-                let tx = unsafe { *(entry as *const u64).byte_add(layout.respond_tx_offset) };
-                if unsafe { looks_like_sender_arc(tx) } {
+                if looks_like_sender_arc(mem, tx) {
                     tracing::info!(
                         "tool_authorization #{count}: matched layout {} at entry[{i}], respond_tx={tx:#x}",
                         layout.name
@@ -602,21 +770,419 @@ fn find_waiting_sender(
     None
 }
 
+// ---- Snapshot + policy gate ----
+
+/// The tool_call_id of the ToolCall held by `entry`, from the match offsets.
+/// `None` for layouts without them.
+pub(crate) fn entry_tool_call_id(
+    mem: &impl SafeMem,
+    entry: u64,
+    layout: &EntryLayout,
+) -> Option<String> {
+    match layout.match_style {
+        MatchStyle::Preview230 {
+            id_ptr_offset,
+            id_len_offset,
+            ..
+        } => arc_str_to_string(mem, read_arc_str(mem, entry, id_ptr_offset, id_len_offset)),
+        MatchStyle::LegacyExact { .. } => None,
+    }
+}
+
+/// Decode what is known about the ToolCall held by `entry`.
+///
+/// The tool_call_id comes from the match offsets; everything else from
+/// `layout.snapshot` when the layout has one.
+pub(crate) fn snapshot(mem: &impl SafeMem, entry: u64, layout: &EntryLayout) -> ToolCallSnapshot {
+    let tool_call_id = entry_tool_call_id(mem, entry, layout);
+
+    match &layout.snapshot {
+        Some(snapshot_layout) => {
+            tool_call_snapshot::read(mem, entry, snapshot_layout, tool_call_id)
+        }
+        None => ToolCallSnapshot {
+            tool_call_id,
+            ..ToolCallSnapshot::default()
+        },
+    }
+}
+
+/// Set once the "denylist without a title or command" warning was logged.
+static UNDECODABLE_DENYLIST_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Evaluate `config`'s policy for a decoded tool call from `agent` in
+/// `workspace`. An `allow` becomes `ask` when `path_scope` rejects the
+/// call's paths.
+///
+/// `log_prefix` identifies the caller in the log (e.g.
+/// `tool_authorization #12 [s:ab12]`). Anything other than `allow` is logged.
+pub(crate) fn policy_decision(
+    config: &YoloConfig,
+    snapshot: &ToolCallSnapshot,
+    agent: Option<&str>,
+    workspace: Option<&Path>,
+    log_prefix: &str,
+) -> Verdict {
+    let mut facts = snapshot.facts();
+    facts.agent = agent.map(str::to_string);
+    facts.workspace = workspace.map(|root| root.to_string_lossy().into_owned());
+    let mut verdict = config.policy.verdict(&facts);
+    if verdict.source == VerdictSource::Undecodable
+        && !config.policy.denylist.is_empty()
+        && facts.title.is_none()
+        && facts.command.is_none()
+        && !UNDECODABLE_DENYLIST_WARNED.swap(true, Ordering::Relaxed)
+    {
+        tracing::warn!(
+            "{log_prefix}: policy.denylist is set but this tool call's title and command can't be decoded — \
+             every such call is left for you (no auto-approval) until the denylist is emptied or the layout decodes titles"
+        );
+    }
+    if verdict.decision == Decision::Allow
+        && let Err(violation) = path_scope::check_call(&config.path_scope, snapshot, workspace)
+    {
+        tracing::info!("{log_prefix}: path_scope: {violation}");
+        verdict = Verdict {
+            decision: Decision::Ask,
+            source: VerdictSource::PathScope,
+        };
+    }
+    match verdict.decision {
+        Decision::Allow => {}
+        Decision::Deny => tracing::info!(
+            "{log_prefix}: policy decision=Deny via {} ({}) — {}",
+            verdict.source,
+            snapshot.summary(),
+            if observe_only(config) {
+                "would reject"
+            } else {
+                "rejecting"
+            }
+        ),
+        Decision::Ask => tracing::info!(
+            "{log_prefix}: policy decision=Ask via {} ({}) — leaving dialog for user",
+            verdict.source,
+            snapshot.summary()
+        ),
+    }
+    verdict
+}
+
+/// What the hook did with a waiting entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Resolution {
+    /// Allow outcome sent; status forced to InProgress.
+    Approved,
+    /// Reject outcome sent; status forced to Rejected.
+    Rejected,
+    /// Policy said ask, no acceptable option, or observe-only; the dialog is
+    /// left for the user.
+    LeftForUser,
+    /// The oneshot send failed (receiver dropped / already consumed).
+    SendFailed,
+    /// Another path (hook or stale scanner) already claimed the entry; nothing
+    /// done.
+    Duplicate,
+    /// The stale scanner hasn't worked out the thread's agent and config yet
+    /// (`thread_context`); the entry is left for its next sweep.
+    Deferred,
+}
+
+/// The thread's context, with the entry claimed (`claim`) for the calling
+/// path. Without a context the entry is `Deferred` unclaimed, so the sweep
+/// that builds the context still decides it; `Duplicate` when another path
+/// has the claim.
+fn claim_entry(
+    context: Option<Arc<thread_context::ThreadContext>>,
+    claim: impl FnOnce() -> bool,
+) -> Result<Arc<thread_context::ThreadContext>, Resolution> {
+    let context = context.ok_or(Resolution::Deferred)?;
+    if !claim() {
+        return Err(Resolution::Duplicate);
+    }
+    Ok(context)
+}
+
+/// Run the policy for a waiting entry and act on its decision.
+///
+/// Shared by the hook (first attempt + retry) and the stale scanner. Every
+/// outcome is written to the audit log. Nothing is decided once the budget
+/// is exhausted or the circuit breaker is open; an approval uses up one unit
+/// of the budget and counts towards the breaker. Otherwise the entry is
+/// claimed in `attempts` first (once the thread's context is known), so the
+/// hook and the scanner never both act on it.
+///
+/// # Safety
+/// `entry` and `respond_tx` must come from a WaitingForConfirmation entry that
+/// matches `layout`: the sender is used as a live oneshot, so this always
+/// works on `LiveMemory`.
+pub(crate) unsafe fn resolve_entry(
+    layout: &EntryLayout,
+    entry: u64,
+    respond_tx: u64,
+    is_plan: bool,
+    snapshot: &ToolCallSnapshot,
+    count: u64,
+    origin: &Origin,
+) -> Resolution {
+    let mem = &LiveMemory;
+    let log_prefix = origin.log_prefix.as_str();
+    let budget = &config::current().budget;
+    if budget::exhausted(budget) {
+        tracing::info!(
+            "{log_prefix}: budget exhausted — leaving dialog for user {}",
+            snapshot.summary()
+        );
+        audit_decision(
+            origin,
+            Action::LeftForUser,
+            layout,
+            snapshot,
+            "budget".to_string(),
+            None,
+        );
+        return Resolution::LeftForUser;
+    }
+    if let Some(trip) = circuit_breaker::tripped() {
+        tracing::info!(
+            "{log_prefix}: circuit breaker open ({trip}) — leaving dialog for user {}",
+            snapshot.summary()
+        );
+        audit_decision(
+            origin,
+            Action::LeftForUser,
+            layout,
+            snapshot,
+            "circuit_breaker".to_string(),
+            None,
+        );
+        return Resolution::LeftForUser;
+    }
+    let context = match claim_entry(thread_context::get(origin.thread), || {
+        attempts::claim(
+            origin.thread,
+            EntryId::new(snapshot.tool_call_id.as_deref(), entry),
+        )
+    }) {
+        Ok(context) => context,
+        Err(Resolution::Deferred) => {
+            tracing::debug!(
+                "{log_prefix}: thread not known to the stale scanner yet — leaving it the entry {}",
+                snapshot.summary()
+            );
+            super::stale_scanner::wake();
+            return Resolution::Deferred;
+        }
+        Err(resolution) => {
+            tracing::debug!(
+                "{log_prefix}: already handled by another path {}",
+                snapshot.summary()
+            );
+            return resolution;
+        }
+    };
+    let agent = context.agent.as_deref();
+    let project = context.project.as_deref();
+    let config: &YoloConfig = &context.config;
+    tracing::debug!(
+        "{log_prefix}: agent={} project={}{}",
+        agent.unwrap_or("?"),
+        project.map_or("?".into(), |root| root.to_string_lossy()),
+        if context.project_file {
+            " (with .zed/yolo.json)"
+        } else {
+            ""
+        }
+    );
+    if config.mode == YoloMode::Disabled {
+        tracing::info!(
+            "{log_prefix}: disabled for this agent — leaving dialog for user {}",
+            snapshot.summary()
+        );
+        audit_decision(
+            origin,
+            Action::LeftForUser,
+            layout,
+            snapshot,
+            "agent".to_string(),
+            None,
+        );
+        return Resolution::LeftForUser;
+    }
+
+    let verdict = policy_decision(config, snapshot, agent, project, log_prefix);
+    let decision = verdict.decision;
+    let record = |action, option: Option<&PermissionOptionInfo>| {
+        audit_decision(
+            origin,
+            action,
+            layout,
+            snapshot,
+            verdict.source.to_string(),
+            option,
+        )
+    };
+    if decision == Decision::Ask {
+        record(Action::LeftForUser, None);
+        return Resolution::LeftForUser;
+    }
+
+    // Choosing reads the dialog's option list only; observe mode stops after it.
+    let Some(option) = choose_option(mem, config, layout, entry, is_plan, decision, log_prefix)
+    else {
+        record(Action::LeftForUser, None);
+        return Resolution::LeftForUser;
+    };
+    if observe_only(config) {
+        let status_head = mem
+            .read_u64(entry + layout.status_offset as u64)
+            .unwrap_or_default();
+        tracing::info!(
+            "{log_prefix}: observe-only — would send option_id=\"{}\" ({:?}) and force status {} \
+             (decision={decision:?} via {}, layout={}, plan_mode={is_plan}, entry={entry:#x}, \
+             status_head={status_head:#x}, respond_tx={respond_tx:#x}) {}",
+            option.id,
+            option.kind,
+            if decision == Decision::Deny {
+                "Rejected"
+            } else {
+                "InProgress"
+            },
+            verdict.source,
+            layout.name,
+            snapshot.summary()
+        );
+        record(Action::Observed, Some(&option));
+        return Resolution::LeftForUser;
+    }
+
+    let left = if decision == Decision::Allow {
+        match budget::take(budget) {
+            Ok(left) => Some(left),
+            Err(_) => {
+                tracing::info!(
+                    "{log_prefix}: budget exhausted — leaving dialog for user {}",
+                    snapshot.summary()
+                );
+                record(Action::LeftForUser, None);
+                return Resolution::LeftForUser;
+            }
+        }
+    } else {
+        None
+    };
+    let session = Some(origin.session_tag.as_str());
+    if left.is_some()
+        && let Err(trip) = circuit_breaker::take(&config::current().circuit_breaker, session)
+    {
+        budget::refund();
+        tracing::info!(
+            "{log_prefix}: circuit breaker open ({trip}) — leaving dialog for user {}",
+            snapshot.summary()
+        );
+        audit_decision(
+            origin,
+            Action::LeftForUser,
+            layout,
+            snapshot,
+            "circuit_breaker".to_string(),
+            None,
+        );
+        return Resolution::LeftForUser;
+    }
+    if !unsafe { send_option(layout, respond_tx, &option, count) } {
+        if left.is_some() {
+            budget::refund();
+            circuit_breaker::refund(session);
+        }
+        record(Action::SendFailed, Some(&option));
+        return Resolution::SendFailed;
+    }
+
+    if decision == Decision::Deny {
+        force_status_rejected(mem, entry, layout);
+        POLICY_REJECT_COUNT.fetch_add(1, Ordering::Relaxed);
+        record(Action::Rejected, Some(&option));
+        Resolution::Rejected
+    } else {
+        // Force the entry status to InProgress so the UI dismisses the dialog.
+        // Without this, the oneshot response is delivered but the status byte
+        // stays as WaitingForConfirmation — the dialog keeps rendering.
+        force_status_in_progress(mem, entry, layout);
+        record(Action::Approved, Some(&option));
+        if let Some(left) = left.filter(|_| budget.is_limited()) {
+            tracing::info!("{log_prefix}: budget {left}");
+        }
+        Resolution::Approved
+    }
+}
+
+/// Observe-only globally (`hooks::observe_only`) or for this agent.
+fn observe_only(config: &YoloConfig) -> bool {
+    super::observe_only() || config.mode == YoloMode::Observe
+}
+
+/// Where a `resolve_entry` call comes from, for log lines and the audit log.
+pub(crate) struct Origin {
+    pub(crate) path: ApprovalPath,
+    /// The AcpThread the entry belongs to (selects its `thread_context`).
+    pub(crate) thread: u64,
+    /// Session tag (`{self_ptr & 0xFFFF:04x}`).
+    pub(crate) session_tag: String,
+    /// Log line prefix, e.g. `tool_authorization #12 [s:ab12]`.
+    pub(crate) log_prefix: String,
+    /// When handling started; the audit latency is measured from here.
+    pub(crate) started: Instant,
+}
+
+fn audit_decision(
+    origin: &Origin,
+    action: Action,
+    layout: &EntryLayout,
+    snapshot: &ToolCallSnapshot,
+    source: String,
+    option: Option<&PermissionOptionInfo>,
+) {
+    audit::record(&audit::Record {
+        session: Some(origin.session_tag.clone()),
+        tool_call_id: snapshot.tool_call_id.clone(),
+        title: snapshot.title.clone(),
+        kind: snapshot.kind,
+        source: Some(source),
+        option_id: option.map(|o| o.id.clone()),
+        option_kind: option.map(|o| o.kind),
+        layout: Some(layout.name),
+        latency_us: origin.started.elapsed().as_micros() as u64,
+        ..audit::Record::new(origin.path, action)
+    });
+}
+
 // ---- Diagnostics for missed approvals ----
 
-fn diagnose_miss(entries_ptr: u64, entries_len: u64, current_call_id: ArcStrRef, count: u64) {
+/// Log why the entry wasn't found. Returns `true` when the miss looks like
+/// layout drift rather than a race (no ToolCall entries, unreadable call id).
+fn diagnose_miss(
+    mem: &impl SafeMem,
+    self_ptr: u64,
+    current_call_id: ArcStrRef,
+    count: u64,
+) -> bool {
     // Collect diagnostic info about why the entry wasn't found
     let mut toolcall_count: u64 = 0;
     let mut id_matched_count: u64 = 0;
     let mut id_matched_statuses: Vec<u64> = Vec::new();
     let mut disc_counts: std::collections::HashMap<u64, u64> = std::collections::HashMap::new();
 
-    // Only scan with the primary layout (v0.230.x)
-    let layout = &ENTRY_LAYOUTS[0];
+    // Only scan with the primary layout
+    let layout = layouts::primary();
+    let (entries_ptr, entries_len) = read_entries(mem, self_ptr, layout);
 
     for i in 0..entries_len {
         let entry = entries_ptr + (i * layout.entry_size as u64);
-        let discriminant = unsafe { *(entry as *const u64).byte_add(ENTRY_DISCRIMINANT_OFFSET) };
+        let Some(discriminant) = mem.read_u64(entry + ENTRY_DISCRIMINANT_OFFSET as u64) else {
+            // Stale Vec: the rest of the buffer is unreadable too.
+            break;
+        };
         *disc_counts.entry(discriminant).or_insert(0) += 1;
 
         if let MatchStyle::Preview230 {
@@ -628,19 +1194,21 @@ fn diagnose_miss(entries_ptr: u64, entries_len: u64, current_call_id: ArcStrRef,
         {
             if discriminant == toolcall_variant {
                 toolcall_count += 1;
-                let entry_id = unsafe { read_arc_str(entry, id_ptr_offset, id_len_offset) };
-                if unsafe { arc_str_eq(entry_id, current_call_id) } {
+                let entry_id = read_arc_str(mem, entry, id_ptr_offset, id_len_offset);
+                if arc_str_eq(mem, entry_id, current_call_id) {
                     id_matched_count += 1;
-                    let status_head =
-                        unsafe { *(entry as *const u64).byte_add(layout.status_offset) };
-                    id_matched_statuses.push(status_head);
+                    if let Some(status_head) = mem.read_u64(entry + layout.status_offset as u64) {
+                        id_matched_statuses.push(status_head);
+                    }
                 }
             }
         }
     }
 
-    let call_id_str =
-        unsafe { arc_str_to_string(current_call_id) }.unwrap_or_else(|| "<unreadable>".to_string());
+    let call_id = arc_str_to_string(mem, current_call_id);
+    let call_id_str = call_id
+        .clone()
+        .unwrap_or_else(|| "<unreadable>".to_string());
 
     if id_matched_count > 0 {
         tracing::warn!(
@@ -664,6 +1232,87 @@ fn diagnose_miss(entries_ptr: u64, entries_len: u64, current_call_id: ArcStrRef,
             disc_summary.join(",")
         );
     }
+
+    toolcall_count == 0 || call_id.is_none()
+}
+
+// ---- Self-calibration on layout drift ----
+
+/// Calibration runs at most once per process: its proposal doesn't change
+/// between misses.
+static CALIBRATION_ATTEMPTED: AtomicBool = AtomicBool::new(false);
+
+/// The calibration `calibrate_once` handed to the stale scanner; taken by
+/// `run_calibration`.
+static CALIBRATION: AtomicPtr<calibrate::Request> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Have the stale scanner propose a layout from live memory after a
+/// drift-looking miss (see `crate::calibrate`). In calibration mode this
+/// runs on the first tool call.
+///
+/// `update_ptr` is the `ToolCallUpdate` argument saved in `on_enter`. By
+/// `on_leave` its fields have been moved into the entry, but the bytes are
+/// still in the caller's frame and the `tool_call_id` Arc is alive (the entry
+/// holds it), which is all calibration needs. The bytes are copied here, in
+/// one read; the scan itself runs on the scanner thread.
+fn calibrate_once(self_ptr: u64, update_ptr: u64, count: u64) {
+    if update_ptr == 0 || CALIBRATION_ATTEMPTED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let request = calibrate::Request::capture(&LiveMemory, self_ptr, update_ptr, count);
+    CALIBRATION.store(Box::into_raw(Box::new(request)), Ordering::Release);
+    super::stale_scanner::wake();
+}
+
+/// Run the calibration `calibrate_once` asked for, if any. Only logs; the
+/// proposal is applied by adding it to the layout database. In calibration
+/// mode the proposal is also written to the report file for `cargo patch
+/// calibrate`. Only call from the stale scanner thread.
+pub(crate) fn run_calibration() {
+    let request = CALIBRATION.swap(std::ptr::null_mut(), Ordering::Acquire);
+    if request.is_null() {
+        return;
+    }
+    // SAFETY: from `Box::into_raw` in `calibrate_once`, stored once and
+    // taken once by the swap above.
+    let request = unsafe { Box::from_raw(request) };
+    let count = request.count;
+
+    let t0 = Instant::now();
+    let hint = layouts::primary();
+    let reason = if calibrate::output_path().is_some() {
+        format!("calibration mode (current layout {})", hint.name)
+    } else {
+        format!("layout drift suspected ({} missed)", hint.name)
+    };
+    let proposal = calibrate::calibrate_request(&LiveMemory, &request, hint);
+    match &proposal {
+        Some(proposal) => tracing::warn!(
+            "tool_authorization #{count}: {reason} — calibration proposes {} in {}us",
+            proposal.summary(),
+            t0.elapsed().as_micros()
+        ),
+        None => tracing::warn!(
+            "tool_authorization #{count}: {reason} — calibration found no candidate in {}us",
+            t0.elapsed().as_micros()
+        ),
+    }
+
+    if let Some(path) = calibrate::output_path()
+        && let Some(app) = crate::APP.get()
+    {
+        let report = calibrate::report(app, proposal.as_ref(), hint);
+        match calibrate::write_report(path, &report) {
+            Ok(()) => tracing::warn!(
+                "tool_authorization #{count}: calibration report written to {}",
+                path.display()
+            ),
+            Err(e) => tracing::error!(
+                "tool_authorization #{count}: cannot write calibration report {}: {e}",
+                path.display()
+            ),
+        }
+    }
 }
 
 // ---- InvocationListener ----
@@ -677,19 +1326,37 @@ impl frida_gum::interceptor::InvocationListener for Listener {
         let tool_call_update_ptr = cpu.reg(1);
 
         SAVED_SELF.with(|c| c.set(self_ptr));
+        SAVED_UPDATE.with(|c| c.set(tool_call_update_ptr));
         // Register this AcpThread for periodic scanning
         super::entry_scanner::register_thread(self_ptr);
-        SAVED_TOOL_CALL_ID.with(|c| {
-            let call_id = unsafe { read_tool_call_id_v230(tool_call_update_ptr) };
-            c.set(call_id);
+        SAVED_TOOL_CALL_IDS.with(|c| {
+            *c.borrow_mut() =
+                read_tool_call_ids(&LiveMemory, tool_call_update_ptr, layouts::active());
         });
+        // The update is consumed by the call: decode what it says now.
+        let update_snapshot = if super::inactive() {
+            None
+        } else {
+            tool_call_snapshot::read_update(&LiveMemory, tool_call_update_ptr, layouts::active())
+        };
+        SAVED_SNAPSHOT.with(|c| *c.borrow_mut() = update_snapshot);
     }
 
     fn on_leave(&mut self, _context: frida_gum::interceptor::InvocationContext) {
+        if super::inactive() {
+            return;
+        }
         let t0 = Instant::now();
         let count = TOOL_AUTHORIZATION_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
         let self_ptr = SAVED_SELF.with(|c| c.get());
-        let current_call_id = SAVED_TOOL_CALL_ID.with(|c| c.get());
+        let update_ptr = SAVED_UPDATE.with(|c| c.get());
+        let call_ids = SAVED_TOOL_CALL_IDS.with(|c| std::mem::take(&mut *c.borrow_mut()));
+        let update_snapshot = SAVED_SNAPSHOT
+            .with(|c| c.borrow_mut().take())
+            .unwrap_or_default();
+        // Logging and miss diagnostics use the primary layout's reading;
+        // matching uses each layout's own.
+        let current_call_id = call_id_for(&call_ids, layouts::primary());
 
         // Session tag: short identifier derived from AcpThread pointer.
         // Each workspace's agent session gets its own AcpThread instance,
@@ -697,7 +1364,7 @@ impl frida_gum::interceptor::InvocationListener for Listener {
         let session_tag = format!("{:04x}", self_ptr & 0xFFFF);
 
         // Read tool_call_id string for log correlation
-        let call_id_str = unsafe { arc_str_to_string(current_call_id) }.unwrap_or_default();
+        let call_id_str = arc_str_to_string(&LiveMemory, current_call_id).unwrap_or_default();
         // Truncate long IDs for log readability (tool_call_ids are often UUIDs)
         let call_id_short = if call_id_str.len() > 12 {
             &call_id_str[..12]
@@ -716,73 +1383,108 @@ impl frida_gum::interceptor::InvocationListener for Listener {
             return;
         }
 
+        // Calibration mode: propose a layout from this call whether or not
+        // the current one matches.
+        if calibrate::output_path().is_some() {
+            calibrate_once(self_ptr, update_ptr, count);
+        }
+
         // Walk self.entries to find the last WaitingForConfirmation entry
-        let (entries_ptr, entries_len) = unsafe {
-            let p = self_ptr as *const u64;
-            let ptr = *p.byte_add(ENTRIES_PTR_OFFSET);
-            let len = *p.byte_add(ENTRIES_LEN_OFFSET);
-            (ptr, len)
-        };
+        let (entries_ptr, entries_len) = read_entries(&LiveMemory, self_ptr, layouts::primary());
 
         tracing::debug!(
             "tool_authorization #{count} [s:{session_tag}]: entries ptr={entries_ptr:#x}, len={entries_len}"
         );
 
-        if entries_ptr == 0 || entries_len == 0 {
+        let any_entries = layouts::active().iter().any(|layout| {
+            let (ptr, len) = read_entries(&LiveMemory, self_ptr, layout);
+            ptr != 0 && len != 0
+        });
+        if !any_entries {
             tracing::warn!("tool_authorization #{count} [s:{session_tag}]: no entries, skipping");
+            // An authorization request always has its own entry: entries moved.
+            calibrate_once(self_ptr, update_ptr, count);
             return;
         }
 
+        let log_prefix = format!("tool_authorization #{count} [s:{session_tag}]");
+        let mut origin = Origin {
+            path: ApprovalPath::Hook,
+            thread: self_ptr,
+            session_tag: session_tag.clone(),
+            log_prefix: log_prefix.clone(),
+            started: t0,
+        };
+
         // First attempt
         if let Some((layout, respond_tx, is_plan, entry_ptr)) =
-            try_find_sender(entries_ptr, entries_len, current_call_id, count)
+            try_find_sender(&LiveMemory, self_ptr, &call_ids, layouts::active(), count)
         {
-            let ok = unsafe { send_allow(layout, respond_tx, is_plan, count) };
-            if ok {
-                // Force the entry status to InProgress so the UI dismisses the dialog.
-                // Without this, the oneshot response is delivered but the status byte
-                // stays as WaitingForConfirmation — the dialog keeps rendering.
-                unsafe { force_status_in_progress(entry_ptr, &layout) };
-            }
+            let snap = update_snapshot.or(snapshot(&LiveMemory, entry_ptr, &layout));
+            let resolution = unsafe {
+                resolve_entry(
+                    &layout, entry_ptr, respond_tx, is_plan, &snap, count, &origin,
+                )
+            };
             let elapsed_us = t0.elapsed().as_micros();
-            if ok {
-                tracing::info!(
-                    "tool_authorization #{count} [s:{session_tag}]: approved in {elapsed_us}us via {} call_id=\"{call_id_short}\"",
-                    layout.name
-                );
+            match resolution {
+                Resolution::Approved => tracing::info!(
+                    "{log_prefix}: approved in {elapsed_us}us via {} call_id=\"{call_id_short}\" {}",
+                    layout.name,
+                    snap.summary()
+                ),
+                Resolution::Rejected => tracing::info!(
+                    "{log_prefix}: rejected in {elapsed_us}us via {} call_id=\"{call_id_short}\" {}",
+                    layout.name,
+                    snap.summary()
+                ),
+                Resolution::LeftForUser => {
+                    LEFT_FOR_USER_COUNT.fetch_add(1, Ordering::Relaxed);
+                }
+                Resolution::SendFailed | Resolution::Duplicate | Resolution::Deferred => {}
             }
             log_stats(count);
             return;
         }
 
         // Single retry after configurable delay
-        let retry_delay = CONFIG.get().map(|c| c.retry_delay_us).unwrap_or(1500);
+        let retry_delay = config::current().retry_delay_us;
 
         if retry_delay > 0 {
             std::thread::sleep(std::time::Duration::from_micros(retry_delay));
 
             // Re-read entries (Vec may have grown)
-            let (entries_ptr2, entries_len2) = unsafe {
-                let p = self_ptr as *const u64;
-                let ptr = *p.byte_add(ENTRIES_PTR_OFFSET);
-                let len = *p.byte_add(ENTRIES_LEN_OFFSET);
-                (ptr, len)
-            };
-
             if let Some((layout, respond_tx, is_plan, entry_ptr)) =
-                try_find_sender(entries_ptr2, entries_len2, current_call_id, count)
+                try_find_sender(&LiveMemory, self_ptr, &call_ids, layouts::active(), count)
             {
-                TOOL_AUTHORIZATION_RETRY_SUCCESS_COUNT.fetch_add(1, Ordering::Relaxed);
-                let ok = unsafe { send_allow(layout, respond_tx, is_plan, count) };
-                if ok {
-                    unsafe { force_status_in_progress(entry_ptr, &layout) };
-                }
+                origin.path = ApprovalPath::Retry;
+                let snap = update_snapshot.or(snapshot(&LiveMemory, entry_ptr, &layout));
+                let resolution = unsafe {
+                    resolve_entry(
+                        &layout, entry_ptr, respond_tx, is_plan, &snap, count, &origin,
+                    )
+                };
                 let elapsed_us = t0.elapsed().as_micros();
-                if ok {
-                    tracing::info!(
-                        "tool_authorization #{count} [s:{session_tag}]: approved on RETRY in {elapsed_us}us via {} call_id=\"{call_id_short}\" (delay={retry_delay}us)",
-                        layout.name
-                    );
+                match resolution {
+                    Resolution::Approved => {
+                        // Only an approval that was actually sent counts as
+                        // a recovered miss.
+                        TOOL_AUTHORIZATION_RETRY_SUCCESS_COUNT.fetch_add(1, Ordering::Relaxed);
+                        tracing::info!(
+                            "{log_prefix}: approved on RETRY in {elapsed_us}us via {} call_id=\"{call_id_short}\" (delay={retry_delay}us) {}",
+                            layout.name,
+                            snap.summary()
+                        )
+                    }
+                    Resolution::Rejected => tracing::info!(
+                        "{log_prefix}: rejected on RETRY in {elapsed_us}us via {} call_id=\"{call_id_short}\" (delay={retry_delay}us) {}",
+                        layout.name,
+                        snap.summary()
+                    ),
+                    Resolution::LeftForUser => {
+                        LEFT_FOR_USER_COUNT.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Resolution::SendFailed | Resolution::Duplicate | Resolution::Deferred => {}
                 }
                 log_stats(count);
                 return;
@@ -791,35 +1493,68 @@ impl frida_gum::interceptor::InvocationListener for Listener {
 
         // Both attempts failed — log diagnostics
         TOOL_AUTHORIZATION_MISS_COUNT.fetch_add(1, Ordering::Relaxed);
+        // The entry may appear later; have the stale scanner look soon.
+        super::stale_scanner::wake();
         tracing::warn!(
             "tool_authorization #{count} [s:{session_tag}]: MISS for call_id=\"{call_id_short}\""
         );
-        diagnose_miss(entries_ptr, entries_len, current_call_id, count);
+        if diagnose_miss(&LiveMemory, self_ptr, current_call_id, count) {
+            calibrate_once(self_ptr, update_ptr, count);
+        }
         log_stats(count);
     }
 }
 
+/// Find the waiting entry with the first layout in `layouts` that has one,
+/// matching it against the call id read with that layout's offsets.
 fn try_find_sender(
-    entries_ptr: u64,
-    entries_len: u64,
-    current_call_id: ArcStrRef,
+    mem: &impl SafeMem,
+    self_ptr: u64,
+    call_ids: &[SavedCallId],
+    layouts: &[EntryLayout],
     count: u64,
 ) -> Option<(EntryLayout, u64, bool, u64)> {
     // Returns (layout, respond_tx, is_plan_mode, entry_ptr)
-    ENTRY_LAYOUTS.iter().copied().find_map(|layout| {
-        find_waiting_sender(entries_ptr, entries_len, layout, current_call_id, count)
-            .map(|(tx, is_plan, entry)| (layout, tx, is_plan, entry))
+    layouts.iter().copied().find_map(|layout| {
+        let (entries_ptr, entries_len) = read_entries(mem, self_ptr, &layout);
+        if entries_ptr == 0 || entries_len == 0 {
+            return None;
+        }
+        find_waiting_sender(
+            mem,
+            entries_ptr,
+            entries_len,
+            layout,
+            call_id_for(call_ids, &layout),
+            count,
+        )
+        .map(|(tx, is_plan, entry)| (layout, tx, is_plan, entry))
     })
 }
 
+/// Read `AcpThread.entries` (ptr, len) using `layout`'s offsets. `(0, 0)`
+/// when `self_ptr` isn't readable (a freed AcpThread).
+pub(crate) fn read_entries(mem: &impl SafeMem, self_ptr: u64, layout: &EntryLayout) -> (u64, u64) {
+    match (
+        mem.read_u64(self_ptr + layout.entries_ptr_offset as u64),
+        mem.read_u64(self_ptr + layout.entries_len_offset as u64),
+    ) {
+        (Some(ptr), Some(len)) => (ptr, len),
+        _ => (0, 0),
+    }
+}
+
 fn log_stats(count: u64) {
     // Log summary stats every 50 approvals or on any miss
     if count % 50 == 0 {
-        let approved = count - TOOL_AUTHORIZATION_MISS_COUNT.load(Ordering::Relaxed);
         let missed = TOOL_AUTHORIZATION_MISS_COUNT.load(Ordering::Relaxed);
+        let rejected = POLICY_REJECT_COUNT.load(Ordering::Relaxed);
+        let left = LEFT_FOR_USER_COUNT.load(Ordering::Relaxed);
+        let approved = count.saturating_sub(missed + rejected + left);
         let retried = TOOL_AUTHORIZATION_RETRY_SUCCESS_COUNT.load(Ordering::Relaxed);
         tracing::info!(
-            "tool_authorization stats: total={count} approved={approved} missed={missed} retry_recovered={retried}"
+            "tool_authorization stats: total={count} approved={approved} missed={missed} retry_recovered={retried} policy_rejected={rejected} left_for_user={left} budget={}",
+            budget::remaining(&config::current().budget)
         );
     }
 }
@@ -830,25 +1565,228 @@ pub const SYMBOL_EXCLUDE: &[&str] = &["drop_in_place", "closure", "vtable", "isl
 
 // ---- pub(crate) wrappers for entry_scanner ----
 
-pub(crate) unsafe fn looks_like_sender_arc_pub(ptr: u64) -> bool {
-    unsafe { looks_like_sender_arc(ptr) }
+pub(crate) fn looks_like_sender_arc_pub(mem: &impl SafeMem, ptr: u64) -> bool {
+    looks_like_sender_arc(mem, ptr)
 }
 
-pub(crate) unsafe fn detect_plan_mode_pub(entry: u64, layout: &EntryLayout) -> bool {
+pub(crate) fn detect_plan_mode_pub(mem: &impl SafeMem, entry: u64, layout: &EntryLayout) -> bool {
     // Simplified: only call for v0.230.x layout
-    unsafe { detect_plan_mode(entry, layout, 0) }
+    detect_plan_mode(mem, entry, layout, 0)
 }
 
-pub(crate) unsafe fn send_allow_pub(
-    send_style: SendStyle,
-    sender_arc_ptr: u64,
-    is_plan: bool,
-    count: u64,
-) -> bool {
-    // Create a minimal layout to pass to send_allow
-    let layout = ENTRY_LAYOUTS[0]; // v0.230.x
-    // Override send_style if needed
-    let mut l = layout;
-    l.send_style = send_style;
-    unsafe { send_allow(l, sender_arc_ptr, is_plan, count) }
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::SliceMemory;
+
+    const THREAD: u64 = 0x1_1000_0000;
+    const ENTRIES: u64 = 0x1_2000_0000;
+    const SENDER: u64 = 0x1_3000_0000;
+    const CALL_ID: u64 = 0x1_4000_0000;
+    const OTHER_ID: u64 = 0x1_4000_1000;
+    const WANTED_ID: u64 = 0x1_4000_2000;
+    const UPDATE: u64 = 0x1_5000_0000;
+
+    fn layout(name: &str) -> EntryLayout {
+        *layouts::ENTRY_LAYOUTS
+            .iter()
+            .find(|layout| layout.name == name)
+            .unwrap()
+    }
+
+    /// Map an `ArcInner<str>` (strong = weak = 1) at `base`.
+    fn add_arc_str(mem: &mut SliceMemory, base: u64, text: &str) -> ArcStrRef {
+        let mut bytes = [1u64, 1].map(u64::to_le_bytes).concat();
+        bytes.extend_from_slice(text.as_bytes());
+        mem.add(base, bytes);
+        ArcStrRef {
+            ptr: base,
+            len: text.len() as u64,
+        }
+    }
+
+    /// Map a v0.230.x thread whose entries are waiting ToolCalls with the
+    /// given ids, all holding the sender at `SENDER`.
+    fn add_preview230_thread(mem: &mut SliceMemory, ids: &[ArcStrRef]) -> EntryLayout {
+        let layout = layout("v0.230.x");
+        let MatchStyle::Preview230 {
+            toolcall_variant,
+            id_ptr_offset,
+            id_len_offset,
+            ..
+        } = layout.match_style
+        else {
+            unreachable!()
+        };
+
+        let mut thread = vec![0u64; layout.entries_len_offset / 8 + 1];
+        thread[layout.entries_ptr_offset / 8] = ENTRIES;
+        thread[layout.entries_len_offset / 8] = ids.len() as u64;
+        mem.add_words(THREAD, &thread);
+
+        let words = layout.entry_size / 8;
+        let mut entries = vec![0u64; words * ids.len()];
+        for (entry, id) in entries.chunks_mut(words).zip(ids) {
+            entry[ENTRY_DISCRIMINANT_OFFSET / 8] = toolcall_variant;
+            entry[layout.status_offset / 8] = 0; // WaitingForConfirmation
+            entry[layout.respond_tx_offset / 8] = SENDER;
+            entry[id_ptr_offset / 8] = id.ptr;
+            entry[id_len_offset / 8] = id.len;
+        }
+        mem.add_words(ENTRIES, &entries);
+        mem.add_words(SENDER, &[1, 1, 0, 0]);
+        layout
+    }
+
+    #[test]
+    fn test_find_waiting_sender_by_tool_call_id() {
+        let mut mem = SliceMemory::default();
+        let current = add_arc_str(&mut mem, CALL_ID, "toolu_02");
+        let wanted = add_arc_str(&mut mem, WANTED_ID, "toolu_02");
+        let other = add_arc_str(&mut mem, OTHER_ID, "toolu_01");
+        let layout = add_preview230_thread(&mut mem, &[wanted, other]);
+
+        let (ptr, len) = read_entries(&mem, THREAD, &layout);
+        assert_eq!((ptr, len), (ENTRIES, 2));
+        // Matched by content, not by pointer; no options payload → not plan.
+        assert_eq!(
+            find_waiting_sender(&mem, ptr, len, layout, current, 1),
+            Some((SENDER, false, ENTRIES))
+        );
+
+        // Answered entries are skipped.
+        mem.write_u64(ENTRIES + layout.status_offset as u64, 0x8000_0000_0000_0002);
+        assert_eq!(
+            find_waiting_sender(&mem, ptr, len, layout, current, 1),
+            None
+        );
+    }
+
+    #[test]
+    fn test_call_id_read_per_layout() {
+        let mut mem = SliceMemory::default();
+        let current = add_arc_str(&mut mem, CALL_ID, "toolu_02");
+        let wanted = add_arc_str(&mut mem, WANTED_ID, "toolu_02");
+        let other = add_arc_str(&mut mem, OTHER_ID, "toolu_01");
+        let matching = add_preview230_thread(&mut mem, &[wanted]);
+
+        // A primary layout that doesn't fit this build: its entries are
+        // elsewhere and its update id offsets land on another id.
+        let primary = EntryLayout {
+            name: "other",
+            entries_ptr_offset: 0xb0,
+            entries_len_offset: 0xb8,
+            update_id_ptr_offset: 0x10,
+            update_id_len_offset: 0x18,
+            ..matching
+        };
+        let mut update = vec![0u64; matching.update_id_len_offset / 8 + 1];
+        update[2] = other.ptr;
+        update[3] = other.len;
+        update[matching.update_id_ptr_offset / 8] = current.ptr;
+        update[matching.update_id_len_offset / 8] = current.len;
+        mem.add_words(UPDATE, &update);
+
+        let layouts = [primary, matching, matching];
+        let ids = read_tool_call_ids(&mem, UPDATE, &layouts);
+        assert_eq!(ids.len(), 2);
+        assert_eq!(call_id_for(&ids, &primary).ptr, OTHER_ID);
+        assert_eq!(call_id_for(&ids, &matching).ptr, CALL_ID);
+
+        // The entry is matched with the id read through its own layout.
+        let found = try_find_sender(&mem, THREAD, &ids, &layouts, 1);
+        assert_eq!(
+            found.map(|(layout, tx, _, entry)| (layout.name, tx, entry)),
+            Some(("v0.230.x", SENDER, ENTRIES))
+        );
+    }
+
+    #[test]
+    fn test_freed_thread_and_entries_are_not_read() {
+        let mut mem = SliceMemory::default();
+        let current = add_arc_str(&mut mem, CALL_ID, "toolu_01");
+        let layout = add_preview230_thread(&mut mem, &[current]);
+
+        // The entries Vec was reallocated: the walk stops instead of faulting.
+        mem.remove(ENTRIES);
+        let (ptr, len) = read_entries(&mem, THREAD, &layout);
+        assert_eq!(
+            find_waiting_sender(&mem, ptr, len, layout, current, 1),
+            None
+        );
+
+        // The AcpThread itself was freed.
+        mem.remove(THREAD);
+        assert_eq!(read_entries(&mem, THREAD, &layout), (0, 0));
+
+        // An id whose Arc<str> was freed never matches.
+        mem.remove(CALL_ID);
+        assert!(!arc_str_eq(&mem, current, current));
+        assert_eq!(arc_str_to_string(&mem, current), None);
+    }
+
+    #[test]
+    fn test_sender_arc_must_be_mapped() {
+        let mut mem = SliceMemory::default();
+        add_preview230_thread(&mut mem, &[]);
+        assert!(looks_like_sender_arc(&mem, SENDER));
+        mem.write_u64(SENDER, 0);
+        assert!(!looks_like_sender_arc(&mem, SENDER));
+        assert!(!looks_like_sender_arc(&mem, SENDER + 0x1000));
+        assert!(!looks_like_sender_arc(&mem, 0x10));
+    }
+
+    #[test]
+    fn test_force_status() {
+        let mut mem = SliceMemory::default();
+        let current = add_arc_str(&mut mem, CALL_ID, "toolu_01");
+        let layout = add_preview230_thread(&mut mem, &[current]);
+        let status = ENTRIES + layout.status_offset as u64;
+        let tx = ENTRIES + layout.respond_tx_offset as u64;
+
+        force_status_in_progress(&mem, ENTRIES, &layout);
+        assert!(mem.read_u64(status).unwrap() >= 0x8000_0000_0000_0002);
+        assert_eq!(mem.read_u64(tx), Some(0));
+
+        // A freed entry is left alone rather than written through.
+        mem.remove(ENTRIES);
+        force_status_rejected(&mem, ENTRIES, &layout);
+        assert_eq!(mem.read_u64(status), None);
+    }
+
+    #[test]
+    fn test_deferred_entry_left_unclaimed_for_the_scanner() {
+        use crate::hooks::attempts::Attempts;
+        use crate::layouts::ThreadLayout;
+        use crate::thread_context::{Contexts, Shared};
+
+        static SHARED: Shared = Shared::new();
+        let attempts = Attempts::new();
+        let id = || EntryId::new(Some("toolu_01"), ENTRIES);
+
+        // The hook runs before the scanner has seen the new thread.
+        let hook = claim_entry(SHARED.get(THREAD), || attempts.claim(THREAD, id(), 0));
+        assert_eq!(hook.unwrap_err(), Resolution::Deferred);
+        assert!(!attempts.contains(THREAD, &id(), 0));
+
+        // Its next sweep builds the context and decides the entry.
+        let config: &'static YoloConfig = Box::leak(Box::default());
+        let mut contexts = Contexts::publishing_to(&SHARED);
+        contexts.refresh(
+            &SliceMemory::default(),
+            &ThreadLayout::UNKNOWN,
+            config,
+            &[THREAD],
+        );
+        let scanner = claim_entry(SHARED.get(THREAD), || attempts.claim(THREAD, id(), 0));
+        assert!(scanner.is_ok());
+
+        // The hook's retry finds it taken.
+        let retry = claim_entry(SHARED.get(THREAD), || attempts.claim(THREAD, id(), 0));
+        assert_eq!(retry.unwrap_err(), Resolution::Duplicate);
+    }
 }
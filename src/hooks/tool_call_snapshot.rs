@@ -0,0 +1,1180 @@
+//! Typed snapshot of a pending tool call.
+//!
+//! `find_waiting_sender` only needs the discriminant, status head, respond_tx
+//! and tool_call_id. Logging, the approval policy and the audit trail also
+//! want to know *what* is being approved, so this module decodes the rest of
+//! the tool call — title, `acp::ToolKind`, locations and `raw_input`.
+//!
+//! Two sources:
+//!
+//!   - the `acp::ToolCallUpdate` passed to `request_tool_call_authorization`
+//!     (`read_update`). It is our pinned `acp` crate's type, so its offsets
+//!     come from probing a value we build ourselves (`update_layout`). The
+//!     probe also finds `tool_call_id`, and is only trusted for a layout
+//!     whose `update_id_ptr_offset` / `update_id_len_offset` (recovered from
+//!     the binary) agree with it.
+//!   - the ToolCall entry itself, with per-version offsets from
+//!     `EntryLayout::snapshot` (layout database only; Zed's own ToolCall
+//!     type has not been laid out for any compiled layout). The stale
+//!     scanner has no update to read, only the entry.
+//!
+//! Every field is optional. Every read goes through `mem::SafeMem`; a field
+//! whose offsets are unknown, whose pointer fails validation or isn't mapped
+//! just yields `None`, and the policy treats unknown facts conservatively.
+//!
+//! ## Field encodings
+//!
+//!   title       — (ptr, len) pair; text at `ptr + data_offset`
+//!                 (`data_offset` = 0x10 for `Arc<str>` / SharedString, 0 for `String`)
+//!   kind        — one byte, `acp::ToolKind` declaration order
+//!   locations   — `Vec<acp::ToolCallLocation>` (ptr, len, element stride) with
+//!                 the `path: PathBuf` (ptr, len) inside each element
+//!   raw_input   — `Option<serde_json::Value>`, decoded node by node with the
+//!                 layout probed from our own serde_json (`value_layout`).
+//!                 That is Zed's layout only if both are built with the same
+//!                 features (`preserve_order`). Numbers decode as `null`;
+//!                 nothing reads them. Anything that doesn't decode cleanly,
+//!                 or is deeper or bigger than the limits below, drops the
+//!                 whole `raw_input`.
+
+use std::sync::{Arc, OnceLock};
+
+use agent_client_protocol as acp;
+use serde_json::Value;
+
+use crate::layouts::EntryLayout;
+use crate::mem::{SafeMem, plausible_ptr};
+use crate::policy::ToolCallFacts;
+
+/// Refuse strings longer than this (titles, ids, paths). Guards against
+/// reading a garbage length as a huge slice.
+const MAX_STR_LEN: u64 = 64 * 1024;
+
+/// Refuse location Vecs longer than this.
+const MAX_LOCATIONS: u64 = 256;
+
+/// `raw_input` nesting deeper than this is not decoded.
+const MAX_JSON_DEPTH: usize = 16;
+/// `raw_input` with more nodes (values and object keys) than this is not decoded.
+const MAX_JSON_NODES: usize = 1024;
+/// `raw_input` needing more bytes read than this is not decoded.
+const MAX_JSON_BYTES: usize = 256 * 1024;
+
+/// `ArcInner<T>` header = strong + weak.
+const ARC_INNER_DATA_OFFSET: usize = 0x10;
+
+/// `acp::ToolKind` variants in declaration order, as ACP wire names.
+const TOOL_KIND_NAMES: &[&str] = &[
+    "read",
+    "edit",
+    "delete",
+    "move",
+    "search",
+    "execute",
+    "think",
+    "fetch",
+    "switch_mode",
+    "other",
+];
+
+/// How `None` is encoded for an `Option<String>` / `Option<Vec<_>>`: the
+/// word at `offset` (the capacity) holds `value`, which no capacity can be.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct NoneNiche {
+    pub(crate) offset: usize,
+    pub(crate) value: u64,
+}
+
+/// Location of a string inside a struct.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StrField {
+    pub(crate) ptr_offset: usize,
+    pub(crate) len_offset: usize,
+    /// Offset of the text from the pointer (0x10 for `Arc<str>`, 0 for `String`).
+    pub(crate) data_offset: usize,
+    /// Set for an `Option<String>`.
+    pub(crate) none: Option<NoneNiche>,
+}
+
+/// Location of a `Vec<T>` inside a struct.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct VecField {
+    pub(crate) ptr_offset: usize,
+    pub(crate) len_offset: usize,
+    pub(crate) elem_size: usize,
+    /// Set for an `Option<Vec<T>>`.
+    pub(crate) none: Option<NoneNiche>,
+}
+
+/// Offsets of the tool call fields, relative to the struct they are read
+/// from (a ToolCall entry or a `ToolCallUpdate`).
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SnapshotLayout {
+    pub(crate) title: Option<StrField>,
+    pub(crate) kind_offset: Option<usize>,
+    pub(crate) locations: Option<VecField>,
+    /// `ToolCallLocation.path`, relative to each location element.
+    pub(crate) location_path: StrField,
+    pub(crate) raw_input_offset: Option<usize>,
+}
+
+/// What we could decode about a pending tool call.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ToolCallSnapshot {
+    pub(crate) tool_call_id: Option<String>,
+    pub(crate) title: Option<String>,
+    pub(crate) kind: Option<&'static str>,
+    pub(crate) locations: Vec<String>,
+    pub(crate) raw_input: Option<serde_json::Value>,
+}
+
+impl ToolCallSnapshot {
+    /// Facts for the approval policy.
+    pub(crate) fn facts(&self) -> ToolCallFacts {
+        ToolCallFacts {
+            kind: self.kind.map(str::to_string),
+            title: self.title.clone(),
+            command: self.command(),
+            ..ToolCallFacts::default()
+        }
+    }
+
+    /// Shell command from `raw_input`, for terminal / execute tool calls.
+    ///
+    /// Agents send either `{"command": "..."}` (Claude, Gemini) or an argv
+    /// array `{"command": ["bash", "-lc", "..."]}` (Codex), which is joined
+    /// with shell quoting so `shell::parse` reads it back.
+    pub(crate) fn command(&self) -> Option<String> {
+        match self.raw_input.as_ref()?.get("command")? {
+            serde_json::Value::String(command) => Some(command.clone()),
+            serde_json::Value::Array(argv) => {
+                let argv: Option<Vec<&str>> = argv.iter().map(|arg| arg.as_str()).collect();
+                Some(crate::shell::join(&argv?))
+            }
+            _ => None,
+        }
+    }
+
+    /// `self`, with the fields it is missing taken from `other`.
+    pub(crate) fn or(self, other: ToolCallSnapshot) -> ToolCallSnapshot {
+        ToolCallSnapshot {
+            tool_call_id: self.tool_call_id.or(other.tool_call_id),
+            title: self.title.or(other.title),
+            kind: self.kind.or(other.kind),
+            locations: if self.locations.is_empty() {
+                other.locations
+            } else {
+                self.locations
+            },
+            raw_input: self.raw_input.or(other.raw_input),
+        }
+    }
+
+    /// One-line summary for approval log lines.
+    pub(crate) fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(kind) = self.kind {
+            parts.push(format!("kind={kind}"));
+        }
+        if let Some(title) = &self.title {
+            parts.push(format!("title={title:?}"));
+        }
+        if !self.locations.is_empty() {
+            parts.push(format!("locations={:?}", self.locations));
+        }
+        if self.raw_input.is_some() {
+            parts.push("raw_input=yes".to_string());
+        }
+        if parts.is_empty() {
+            "<undecoded>".to_string()
+        } else {
+            parts.join(" ")
+        }
+    }
+}
+
+/// Map an `acp::ToolKind` discriminant to its ACP wire name.
+pub(crate) fn tool_kind_name(discriminant: u8) -> Option<&'static str> {
+    TOOL_KIND_NAMES.get(discriminant as usize).copied()
+}
+
+/// Whether the `Option` described by `none` is `None` (or unreadable).
+fn is_none(mem: &impl SafeMem, base: u64, none: Option<NoneNiche>) -> bool {
+    none.is_some_and(|none| {
+        mem.read_u64(base + none.offset as u64)
+            .is_none_or(|word| word == none.value)
+    })
+}
+
+/// Read a string described by `field` from the struct at `base`.
+pub(crate) fn read_str(mem: &impl SafeMem, base: u64, field: StrField) -> Option<String> {
+    if is_none(mem, base, field.none) {
+        return None;
+    }
+    let ptr = mem.read_u64(base + field.ptr_offset as u64)?;
+    let len = mem.read_u64(base + field.len_offset as u64)?;
+
+    if len == 0 {
+        return Some(String::new());
+    }
+    if len > MAX_STR_LEN || !plausible_ptr(ptr) {
+        return None;
+    }
+    if field.data_offset != 0 {
+        // Arc<str>: sanity-check the strong/weak header before trusting it.
+        let strong = mem.read_u64(ptr)?;
+        let weak = mem.read_u64(ptr + 8)?;
+        if !(1..=1024).contains(&strong) || !(1..=1024).contains(&weak) {
+            return None;
+        }
+    }
+
+    let bytes = mem.read_bytes(ptr + field.data_offset as u64, len as usize)?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Decode a snapshot of the tool call fields of the struct at `base`.
+///
+/// `tool_call_id` is decoded by the caller (it is part of `MatchStyle` or
+/// the update layout, not the snapshot layout) and passed through.
+pub(crate) fn read(
+    mem: &impl SafeMem,
+    base: u64,
+    layout: &SnapshotLayout,
+    tool_call_id: Option<String>,
+) -> ToolCallSnapshot {
+    let title = layout.title.and_then(|field| read_str(mem, base, field));
+
+    let kind = layout
+        .kind_offset
+        .and_then(|offset| mem.read_u8(base + offset as u64))
+        .and_then(tool_kind_name);
+
+    let mut locations = Vec::new();
+    if let Some(vec) = layout.locations
+        && !is_none(mem, base, vec.none)
+        && let Some(ptr) = mem.read_u64(base + vec.ptr_offset as u64)
+        && let Some(len) = mem.read_u64(base + vec.len_offset as u64)
+        && len <= MAX_LOCATIONS
+        && (len == 0 || plausible_ptr(ptr))
+    {
+        for i in 0..len {
+            let element = ptr + i * vec.elem_size as u64;
+            if let Some(path) = read_str(mem, element, layout.location_path) {
+                locations.push(path);
+            }
+        }
+    }
+
+    let raw_input = layout
+        .raw_input_offset
+        .and_then(|offset| read_json(mem, base + offset as u64));
+
+    ToolCallSnapshot {
+        tool_call_id,
+        title,
+        kind,
+        locations,
+        raw_input,
+    }
+}
+
+/// Decode the `acp::ToolCallUpdate` at `update`, the hooked function's
+/// argument. `None` unless the probed update layout agrees with one of
+/// `layouts` (see `update_layout`). Call it before the hooked function
+/// consumes the update.
+pub(crate) fn read_update(
+    mem: &impl SafeMem,
+    update: u64,
+    layouts: &[EntryLayout],
+) -> Option<ToolCallSnapshot> {
+    let probed = update_layout().ok()?;
+    if !layouts.iter().any(|layout| probed.matches(layout)) {
+        return None;
+    }
+    let id = StrField {
+        ptr_offset: probed.id_ptr_offset,
+        len_offset: probed.id_len_offset,
+        data_offset: ARC_INNER_DATA_OFFSET,
+        none: None,
+    };
+    let tool_call_id = read_str(mem, update, id);
+    Some(read(mem, update, &probed.snapshot, tool_call_id))
+}
+
+// ---------------------------------------------------------------------------
+// raw_input
+// ---------------------------------------------------------------------------
+
+/// Decode the `Option<serde_json::Value>` at `addr`. `None` for `None`, and
+/// for anything that doesn't decode within the limits.
+pub(crate) fn read_json(mem: &impl SafeMem, addr: u64) -> Option<Value> {
+    let layout = value_layout().ok()?;
+    let mut budget = Budget::default();
+    budget.count(1)?;
+    let node = budget.read(mem, addr, layout.size)?;
+    decode(mem, &node, layout, 0, &mut budget)
+}
+
+/// What one `read_json` may still read.
+#[derive(Default)]
+struct Budget {
+    nodes: usize,
+    bytes: usize,
+}
+
+impl Budget {
+    fn count(&mut self, nodes: usize) -> Option<()> {
+        self.nodes += nodes;
+        (self.nodes <= MAX_JSON_NODES).then_some(())
+    }
+
+    fn read(&mut self, mem: &impl SafeMem, addr: u64, len: usize) -> Option<Vec<u8>> {
+        self.bytes += len;
+        if self.bytes > MAX_JSON_BYTES {
+            return None;
+        }
+        mem.read_bytes(addr, len)
+    }
+
+    /// Text of the `String` whose (ptr, len) words are in `node`.
+    fn string(&mut self, mem: &impl SafeMem, node: &[u8], field: StrField) -> Option<String> {
+        let ptr = word(node, field.ptr_offset)?;
+        let len = word(node, field.len_offset)?;
+        if len == 0 {
+            return Some(String::new());
+        }
+        if len > MAX_STR_LEN || !plausible_ptr(ptr) {
+            return None;
+        }
+        String::from_utf8(self.read(mem, ptr, len as usize)?).ok()
+    }
+
+    /// Elements of the `Vec` whose (ptr, len) words are in `node`, read in
+    /// one go and counted as nodes.
+    fn elements(&mut self, mem: &impl SafeMem, node: &[u8], field: VecField) -> Option<Vec<u8>> {
+        let ptr = word(node, field.ptr_offset)?;
+        let len = usize::try_from(word(node, field.len_offset)?).ok()?;
+        self.count(len)?;
+        if len == 0 {
+            return Some(Vec::new());
+        }
+        if !plausible_ptr(ptr) {
+            return None;
+        }
+        self.read(mem, ptr, len * field.elem_size)
+    }
+}
+
+/// Decode one `Value` from its bytes.
+fn decode(
+    mem: &impl SafeMem,
+    node: &[u8],
+    layout: &ValueLayout,
+    depth: usize,
+    budget: &mut Budget,
+) -> Option<Value> {
+    if depth > MAX_JSON_DEPTH {
+        return None;
+    }
+    Some(match layout.tag.kind(node)? {
+        JsonKind::None => return None,
+        JsonKind::Null | JsonKind::Number => Value::Null,
+        JsonKind::Bool => match node.get(layout.bool_offset)? {
+            0 => Value::Bool(false),
+            1 => Value::Bool(true),
+            _ => return None,
+        },
+        JsonKind::String => Value::String(budget.string(mem, node, layout.string)?),
+        JsonKind::Array => {
+            let items = budget.elements(mem, node, layout.array)?;
+            let items: Option<Vec<Value>> = items
+                .chunks(layout.size)
+                .map(|item| decode(mem, item, layout, depth + 1, budget))
+                .collect();
+            Value::Array(items?)
+        }
+        JsonKind::Object => {
+            let buckets = budget.elements(mem, node, layout.entries)?;
+            let mut map = serde_json::Map::new();
+            for bucket in buckets.chunks(layout.entries.elem_size) {
+                let key = budget.string(mem, bucket, layout.key)?;
+                let value = bucket.get(layout.value_offset..layout.value_offset + layout.size)?;
+                map.insert(key, decode(mem, value, layout, depth + 1, budget)?);
+            }
+            Value::Object(map)
+        }
+    })
+}
+
+fn word(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+// ---------------------------------------------------------------------------
+// Probed layouts
+// ---------------------------------------------------------------------------
+//
+// Field order inside `String`, `Vec`, `PathBuf`, `serde_json::Value` and the
+// IndexMap behind `serde_json::Map` is up to the compiler. Rather than
+// hard-code it, build values with known pointers, lengths and capacities and
+// look for them: what we find is how our build lays these types out, which
+// is Zed's for as long as the `acp` / serde_json pins match.
+
+/// `ToolCallUpdate` offsets, probed from our `acp` crate.
+#[derive(Debug)]
+pub(crate) struct UpdateLayout {
+    pub(crate) id_ptr_offset: usize,
+    pub(crate) id_len_offset: usize,
+    pub(crate) snapshot: SnapshotLayout,
+}
+
+impl UpdateLayout {
+    /// Whether `layout`'s update id offsets (from the binary) agree with ours.
+    pub(crate) fn matches(&self, layout: &EntryLayout) -> bool {
+        layout.update_id_ptr_offset == self.id_ptr_offset
+            && layout.update_id_len_offset == self.id_len_offset
+    }
+}
+
+static UPDATE_LAYOUT: OnceLock<Result<UpdateLayout, String>> = OnceLock::new();
+
+/// Our `acp::ToolCallUpdate` layout, or why it couldn't be probed.
+pub(crate) fn update_layout() -> Result<&'static UpdateLayout, &'static str> {
+    UPDATE_LAYOUT
+        .get_or_init(probe_update_layout)
+        .as_ref()
+        .map_err(String::as_str)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum JsonKind {
+    /// `Option::None` around the value.
+    None,
+    Null,
+    Bool,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+const JSON_KINDS: [JsonKind; 7] = [
+    JsonKind::None,
+    JsonKind::Null,
+    JsonKind::Bool,
+    JsonKind::Number,
+    JsonKind::String,
+    JsonKind::Array,
+    JsonKind::Object,
+];
+
+/// Where an `Option<Value>` keeps its variant: `word & mask` at `offset`.
+#[derive(Debug)]
+struct Tag {
+    offset: usize,
+    mask: u64,
+    /// Tag per `JSON_KINDS` entry; `None` for the variant whose data fills
+    /// the word (the others are niches in it).
+    values: [Option<u64>; 7],
+}
+
+impl Tag {
+    fn kind(&self, node: &[u8]) -> Option<JsonKind> {
+        let tag = word(node, self.offset)? & self.mask;
+        let index = self
+            .values
+            .iter()
+            .position(|value| *value == Some(tag))
+            .or_else(|| self.values.iter().position(Option::is_none))?;
+        Some(JSON_KINDS[index])
+    }
+}
+
+/// `Option<serde_json::Value>` layout, probed from our serde_json.
+#[derive(Debug)]
+pub(crate) struct ValueLayout {
+    size: usize,
+    tag: Tag,
+    bool_offset: usize,
+    string: StrField,
+    array: VecField,
+    /// `IndexMap` entries: `Bucket { hash, key, value }`.
+    entries: VecField,
+    /// Bucket key, relative to the bucket.
+    key: StrField,
+    /// Bucket value, relative to the bucket.
+    value_offset: usize,
+}
+
+static VALUE_LAYOUT: OnceLock<Result<ValueLayout, String>> = OnceLock::new();
+
+/// Our `Option<serde_json::Value>` layout, or why it couldn't be probed.
+pub(crate) fn value_layout() -> Result<&'static ValueLayout, &'static str> {
+    VALUE_LAYOUT
+        .get_or_init(probe_value_layout)
+        .as_ref()
+        .map_err(String::as_str)
+}
+
+/// The bytes of one of our own values. Padding comes along as whatever it
+/// holds; the probes only match words they put there.
+fn bytes_of<T>(value: &T) -> Vec<u8> {
+    let ptr = (value as *const T).cast::<u8>();
+    unsafe { std::slice::from_raw_parts(ptr, size_of::<T>()) }.to_vec()
+}
+
+/// Offset of `inner` inside `outer`.
+fn offset_in<T, U>(outer: &T, inner: &U) -> usize {
+    inner as *const U as usize - outer as *const T as usize
+}
+
+/// The only word-aligned offset in `bytes` holding `value`.
+fn find_word(bytes: &[u8], value: u64) -> Option<usize> {
+    let mut found = (0..bytes.len())
+        .step_by(8)
+        .filter(|&offset| word(bytes, offset) == Some(value));
+    let offset = found.next()?;
+    found.next().is_none().then_some(offset)
+}
+
+/// (ptr, len) offsets of the `Vec`-like value in `bytes`. `len` must differ
+/// from the capacity.
+fn find_ptr_len(bytes: &[u8], ptr: *const u8, len: usize) -> Result<(usize, usize), String> {
+    match (find_word(bytes, ptr as u64), find_word(bytes, len as u64)) {
+        (Some(ptr), Some(len)) => Ok((ptr, len)),
+        _ => Err("pointer/length not found".to_string()),
+    }
+}
+
+/// The `None` niche of an `Option` whose `Some` has `capacity` somewhere in
+/// `some`.
+fn none_niche(some: &[u8], capacity: usize, none: &[u8]) -> Result<NoneNiche, String> {
+    let offset = find_word(some, capacity as u64).ok_or("capacity not found")?;
+    let value = word(none, offset).ok_or("None too small")?;
+    if value <= isize::MAX as u64 {
+        return Err(format!("None holds {value:#x}, a valid capacity"));
+    }
+    Ok(NoneNiche { offset, value })
+}
+
+/// `StrField` for a `String` at `base`, probed from `sample`.
+fn string_field(sample: &String, base: usize) -> Result<StrField, String> {
+    let (ptr, len) = find_ptr_len(&bytes_of(sample), sample.as_ptr(), sample.len())?;
+    Ok(StrField {
+        ptr_offset: base + ptr,
+        len_offset: base + len,
+        data_offset: 0,
+        none: None,
+    })
+}
+
+/// Find the tag in `samples`: a word where every kind but at most one (the
+/// dataful variant, with `allow_data`) has one fixed value, distinct from
+/// the others'.
+fn tag_at(
+    samples: &[(JsonKind, Vec<u8>)],
+    offset: usize,
+    mask: u64,
+    allow_data: bool,
+) -> Option<Tag> {
+    let mut values = [None; 7];
+    let mut data = None;
+    for (i, kind) in JSON_KINDS.iter().enumerate() {
+        let words: Option<Vec<u64>> = samples
+            .iter()
+            .filter(|(k, _)| k == kind)
+            .map(|(_, bytes)| Some(word(bytes, offset)? & mask))
+            .collect();
+        let words = words?;
+        if words.windows(2).all(|pair| pair[0] == pair[1]) {
+            values[i] = Some(*words.first()?);
+        } else if allow_data && data.is_none() {
+            data = Some(words);
+        } else {
+            return None;
+        }
+    }
+
+    let fixed: Vec<u64> = values.iter().flatten().copied().collect();
+    let distinct = fixed
+        .iter()
+        .enumerate()
+        .all(|(i, value)| !fixed[..i].contains(value));
+    let data_clear = data.is_none_or(|words| words.iter().all(|w| !fixed.contains(w)));
+    (distinct && data_clear).then_some(Tag {
+        offset,
+        mask,
+        values,
+    })
+}
+
+fn probe_tag(samples: &[(JsonKind, Vec<u8>)], size: usize) -> Option<Tag> {
+    // A niche in a whole word (the usual case)...
+    (0..size)
+        .step_by(8)
+        .find_map(|offset| tag_at(samples, offset, u64::MAX, true))
+        // ...or a tag byte with padding around it.
+        .or_else(|| {
+            (0..size - size % 8).find_map(|byte| {
+                let mask = 0xff << ((byte % 8) * 8);
+                tag_at(samples, byte - byte % 8, mask, false)
+            })
+        })
+}
+
+/// A `Map` with `len` entries and spare capacity, so its length words stand
+/// out from the capacity.
+fn probe_map(len: usize) -> serde_json::Map<String, Value> {
+    let mut map = serde_json::Map::with_capacity(len * 2 + 4);
+    for i in 0..len {
+        map.insert(format!("probe-key-{i}"), Value::Null);
+    }
+    map
+}
+
+fn probe_value_layout() -> Result<ValueLayout, String> {
+    let size = size_of::<Value>();
+    if size_of::<Option<Value>>() != size || !size.is_multiple_of(8) {
+        return Err("Option<Value> is not niche-encoded".to_string());
+    }
+
+    let text = String::with_capacity(0x35) + "probe";
+    let string = Value::String(text);
+    let mut items = Vec::with_capacity(7);
+    items.extend([Value::Null, Value::Bool(true)]);
+    let array = Value::Array(items);
+    let object = Value::Object(probe_map(3));
+    let bigger_object = Value::Object(probe_map(5));
+    let flag = Value::Bool(true);
+
+    let samples: Vec<(JsonKind, Vec<u8>)> = vec![
+        (JsonKind::None, bytes_of(&None::<Value>)),
+        (JsonKind::Null, bytes_of(&Value::Null)),
+        (JsonKind::Bool, bytes_of(&Value::Bool(false))),
+        (JsonKind::Bool, bytes_of(&flag)),
+        (JsonKind::Number, bytes_of(&Value::from(7))),
+        (JsonKind::Number, bytes_of(&Value::from(-7))),
+        (JsonKind::Number, bytes_of(&Value::from(1.5))),
+        (JsonKind::String, bytes_of(&Value::String(String::new()))),
+        (JsonKind::String, bytes_of(&string)),
+        (JsonKind::Array, bytes_of(&Value::Array(Vec::new()))),
+        (JsonKind::Array, bytes_of(&array)),
+        (
+            JsonKind::Object,
+            bytes_of(&Value::Object(serde_json::Map::new())),
+        ),
+        (JsonKind::Object, bytes_of(&object)),
+        (JsonKind::Object, bytes_of(&bigger_object)),
+    ];
+    let tag = probe_tag(&samples, size).ok_or("variant tag not found")?;
+
+    let Value::Bool(flag_value) = &flag else {
+        unreachable!()
+    };
+    let bool_offset = offset_in(&flag, flag_value);
+
+    let Value::String(text) = &string else {
+        unreachable!()
+    };
+    let text_field = string_field(text, offset_in(&string, text))?;
+
+    let Value::Array(items) = &array else {
+        unreachable!()
+    };
+    let base = offset_in(&array, items);
+    let (ptr, len) = find_ptr_len(&bytes_of(items), items.as_ptr().cast(), items.len())?;
+    let array_field = VecField {
+        ptr_offset: base + ptr,
+        len_offset: base + len,
+        elem_size: size,
+        none: None,
+    };
+
+    let (Value::Object(map), Value::Object(bigger_map)) = (&object, &bigger_object) else {
+        unreachable!()
+    };
+    let base = offset_in(&object, map);
+    let map_bytes = bytes_of(map);
+    let entries: Vec<(usize, usize)> = map
+        .iter()
+        .map(|(key, value)| {
+            (
+                key as *const String as usize,
+                value as *const Value as usize,
+            )
+        })
+        .collect();
+    let stride = entries[1].0 - entries[0].0;
+    if stride == 0 || entries[2].0 - entries[1].0 != stride || !stride.is_multiple_of(8) {
+        return Err("map entries are not evenly spaced".to_string());
+    }
+    let (key0, value0) = entries[0];
+    let first = key0.min(value0);
+    let last = (key0 + size_of::<String>()).max(value0 + size);
+    let ptr = (0..map_bytes.len())
+        .step_by(8)
+        .filter(|&offset| {
+            word(&map_bytes, offset).is_some_and(|bucket| {
+                let bucket = bucket as usize;
+                bucket <= first && last <= bucket + stride
+            })
+        })
+        .collect::<Vec<_>>();
+    let &[ptr] = ptr.as_slice() else {
+        return Err("map entries pointer not found".to_string());
+    };
+    let bigger_bytes = bytes_of(bigger_map);
+    let len = (0..map_bytes.len())
+        .step_by(8)
+        .find(|&offset| {
+            offset != ptr
+                && word(&map_bytes, offset) == Some(map.len() as u64)
+                && word(&bigger_bytes, offset) == Some(bigger_map.len() as u64)
+        })
+        .ok_or("map length not found")?;
+    let bucket = word(&map_bytes, ptr).ok_or("map too small")? as usize;
+    let key_field = string_field(map.keys().next().ok_or("empty map")?, key0 - bucket)?;
+
+    Ok(ValueLayout {
+        size,
+        tag,
+        bool_offset,
+        string: text_field,
+        array: array_field,
+        entries: VecField {
+            ptr_offset: base + ptr,
+            len_offset: base + len,
+            elem_size: stride,
+            none: None,
+        },
+        key: key_field,
+        value_offset: value0 - bucket,
+    })
+}
+
+fn probe_update_layout() -> Result<UpdateLayout, String> {
+    let id: Arc<str> = Arc::from("probe-tool-call-id");
+    let path = |name: &str| {
+        acp::ToolCallLocation::new(std::path::PathBuf::from(String::with_capacity(0x35) + name))
+    };
+    let mut locations = Vec::with_capacity(7);
+    locations.extend([path("/probe/a"), path("/probe/b")]);
+    let update = acp::ToolCallUpdate::new(
+        id.clone(),
+        acp::ToolCallUpdateFields::new()
+            .kind(acp::ToolKind::Execute)
+            .title(String::with_capacity(0x35) + "probe")
+            .locations(locations)
+            .raw_input(serde_json::json!({ "command": "probe" })),
+    );
+    let fields = &update.fields;
+
+    // tool_call_id: Arc<str> = (ArcInner ptr, len)
+    let inner = Arc::as_ptr(&id)
+        .cast::<u8>()
+        .wrapping_sub(ARC_INNER_DATA_OFFSET);
+    let id_base = offset_in(&update, &update.tool_call_id);
+    let (id_ptr, id_len) = find_ptr_len(&bytes_of(&update.tool_call_id), inner, id.len())?;
+
+    let title = fields.title.as_ref().ok_or("title not set")?;
+    let title_base = offset_in(&update, title);
+    if title_base != offset_in(&update, &fields.title) {
+        return Err("Option<String> is not niche-encoded".to_string());
+    }
+    let mut title_field = string_field(title, title_base)?;
+    let niche = none_niche(
+        &bytes_of(title),
+        title.capacity(),
+        &bytes_of(&None::<String>),
+    )?;
+    title_field.none = Some(NoneNiche {
+        offset: title_base + niche.offset,
+        ..niche
+    });
+
+    let kind_offset = offset_in(&update, &fields.kind);
+    let kind_byte = *bytes_of(&update)
+        .get(kind_offset)
+        .ok_or("kind out of range")?;
+    let none_kind = bytes_of(&None::<acp::ToolKind>)[0];
+    if size_of::<Option<acp::ToolKind>>() != 1
+        || tool_kind_name(kind_byte) != Some("execute")
+        || tool_kind_name(none_kind).is_some()
+    {
+        return Err("ToolKind is not one declaration-order byte".to_string());
+    }
+
+    let locations = fields.locations.as_ref().ok_or("locations not set")?;
+    let locations_base = offset_in(&update, locations);
+    if locations_base != offset_in(&update, &fields.locations) {
+        return Err("Option<Vec> is not niche-encoded".to_string());
+    }
+    let (ptr, len) = find_ptr_len(
+        &bytes_of(locations),
+        locations.as_ptr().cast(),
+        locations.len(),
+    )?;
+    let niche = none_niche(
+        &bytes_of(locations),
+        locations.capacity(),
+        &bytes_of(&None::<Vec<acp::ToolCallLocation>>),
+    )?;
+    let locations_field = VecField {
+        ptr_offset: locations_base + ptr,
+        len_offset: locations_base + len,
+        elem_size: size_of::<acp::ToolCallLocation>(),
+        none: Some(NoneNiche {
+            offset: locations_base + niche.offset,
+            ..niche
+        }),
+    };
+
+    let location = &locations[0];
+    let path_bytes = location.path.as_os_str().as_encoded_bytes();
+    let path_base = offset_in(location, &location.path);
+    let (ptr, len) = find_ptr_len(
+        &bytes_of(&location.path),
+        path_bytes.as_ptr(),
+        path_bytes.len(),
+    )?;
+
+    Ok(UpdateLayout {
+        id_ptr_offset: id_base + id_ptr,
+        id_len_offset: id_base + id_len,
+        snapshot: SnapshotLayout {
+            title: Some(title_field),
+            kind_offset: Some(kind_offset),
+            locations: Some(locations_field),
+            location_path: StrField {
+                ptr_offset: path_base + ptr,
+                len_offset: path_base + len,
+                data_offset: 0,
+                none: None,
+            },
+            raw_input_offset: Some(offset_in(&update, &fields.raw_input)),
+        },
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layouts::ENTRY_LAYOUTS;
+    use crate::mem::{ProcessMemory, SliceMemory};
+    use serde_json::json;
+
+    const TITLE: StrField = StrField {
+        ptr_offset: 0x08,
+        len_offset: 0x10,
+        data_offset: 0x10,
+        none: None,
+    };
+    const PATH: StrField = StrField {
+        ptr_offset: 0x08,
+        len_offset: 0x10,
+        data_offset: 0,
+        none: None,
+    };
+    const LAYOUT: SnapshotLayout = SnapshotLayout {
+        title: Some(TITLE),
+        kind_offset: Some(0x18),
+        locations: Some(VecField {
+            ptr_offset: 0x20,
+            len_offset: 0x28,
+            elem_size: 0x20,
+            none: None,
+        }),
+        location_path: PATH,
+        raw_input_offset: Some(0x30),
+    };
+
+    const ENTRY: u64 = 0x1_1000_0000;
+    const TITLE_ARC: u64 = 0x1_2000_0000;
+    const LOCATIONS: u64 = 0x1_3000_0000;
+    const PATHS: u64 = 0x1_4000_0000;
+    const VALUE: u64 = 0x1_5000_0000;
+
+    /// Synthetic heap: an entry laid out per `LAYOUT` (with `raw_input`
+    /// `None`) and the objects it points at.
+    struct Fixture {
+        mem: SliceMemory,
+        entry: Vec<u64>,
+    }
+
+    impl Fixture {
+        /// Map the (possibly modified) entry words.
+        fn map(mut self) -> Self {
+            self.mem.add_words(ENTRY, &self.entry);
+            self
+        }
+    }
+
+    fn fixture() -> Fixture {
+        let mut mem = SliceMemory::default();
+        let title = "Run `cargo test`";
+        let mut arc = vec![1u64, 1];
+        arc.extend(title.as_bytes().chunks(8).map(|chunk| {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(word)
+        }));
+        mem.add_words(TITLE_ARC, &arc);
+
+        let paths = ["/tmp/a.rs", "/tmp/b.rs"];
+        let mut locations = Vec::new();
+        for (i, path) in paths.iter().enumerate() {
+            let ptr = PATHS + i as u64 * 0x1000;
+            mem.add(ptr, path.as_bytes().to_vec());
+            locations.extend([0, ptr, path.len() as u64, 0]);
+        }
+        mem.add_words(LOCATIONS, &locations);
+
+        let mut entry = vec![0u64; 6];
+        entry[1] = TITLE_ARC;
+        entry[2] = title.len() as u64;
+        entry[3] = 5; // ToolKind::Execute
+        entry[4] = LOCATIONS;
+        entry[5] = paths.len() as u64;
+        let none = bytes_of(&None::<Value>);
+        entry.extend(
+            none.chunks(8)
+                .map(|w| u64::from_le_bytes(w.try_into().unwrap())),
+        );
+
+        Fixture { mem, entry }
+    }
+
+    #[test]
+    fn test_tool_kind_names() {
+        assert_eq!(tool_kind_name(0), Some("read"));
+        assert_eq!(tool_kind_name(5), Some("execute"));
+        assert_eq!(tool_kind_name(8), Some("switch_mode"));
+        assert_eq!(tool_kind_name(9), Some("other"));
+        assert_eq!(tool_kind_name(10), None);
+    }
+
+    #[test]
+    fn test_read_full_snapshot() {
+        let f = fixture().map();
+
+        let snap = read(&f.mem, ENTRY, &LAYOUT, Some("toolu_01".to_string()));
+        assert_eq!(snap.tool_call_id.as_deref(), Some("toolu_01"));
+        assert_eq!(snap.title.as_deref(), Some("Run `cargo test`"));
+        assert_eq!(snap.kind, Some("execute"));
+        assert_eq!(snap.locations, vec!["/tmp/a.rs", "/tmp/b.rs"]);
+        assert_eq!(snap.raw_input, None);
+
+        let facts = snap.facts();
+        assert_eq!(facts.kind.as_deref(), Some("execute"));
+        assert_eq!(facts.title.as_deref(), Some("Run `cargo test`"));
+    }
+
+    #[test]
+    fn test_update_layout_matches_compiled_layouts() {
+        // The id offsets probed from our acp crate are the ones recovered
+        // from Zed's binary: the update's other offsets can be trusted too.
+        let probed = update_layout().unwrap();
+        for layout in ENTRY_LAYOUTS {
+            assert!(probed.matches(layout), "{}", layout.name);
+        }
+        assert!(value_layout().is_ok());
+    }
+
+    #[test]
+    fn test_read_update() {
+        let update = acp::ToolCallUpdate::new(
+            "toolu_01",
+            acp::ToolCallUpdateFields::new()
+                .kind(acp::ToolKind::Execute)
+                .title("Run `cargo test`")
+                .locations(vec![acp::ToolCallLocation::new("/work/src/main.rs")])
+                .raw_input(json!({ "command": "cargo test", "cwd": "/work" })),
+        );
+        let addr = &update as *const acp::ToolCallUpdate as u64;
+
+        let snap = read_update(&ProcessMemory, addr, ENTRY_LAYOUTS).unwrap();
+        assert_eq!(snap.tool_call_id.as_deref(), Some("toolu_01"));
+        assert_eq!(snap.title.as_deref(), Some("Run `cargo test`"));
+        assert_eq!(snap.kind, Some("execute"));
+        assert_eq!(snap.locations, vec!["/work/src/main.rs"]);
+        assert_eq!(snap.command().as_deref(), Some("cargo test"));
+        assert_eq!(
+            snap.raw_input,
+            Some(json!({ "command": "cargo test", "cwd": "/work" }))
+        );
+
+        // Fields the agent left out stay unknown.
+        let bare = acp::ToolCallUpdate::new("toolu_02", acp::ToolCallUpdateFields::new());
+        let addr = &bare as *const acp::ToolCallUpdate as u64;
+        let snap = read_update(&ProcessMemory, addr, ENTRY_LAYOUTS).unwrap();
+        assert_eq!(
+            snap,
+            ToolCallSnapshot {
+                tool_call_id: Some("toolu_02".to_string()),
+                ..ToolCallSnapshot::default()
+            }
+        );
+
+        // A layout whose update offsets disagree with ours isn't trusted.
+        let other = EntryLayout {
+            update_id_ptr_offset: 0x10,
+            ..ENTRY_LAYOUTS[0]
+        };
+        assert_eq!(read_update(&ProcessMemory, addr, &[other]), None);
+    }
+
+    #[test]
+    fn test_read_json() {
+        let values = [
+            json!({
+                "command": ["bash", "-lc", "git status"],
+                "nested": { "list": [null, true, false, "ü"], "empty": {} },
+                "count": 3
+            }),
+            json!("text"),
+            json!([]),
+            json!(true),
+        ];
+        for value in values {
+            let slot = Some(value.clone());
+            let addr = &slot as *const Option<Value> as u64;
+            let expected = match value {
+                // Numbers aren't decoded.
+                Value::Object(mut map) => {
+                    map.insert("count".to_string(), Value::Null);
+                    Value::Object(map)
+                }
+                value => value,
+            };
+            assert_eq!(read_json(&ProcessMemory, addr), Some(expected));
+        }
+
+        let none: Option<Value> = None;
+        assert_eq!(read_json(&ProcessMemory, &none as *const _ as u64), None);
+    }
+
+    #[test]
+    fn test_read_json_limits() {
+        let mut deep = json!("leaf");
+        for _ in 0..=MAX_JSON_DEPTH {
+            deep = json!([deep]);
+        }
+        let slot = Some(deep);
+        assert_eq!(read_json(&ProcessMemory, &slot as *const _ as u64), None);
+
+        let wide = Some(Value::Array(vec![Value::Null; MAX_JSON_NODES + 1]));
+        assert_eq!(read_json(&ProcessMemory, &wide as *const _ as u64), None);
+    }
+
+    #[test]
+    fn test_read_json_unmapped() {
+        // A string whose bytes aren't mapped drops the whole value.
+        let value = Some(json!("a string on the real heap"));
+        let mut mem = SliceMemory::default();
+        mem.add(VALUE, bytes_of(&value));
+        assert_eq!(read_json(&mem, VALUE), None);
+        assert_eq!(read_json(&mem, VALUE + 0x1000), None);
+
+        // A node that is no Value at all.
+        let layout = value_layout().unwrap();
+        let mut mem = SliceMemory::default();
+        mem.add(VALUE, vec![0xa5; layout.size]);
+        assert_eq!(read_json(&mem, VALUE), None);
+    }
+
+    #[test]
+    fn test_snapshot_or() {
+        let update = ToolCallSnapshot {
+            title: Some("from update".to_string()),
+            raw_input: Some(json!({})),
+            ..ToolCallSnapshot::default()
+        };
+        let entry = ToolCallSnapshot {
+            tool_call_id: Some("toolu_01".to_string()),
+            title: Some("from entry".to_string()),
+            locations: vec!["/a".to_string()],
+            ..ToolCallSnapshot::default()
+        };
+        let merged = update.or(entry);
+        assert_eq!(merged.tool_call_id.as_deref(), Some("toolu_01"));
+        assert_eq!(merged.title.as_deref(), Some("from update"));
+        assert_eq!(merged.locations, vec!["/a"]);
+        assert!(merged.raw_input.is_some());
+    }
+
+    #[test]
+    fn test_command_from_raw_input() {
+        let snap = |raw: serde_json::Value| ToolCallSnapshot {
+            raw_input: Some(raw),
+            ..ToolCallSnapshot::default()
+        };
+        assert_eq!(
+            snap(serde_json::json!({ "command": "ls -la" })).command(),
+            Some("ls -la".to_string())
+        );
+        assert_eq!(
+            snap(serde_json::json!({ "command": ["bash", "-lc", "git status"] })).command(),
+            Some("bash -lc 'git status'".to_string())
+        );
+        assert_eq!(
+            snap(serde_json::json!({ "file_path": "/tmp/x" })).command(),
+            None
+        );
+        assert_eq!(ToolCallSnapshot::default().command(), None);
+    }
+
+    #[test]
+    fn test_partial_layout() {
+        let f = fixture().map();
+        let layout = SnapshotLayout {
+            title: None,
+            locations: None,
+            raw_input_offset: None,
+            ..LAYOUT
+        };
+
+        let snap = read(&f.mem, ENTRY, &layout, None);
+        assert_eq!(snap.title, None);
+        assert_eq!(snap.kind, Some("execute"));
+        assert!(snap.locations.is_empty());
+        assert_eq!(snap.raw_input, None);
+        assert_eq!(snap.summary(), "kind=execute");
+    }
+
+    #[test]
+    fn test_rejects_garbage_pointers() {
+        let mut f = fixture();
+        f.entry[1] = 0x1234; // title ptr below the plausible heap range
+        f.entry[5] = 100_000; // absurd location count
+        let f = f.map();
+
+        let snap = read(&f.mem, ENTRY, &LAYOUT, None);
+        assert_eq!(snap.title, None);
+        assert!(snap.locations.is_empty());
+        assert_eq!(ToolCallSnapshot::default().summary(), "<undecoded>");
+    }
+
+    #[test]
+    fn test_unmapped_pointers() {
+        let mut f = fixture();
+        f.entry[1] = TITLE_ARC + 0x10_0000; // plausible, but nothing there
+        f.entry[4] = LOCATIONS + 0x10_0000;
+        let mut f = f.map();
+        f.mem.remove(PATHS);
+
+        let snap = read(&f.mem, ENTRY, &LAYOUT, None);
+        assert_eq!(snap.title, None);
+        assert!(snap.locations.is_empty());
+        assert_eq!(snap.kind, Some("execute"));
+
+        // The entry itself gone (a freed AcpThread's entries): nothing at all.
+        let snap = read(&f.mem, ENTRY + 0x100_0000, &LAYOUT, None);
+        assert_eq!(snap, ToolCallSnapshot::default());
+    }
+}
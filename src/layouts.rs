@@ -0,0 +1,1386 @@
+//! AcpThread / AgentThreadEntry memory layouts.
+//!
+//! Every Zed release can move the fields we read (`entries` Vec, entry stride,
+//! status, respond_tx, tool_call_id). The compiled `ENTRY_LAYOUTS` table covers
+//! the versions we have disassembled; newer versions can be described in a
+//! layout database without rebuilding the dylib.
+//!
+//! ## Layout database location
+//!
+//! `~/.config/dylib-hooks/{app_id}/zed-yolo-layouts.json`
+//!
+//! ## Format
+//!
+//! ```json
+//! {
+//!   "layouts": [
+//!     {
+//!       "name": "v0.233.x",
+//!       "versions": ["0.233.*"],
+//!       "binary_hashes": [],
+//!       "entries_ptr_offset": "0xb0",
+//!       "entries_len_offset": "0xb8",
+//!       "entry_size": "0x1c0",
+//!       "status_offset": "0x118",
+//!       "respond_tx_offset": "0x160",
+//!       "update_id_ptr_offset": "0x128",
+//!       "update_id_len_offset": "0x130",
+//!       "send_style": "selected_outcome",
+//!       "match": {
+//!         "style": "preview230",
+//!         "toolcall_variant": 2,
+//!         "id_ptr_offset": "0x168",
+//!         "id_len_offset": "0x170",
+//!         "waiting_payload_niche_start": "0x8000000000000002"
+//!       }
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Offsets accept JSON numbers or strings (`"0x1c0"` or `"448"`). `versions`
+//! entries are `*`/`?` wildcards; `binary_hashes` are compared exactly
+//! (case-insensitive). An optional `snapshot` object supplies the ToolCall
+//! entry's title/kind/locations/raw_input offsets (see `tool_call_snapshot`),
+//! checked against `entry_size` like the other offsets. The hook reads the
+//! same fields from the `ToolCallUpdate` whenever `update_id_ptr_offset` /
+//! `update_id_len_offset` agree with our `acp` crate; entry offsets are what
+//! the stale scanner, which only has the entry, needs.
+//!
+//! An optional `thread` object locates AcpThread fields besides `entries`.
+//! `agent_name` is the name of the agent server behind the thread's
+//! connection, which `agents` entries are keyed by (see `agent.rs`): a
+//! string reached by following the pointers at each `deref` offset in turn,
+//! starting at the AcpThread. `work_dirs` is the thread's list of worktree
+//! roots, found the same way: a Vec of `elem_size` elements, each holding a
+//! `path` string. The first one is the thread's project (see `project.rs`).
+//! `session_id` is the thread's ACP session id, which never changes while
+//! the thread lives; it tells a live thread from a new one allocated at a
+//! freed one's address (see `entry_scanner::thread_signature`).
+//!
+//! ```json
+//! "thread": {
+//!   "agent_name": { "deref": ["0x1a0"], "ptr_offset": "0x28", "len_offset": "0x30", "data_offset": "0x10" },
+//!   "session_id": { "ptr_offset": "0x1c0", "len_offset": "0x1c8", "data_offset": "0x10" },
+//!   "work_dirs": {
+//!     "deref": ["0x1b8"], "ptr_offset": "0x8", "len_offset": "0x10", "elem_size": "0x18",
+//!     "path": { "ptr_offset": "0x8", "len_offset": "0x10", "data_offset": 0 }
+//!   }
+//! }
+//! ```
+//!
+//! ## Selection
+//!
+//! The running binary's fingerprint (see `fingerprint`) picks exactly one
+//! layout: a database layout whose `binary_hashes` contains its hash, then a
+//! database layout whose `versions` match, then a compiled layout verified on
+//! that version. An unrecognised binary falls back to probing every layout,
+//! database first, then the compiled table.
+
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::hooks::tool_call_snapshot::{SnapshotLayout, StrField, VecField, read_str};
+use crate::mem::{SafeMem, plausible_ptr};
+use crate::policy::wildcard_match;
+
+// ---------------------------------------------------------------------------
+// Layout types
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SendStyle {
+    LegacyOptionId,
+    SelectedOutcome,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MatchStyle {
+    // Preview 0.230.x:
+    // - AgentThreadEntry::ToolCall discriminant = 0x2
+    // - ToolCall.id at entry+0x168 / +0x170
+    // - ToolCallStatus::WaitingForConfirmation is niche-encoded:
+    //   any payload head < 0x8000_0000_0000_0002
+    Preview230 {
+        toolcall_variant: u64,
+        id_ptr_offset: usize,
+        id_len_offset: usize,
+        waiting_payload_niche_start: u64,
+    },
+    LegacyExact {
+        toolcall_variant: u64,
+        waiting_variant: u64,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct EntryLayout {
+    pub(crate) name: &'static str,
+    /// `AcpThread.entries` Vec<AgentThreadEntry> ptr / len.
+    pub(crate) entries_ptr_offset: usize,
+    pub(crate) entries_len_offset: usize,
+    pub(crate) entry_size: usize,
+    pub(crate) status_offset: usize,
+    pub(crate) respond_tx_offset: usize,
+    /// `ToolCallUpdate.tool_call_id` ptr / len (the hooked function's 2nd argument).
+    pub(crate) update_id_ptr_offset: usize,
+    pub(crate) update_id_len_offset: usize,
+    pub(crate) send_style: SendStyle,
+    pub(crate) match_style: MatchStyle,
+    /// Offsets of the ToolCall entry's title/kind/locations/raw_input, if known.
+    pub(crate) snapshot: Option<SnapshotLayout>,
+    /// Other AcpThread fields, if known.
+    pub(crate) thread: ThreadLayout,
+}
+
+/// A string reached from a struct through pointers: each `deref` offset is
+/// read as a pointer from the address so far, then `field` is read from
+/// where that leads.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ChainedStr {
+    pub(crate) deref: &'static [usize],
+    pub(crate) field: StrField,
+}
+
+impl ChainedStr {
+    /// The string reached from `base`; `None` when a pointer or the string
+    /// can't be read.
+    pub(crate) fn read(&self, mem: &impl SafeMem, base: u64) -> Option<String> {
+        let mut addr = base;
+        for &offset in self.deref {
+            addr = mem
+                .read_u64(addr + offset as u64)
+                .filter(|&ptr| plausible_ptr(ptr))?;
+        }
+        read_str(mem, addr, self.field)
+    }
+}
+
+/// A Vec of strings reached from a struct through pointers, like
+/// `ChainedStr`; `path` is relative to each element.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ChainedStrs {
+    pub(crate) deref: &'static [usize],
+    pub(crate) list: VecField,
+    pub(crate) path: StrField,
+}
+
+/// AcpThread fields besides `entries`. Only the layout database supplies
+/// them so far.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ThreadLayout {
+    /// Name of the agent server behind `AcpThread.connection`.
+    pub(crate) agent_name: Option<ChainedStr>,
+    /// `AcpThread.session_id`: set when the thread is created and never
+    /// changed, so it tells a live thread from a new one at its address.
+    pub(crate) session_id: Option<ChainedStr>,
+    /// Worktree roots the thread works in.
+    pub(crate) work_dirs: Option<ChainedStrs>,
+}
+
+impl ThreadLayout {
+    pub(crate) const UNKNOWN: ThreadLayout = ThreadLayout {
+        agent_name: None,
+        session_id: None,
+        work_dirs: None,
+    };
+}
+
+/// Most pointers a `ChainedStr` follows.
+const MAX_DEREF: usize = 4;
+
+pub(crate) const ENTRY_LAYOUTS: &[EntryLayout] = &[
+    // Zed Preview 0.233.0 – 1.1.x (docs/19, docs/20, docs/21)
+    // entries moved 0x90/0x98 → 0xb0/0xb8 (new `cost` field on AcpThread);
+    // the entry itself is unchanged from 0.230.x.
+    EntryLayout {
+        name: "v0.233.x",
+        entries_ptr_offset: 0xb0,
+        entries_len_offset: 0xb8,
+        entry_size: 0x1c0,
+        status_offset: 0x118,
+        respond_tx_offset: 0x160,
+        update_id_ptr_offset: 0x128,
+        update_id_len_offset: 0x130,
+        send_style: SendStyle::SelectedOutcome,
+        match_style: MatchStyle::Preview230 {
+            toolcall_variant: 0x02,
+            id_ptr_offset: 0x168,
+            id_len_offset: 0x170,
+            waiting_payload_niche_start: 0x8000_0000_0000_0002,
+        },
+        // The entry's ToolCall fields are not yet recovered from
+        // disassembly; the hook reads them from the ToolCallUpdate instead
+        // (`tool_call_snapshot::update_layout`).
+        snapshot: None,
+        thread: ThreadLayout::UNKNOWN,
+    },
+    // Zed Preview 0.230.0 9437a84390a396d666f04b38db87d89bb07284c1 – 0.232.x
+    EntryLayout {
+        name: "v0.230.x",
+        entries_ptr_offset: 0x90,
+        entries_len_offset: 0x98,
+        entry_size: 0x1c0,
+        status_offset: 0x118,
+        respond_tx_offset: 0x160,
+        update_id_ptr_offset: 0x128,
+        update_id_len_offset: 0x130,
+        send_style: SendStyle::SelectedOutcome,
+        match_style: MatchStyle::Preview230 {
+            toolcall_variant: 0x02,
+            id_ptr_offset: 0x168,
+            id_len_offset: 0x170,
+            waiting_payload_niche_start: 0x8000_0000_0000_0002,
+        },
+        snapshot: None,
+        thread: ThreadLayout::UNKNOWN,
+    },
+    // Zed Preview 0.228.x / 0.229.x
+    EntryLayout {
+        name: "v0.228.x",
+        entries_ptr_offset: 0x90,
+        entries_len_offset: 0x98,
+        entry_size: 0x1b0,
+        status_offset: 0x48,
+        respond_tx_offset: 0x68,
+        update_id_ptr_offset: 0x128,
+        update_id_len_offset: 0x130,
+        send_style: SendStyle::LegacyOptionId,
+        match_style: MatchStyle::LegacyExact {
+            toolcall_variant: 0x07,
+            waiting_variant: 0x00,
+        },
+        snapshot: None,
+        thread: ThreadLayout::UNKNOWN,
+    },
+];
+
+/// Zed versions each compiled layout was verified on, same order as `ENTRY_LAYOUTS`.
+const COMPILED_VERSIONS: &[&[&str]] = &[
+    &["0.233.*", "1.0.*", "1.1.*"],
+    &["0.230.*", "0.231.*", "0.232.*"],
+    &["0.228.*", "0.229.*"],
+];
+
+const _: () = assert!(COMPILED_VERSIONS.len() == ENTRY_LAYOUTS.len());
+
+// ---------------------------------------------------------------------------
+// Layout database file
+// ---------------------------------------------------------------------------
+
+/// Offset that deserializes from a JSON number or a `"0x…"` / decimal string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Offset(u64);
+
+impl<'de> Deserialize<'de> for Offset {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(n) => Ok(Offset(n)),
+            Raw::Text(s) => parse_offset(&s)
+                .map(Offset)
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid offset {s:?}"))),
+        }
+    }
+}
+
+fn parse_offset(s: &str) -> Option<u64> {
+    let s = s.trim().replace('_', "");
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LayoutFile {
+    layouts: Vec<LayoutSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LayoutSpec {
+    name: String,
+    #[serde(default)]
+    versions: Vec<String>,
+    #[serde(default)]
+    binary_hashes: Vec<String>,
+    entries_ptr_offset: Offset,
+    entries_len_offset: Offset,
+    entry_size: Offset,
+    status_offset: Offset,
+    respond_tx_offset: Offset,
+    update_id_ptr_offset: Offset,
+    update_id_len_offset: Offset,
+    send_style: SendStyleSpec,
+    #[serde(rename = "match")]
+    match_style: MatchSpec,
+    #[serde(default)]
+    snapshot: Option<SnapshotSpec>,
+    #[serde(default)]
+    thread: Option<ThreadSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SendStyleSpec {
+    LegacyOptionId,
+    SelectedOutcome,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "style", rename_all = "snake_case", deny_unknown_fields)]
+enum MatchSpec {
+    Preview230 {
+        toolcall_variant: Offset,
+        id_ptr_offset: Offset,
+        id_len_offset: Offset,
+        waiting_payload_niche_start: Offset,
+    },
+    LegacyExact {
+        toolcall_variant: Offset,
+        waiting_variant: Offset,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StrSpec {
+    ptr_offset: Offset,
+    len_offset: Offset,
+    data_offset: Offset,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VecSpec {
+    ptr_offset: Offset,
+    len_offset: Offset,
+    elem_size: Offset,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SnapshotSpec {
+    #[serde(default)]
+    title: Option<StrSpec>,
+    #[serde(default)]
+    kind_offset: Option<Offset>,
+    #[serde(default)]
+    locations: Option<VecSpec>,
+    location_path: StrSpec,
+    #[serde(default)]
+    raw_input_offset: Option<Offset>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainedStrSpec {
+    #[serde(default)]
+    deref: Vec<Offset>,
+    ptr_offset: Offset,
+    len_offset: Offset,
+    data_offset: Offset,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainedStrsSpec {
+    #[serde(default)]
+    deref: Vec<Offset>,
+    ptr_offset: Offset,
+    len_offset: Offset,
+    elem_size: Offset,
+    path: StrSpec,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThreadSpec {
+    #[serde(default)]
+    agent_name: Option<ChainedStrSpec>,
+    #[serde(default)]
+    session_id: Option<ChainedStrSpec>,
+    #[serde(default)]
+    work_dirs: Option<ChainedStrsSpec>,
+}
+
+/// A validated database layout plus the keys it applies to.
+#[derive(Clone, Debug)]
+pub(crate) struct LayoutRecord {
+    pub(crate) layout: EntryLayout,
+    pub(crate) versions: Vec<String>,
+    pub(crate) binary_hashes: Vec<String>,
+}
+
+/// Why a layout database was rejected.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum LayoutError {
+    Json(String),
+    Invalid { layout: String, reason: String },
+}
+
+impl std::fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutError::Json(e) => write!(f, "invalid JSON: {e}"),
+            LayoutError::Invalid { layout, reason } => write!(f, "layout {layout:?}: {reason}"),
+        }
+    }
+}
+
+/// Largest plausible struct offset. AcpThread and AgentThreadEntry are a few
+/// hundred bytes; anything beyond this is a typo.
+const MAX_OFFSET: u64 = 0x1_0000;
+
+fn usize_offset(value: Offset, field: &str) -> Result<usize, String> {
+    if value.0 >= MAX_OFFSET {
+        return Err(format!("{field}={:#x} is out of range", value.0));
+    }
+    Ok(value.0 as usize)
+}
+
+/// Check that an 8-byte word at `offset` fits inside an entry of `entry_size`.
+fn word_in_entry(offset: usize, entry_size: usize, field: &str) -> Result<(), String> {
+    if !offset.is_multiple_of(8) {
+        return Err(format!("{field}={offset:#x} is not 8-byte aligned"));
+    }
+    if offset + 8 > entry_size {
+        return Err(format!(
+            "{field}={offset:#x} is outside the entry ({entry_size:#x} bytes)"
+        ));
+    }
+    Ok(())
+}
+
+fn str_field(spec: &StrSpec, field: &str) -> Result<StrField, String> {
+    Ok(StrField {
+        ptr_offset: usize_offset(spec.ptr_offset, &format!("{field}.ptr_offset"))?,
+        len_offset: usize_offset(spec.len_offset, &format!("{field}.len_offset"))?,
+        data_offset: usize_offset(spec.data_offset, &format!("{field}.data_offset"))?,
+        none: None,
+    })
+}
+
+/// Check that a `size`-byte field at `offset` fits inside an entry.
+fn field_in_entry(
+    offset: usize,
+    size: usize,
+    entry_size: usize,
+    field: &str,
+) -> Result<(), String> {
+    if offset + size > entry_size {
+        return Err(format!(
+            "{field}={offset:#x} is outside the entry ({entry_size:#x} bytes)"
+        ));
+    }
+    Ok(())
+}
+
+impl SnapshotSpec {
+    /// Snapshot offsets are entry-relative, except `location_path`, which is
+    /// relative to each location element.
+    fn build(&self, entry_size: usize) -> Result<SnapshotLayout, String> {
+        let layout = SnapshotLayout {
+            title: self
+                .title
+                .as_ref()
+                .map(|t| str_field(t, "snapshot.title"))
+                .transpose()?,
+            kind_offset: self
+                .kind_offset
+                .map(|o| usize_offset(o, "snapshot.kind_offset"))
+                .transpose()?,
+            locations: self
+                .locations
+                .as_ref()
+                .map(|v| -> Result<VecField, String> {
+                    Ok(VecField {
+                        ptr_offset: usize_offset(v.ptr_offset, "snapshot.locations.ptr_offset")?,
+                        len_offset: usize_offset(v.len_offset, "snapshot.locations.len_offset")?,
+                        elem_size: usize_offset(v.elem_size, "snapshot.locations.elem_size")?,
+                        none: None,
+                    })
+                })
+                .transpose()?,
+            location_path: str_field(&self.location_path, "snapshot.location_path")?,
+            raw_input_offset: self
+                .raw_input_offset
+                .map(|o| usize_offset(o, "snapshot.raw_input_offset"))
+                .transpose()?,
+        };
+
+        if let Some(title) = &layout.title {
+            word_in_entry(title.ptr_offset, entry_size, "snapshot.title.ptr_offset")?;
+            word_in_entry(title.len_offset, entry_size, "snapshot.title.len_offset")?;
+        }
+        if let Some(kind) = layout.kind_offset {
+            field_in_entry(kind, 1, entry_size, "snapshot.kind_offset")?;
+        }
+        if let Some(locations) = &layout.locations {
+            word_in_entry(
+                locations.ptr_offset,
+                entry_size,
+                "snapshot.locations.ptr_offset",
+            )?;
+            word_in_entry(
+                locations.len_offset,
+                entry_size,
+                "snapshot.locations.len_offset",
+            )?;
+            let elem_size = locations.elem_size;
+            if elem_size == 0 || !elem_size.is_multiple_of(8) {
+                return Err(format!(
+                    "snapshot.locations.elem_size={elem_size:#x} must be a non-zero multiple of 8"
+                ));
+            }
+            let path = &layout.location_path;
+            word_in_entry(
+                path.ptr_offset,
+                elem_size,
+                "snapshot.location_path.ptr_offset",
+            )?;
+            word_in_entry(
+                path.len_offset,
+                elem_size,
+                "snapshot.location_path.len_offset",
+            )?;
+        }
+        if let Some(raw_input) = layout.raw_input_offset {
+            if !raw_input.is_multiple_of(8) {
+                return Err(format!(
+                    "snapshot.raw_input_offset={raw_input:#x} is not 8-byte aligned"
+                ));
+            }
+            field_in_entry(
+                raw_input,
+                size_of::<Option<serde_json::Value>>(),
+                entry_size,
+                "snapshot.raw_input_offset",
+            )?;
+        }
+        Ok(layout)
+    }
+}
+
+/// Check that `offset` can hold a word (the struct size isn't known).
+fn aligned(offset: usize, field: &str) -> Result<usize, String> {
+    if !offset.is_multiple_of(8) {
+        return Err(format!("{field}={offset:#x} is not 8-byte aligned"));
+    }
+    Ok(offset)
+}
+
+/// Validate and leak the pointer offsets of a chained field.
+fn deref(offsets: &[Offset], field: &str) -> Result<&'static [usize], String> {
+    if offsets.len() > MAX_DEREF {
+        return Err(format!(
+            "{field}.deref has {} offsets (at most {MAX_DEREF})",
+            offsets.len()
+        ));
+    }
+    let deref = offsets
+        .iter()
+        .map(|&o| {
+            aligned(
+                usize_offset(o, &format!("{field}.deref"))?,
+                &format!("{field}.deref"),
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // Leaked like the layout name: layouts live for the whole process.
+    Ok(Box::leak(deref.into_boxed_slice()))
+}
+
+impl ChainedStrSpec {
+    fn build(&self, field: &str) -> Result<ChainedStr, String> {
+        let deref = deref(&self.deref, field)?;
+        let text = str_field(
+            &StrSpec {
+                ptr_offset: self.ptr_offset,
+                len_offset: self.len_offset,
+                data_offset: self.data_offset,
+            },
+            field,
+        )?;
+        aligned(text.ptr_offset, &format!("{field}.ptr_offset"))?;
+        aligned(text.len_offset, &format!("{field}.len_offset"))?;
+        Ok(ChainedStr { deref, field: text })
+    }
+}
+
+impl ChainedStrsSpec {
+    fn build(&self, field: &str) -> Result<ChainedStrs, String> {
+        let deref = deref(&self.deref, field)?;
+        let list = VecField {
+            ptr_offset: aligned(
+                usize_offset(self.ptr_offset, &format!("{field}.ptr_offset"))?,
+                &format!("{field}.ptr_offset"),
+            )?,
+            len_offset: aligned(
+                usize_offset(self.len_offset, &format!("{field}.len_offset"))?,
+                &format!("{field}.len_offset"),
+            )?,
+            elem_size: usize_offset(self.elem_size, &format!("{field}.elem_size"))?,
+            none: None,
+        };
+        if list.elem_size == 0 || !list.elem_size.is_multiple_of(8) {
+            return Err(format!(
+                "{field}.elem_size={:#x} must be a non-zero multiple of 8",
+                list.elem_size
+            ));
+        }
+        let path = str_field(&self.path, &format!("{field}.path"))?;
+        word_in_entry(
+            path.ptr_offset,
+            list.elem_size,
+            &format!("{field}.path.ptr_offset"),
+        )?;
+        word_in_entry(
+            path.len_offset,
+            list.elem_size,
+            &format!("{field}.path.len_offset"),
+        )?;
+        Ok(ChainedStrs { deref, list, path })
+    }
+}
+
+impl ThreadSpec {
+    fn build(&self) -> Result<ThreadLayout, String> {
+        Ok(ThreadLayout {
+            agent_name: self
+                .agent_name
+                .as_ref()
+                .map(|name| name.build("thread.agent_name"))
+                .transpose()?,
+            session_id: self
+                .session_id
+                .as_ref()
+                .map(|id| id.build("thread.session_id"))
+                .transpose()?,
+            work_dirs: self
+                .work_dirs
+                .as_ref()
+                .map(|dirs| dirs.build("thread.work_dirs"))
+                .transpose()?,
+        })
+    }
+}
+
+impl LayoutSpec {
+    fn build(&self) -> Result<LayoutRecord, String> {
+        if self.name.trim().is_empty() {
+            return Err("name is empty".to_string());
+        }
+        if self.versions.is_empty() && self.binary_hashes.is_empty() {
+            return Err("needs at least one of `versions` / `binary_hashes`".to_string());
+        }
+
+        let entries_ptr_offset = usize_offset(self.entries_ptr_offset, "entries_ptr_offset")?;
+        let entries_len_offset = usize_offset(self.entries_len_offset, "entries_len_offset")?;
+        if entries_ptr_offset == entries_len_offset {
+            return Err("entries_ptr_offset and entries_len_offset are equal".to_string());
+        }
+
+        let entry_size = usize_offset(self.entry_size, "entry_size")?;
+        if entry_size == 0 || !entry_size.is_multiple_of(8) {
+            return Err(format!(
+                "entry_size={entry_size:#x} must be a non-zero multiple of 8"
+            ));
+        }
+
+        let status_offset = usize_offset(self.status_offset, "status_offset")?;
+        let respond_tx_offset = usize_offset(self.respond_tx_offset, "respond_tx_offset")?;
+        word_in_entry(status_offset, entry_size, "status_offset")?;
+        word_in_entry(respond_tx_offset, entry_size, "respond_tx_offset")?;
+        if status_offset == respond_tx_offset {
+            return Err("status_offset and respond_tx_offset are equal".to_string());
+        }
+
+        let match_style = match &self.match_style {
+            MatchSpec::Preview230 {
+                toolcall_variant,
+                id_ptr_offset,
+                id_len_offset,
+                waiting_payload_niche_start,
+            } => {
+                let id_ptr_offset = usize_offset(*id_ptr_offset, "match.id_ptr_offset")?;
+                let id_len_offset = usize_offset(*id_len_offset, "match.id_len_offset")?;
+                word_in_entry(id_ptr_offset, entry_size, "match.id_ptr_offset")?;
+                word_in_entry(id_len_offset, entry_size, "match.id_len_offset")?;
+                MatchStyle::Preview230 {
+                    toolcall_variant: toolcall_variant.0,
+                    id_ptr_offset,
+                    id_len_offset,
+                    waiting_payload_niche_start: waiting_payload_niche_start.0,
+                }
+            }
+            MatchSpec::LegacyExact {
+                toolcall_variant,
+                waiting_variant,
+            } => MatchStyle::LegacyExact {
+                toolcall_variant: toolcall_variant.0,
+                waiting_variant: waiting_variant.0,
+            },
+        };
+
+        let snapshot = self
+            .snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.build(entry_size))
+            .transpose()?;
+        let thread = match &self.thread {
+            Some(thread) => thread.build()?,
+            None => ThreadLayout::UNKNOWN,
+        };
+
+        Ok(LayoutRecord {
+            layout: EntryLayout {
+                // Layouts live for the whole process; leaking the name keeps
+                // `EntryLayout` Copy like the compiled table.
+                name: Box::leak(self.name.clone().into_boxed_str()),
+                entries_ptr_offset,
+                entries_len_offset,
+                entry_size,
+                status_offset,
+                respond_tx_offset,
+                update_id_ptr_offset: usize_offset(
+                    self.update_id_ptr_offset,
+                    "update_id_ptr_offset",
+                )?,
+                update_id_len_offset: usize_offset(
+                    self.update_id_len_offset,
+                    "update_id_len_offset",
+                )?,
+                send_style: match self.send_style {
+                    SendStyleSpec::LegacyOptionId => SendStyle::LegacyOptionId,
+                    SendStyleSpec::SelectedOutcome => SendStyle::SelectedOutcome,
+                },
+                match_style,
+                snapshot,
+                thread,
+            },
+            versions: self.versions.clone(),
+            binary_hashes: self.binary_hashes.clone(),
+        })
+    }
+}
+
+/// Parse and validate a layout database. Any invalid layout rejects the whole
+/// file — a half-applied database is harder to reason about than none.
+pub(crate) fn parse(json: &str) -> Result<Vec<LayoutRecord>, LayoutError> {
+    let file: LayoutFile =
+        serde_json::from_str(json).map_err(|e| LayoutError::Json(e.to_string()))?;
+
+    let mut records: Vec<LayoutRecord> = Vec::with_capacity(file.layouts.len());
+    for spec in &file.layouts {
+        let record = spec.build().map_err(|reason| LayoutError::Invalid {
+            layout: spec.name.clone(),
+            reason,
+        })?;
+        if records.iter().any(|r| r.layout.name == record.layout.name) {
+            return Err(LayoutError::Invalid {
+                layout: spec.name.clone(),
+                reason: "duplicate name".to_string(),
+            });
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Layout database entry for `layout` (the inverse of `parse`), with hex
+/// offsets so it reads like the hand-written ones.
+pub(crate) fn to_json(
+    layout: &EntryLayout,
+    versions: &[String],
+    binary_hashes: &[String],
+) -> serde_json::Value {
+    use serde_json::json;
+
+    let hex = |v: usize| json!(format!("{v:#x}"));
+    let str_field = |f: &StrField| {
+        json!({
+            "ptr_offset": hex(f.ptr_offset),
+            "len_offset": hex(f.len_offset),
+            "data_offset": hex(f.data_offset),
+        })
+    };
+
+    let match_style = match layout.match_style {
+        MatchStyle::Preview230 {
+            toolcall_variant,
+            id_ptr_offset,
+            id_len_offset,
+            waiting_payload_niche_start,
+        } => json!({
+            "style": "preview230",
+            "toolcall_variant": toolcall_variant,
+            "id_ptr_offset": hex(id_ptr_offset),
+            "id_len_offset": hex(id_len_offset),
+            "waiting_payload_niche_start": format!("{waiting_payload_niche_start:#x}"),
+        }),
+        MatchStyle::LegacyExact {
+            toolcall_variant,
+            waiting_variant,
+        } => json!({
+            "style": "legacy_exact",
+            "toolcall_variant": toolcall_variant,
+            "waiting_variant": waiting_variant,
+        }),
+    };
+
+    let mut value = json!({
+        "name": layout.name,
+        "versions": versions,
+        "binary_hashes": binary_hashes,
+        "entries_ptr_offset": hex(layout.entries_ptr_offset),
+        "entries_len_offset": hex(layout.entries_len_offset),
+        "entry_size": hex(layout.entry_size),
+        "status_offset": hex(layout.status_offset),
+        "respond_tx_offset": hex(layout.respond_tx_offset),
+        "update_id_ptr_offset": hex(layout.update_id_ptr_offset),
+        "update_id_len_offset": hex(layout.update_id_len_offset),
+        "send_style": match layout.send_style {
+            SendStyle::LegacyOptionId => "legacy_option_id",
+            SendStyle::SelectedOutcome => "selected_outcome",
+        },
+        "match": match_style,
+    });
+
+    if let Some(snapshot) = &layout.snapshot {
+        let mut snap = json!({ "location_path": str_field(&snapshot.location_path) });
+        if let Some(title) = &snapshot.title {
+            snap["title"] = str_field(title);
+        }
+        if let Some(kind) = snapshot.kind_offset {
+            snap["kind_offset"] = hex(kind);
+        }
+        if let Some(locations) = &snapshot.locations {
+            snap["locations"] = json!({
+                "ptr_offset": hex(locations.ptr_offset),
+                "len_offset": hex(locations.len_offset),
+                "elem_size": hex(locations.elem_size),
+            });
+        }
+        if let Some(raw_input) = snapshot.raw_input_offset {
+            snap["raw_input_offset"] = hex(raw_input);
+        }
+        value["snapshot"] = snap;
+    }
+
+    let mut thread = serde_json::Map::new();
+    for (key, chained) in [
+        ("agent_name", &layout.thread.agent_name),
+        ("session_id", &layout.thread.session_id),
+    ] {
+        if let Some(chained) = chained {
+            let mut value = str_field(&chained.field);
+            value["deref"] = chained.deref.iter().map(|&o| hex(o)).collect();
+            thread.insert(key.to_string(), value);
+        }
+    }
+    if let Some(dirs) = &layout.thread.work_dirs {
+        thread.insert(
+            "work_dirs".to_string(),
+            json!({
+                "deref": dirs.deref.iter().map(|&o| hex(o)).collect::<Vec<_>>(),
+                "ptr_offset": hex(dirs.list.ptr_offset),
+                "len_offset": hex(dirs.list.len_offset),
+                "elem_size": hex(dirs.list.elem_size),
+                "path": str_field(&dirs.path),
+            }),
+        );
+    }
+    if !thread.is_empty() {
+        value["thread"] = thread.into();
+    }
+
+    value
+}
+
+/// How the running binary was matched to a layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MatchedBy {
+    /// Database layout listing the binary's hash.
+    BinaryHash,
+    /// Database layout whose `versions` match.
+    Version,
+    /// Compiled layout verified on this version.
+    CompiledVersion,
+}
+
+/// Result of `select`: the layouts to try and whether the binary was recognised.
+#[derive(Clone, Debug)]
+pub(crate) struct Selection {
+    pub(crate) layouts: Vec<EntryLayout>,
+    /// `None` = unknown binary; `layouts` is then every candidate (probing).
+    pub(crate) matched: Option<(&'static str, MatchedBy)>,
+}
+
+/// Pick the layout for a binary: a database layout listing its hash, then a
+/// database layout matching its version, then a compiled layout verified on
+/// its version. An unrecognised binary gets every layout (database first) to
+/// probe, the pre-fingerprint behaviour.
+pub(crate) fn select(
+    records: &[LayoutRecord],
+    version: Option<&str>,
+    binary_hash: Option<&str>,
+) -> Selection {
+    let matched = |layout: EntryLayout, by: MatchedBy| Selection {
+        layouts: vec![layout],
+        matched: Some((layout.name, by)),
+    };
+
+    if let Some(hash) = binary_hash
+        && let Some(record) = records
+            .iter()
+            .find(|r| r.binary_hashes.iter().any(|h| h.eq_ignore_ascii_case(hash)))
+    {
+        return matched(record.layout, MatchedBy::BinaryHash);
+    }
+
+    if let Some(version) = version {
+        if let Some(record) = records.iter().find(|r| {
+            r.versions
+                .iter()
+                .any(|pattern| wildcard_match(pattern, version))
+        }) {
+            return matched(record.layout, MatchedBy::Version);
+        }
+        if let Some(i) = COMPILED_VERSIONS
+            .iter()
+            .position(|patterns| patterns.iter().any(|p| wildcard_match(p, version)))
+        {
+            return matched(ENTRY_LAYOUTS[i], MatchedBy::CompiledVersion);
+        }
+    }
+
+    let mut layouts: Vec<EntryLayout> = records.iter().map(|r| r.layout).collect();
+    layouts.extend(ENTRY_LAYOUTS.iter().copied());
+    Selection {
+        layouts,
+        matched: None,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Runtime
+// ---------------------------------------------------------------------------
+
+static ACTIVE_LAYOUTS: OnceLock<&'static [EntryLayout]> = OnceLock::new();
+
+/// Layouts to try, in order. The compiled table until `init` has run.
+pub(crate) fn active() -> &'static [EntryLayout] {
+    ACTIVE_LAYOUTS.get().copied().unwrap_or(ENTRY_LAYOUTS)
+}
+
+/// The layout used for single-layout scans (stale scanner, miss diagnostics).
+pub(crate) fn primary() -> &'static EntryLayout {
+    &active()[0]
+}
+
+/// Layout database path: `~/.config/dylib-hooks/{app_id}/zed-yolo-layouts.json`
+pub fn layouts_path(app_id: &str) -> Option<PathBuf> {
+    let home = dirs::home_dir()?;
+    Some(
+        home.join(".config")
+            .join("dylib-hooks")
+            .join(app_id)
+            .join("zed-yolo-layouts.json"),
+    )
+}
+
+/// Load the layout database (if any) and fix the active layouts for this binary.
+/// Call once during init, before any hook is installed.
+///
+/// Returns how the binary was recognised, or `None` if it is unknown and all
+/// layouts will be probed.
+pub(crate) fn init(
+    app_id: &str,
+    version: Option<&str>,
+    binary_hash: Option<&str>,
+) -> Option<(&'static str, MatchedBy)> {
+    let records = match layouts_path(app_id) {
+        Some(path) if path.exists() => match std::fs::read_to_string(&path) {
+            Ok(json) => match parse(&json) {
+                Ok(records) => {
+                    tracing::info!(
+                        "layouts: loaded {} layout(s) from {}",
+                        records.len(),
+                        path.display()
+                    );
+                    records
+                }
+                Err(e) => {
+                    tracing::warn!("layouts: ignoring {}: {e}", path.display());
+                    Vec::new()
+                }
+            },
+            Err(e) => {
+                tracing::warn!("layouts: failed to read {}: {e}", path.display());
+                Vec::new()
+            }
+        },
+        _ => Vec::new(),
+    };
+
+    let selection = select(&records, version, binary_hash);
+    match selection.matched {
+        Some((name, by)) => tracing::info!("layouts: using {name} (matched by {by:?})"),
+        None => {
+            let names: Vec<&str> = selection.layouts.iter().map(|l| l.name).collect();
+            tracing::info!("layouts: no match, probing {names:?}");
+        }
+    }
+    let _ = ACTIVE_LAYOUTS.set(Box::leak(selection.layouts.into_boxed_slice()));
+    selection.matched
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout_json(name: &str, extra: &str) -> String {
+        format!(
+            r#"{{
+                "name": "{name}",
+                "versions": ["0.233.*"],
+                "entries_ptr_offset": "0xb0",
+                "entries_len_offset": "0xb8",
+                "entry_size": "0x1c0",
+                "status_offset": "0x118",
+                "respond_tx_offset": 352,
+                "update_id_ptr_offset": "0x128",
+                "update_id_len_offset": "0x130",
+                "send_style": "selected_outcome",
+                "match": {{
+                    "style": "preview230",
+                    "toolcall_variant": 2,
+                    "id_ptr_offset": "0x168",
+                    "id_len_offset": "0x170",
+                    "waiting_payload_niche_start": "0x8000_0000_0000_0002"
+                }}{extra}
+            }}"#
+        )
+    }
+
+    fn file(layouts: &[String]) -> String {
+        format!(r#"{{ "layouts": [{}] }}"#, layouts.join(","))
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("0x1c0"), Some(0x1c0));
+        assert_eq!(parse_offset("0X1C0"), Some(0x1c0));
+        assert_eq!(parse_offset("448"), Some(448));
+        assert_eq!(
+            parse_offset(" 0x8000_0000_0000_0002 "),
+            Some(0x8000_0000_0000_0002)
+        );
+        assert_eq!(parse_offset("0xzz"), None);
+        assert_eq!(parse_offset(""), None);
+    }
+
+    #[test]
+    fn test_parse_matches_compiled_layout() {
+        let records = parse(&file(&[layout_json("v0.233.x", "")])).unwrap();
+        assert_eq!(records.len(), 1);
+
+        let parsed = records[0].layout;
+        let compiled = ENTRY_LAYOUTS[0];
+        assert_eq!(compiled.name, "v0.233.x");
+        assert_eq!(parsed.name, "v0.233.x");
+        assert_eq!(parsed.entries_ptr_offset, compiled.entries_ptr_offset);
+        assert_eq!(parsed.entries_len_offset, compiled.entries_len_offset);
+        assert_eq!(parsed.entry_size, compiled.entry_size);
+        assert_eq!(parsed.status_offset, compiled.status_offset);
+        assert_eq!(parsed.respond_tx_offset, compiled.respond_tx_offset);
+        assert_eq!(parsed.update_id_ptr_offset, compiled.update_id_ptr_offset);
+        assert_eq!(parsed.update_id_len_offset, compiled.update_id_len_offset);
+        assert_eq!(parsed.send_style, compiled.send_style);
+        assert_eq!(parsed.match_style, compiled.match_style);
+        assert!(parsed.snapshot.is_none());
+        assert_eq!(records[0].versions, vec!["0.233.*"]);
+    }
+
+    #[test]
+    fn test_parse_legacy_and_snapshot() {
+        let legacy = layout_json("legacy", "")
+            .replace("selected_outcome", "legacy_option_id")
+            .replace(
+                r#""style": "preview230",
+                    "toolcall_variant": 2,
+                    "id_ptr_offset": "0x168",
+                    "id_len_offset": "0x170",
+                    "waiting_payload_niche_start": "0x8000_0000_0000_0002""#,
+                r#""style": "legacy_exact", "toolcall_variant": 7, "waiting_variant": 0"#,
+            );
+        let with_snapshot = layout_json(
+            "snap",
+            r#", "snapshot": {
+                "title": { "ptr_offset": "0x20", "len_offset": "0x28", "data_offset": "0x10" },
+                "kind_offset": "0x1b8",
+                "locations": { "ptr_offset": "0x40", "len_offset": "0x48", "elem_size": "0x28" },
+                "location_path": { "ptr_offset": 0, "len_offset": 16, "data_offset": 0 }
+            }"#,
+        );
+
+        let records = parse(&file(&[legacy, with_snapshot])).unwrap();
+        assert_eq!(records[0].layout.send_style, SendStyle::LegacyOptionId);
+        assert_eq!(
+            records[0].layout.match_style,
+            MatchStyle::LegacyExact {
+                toolcall_variant: 7,
+                waiting_variant: 0
+            }
+        );
+
+        let snapshot = records[1].layout.snapshot.unwrap();
+        assert_eq!(snapshot.title.unwrap().ptr_offset, 0x20);
+        assert_eq!(snapshot.kind_offset, Some(0x1b8));
+        assert_eq!(snapshot.locations.unwrap().elem_size, 0x28);
+        assert_eq!(snapshot.location_path.len_offset, 16);
+        assert!(snapshot.raw_input_offset.is_none());
+    }
+
+    #[test]
+    fn test_to_json_roundtrip() {
+        for compiled in ENTRY_LAYOUTS {
+            let versions = vec!["9.9.*".to_string()];
+            let json = serde_json::json!({ "layouts": [to_json(compiled, &versions, &[])] });
+            let records = parse(&json.to_string()).unwrap();
+            let parsed = records[0].layout;
+            assert_eq!(parsed.name, compiled.name);
+            assert_eq!(parsed.entries_ptr_offset, compiled.entries_ptr_offset);
+            assert_eq!(parsed.entry_size, compiled.entry_size);
+            assert_eq!(parsed.status_offset, compiled.status_offset);
+            assert_eq!(parsed.respond_tx_offset, compiled.respond_tx_offset);
+            assert_eq!(parsed.update_id_len_offset, compiled.update_id_len_offset);
+            assert_eq!(parsed.send_style, compiled.send_style);
+            assert_eq!(parsed.match_style, compiled.match_style);
+            assert_eq!(records[0].versions, versions);
+        }
+
+        let with_snapshot = parse(&file(&[layout_json(
+            "snap",
+            r#", "snapshot": {
+                "kind_offset": "0x1b8",
+                "location_path": { "ptr_offset": 0, "len_offset": 16, "data_offset": 0 },
+                "raw_input_offset": "0x60"
+            }"#,
+        )]))
+        .unwrap();
+        let json = serde_json::json!({
+            "layouts": [to_json(&with_snapshot[0].layout, &["0.233.*".to_string()], &[])]
+        });
+        let snapshot = parse(&json.to_string()).unwrap()[0]
+            .layout
+            .snapshot
+            .unwrap();
+        assert_eq!(snapshot.kind_offset, Some(0x1b8));
+        assert_eq!(snapshot.raw_input_offset, Some(0x60));
+        assert!(snapshot.title.is_none());
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let invalid = |json: String| match parse(&file(&[json])) {
+            Err(LayoutError::Invalid { reason, .. }) => reason,
+            other => panic!("expected Invalid, got {other:?}"),
+        };
+
+        assert!(invalid(layout_json("a", "").replace("0x118", "0x1c0")).contains("outside"));
+        assert!(invalid(layout_json("a", "").replace("0x118", "0x11c")).contains("aligned"));
+        assert!(invalid(layout_json("a", "").replace("\"0x1c0\"", "0")).contains("entry_size"));
+        assert!(invalid(layout_json("a", "").replace("0x168", "0x10000")).contains("range"));
+        assert!(invalid(layout_json("a", "").replace("0xb8", "0xb0")).contains("equal"));
+        assert!(invalid(layout_json("", "")).contains("empty"));
+        assert!(
+            invalid(layout_json("a", "").replace(r#""versions": ["0.233.*"],"#, ""))
+                .contains("versions")
+        );
+    }
+
+    #[test]
+    fn test_snapshot_offsets_checked_against_entry() {
+        let invalid = |snapshot: &str| match parse(&file(&[layout_json(
+            "a",
+            &format!(r#", "snapshot": {{ {snapshot} }}"#),
+        )])) {
+            Err(LayoutError::Invalid { reason, .. }) => reason,
+            other => panic!("expected Invalid, got {other:?}"),
+        };
+        let path = r#""location_path": { "ptr_offset": 0, "len_offset": 16, "data_offset": 0 }"#;
+
+        let title =
+            r#""title": { "ptr_offset": "0x1c0", "len_offset": "0x28", "data_offset": "0x10" }"#;
+        assert!(invalid(&format!("{title}, {path}")).contains("snapshot.title.ptr_offset"));
+        assert!(
+            invalid(&format!(r#""kind_offset": "0x1c0", {path}"#)).contains("snapshot.kind_offset")
+        );
+        let locations =
+            r#""locations": { "ptr_offset": "0x40", "len_offset": "0x48", "elem_size": "0x10" }"#;
+        assert!(
+            invalid(&format!("{locations}, {path}")).contains("snapshot.location_path.len_offset")
+        );
+        assert!(
+            invalid(&format!(r#""raw_input_offset": "0x1a0", {path}"#))
+                .contains("snapshot.raw_input_offset")
+        );
+        assert!(invalid(&format!(r#""raw_input_offset": "0x64", {path}"#)).contains("aligned"));
+    }
+
+    #[test]
+    fn test_parse_thread() {
+        let thread = r#", "thread": {
+            "agent_name": { "deref": ["0x1a0", 16], "ptr_offset": "0x28", "len_offset": "0x30", "data_offset": "0x10" },
+            "session_id": { "ptr_offset": "0x1c0", "len_offset": "0x1c8", "data_offset": "0x10" },
+            "work_dirs": {
+                "deref": ["0x1b8"], "ptr_offset": 8, "len_offset": 16, "elem_size": "0x18",
+                "path": { "ptr_offset": 8, "len_offset": 16, "data_offset": 0 }
+            }
+        }"#;
+        let records = parse(&file(&[layout_json("a", thread)])).unwrap();
+        let name = records[0].layout.thread.agent_name.unwrap();
+        assert_eq!(name.deref, &[0x1a0, 0x10]);
+        assert_eq!(name.field.ptr_offset, 0x28);
+        assert_eq!(name.field.data_offset, 0x10);
+        assert!(ENTRY_LAYOUTS[0].thread.agent_name.is_none());
+
+        let json = serde_json::json!({
+            "layouts": [to_json(&records[0].layout, &["0.233.*".to_string()], &[])]
+        });
+        let reparsed = parse(&json.to_string()).unwrap()[0].layout.thread;
+        let parsed = reparsed.agent_name.unwrap();
+        assert_eq!(parsed.deref, name.deref);
+        assert_eq!(parsed.field.len_offset, 0x30);
+        let session = reparsed.session_id.unwrap();
+        assert!(session.deref.is_empty());
+        assert_eq!(session.field.ptr_offset, 0x1c0);
+        let dirs = reparsed.work_dirs.unwrap();
+        assert_eq!(dirs.deref, &[0x1b8]);
+        assert_eq!(dirs.list.elem_size, 0x18);
+        assert_eq!(dirs.path.len_offset, 16);
+
+        let invalid = |agent_name: &str| match parse(&file(&[layout_json(
+            "a",
+            &format!(r#", "thread": {{ "agent_name": {agent_name} }}"#),
+        )])) {
+            Err(LayoutError::Invalid { reason, .. }) => reason,
+            other => panic!("expected Invalid, got {other:?}"),
+        };
+        let field = r#""ptr_offset": 0, "len_offset": 8, "data_offset": 0"#;
+        assert!(
+            invalid(&format!(r#"{{ "deref": [1, 2, 3, 4, 5], {field} }}"#)).contains("at most")
+        );
+        assert!(invalid(&format!(r#"{{ "deref": ["0x1a4"], {field} }}"#)).contains("aligned"));
+        assert!(
+            invalid(r#"{ "ptr_offset": 4, "len_offset": 8, "data_offset": 0 }"#)
+                .contains("thread.agent_name.ptr_offset")
+        );
+        let work_dirs = parse(&file(&[layout_json(
+            "a",
+            r#", "thread": { "work_dirs": {
+                "ptr_offset": 8, "len_offset": 16, "elem_size": 16,
+                "path": { "ptr_offset": 8, "len_offset": 16, "data_offset": 0 }
+            } }"#,
+        )]));
+        assert!(matches!(
+            work_dirs,
+            Err(LayoutError::Invalid { reason, .. }) if reason.contains("thread.work_dirs.path.len_offset")
+        ));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(parse("not json"), Err(LayoutError::Json(_))));
+        assert!(matches!(
+            parse(&file(&[layout_json("a", r#", "bogus": 1"#)])),
+            Err(LayoutError::Json(_))
+        ));
+        assert!(matches!(
+            parse(&file(&[
+                layout_json("a", "").replace("\"0xb0\"", "\"0xzz\"")
+            ])),
+            Err(LayoutError::Json(_))
+        ));
+        assert!(matches!(
+            parse(&file(&[layout_json("dup", ""), layout_json("dup", "")])),
+            Err(LayoutError::Invalid { .. })
+        ));
+        assert!(parse(r#"{ "layouts": [] }"#).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_select() {
+        let by_hash = layout_json("by-hash", "").replace(
+            r#""versions": ["0.233.*"],"#,
+            r#""binary_hashes": ["ABCDEF"],"#,
+        );
+        let records = parse(&file(&[layout_json("db-0.233", ""), by_hash])).unwrap();
+        let matched = |selection: Selection| {
+            assert_eq!(selection.layouts.len(), 1);
+            selection.matched.unwrap()
+        };
+
+        assert_eq!(
+            matched(select(&records, Some("0.233.3"), Some("abcdef"))),
+            ("by-hash", MatchedBy::BinaryHash)
+        );
+        assert_eq!(
+            matched(select(&records, Some("0.233.3"), Some("other"))),
+            ("db-0.233", MatchedBy::Version)
+        );
+        // The database overrides the compiled table for the same version.
+        assert_eq!(
+            matched(select(&[], Some("0.233.3"), None)),
+            ("v0.233.x", MatchedBy::CompiledVersion)
+        );
+        assert_eq!(
+            matched(select(&records, Some("1.1.2"), None)),
+            ("v0.233.x", MatchedBy::CompiledVersion)
+        );
+        assert_eq!(
+            matched(select(&records, Some("0.231.1"), None)),
+            ("v0.230.x", MatchedBy::CompiledVersion)
+        );
+        assert_eq!(
+            matched(select(&records, Some("0.228.0"), None)),
+            ("v0.228.x", MatchedBy::CompiledVersion)
+        );
+    }
+
+    #[test]
+    fn test_select_unknown_probes_everything() {
+        let records = parse(&file(&[layout_json("db-0.233", "")])).unwrap();
+        let names = |selection: Selection| -> Vec<&'static str> {
+            assert!(selection.matched.is_none());
+            selection.layouts.iter().map(|l| l.name).collect()
+        };
+
+        assert_eq!(
+            names(select(&records, Some("2.0.0"), Some("other"))),
+            vec!["db-0.233", "v0.233.x", "v0.230.x", "v0.228.x"]
+        );
+        assert_eq!(
+            names(select(&records, None, None)),
+            vec!["db-0.233", "v0.233.x", "v0.230.x", "v0.228.x"]
+        );
+        assert_eq!(
+            names(select(&[], None, None)),
+            vec!["v0.233.x", "v0.230.x", "v0.228.x"]
+        );
+    }
+}
@@ -3,24 +3,49 @@
 //! Auto-approves ALL tool call permission dialogs via two hooks:
 //!
 //! 1. `permission_decision` — hooks `ToolPermissionDecision::from_input`
-//!    to always return `Allow` (built-in tools).
+//!    to return `Allow` when the policy allows (built-in tools).
 //!
 //! 2. `tool_authorization` — hooks `AcpThread::request_tool_call_authorization`
 //!    to auto-send the matching ACP allow outcome through the oneshot channel
 //!    (external ACP agents). Supports both regular tool permissions and
 //!    ExitPlanMode prompts with configurable option_ids.
 //!
+//! Before any approval is sent, the pending tool call is run through the
+//! rule-based `policy` (allow / deny / ask). Calls that are not allowed are
+//! left for the human. Every decision is appended to a JSONL audit log
+//! (`audit.rs`).
+//!
 //! Configuration is loaded from `~/.config/dylib-hooks/{app_id}/zed-yolo-hook.json`
-//! with environment variable overrides. See `config.rs` for details.
+//! with environment variable overrides, and reloaded when the file changes.
+//! See `config.rs` for details. Memory
+//! layouts for new Zed versions can be supplied in `zed-yolo-layouts.json`
+//! next to it; see `layouts.rs`.
 
+mod agent;
+mod audit;
+mod budget;
+mod calibrate;
+mod circuit_breaker;
 mod config;
+mod control;
 mod ffi;
+mod fingerprint;
 mod hooks;
+mod layouts;
 mod logging;
+mod mem;
+mod path_scope;
+mod policy;
 mod process_role;
+mod project;
+mod scan_schedule;
+mod shell;
 mod symbols;
+mod thread_context;
 
-pub use config::{PlanOption, ToolOption, YoloConfig, YoloMode};
+pub use config::{OptionKind, PlanOption, ToolOption, UnknownVersion, YoloConfig, YoloMode};
+pub use fingerprint::AppFingerprint;
+pub use policy::{Decision, Policy, Rule, RuleAction, ToolCallFacts};
 
 use ctor::ctor;
 use frida_gum::{Gum, Process, interceptor::Interceptor};
@@ -29,8 +54,8 @@ use std::sync::OnceLock;
 static GUM: OnceLock<Gum> = OnceLock::new();
 static INIT_ONCE: std::sync::Once = std::sync::Once::new();
 
-/// Global config, set once during init, readable from hook listeners.
-pub(crate) static CONFIG: OnceLock<YoloConfig> = OnceLock::new();
+/// Fingerprint of the running Zed build, set once during init.
+pub(crate) static APP: OnceLock<AppFingerprint> = OnceLock::new();
 
 #[ctor]
 fn init() {
@@ -66,37 +91,78 @@ fn init_inner() {
         return;
     }
 
-    let app_id = config::detect_app_id();
-    let cfg = YoloConfig::load(&app_id);
+    let app = fingerprint::detect();
+    let app_id = app.app_id();
+    let (cfg, config_error) = YoloConfig::load(&app_id);
 
     logging::init(&cfg.log_level);
 
     let pid = unsafe { libc::getpid() };
     tracing::info!("=== zed-yolo-hook v{} ===", env!("CARGO_PKG_VERSION"));
     tracing::info!(
-        "DIAGNOSTIC: role={:?} ppid={} pid={} mode={:?} tool_option={:?} plan_option={:?} retry_delay_us={}",
+        "DIAGNOSTIC: role={:?} ppid={} pid={} app=[{}] mode={:?} tool_option={:?} plan_option={:?} retry_delay_us={} policy_rules={} policy_default={:?}",
         role,
         unsafe { libc::getppid() },
         pid,
+        app,
         cfg.mode,
         cfg.tool_option,
         cfg.plan_option,
-        cfg.retry_delay_us
+        cfg.retry_delay_us,
+        cfg.policy.rules.len(),
+        cfg.policy.default
     );
 
     if let Some(path) = config::config_path(&app_id) {
         tracing::info!("config file: {}", path.display());
+        if let Some(e) = &config_error {
+            tracing::error!(
+                "config: {} is invalid, starting in observe mode until it is fixed: {e}",
+                path.display()
+            );
+        }
     }
 
     if !cfg.is_enabled() {
         tracing::info!("YOLO disabled (pid={pid}).");
-        let _ = CONFIG.set(cfg);
+        config::install(cfg);
         return;
     }
 
-    // Store config for hook listeners
-    let mode = cfg.mode;
-    let _ = CONFIG.set(cfg);
+    // Memory layouts: pick the one matching this build (layout database first,
+    // then the compiled table).
+    let binary_hash = app.binary_hash();
+    if layouts::init(&app_id, app.version.as_deref(), binary_hash.as_deref()).is_none() {
+        report_unknown_version(&app, &app_id, cfg.unknown_version);
+    }
+    log_update_layout();
+
+    // Observe mode: everything runs and logs, nothing is sent or rewritten
+    // (`hooks::observe_only` follows the live config).
+    if cfg.mode == YoloMode::Observe {
+        tracing::warn!(
+            "OBSERVE MODE: hooks log every decision with what they would do; nothing is approved, rejected or rewritten"
+        );
+    }
+
+    // Calibration mode (`cargo patch calibrate`): propose a layout from the
+    // first tool call, approve nothing.
+    if let Some(path) = calibrate::init_from_env() {
+        hooks::OBSERVE_ONLY.store(true, std::sync::atomic::Ordering::Relaxed);
+        tracing::warn!(
+            "CALIBRATION MODE: OBSERVE-ONLY — the first tool call's layout proposal is written to {}",
+            path.display()
+        );
+    }
+    let _ = APP.set(app);
+
+    audit::init(&cfg.audit_log);
+    budget::resume(&cfg.budget, budget::state_path(&app_id).as_deref());
+
+    // Publish config for hook listeners; the scanner thread reloads it when
+    // the file changes.
+    let startup_cfg = cfg.clone();
+    config::install(cfg);
 
     let gum = GUM.get_or_init(|| Gum::obtain());
     let process = Process::obtain(gum);
@@ -106,26 +172,29 @@ fn init_inner() {
     // -----------------------------------------------------------------------
     // Hook 1: permission_decision (native tool permissions)
     // -----------------------------------------------------------------------
-    if matches!(mode, YoloMode::AllowAll) {
-        if let Some((name, ptr)) = symbols::find_by_pattern(
-            &main_module,
-            hooks::permission_decision::SYMBOL_INCLUDE,
-            hooks::permission_decision::SYMBOL_EXCLUDE,
-        ) {
-            tracing::info!("permission_decision: Found {} at {:?}", name, ptr);
-            let mut listener = hooks::permission_decision::Listener;
-            match interceptor.attach(ptr, &mut listener) {
-                Ok(_) => {
-                    std::mem::forget(listener);
-                    tracing::info!("permission_decision: hook installed");
-                }
-                Err(e) => tracing::error!("permission_decision: attach failed: {:?}", e),
+    // Installed in every enabled mode; the policy and the live `mode` decide
+    // per call whether Zed's decision is rewritten.
+    if let Err(e) = hooks::permission_decision::check_layout() {
+        tracing::warn!(
+            "permission_decision: return value layout not understood ({}) — hook not installed",
+            e
+        );
+    } else if let Some((name, ptr)) = symbols::find_by_pattern(
+        &main_module,
+        hooks::permission_decision::SYMBOL_INCLUDE,
+        hooks::permission_decision::SYMBOL_EXCLUDE,
+    ) {
+        tracing::info!("permission_decision: Found {} at {:?}", name, ptr);
+        let mut listener = hooks::permission_decision::Listener;
+        match interceptor.attach(ptr, &mut listener) {
+            Ok(_) => {
+                std::mem::forget(listener);
+                tracing::info!("permission_decision: hook installed");
             }
-        } else {
-            tracing::warn!("permission_decision: from_input symbol not found");
+            Err(e) => tracing::error!("permission_decision: attach failed: {:?}", e),
         }
     } else {
-        tracing::info!("permission_decision: skipped (mode={:?})", mode);
+        tracing::warn!("permission_decision: from_input symbol not found");
     }
 
     // -----------------------------------------------------------------------
@@ -217,23 +286,100 @@ fn init_inner() {
         tracing::warn!("push_entry_hook: push_entry symbol not found");
     }
 
+    // -----------------------------------------------------------------------
+    // Hook 6: drop_in_place::<AcpThread> — unregister closed threads
+    // -----------------------------------------------------------------------
+    // Optional: without it the stale scanner evicts freed threads itself once
+    // their session id changes.
+    if let Some((name, ptr)) = symbols::find_by_pattern(
+        &main_module,
+        hooks::drop_hook::SYMBOL_INCLUDE,
+        hooks::drop_hook::SYMBOL_EXCLUDE,
+    ) {
+        tracing::info!("drop_hook: Found {} at {:?}", name, ptr);
+        let mut listener = hooks::drop_hook::Listener;
+        match interceptor.attach(ptr, &mut listener) {
+            Ok(_) => {
+                std::mem::forget(listener);
+                tracing::info!("drop_hook: hook installed");
+            }
+            Err(e) => tracing::error!("drop_hook: attach failed: {:?}", e),
+        }
+    } else {
+        tracing::info!(
+            "drop_hook: drop_in_place::<AcpThread> symbol not found (inlined?) — the stale scanner evicts freed threads"
+        );
+    }
+
     // -----------------------------------------------------------------------
     // Approach 3: Periodic stale scanner thread
     // -----------------------------------------------------------------------
-    // Scanner interval: 2 seconds (much longer than the per-call retry_delay_us)
-    hooks::stale_scanner::start(2000);
+    // Interval adapts between `scan_schedule.min_interval_ms` and
+    // `max_interval_ms`; the hooks wake it when a dialog may be pending.
+    let watcher = config::config_path(&app_id).map(config::Watcher::new);
+    let budget_saver = budget::state_path(&app_id).map(budget::Saver::new);
+    hooks::stale_scanner::start(watcher, budget_saver, startup_cfg);
+
+    // Control socket for scripts (pause / resume / status)
+    control::start(&app_id);
 
     // Register in shared hook registry
-    register_in_registry(&app_id, mode);
+    register_in_registry(&app_id);
 
     tracing::info!("YOLO mode ACTIVE (pid={})", pid);
 }
 
+/// Say whether tool call facts (title, kind, locations, raw_input) can be
+/// read from the hooked `ToolCallUpdate` on this build.
+fn log_update_layout() {
+    use hooks::tool_call_snapshot::{update_layout, value_layout};
+
+    match update_layout() {
+        Ok(update) if layouts::active().iter().any(|l| update.matches(l)) => {
+            tracing::info!("tool_call_snapshot: ToolCallUpdate layout matches this build");
+        }
+        Ok(_) => tracing::warn!(
+            "tool_call_snapshot: ToolCallUpdate layout differs from this build's — tool call facts are not decoded"
+        ),
+        Err(e) => tracing::warn!(
+            "tool_call_snapshot: ToolCallUpdate layout not understood ({e}) — tool call facts are not decoded"
+        ),
+    }
+    if let Err(e) = value_layout() {
+        tracing::warn!(
+            "tool_call_snapshot: serde_json::Value layout not understood ({e}) — raw_input is not decoded"
+        );
+    }
+}
+
+/// No layout is known for this build: say so loudly and apply `unknown_version`.
+fn report_unknown_version(app: &AppFingerprint, app_id: &str, policy: UnknownVersion) {
+    tracing::error!("!!! UNKNOWN ZED BUILD: {app} !!!");
+    tracing::error!(
+        "No memory layout is keyed to this version or binary hash. Add one to {} \
+         (see docs/06_yolo_upgrade_guide.md).",
+        layouts::layouts_path(app_id)
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| "zed-yolo-layouts.json".to_string())
+    );
+    match policy {
+        UnknownVersion::Probe => tracing::warn!(
+            "unknown_version=probe: trying every known layout; approvals may miss or misfire"
+        ),
+        UnknownVersion::Observe => {
+            hooks::OBSERVE_ONLY.store(true, std::sync::atomic::Ordering::Relaxed);
+            tracing::warn!(
+                "unknown_version=observe: OBSERVE-ONLY — hooks log what they would do, nothing is approved"
+            );
+        }
+    }
+}
+
 /// Register this hook in the shared dylib-hook-registry.
 ///
 /// Uses `locked_register` to avoid race conditions when multiple Zed processes
 /// load their `#[ctor]` functions concurrently.
-fn register_in_registry(app_id: &str, mode: YoloMode) {
+fn register_in_registry(app_id: &str) {
     use dylib_hook_registry::{HookEntry, HookRegistry};
 
     let dylib_path = format!(
@@ -246,14 +392,12 @@ fn register_in_registry(app_id: &str, mode: YoloMode) {
         .with_features(&["yolo-mode", "auto-approve-tools"])
         .with_load_order(1);
 
-    // Record which symbols we actually hooked (depends on mode)
-    if matches!(mode, YoloMode::AllowAll) {
-        entry = entry.with_symbol(
-            "ToolPermissionDecision::from_input",
-            "attach",
-            "Auto-approve built-in tool calls",
-        );
-    }
+    // Record which symbols we hook
+    entry = entry.with_symbol(
+        "ToolPermissionDecision::from_input",
+        "attach",
+        "Auto-approve built-in tool calls the policy allows",
+    );
     entry = entry.with_symbol(
         "AcpThread::request_tool_call_authorization",
         "attach",
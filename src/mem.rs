@@ -0,0 +1,233 @@
+//! Checked access to Zed's memory.
+//!
+//! The hooks walk Zed's heap from a handful of pointers: AcpThread →
+//! entries Vec → ToolCall entry → Arc<str> ids, option lists, respond_tx.
+//! Any of those can be stale (a freed AcpThread, a reallocated Vec), and a
+//! plain dereference of a stale pointer takes the whole editor down (see
+//! `docs/08_crash_analysis_2026-03-03.md`). Every walk therefore reads
+//! through `SafeMem`, whose reads check the range first and return `None` /
+//! `false` instead of faulting.
+//!
+//! Backends:
+//!   - `LiveMemory`  — this process, through the Mach VM API
+//!     (`mach_vm_read_overwrite` copies in the kernel, so a page unmapped
+//!     between check and read can't fault either)
+//!   - `SliceMemory` — tests only: explicit regions, synthetic heaps
+//!   - `ProcessMemory` — tests only: values the test built in this process
+//!
+//! A check only proves the bytes are mapped, not that they still belong to
+//! the object we think; callers keep validating what they read (Arc headers,
+//! length limits, discriminants).
+
+#[cfg(test)]
+use std::cell::RefCell;
+use std::mem::{ManuallyDrop, MaybeUninit};
+
+/// Read-only view of a process's memory, plus the few word writes the hooks
+/// make to entries they resolved.
+pub(crate) trait SafeMem {
+    /// Fill `buf` from `addr`. `false` if any byte is unmapped or unreadable.
+    fn read(&self, addr: u64, buf: &mut [u8]) -> bool;
+
+    /// Store `value` at `addr`. `false` (nothing written) if the word is not
+    /// mapped writable.
+    fn write_u64(&self, addr: u64, value: u64) -> bool;
+
+    fn read_u64(&self, addr: u64) -> Option<u64> {
+        let mut buf = [0u8; 8];
+        self.read(addr, &mut buf).then(|| u64::from_le_bytes(buf))
+    }
+
+    fn read_u8(&self, addr: u64) -> Option<u8> {
+        let mut buf = [0u8; 1];
+        self.read(addr, &mut buf).then_some(buf[0])
+    }
+
+    fn read_bytes(&self, addr: u64, len: usize) -> Option<Vec<u8>> {
+        let mut buf = vec![0u8; len];
+        self.read(addr, &mut buf).then_some(buf)
+    }
+
+    /// Bitwise copy of the `T` at `addr`, never dropped.
+    ///
+    /// # Safety
+    /// The bytes must be a valid `T`. Only the copy's own bytes are checked;
+    /// anything it points to is read unchecked when the copy is used.
+    unsafe fn read_copy<T>(&self, addr: u64) -> Option<ManuallyDrop<T>>
+    where
+        Self: Sized,
+    {
+        let mut value = MaybeUninit::<T>::uninit();
+        let buf = unsafe {
+            std::slice::from_raw_parts_mut(value.as_mut_ptr().cast::<u8>(), size_of::<T>())
+        };
+        self.read(addr, buf)
+            .then(|| ManuallyDrop::new(unsafe { value.assume_init() }))
+    }
+}
+
+/// Whether `ptr` can be a heap pointer at all: userspace on arm64 macOS,
+/// above the 4 GiB zero page and below 2^47. A cheap pre-check before
+/// asking the kernel.
+pub(crate) fn plausible_ptr(ptr: u64) -> bool {
+    ptr > 0x1_0000_0000 && ptr < 0x8000_0000_0000
+}
+
+// ---------------------------------------------------------------------------
+// Live memory
+// ---------------------------------------------------------------------------
+
+/// The current process's memory, checked by the kernel.
+pub(crate) struct LiveMemory;
+
+impl SafeMem for LiveMemory {
+    fn read(&self, addr: u64, buf: &mut [u8]) -> bool {
+        if !plausible_ptr(addr) || addr.checked_add(buf.len() as u64).is_none() {
+            return false;
+        }
+        buf.is_empty() || crate::ffi::mach::read(addr, buf)
+    }
+
+    fn write_u64(&self, addr: u64, value: u64) -> bool {
+        use crate::ffi::mach::{VM_PROT_READ, VM_PROT_WRITE, region};
+
+        if !plausible_ptr(addr) || addr & 7 != 0 {
+            return false;
+        }
+        let writable = VM_PROT_READ | VM_PROT_WRITE;
+        match region(addr) {
+            Some((_, end, protection)) if addr + 8 <= end && protection & writable == writable => {
+                unsafe { std::ptr::write_volatile(addr as *mut u64, value) };
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Synthetic memory
+// ---------------------------------------------------------------------------
+
+/// Memory made of explicit regions (test fixtures). Every region is readable
+/// and writable; everything else is unmapped.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct SliceMemory {
+    regions: RefCell<Vec<(u64, Vec<u8>)>>,
+}
+
+#[cfg(test)]
+impl SliceMemory {
+    pub(crate) fn add(&mut self, base: u64, bytes: Vec<u8>) {
+        self.regions.get_mut().push((base, bytes));
+    }
+
+    /// Unmap the region added at `base`.
+    pub(crate) fn remove(&mut self, base: u64) {
+        self.regions.get_mut().retain(|(start, _)| *start != base);
+    }
+
+    /// Map `words` (little-endian) at `base`.
+    pub(crate) fn add_words(&mut self, base: u64, words: &[u64]) {
+        self.add(base, words.iter().flat_map(|w| w.to_le_bytes()).collect());
+    }
+
+    /// Run `f` on the region bytes covering `len` bytes at `addr`.
+    fn with_range<R>(&self, addr: u64, len: usize, f: impl FnOnce(&mut [u8]) -> R) -> Option<R> {
+        let mut regions = self.regions.borrow_mut();
+        let range = regions.iter_mut().find_map(|(base, bytes)| {
+            let start = usize::try_from(addr.checked_sub(*base)?).ok()?;
+            bytes.get_mut(start..start.checked_add(len)?)
+        })?;
+        Some(f(range))
+    }
+}
+
+#[cfg(test)]
+impl SafeMem for SliceMemory {
+    fn read(&self, addr: u64, buf: &mut [u8]) -> bool {
+        self.with_range(addr, buf.len(), |src| buf.copy_from_slice(src))
+            .is_some()
+    }
+
+    fn write_u64(&self, addr: u64, value: u64) -> bool {
+        self.with_range(addr, 8, |dst| dst.copy_from_slice(&value.to_le_bytes()))
+            .is_some()
+    }
+}
+
+/// This process's memory, read directly. Tests only: every address read must
+/// belong to a live value the test owns.
+#[cfg(test)]
+pub(crate) struct ProcessMemory;
+
+#[cfg(test)]
+impl SafeMem for ProcessMemory {
+    fn read(&self, addr: u64, buf: &mut [u8]) -> bool {
+        if !plausible_ptr(addr) {
+            return false;
+        }
+        unsafe { std::ptr::copy_nonoverlapping(addr as *const u8, buf.as_mut_ptr(), buf.len()) };
+        true
+    }
+
+    fn write_u64(&self, _addr: u64, _value: u64) -> bool {
+        false
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice_memory() {
+        let mut mem = SliceMemory::default();
+        mem.add(0x1000, (0u8..32).collect());
+        assert_eq!(mem.read_u64(0x1000), Some(0x0706_0504_0302_0100));
+        assert_eq!(mem.read_u64(0x1018), Some(0x1f1e_1d1c_1b1a_1918));
+        assert_eq!(mem.read_u64(0x1019), None);
+        assert_eq!(mem.read_u64(0xff8), None);
+        assert_eq!(mem.read_u8(0x101f), Some(0x1f));
+        assert_eq!(mem.read_bytes(0x1010, 2), Some(vec![0x10, 0x11]));
+        assert_eq!(mem.read_bytes(0x1010, 0x20), None);
+        assert_eq!(mem.read_u64(u64::MAX - 3), None);
+    }
+
+    #[test]
+    fn test_slice_memory_writes() {
+        let mut mem = SliceMemory::default();
+        mem.add_words(0x2000, &[1, 2]);
+        assert!(mem.write_u64(0x2008, 0xfeed));
+        assert_eq!(mem.read_u64(0x2008), Some(0xfeed));
+        assert_eq!(mem.read_u64(0x2000), Some(1));
+        assert!(!mem.write_u64(0x200c, 0));
+        assert!(!mem.write_u64(0x3000, 0));
+    }
+
+    #[test]
+    fn test_read_copy() {
+        let name = String::from("Allow once");
+        let mut mem = SliceMemory::default();
+        let bytes: [u8; size_of::<String>()] = unsafe { std::mem::transmute_copy(&name) };
+        mem.add(0x4000, bytes.to_vec());
+
+        let copy = unsafe { mem.read_copy::<String>(0x4000) }.unwrap();
+        assert_eq!(copy.as_str(), "Allow once");
+        assert_eq!(copy.as_ptr(), name.as_ptr());
+        assert!(unsafe { mem.read_copy::<String>(0x4008) }.is_none());
+    }
+
+    #[test]
+    fn test_plausible_ptr() {
+        assert!(!plausible_ptr(0));
+        assert!(!plausible_ptr(0xdead_beef));
+        assert!(plausible_ptr(0x1_4000_0000));
+        assert!(!plausible_ptr(0xffff_8000_0000_0000));
+    }
+}
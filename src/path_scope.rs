@@ -0,0 +1,504 @@
+//! Path-scoped approval for file edit tool calls.
+//!
+//! With `"path_scope": { "enabled": true }`, an `edit`, `delete` or `move`
+//! tool call is only approved when every path it targets is inside a
+//! workspace root and matches none of the `protected` globs. Anything else is
+//! left for the user, like an `ask` rule (policy source `path_scope`). Denied
+//! calls stay denied.
+//!
+//! ```json
+//! "path_scope": {
+//!   "enabled": true,
+//!   "kinds": ["edit", "delete", "move"],
+//!   "roots": ["~/scratch"],
+//!   "protected": [".git/**", ".env*", "~/.ssh/**", ".github/workflows/**"]
+//! }
+//! ```
+//!
+//! Targets are the tool call's `locations` plus the path arguments in its
+//! `raw_input`. Roots are the thread's project root (`project.rs`) and
+//! `roots`. A call without targets, in a thread whose project isn't known
+//! yet, or whose kind isn't decodable is out of scope.
+//!
+//! ## Normalization
+//!
+//! `~` is expanded and relative paths are taken from the project root. The
+//! path is then resolved one component at a time, the way the OS opens it:
+//! every prefix that exists is canonicalized (so a symlink to `/etc` leaves
+//! the workspace), `..` drops the last resolved component, and whatever
+//! doesn't exist yet (a file about to be created) is appended as is, until a
+//! `..` leads back to a directory that does. Roots go through the same steps.
+//!
+//! ## Protected globs
+//!
+//! `*` and `?` match within one path segment and `**` any number of segments.
+//! A pattern with a `/` is anchored at the root the target is in
+//! (`.github/workflows/**`); one without matches any segment, so `.env*`
+//! protects `config/.env.local` and `.git` everything inside a `.git`
+//! directory. `~/…` and absolute patterns match the whole path. A pattern
+//! that matches a directory protects everything inside it. Matching is
+//! case-insensitive.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+use crate::hooks::tool_call_snapshot::ToolCallSnapshot;
+use crate::policy::wildcard_match;
+
+/// `raw_input` keys holding a path the tool writes to.
+const TARGET_KEYS: &[&str] = &[
+    "file_path",
+    "abs_path",
+    "notebook_path",
+    "path",
+    "source_path",
+    "destination_path",
+];
+
+/// `path_scope` config section.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathScope {
+    pub enabled: bool,
+    /// ACP tool kinds whose paths are checked.
+    pub kinds: Vec<String>,
+    /// Roots besides the project root (absolute or `~/…`).
+    pub roots: Vec<String>,
+    /// Globs that are never approved, even inside a root.
+    pub protected: Vec<String>,
+}
+
+impl Default for PathScope {
+    fn default() -> Self {
+        PathScope {
+            enabled: false,
+            kinds: ["edit", "delete", "move"].map(str::to_string).to_vec(),
+            roots: Vec::new(),
+            protected: [
+                ".git/**",
+                ".env*",
+                "~/.ssh/**",
+                ".zed/yolo.json",
+                ".github/workflows/**",
+                ".gitlab-ci.yml",
+                ".circleci/**",
+                ".buildkite/**",
+                "Jenkinsfile",
+            ]
+            .map(str::to_string)
+            .to_vec(),
+        }
+    }
+}
+
+/// Why a tool call is out of scope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// No target paths decoded.
+    NoTargets,
+    /// No workspace root known.
+    NoRoot,
+    Outside(PathBuf),
+    Protected {
+        path: PathBuf,
+        pattern: String,
+    },
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::NoTargets => write!(f, "no target paths"),
+            Violation::NoRoot => write!(f, "workspace root not known"),
+            Violation::Outside(path) => write!(f, "{} is outside the workspace", path.display()),
+            Violation::Protected { path, pattern } => {
+                write!(f, "{} is protected by {pattern:?}", path.display())
+            }
+        }
+    }
+}
+
+/// `~`, `~/…` → under `home`.
+fn expand_home(path: &str, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Normalize `path` as described in the module docs. `canonicalize`
+/// returns the real path of an existing file or directory.
+pub fn normalize(
+    path: &str,
+    base: &Path,
+    home: Option<&Path>,
+    canonicalize: &impl Fn(&Path) -> Option<PathBuf>,
+) -> PathBuf {
+    let path = expand_home(path, home);
+    let path = base.join(path);
+    let mut out = PathBuf::new();
+    // Once a component is missing, nothing below it exists either.
+    let mut exists = true;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => out.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+                // Back out of a missing directory: what's left may exist
+                // again, and its symlinks must still be followed.
+                if !exists {
+                    exists = canonicalize(&out).is_some();
+                }
+            }
+            Component::Normal(name) => {
+                out.push(name);
+                if exists {
+                    match canonicalize(&out) {
+                        Some(real) => out = real,
+                        None => exists = false,
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+fn segments(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Match path segments against glob segments (`**` = any number).
+fn glob_match(pattern: &[&str], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+        Some((segment, rest)) => path
+            .split_first()
+            .is_some_and(|(first, tail)| wildcard_match(segment, first) && glob_match(rest, tail)),
+    }
+}
+
+/// Whether `pattern` matches `path` or one of its directories.
+fn glob_match_prefix(pattern: &[&str], path: &[String]) -> bool {
+    (1..=path.len()).any(|len| glob_match(pattern, &path[..len]))
+}
+
+/// Whether `pattern` protects `path`, which is inside `root`.
+fn is_protected(pattern: &str, path: &Path, root: &Path, home: Option<&Path>) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    if pattern.starts_with('~') || pattern.starts_with('/') {
+        let pattern = segments(&expand_home(pattern, home));
+        let pattern: Vec<&str> = pattern.iter().map(String::as_str).collect();
+        return glob_match_prefix(&pattern, &segments(path));
+    }
+    let relative = segments(path.strip_prefix(root).unwrap_or(path));
+    if pattern.contains('/') {
+        let pattern: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+        return glob_match_prefix(&pattern, &relative);
+    }
+    relative
+        .iter()
+        .any(|segment| wildcard_match(pattern, segment))
+}
+
+/// Check normalized `targets` against `roots`.
+pub fn check(
+    scope: &PathScope,
+    targets: &[PathBuf],
+    roots: &[PathBuf],
+    home: Option<&Path>,
+) -> Result<(), Violation> {
+    if targets.is_empty() {
+        return Err(Violation::NoTargets);
+    }
+    if roots.is_empty() {
+        return Err(Violation::NoRoot);
+    }
+    for target in targets {
+        let root = roots
+            .iter()
+            .find(|root| target.starts_with(root))
+            .ok_or_else(|| Violation::Outside(target.clone()))?;
+        if let Some(pattern) = scope
+            .protected
+            .iter()
+            .find(|pattern| is_protected(pattern, target, root, home))
+        {
+            return Err(Violation::Protected {
+                path: target.clone(),
+                pattern: pattern.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Paths a tool call writes to, as sent by the agent.
+pub(crate) fn targets(snapshot: &ToolCallSnapshot) -> Vec<String> {
+    let from_input = snapshot.raw_input.as_ref().into_iter().flat_map(|input| {
+        TARGET_KEYS
+            .iter()
+            .filter_map(|key| input.get(*key)?.as_str())
+    });
+    let mut targets: Vec<String> = snapshot
+        .locations
+        .iter()
+        .map(String::as_str)
+        .chain(from_input)
+        .map(str::to_string)
+        .collect();
+    targets.sort();
+    targets.dedup();
+    targets
+}
+
+/// Check a decoded tool call from a thread in `project_root`. `Ok` when the
+/// call is in scope or path scoping doesn't apply to it.
+pub(crate) fn check_call(
+    scope: &PathScope,
+    snapshot: &ToolCallSnapshot,
+    project_root: Option<&Path>,
+) -> Result<(), Violation> {
+    if !scope.enabled {
+        return Ok(());
+    }
+    // An undecodable kind might be an edit.
+    if let Some(kind) = snapshot.kind
+        && !scope.kinds.iter().any(|k| k == kind)
+    {
+        return Ok(());
+    }
+
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let home = home.as_deref();
+    let canonicalize = |path: &Path| std::fs::canonicalize(path).ok();
+    let extra = scope.roots.iter().map(|root| expand_home(root, home));
+    let roots: Vec<PathBuf> = project_root
+        .map(Path::to_path_buf)
+        .into_iter()
+        .chain(extra)
+        .filter(|root| root.is_absolute())
+        .map(|root| normalize(&root.to_string_lossy(), Path::new("/"), home, &canonicalize))
+        .collect();
+    let Some(base) = roots.first() else {
+        return Err(Violation::NoRoot);
+    };
+    let targets: Vec<PathBuf> = targets(snapshot)
+        .iter()
+        .map(|target| normalize(target, base, home, &canonicalize))
+        .collect();
+    check(scope, &targets, &roots, home)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const HOME: &str = "/Users/me";
+    const ROOT: &str = "/Users/me/src/app";
+
+    /// A fake filesystem: existing paths, and symlinks to their targets.
+    fn fake_fs() -> impl Fn(&Path) -> Option<PathBuf> {
+        let links: HashMap<&str, &str> = [
+            ("/Users/me/src/app/etc", "/etc"),
+            ("/Users/me/src/app/shared", "/Users/me/src/shared"),
+            ("/tmp", "/private/tmp"),
+        ]
+        .into();
+        let existing = [
+            "/Users",
+            "/Users/me",
+            "/Users/me/.ssh",
+            "/Users/me/src",
+            "/Users/me/src/app",
+            "/Users/me/src/app/src",
+            "/Users/me/src/app/src/main.rs",
+            "/Users/me/src/app/.git",
+            "/Users/me/src/shared",
+            "/etc",
+            "/private",
+            "/private/tmp",
+        ];
+        move |path: &Path| {
+            let path = path.to_str()?;
+            links
+                .get(path)
+                .map(PathBuf::from)
+                .or_else(|| existing.contains(&path).then(|| PathBuf::from(path)))
+        }
+    }
+
+    fn norm(path: &str) -> PathBuf {
+        normalize(path, Path::new(ROOT), Some(Path::new(HOME)), &fake_fs())
+    }
+
+    #[test]
+    fn test_normalize() {
+        let cases = [
+            ("src/main.rs", "/Users/me/src/app/src/main.rs"),
+            ("./src/./new.rs", "/Users/me/src/app/src/new.rs"),
+            (
+                "/Users/me/src/app/src/main.rs",
+                "/Users/me/src/app/src/main.rs",
+            ),
+            ("src/../../other/x", "/Users/me/src/other/x"),
+            ("../../../../../../x", "/x"),
+            ("~/.ssh/config", "/Users/me/.ssh/config"),
+            ("~", "/Users/me"),
+            ("~other/x", "/Users/me/src/app/~other/x"),
+            // Symlinks are followed before `..`.
+            ("etc/passwd", "/etc/passwd"),
+            ("etc/../x", "/x"),
+            ("shared/lib.rs", "/Users/me/src/shared/lib.rs"),
+            ("/tmp/scratch/a", "/private/tmp/scratch/a"),
+            // Missing directories are kept as written.
+            ("new/dir/../file", "/Users/me/src/app/new/file"),
+            // ... until `..` leads back to one that exists.
+            ("newdir/../etc/passwd", "/etc/passwd"),
+            ("new/dir/../../shared/x", "/Users/me/src/shared/x"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(norm(path), PathBuf::from(expected), "normalizing {path:?}");
+        }
+    }
+
+    #[test]
+    fn test_protected_globs() {
+        let root = Path::new(ROOT);
+        let home = Some(Path::new(HOME));
+        let cases = [
+            (".git/**", ".git/config", true),
+            (".git/**", ".git", true),
+            (".git/**", "sub/.git/config", false),
+            (".git", "sub/.git/config", true),
+            (".env*", ".env", true),
+            (".env*", "config/.env.local", true),
+            (".env*", "src/environment.rs", false),
+            (".github/workflows/**", ".github/workflows/ci.yml", true),
+            (".github/workflows/**", ".github/CODEOWNERS", false),
+            (".github/workflows/", ".github/workflows/ci.yml", true),
+            (".github/workflows", ".github/workflows-old/x.yml", false),
+            ("**/*.pem", "certs/dev/key.pem", true),
+            ("**/*.pem", "key.pem", true),
+            ("Jenkinsfile", "jenkinsfile", true),
+            ("~/.ssh/**", "/Users/me/.ssh/id_rsa", true),
+            ("/etc/**", "/etc/hosts", true),
+            ("/etc", "/etc/ssh/sshd_config", true),
+            ("src/*.rs", "src/main.rs", true),
+            ("src/*.rs", "src/bin/x.rs", false),
+        ];
+        for (pattern, path, expected) in cases {
+            let path = root.join(path);
+            assert_eq!(
+                is_protected(pattern, &path, root, home),
+                expected,
+                "{pattern:?} vs {}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let scope = PathScope {
+            enabled: true,
+            ..PathScope::default()
+        };
+        let roots = [PathBuf::from(ROOT), PathBuf::from("/Users/me/src/shared")];
+        let home = Some(Path::new(HOME));
+        let check = |paths: &[&str]| {
+            let targets: Vec<PathBuf> = paths.iter().map(|p| norm(p)).collect();
+            check(&scope, &targets, &roots, home)
+        };
+
+        assert_eq!(check(&["src/main.rs", "src/new.rs"]), Ok(()));
+        assert_eq!(check(&["shared/lib.rs"]), Ok(()));
+        assert_eq!(
+            check(&["src/main.rs", "../other/x"]),
+            Err(Violation::Outside(PathBuf::from("/Users/me/src/other/x")))
+        );
+        assert_eq!(
+            check(&["etc/hosts"]),
+            Err(Violation::Outside(PathBuf::from("/etc/hosts")))
+        );
+        assert!(matches!(
+            check(&[".git/hooks/pre-commit"]),
+            Err(Violation::Protected { .. })
+        ));
+        assert!(matches!(
+            check(&["config/.env.production"]),
+            Err(Violation::Protected { pattern, .. }) if pattern == ".env*"
+        ));
+        assert!(matches!(
+            check(&[".zed/yolo.json"]),
+            Err(Violation::Protected { .. })
+        ));
+        assert_eq!(check(&[]), Err(Violation::NoTargets));
+        assert_eq!(
+            super::check(&scope, &[norm("src/main.rs")], &[], home),
+            Err(Violation::NoRoot)
+        );
+        // `/Users/me/src/app-old` is not inside `/Users/me/src/app`.
+        assert!(matches!(
+            check(&["/Users/me/src/app-old/x"]),
+            Err(Violation::Outside(_))
+        ));
+    }
+
+    #[test]
+    fn test_targets() {
+        let snapshot = ToolCallSnapshot {
+            locations: vec!["/a/x.rs".to_string()],
+            raw_input: Some(serde_json::json!({
+                "file_path": "/a/x.rs",
+                "destination_path": "/a/y.rs",
+                "content": "/not/a/target"
+            })),
+            ..ToolCallSnapshot::default()
+        };
+        assert_eq!(targets(&snapshot), ["/a/x.rs", "/a/y.rs"]);
+    }
+
+    #[test]
+    fn test_check_call_kinds() {
+        let scope = PathScope {
+            enabled: true,
+            ..PathScope::default()
+        };
+        let read = ToolCallSnapshot {
+            kind: Some("read"),
+            ..ToolCallSnapshot::default()
+        };
+        assert_eq!(check_call(&scope, &read, None), Ok(()));
+        let edit = ToolCallSnapshot {
+            kind: Some("edit"),
+            ..read.clone()
+        };
+        assert_eq!(check_call(&scope, &edit, None), Err(Violation::NoRoot));
+        assert_eq!(check_call(&PathScope::default(), &edit, None), Ok(()));
+    }
+
+    #[test]
+    fn test_path_scope_json() {
+        let scope: PathScope =
+            serde_json::from_str(r#"{"enabled": true, "roots": ["~/scratch"]}"#).unwrap();
+        assert!(scope.enabled);
+        assert_eq!(scope.roots, ["~/scratch"]);
+        assert_eq!(scope.kinds, PathScope::default().kinds);
+        assert!(scope.protected.contains(&".git/**".to_string()));
+    }
+}
@@ -0,0 +1,357 @@
+//! Per-project policy additions (`.zed/yolo.json` in a worktree root).
+//!
+//! The global config applies to every Zed window. With
+//! `project_overrides: true`, a project can tighten it for its own agent
+//! threads with a `.zed/yolo.json`:
+//!
+//! ```json
+//! {
+//!   "policy": {
+//!     "denylist": ["terraform apply"],
+//!     "rules": [{ "action": "ask", "kind": "execute" }]
+//!   }
+//! }
+//! ```
+//!
+//! The file is checked into the repository, so whoever wrote it is not
+//! necessarily the user: it can only tighten. `policy.denylist` is added to
+//! the global denylist and `policy.rules` are appended after the global
+//! rules (and the agent's), so they only decide calls nothing the user
+//! wrote matched. Only `deny` and `ask` rules are taken, and only those
+//! stricter than `policy.default` (an `ask` rule under a `deny` default
+//! would loosen it); the others are logged and dropped. Other keys (`mode`,
+//! `tool_option`, `policy.default`, ...) make the file invalid (logged,
+//! file ignored). `project_overrides` is off by default.
+//!
+//! ## Finding the project
+//!
+//! A thread's project is its first worktree root, read from the AcpThread by
+//! the stale scanner (`layouts::ThreadLayout::work_dirs`, from the layout
+//! database). Nothing in the tool calls, which the agent writes, decides it.
+//! It is also the policy's `workspace` and the `path_scope` root. Without
+//! `work_dirs` in the layout, no thread has a project.
+//!
+//! The scanner thread reads the files (`Files`) and re-reads one when its
+//! modification time changes; hooks only see the merged config
+//! (`thread_context`).
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::config::YoloConfig;
+use crate::hooks::tool_call_snapshot::read_str;
+use crate::layouts::ThreadLayout;
+use crate::mem::{SafeMem, plausible_ptr};
+use crate::policy::{Decision, Rule};
+
+/// Override file, relative to the project root.
+pub(crate) const PROJECT_FILE: &str = ".zed/yolo.json";
+
+/// Refuse worktree lists longer than this.
+const MAX_WORK_DIRS: u64 = 64;
+
+/// A project's `.zed/yolo.json`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectFile {
+    pub policy: ProjectPolicy,
+}
+
+/// `policy` of a project file: additions only.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectPolicy {
+    /// Added to `policy.denylist`.
+    pub denylist: Vec<String>,
+    /// Appended to `policy.rules`; `deny` and `ask` only.
+    pub rules: Vec<Rule>,
+}
+
+fn strictness(decision: Decision) -> u8 {
+    match decision {
+        Decision::Allow => 0,
+        Decision::Ask => 1,
+        Decision::Deny => 2,
+    }
+}
+
+impl ProjectFile {
+    /// Add this file's denylist and rules to `config`. Rules not stricter
+    /// than `config.policy.default` are dropped.
+    pub fn apply(&self, config: &mut YoloConfig) {
+        config
+            .policy
+            .denylist
+            .extend(self.policy.denylist.iter().cloned());
+        let floor = strictness(config.policy.default);
+        for (i, rule) in self.policy.rules.iter().enumerate() {
+            if strictness(rule.action.into()) > floor {
+                config.policy.rules.push(rule.clone());
+            } else {
+                tracing::warn!(
+                    "project: ignoring rules[{i}] ({:?}): not stricter than policy.default ({:?})",
+                    rule.action,
+                    config.policy.default
+                );
+            }
+        }
+    }
+}
+
+/// Worktree roots of the AcpThread at `thread`, absolute paths only.
+pub(crate) fn read_work_dirs(
+    mem: &impl SafeMem,
+    thread: u64,
+    layout: &ThreadLayout,
+) -> Vec<PathBuf> {
+    let Some(dirs) = layout.work_dirs else {
+        return Vec::new();
+    };
+    let mut addr = thread;
+    for &offset in dirs.deref {
+        match mem.read_u64(addr + offset as u64) {
+            Some(ptr) if plausible_ptr(ptr) => addr = ptr,
+            _ => return Vec::new(),
+        }
+    }
+    let (Some(ptr), Some(len)) = (
+        mem.read_u64(addr + dirs.list.ptr_offset as u64),
+        mem.read_u64(addr + dirs.list.len_offset as u64),
+    ) else {
+        return Vec::new();
+    };
+    if len == 0 || len > MAX_WORK_DIRS || !plausible_ptr(ptr) {
+        return Vec::new();
+    }
+    (0..len)
+        .filter_map(|i| read_str(mem, ptr + i * dirs.list.elem_size as u64, dirs.path))
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .collect()
+}
+
+/// A parsed project file and the mtime it was read at.
+struct Loaded {
+    modified: Option<SystemTime>,
+    file: Option<Arc<ProjectFile>>,
+}
+
+/// Project files by root. Lives on the scanner thread.
+#[derive(Default)]
+pub(crate) struct Files {
+    loaded: HashMap<PathBuf, Loaded>,
+}
+
+impl Files {
+    /// The file of `root`, read on first use.
+    pub(crate) fn get(&mut self, root: &Path) -> Option<Arc<ProjectFile>> {
+        if let Some(loaded) = self.loaded.get(root) {
+            return loaded.file.clone();
+        }
+        let loaded = load(root);
+        let file = loaded.file.clone();
+        self.loaded.insert(root.to_path_buf(), loaded);
+        file
+    }
+
+    /// Re-read the files that changed since they were read; their roots.
+    pub(crate) fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for (root, loaded) in &mut self.loaded {
+            if modified(root) != loaded.modified {
+                *loaded = load(root);
+                changed.push(root.clone());
+            }
+        }
+        changed
+    }
+
+    /// Forget the roots `keep` rejects.
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&Path) -> bool) {
+        self.loaded.retain(|root, _| keep(root));
+    }
+}
+
+fn modified(root: &Path) -> Option<SystemTime> {
+    std::fs::metadata(root.join(PROJECT_FILE))
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+fn load(root: &Path) -> Loaded {
+    let path = root.join(PROJECT_FILE);
+    let modified = modified(root);
+    let file = modified.and_then(|_| match read(&path) {
+        Ok(file) => {
+            tracing::info!("project: loaded {}", path.display());
+            Some(Arc::new(file))
+        }
+        Err(e) => {
+            tracing::warn!("project: ignoring {}: {e}", path.display());
+            None
+        }
+    });
+    Loaded { modified, file }
+}
+
+fn read(path: &Path) -> Result<ProjectFile, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&content).map_err(|e| e.to_string())
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::tool_call_snapshot::{StrField, VecField};
+    use crate::layouts::ChainedStrs;
+    use crate::mem::SliceMemory;
+    use crate::policy::{Policy, RuleAction, ToolCallFacts};
+
+    #[test]
+    fn test_only_additions_accepted() {
+        let file = |json: &str| serde_json::from_str::<ProjectFile>(json);
+        assert!(file(r#"{"policy": {"denylist": ["terraform apply"]}}"#).is_ok());
+        assert!(file(r#"{"mode": "allow_all"}"#).is_err());
+        assert!(file(r#"{"tool_option": "allow_always"}"#).is_err());
+        assert!(file(r#"{"policy": {"default": "allow"}}"#).is_err());
+        assert!(file(r#"{"audit_log": "off"}"#).is_err());
+    }
+
+    #[test]
+    fn test_apply_only_tightens() {
+        let file: ProjectFile = serde_json::from_str(
+            r#"{ "policy": {
+                "denylist": ["terraform apply"],
+                "rules": [
+                    { "action": "allow", "kind": "execute" },
+                    { "action": "ask", "kind": "execute" },
+                    { "action": "deny", "kind": "delete" }
+                ]
+            } }"#,
+        )
+        .unwrap();
+        let global = Rule {
+            action: RuleAction::Allow,
+            kind: Some("read".to_string()),
+            ..Rule::default()
+        };
+        let mut config = YoloConfig {
+            policy: Policy {
+                default: Decision::Allow,
+                denylist: vec!["rm -rf".to_string()],
+                rules: vec![global.clone()],
+            },
+            ..YoloConfig::default()
+        };
+        file.apply(&mut config);
+        assert_eq!(config.policy.denylist, ["rm -rf", "terraform apply"]);
+        // Appended after the global rules.
+        assert_eq!(config.policy.rules.len(), 3);
+        assert_eq!(config.policy.rules[0], global);
+        let facts = |kind: &str| ToolCallFacts {
+            kind: Some(kind.to_string()),
+            title: Some("x".to_string()),
+            ..ToolCallFacts::default()
+        };
+        assert_eq!(config.policy.evaluate(&facts("read")), Decision::Allow);
+        assert_eq!(config.policy.evaluate(&facts("execute")), Decision::Ask);
+        assert_eq!(config.policy.evaluate(&facts("delete")), Decision::Deny);
+
+        // Under `ask`, an `ask` rule adds nothing; under `deny`, nothing is
+        // stricter.
+        let mut ask = YoloConfig::default();
+        ask.policy.default = Decision::Ask;
+        file.apply(&mut ask);
+        assert_eq!(ask.policy.rules.len(), 1);
+        assert_eq!(ask.policy.rules[0].action, RuleAction::Deny);
+        let mut deny = YoloConfig::default();
+        deny.policy.default = Decision::Deny;
+        file.apply(&mut deny);
+        assert!(deny.policy.rules.is_empty());
+    }
+
+    #[test]
+    fn test_read_work_dirs() {
+        const THREAD: u64 = 0x1_1000_0000;
+        const DIRS: u64 = 0x1_2000_0000;
+        const TEXT: u64 = 0x1_3000_0000;
+
+        let mut mem = SliceMemory::default();
+        mem.add_words(THREAD, &[0, DIRS, 3]);
+        // PathBuf elements: (cap, ptr, len).
+        mem.add_words(DIRS, &[0, TEXT, 11, 0, TEXT + 0x100, 8, 0, TEXT + 0x200, 4]);
+        mem.add(TEXT, b"/work/infra".to_vec());
+        mem.add(TEXT + 0x100, b"relative".to_vec());
+        mem.add(TEXT + 0x200, b"/tmp".to_vec());
+        let layout = ThreadLayout {
+            work_dirs: Some(ChainedStrs {
+                deref: &[],
+                list: VecField {
+                    ptr_offset: 8,
+                    len_offset: 16,
+                    elem_size: 0x18,
+                    none: None,
+                },
+                path: StrField {
+                    ptr_offset: 8,
+                    len_offset: 16,
+                    data_offset: 0,
+                    none: None,
+                },
+            }),
+            ..ThreadLayout::UNKNOWN
+        };
+        assert_eq!(
+            read_work_dirs(&mem, THREAD, &layout),
+            [PathBuf::from("/work/infra"), PathBuf::from("/tmp")]
+        );
+        assert!(read_work_dirs(&mem, THREAD, &ThreadLayout::UNKNOWN).is_empty());
+    }
+
+    #[test]
+    fn test_files_reread_changed_file() {
+        let root =
+            std::env::temp_dir().join(format!("zed-yolo-project-test-{}", std::process::id()));
+        std::fs::create_dir_all(root.join(".zed")).unwrap();
+        let path = root.join(PROJECT_FILE);
+        let write = |json: &str, secs: u64| {
+            std::fs::write(&path, json).unwrap();
+            let mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        };
+        let denylist = |files: &mut Files| files.get(&root).map(|f| f.policy.denylist.clone());
+
+        let mut files = Files::default();
+        write(r#"{"policy": {"denylist": ["a"]}}"#, 1_000);
+        assert_eq!(denylist(&mut files), Some(vec!["a".to_string()]));
+        assert!(files.poll().is_empty());
+
+        write(r#"{"policy": {"denylist": ["b"]}}"#, 2_000);
+        assert_eq!(files.poll(), [root.as_path()]);
+        assert_eq!(denylist(&mut files), Some(vec!["b".to_string()]));
+
+        write(r#"{"mode": "allow_all"}"#, 3_000);
+        assert_eq!(files.poll().len(), 1);
+        assert!(files.get(&root).is_none());
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(files.poll().len(), 1);
+        assert!(files.get(&root).is_none());
+
+        files.retain(|_| false);
+        assert!(files.poll().is_empty());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
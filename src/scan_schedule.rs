@@ -0,0 +1,256 @@
+//! When the stale scanner sweeps.
+//!
+//! A fixed interval is either slow to pick up a missed dialog or wakes the
+//! scanner thread all day for idle sessions. The schedule adapts instead:
+//!
+//! ```json
+//! "scan_schedule": { "startup_delay_ms": 5000, "min_interval_ms": 100, "max_interval_ms": 10000, "fast_sweeps": 20 }
+//! ```
+//!
+//! A hook registering an AcpThread (a tool call is being inserted) or missing
+//! a dialog wakes the scanner, which then sweeps every `min_interval_ms` for
+//! `fast_sweeps` sweeps. A sweep that approves something starts the fast
+//! phase over. After it the interval doubles on every sweep up to
+//! `max_interval_ms`. Sweeps are never closer than `min_interval_ms`, however
+//! often the hooks wake the scanner.
+//!
+//! The scanner also polls the config file once per sweep, so an idle scanner
+//! picks up a changed config within `max_interval_ms`. Everything but
+//! `startup_delay_ms` applies on reload.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Shortest interval accepted, whatever the config says.
+pub(crate) const MIN_INTERVAL_FLOOR_MS: u64 = 50;
+/// Sweep durations kept for the latency percentiles.
+pub(crate) const LATENCY_SAMPLES: usize = 512;
+
+/// `scan_schedule` config section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScanSchedule {
+    /// Wait after the hooks are installed before the first sweep, for Zed to
+    /// finish starting.
+    pub startup_delay_ms: u64,
+    /// Interval while dialogs are likely (at least 50).
+    pub min_interval_ms: u64,
+    /// Interval the back-off stops at (at least `min_interval_ms`).
+    pub max_interval_ms: u64,
+    /// Sweeps at `min_interval_ms` after a wake-up or an approval.
+    pub fast_sweeps: u32,
+}
+
+impl Default for ScanSchedule {
+    fn default() -> Self {
+        ScanSchedule {
+            startup_delay_ms: 5000,
+            min_interval_ms: 100,
+            max_interval_ms: 10_000,
+            fast_sweeps: 20,
+        }
+    }
+}
+
+impl ScanSchedule {
+    pub(crate) fn min_interval_ms(&self) -> u64 {
+        self.min_interval_ms.max(MIN_INTERVAL_FLOOR_MS)
+    }
+
+    pub(crate) fn max_interval_ms(&self) -> u64 {
+        self.max_interval_ms.max(self.min_interval_ms())
+    }
+}
+
+/// The interval before the next sweep.
+pub(crate) struct Backoff {
+    interval_ms: u64,
+    fast_left: u32,
+}
+
+impl Backoff {
+    /// Starts fast: Zed has just started and sessions are being restored.
+    pub(crate) fn new(schedule: &ScanSchedule) -> Self {
+        Backoff {
+            interval_ms: schedule.min_interval_ms(),
+            fast_left: schedule.fast_sweeps,
+        }
+    }
+
+    /// How long to wait before the next sweep, within the current limits.
+    pub(crate) fn interval(&self, schedule: &ScanSchedule) -> Duration {
+        Duration::from_millis(
+            self.interval_ms
+                .clamp(schedule.min_interval_ms(), schedule.max_interval_ms()),
+        )
+    }
+
+    /// A hook woke the scanner, or a sweep approved something: poll fast.
+    pub(crate) fn hurry(&mut self, schedule: &ScanSchedule) {
+        self.interval_ms = schedule.min_interval_ms();
+        self.fast_left = schedule.fast_sweeps;
+    }
+
+    /// One sweep done that approved nothing.
+    pub(crate) fn idle(&mut self, schedule: &ScanSchedule) {
+        if self.fast_left > 0 {
+            self.fast_left -= 1;
+            self.interval_ms = schedule.min_interval_ms();
+        } else {
+            self.interval_ms = self
+                .interval_ms
+                .saturating_mul(2)
+                .clamp(schedule.min_interval_ms(), schedule.max_interval_ms());
+        }
+    }
+}
+
+/// Percentiles of the latest sweep durations, in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct Percentiles {
+    pub samples: usize,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+/// The latest `LATENCY_SAMPLES` sweep durations.
+pub(crate) struct Latencies {
+    samples: VecDeque<u64>,
+}
+
+impl Latencies {
+    pub(crate) const fn new() -> Self {
+        Latencies {
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, micros: u64) {
+        if self.samples.len() == LATENCY_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(micros);
+    }
+
+    /// Nearest-rank percentiles; `None` before the first sweep.
+    pub(crate) fn percentiles(&self) -> Option<Percentiles> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<u64> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = |p: usize| sorted[(sorted.len() * p).div_ceil(100) - 1];
+        Some(Percentiles {
+            samples: sorted.len(),
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(fast_sweeps: u32) -> ScanSchedule {
+        ScanSchedule {
+            startup_delay_ms: 0,
+            min_interval_ms: 100,
+            max_interval_ms: 1000,
+            fast_sweeps,
+        }
+    }
+
+    fn ms(backoff: &Backoff, schedule: &ScanSchedule) -> u128 {
+        backoff.interval(schedule).as_millis()
+    }
+
+    #[test]
+    fn test_backs_off_after_fast_sweeps() {
+        let schedule = schedule(2);
+        let mut backoff = Backoff::new(&schedule);
+        let mut intervals = vec![ms(&backoff, &schedule)];
+        for _ in 0..7 {
+            backoff.idle(&schedule);
+            intervals.push(ms(&backoff, &schedule));
+        }
+        assert_eq!(intervals, [100, 100, 100, 200, 400, 800, 1000, 1000]);
+    }
+
+    #[test]
+    fn test_hurry_starts_fast_phase_over() {
+        let schedule = schedule(1);
+        let mut backoff = Backoff::new(&schedule);
+        for _ in 0..10 {
+            backoff.idle(&schedule);
+        }
+        assert_eq!(ms(&backoff, &schedule), 1000);
+        backoff.hurry(&schedule);
+        assert_eq!(ms(&backoff, &schedule), 100);
+        backoff.idle(&schedule);
+        assert_eq!(ms(&backoff, &schedule), 100);
+        backoff.idle(&schedule);
+        assert_eq!(ms(&backoff, &schedule), 200);
+    }
+
+    #[test]
+    fn test_limits_are_sane() {
+        let odd = ScanSchedule {
+            min_interval_ms: 0,
+            max_interval_ms: 10,
+            ..ScanSchedule::default()
+        };
+        assert_eq!(odd.min_interval_ms(), MIN_INTERVAL_FLOOR_MS);
+        assert_eq!(odd.max_interval_ms(), MIN_INTERVAL_FLOOR_MS);
+        let mut backoff = Backoff::new(&ScanSchedule::default());
+        for _ in 0..100 {
+            backoff.idle(&ScanSchedule::default());
+        }
+        // A reload that lowers the maximum applies right away.
+        assert_eq!(backoff.interval(&odd).as_millis(), 50);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut latencies = Latencies::new();
+        assert_eq!(latencies.percentiles(), None);
+        latencies.record(7);
+        assert_eq!(
+            latencies.percentiles(),
+            Some(Percentiles {
+                samples: 1,
+                p50: 7,
+                p90: 7,
+                p99: 7,
+                max: 7
+            })
+        );
+        for micros in (1..=LATENCY_SAMPLES as u64).rev() {
+            latencies.record(micros * 10);
+        }
+        let p = latencies.percentiles().unwrap();
+        assert_eq!(p.samples, LATENCY_SAMPLES);
+        assert_eq!((p.p50, p.p90, p.p99, p.max), (2560, 4610, 5070, 5120));
+    }
+
+    #[test]
+    fn test_scan_schedule_json() {
+        let schedule: ScanSchedule = serde_json::from_str(r#"{"max_interval_ms": 30000}"#).unwrap();
+        assert_eq!(
+            schedule,
+            ScanSchedule {
+                max_interval_ms: 30_000,
+                ..ScanSchedule::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,429 @@
+//! What the hooks know about each AcpThread: its agent, project and config.
+//!
+//! `resolve_entry` needs the thread's agent (`agent.rs`), its project
+//! (`project.rs`) and the config merged for them. Working those out reads
+//! the AcpThread and the project's `.zed/yolo.json`, so the stale scanner
+//! does it, once per thread, before each sweep (`Contexts::refresh`) and
+//! publishes the result: a map from AcpThread pointer to `ThreadContext`,
+//! replaced whenever a thread comes or goes, the config is reloaded or a
+//! project file changes. Threads with the same agent and project file share
+//! one merged config. A hook only looks the thread up and clones an `Arc`:
+//! no lock, no I/O and no config clone in interceptor context.
+//!
+//! A replaced map may still be read by a hook. Hooks count themselves in
+//! `Shared::readers` around the lookup (SeqCst), and the scanner frees
+//! replaced maps only when it sees no reader after the swap: any hook that
+//! loaded the old pointer was counted before it did.
+//!
+//! A hook for a thread the scanner hasn't seen yet finds no context and
+//! leaves the entry for the scanner (`Resolution::Deferred`); registering
+//! the thread woke it, so the next sweep decides.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use crate::agent;
+use crate::config::{DEFAULT_AGENT, YoloConfig};
+use crate::layouts::ThreadLayout;
+use crate::mem::SafeMem;
+use crate::project::{self, Files};
+
+/// The agent and config of one AcpThread.
+#[derive(Debug)]
+pub(crate) struct ThreadContext {
+    /// `agent::identify` of the thread's agent server; `None` when unknown.
+    pub(crate) agent: Option<String>,
+    /// The thread's first worktree root; `None` when unknown.
+    pub(crate) project: Option<PathBuf>,
+    /// Whether `config` has the project's `.zed/yolo.json` applied.
+    pub(crate) project_file: bool,
+    /// The config with the agent's `agents` entry and the project file
+    /// applied.
+    pub(crate) config: Arc<YoloConfig>,
+}
+
+type Map = HashMap<u64, Arc<ThreadContext>>;
+
+/// The published map and the hooks reading it.
+pub(crate) struct Shared {
+    map: AtomicPtr<Map>,
+    readers: AtomicUsize,
+}
+
+impl Shared {
+    pub(crate) const fn new() -> Self {
+        Shared {
+            map: AtomicPtr::new(std::ptr::null_mut()),
+            readers: AtomicUsize::new(0),
+        }
+    }
+
+    /// The context of `thread`, if the scanner has published one.
+    pub(crate) fn get(&self, thread: u64) -> Option<Arc<ThreadContext>> {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        let map = self.map.load(Ordering::SeqCst);
+        // SAFETY: a published map is freed only once no reader is counted
+        // after it was replaced (`Contexts::reclaim`).
+        let context = unsafe { map.as_ref() }.and_then(|map| map.get(&thread).cloned());
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+        context
+    }
+}
+
+static SHARED: Shared = Shared::new();
+
+/// The context of `thread`. Lock-free — safe to call from Frida interceptor
+/// context.
+pub(crate) fn get(thread: u64) -> Option<Arc<ThreadContext>> {
+    SHARED.get(thread)
+}
+
+/// The scanner's side: builds contexts and publishes them to a `Shared`.
+/// Lives on the scanner thread.
+pub(crate) struct Contexts {
+    shared: &'static Shared,
+    /// The config the contexts were built from.
+    config: Option<&'static YoloConfig>,
+    contexts: Map,
+    /// Merged configs by agent and project (when it has a file), shared by
+    /// the threads of that agent and project.
+    configs: HashMap<(Option<String>, Option<PathBuf>), Arc<YoloConfig>>,
+    files: Files,
+    /// Replaced maps not freed yet.
+    retired: Vec<*mut Map>,
+}
+
+impl Contexts {
+    /// Publishing to the map hooks read with `get`.
+    pub(crate) fn new() -> Self {
+        Self::publishing_to(&SHARED)
+    }
+
+    pub(crate) fn publishing_to(shared: &'static Shared) -> Self {
+        Contexts {
+            shared,
+            config: None,
+            contexts: HashMap::new(),
+            configs: HashMap::new(),
+            files: Files::default(),
+            retired: Vec::new(),
+        }
+    }
+
+    /// Bring the contexts in line with `threads` (the live AcpThreads),
+    /// `config` and the project files, and publish them if anything changed.
+    pub(crate) fn refresh(
+        &mut self,
+        mem: &impl SafeMem,
+        layout: &ThreadLayout,
+        config: &'static YoloConfig,
+        threads: &[u64],
+    ) {
+        let mut changed = false;
+        if !self.config.is_some_and(|built| std::ptr::eq(built, config)) {
+            if layout.agent_name.is_none() && config.agents.keys().any(|name| name != DEFAULT_AGENT)
+            {
+                tracing::warn!(
+                    "thread_context: the layout has no `thread.agent_name` — agents can't be \
+                     told apart and every agent thread uses `agents.default`"
+                );
+            }
+            self.config = Some(config);
+            self.contexts.clear();
+            self.configs.clear();
+            changed = true;
+        }
+        if config.project_overrides {
+            for root in self.files.poll() {
+                let before = self.contexts.len();
+                self.contexts
+                    .retain(|_, context| context.project.as_ref() != Some(&root));
+                changed |= self.contexts.len() != before;
+                self.configs
+                    .retain(|(_, project), _| project.as_ref() != Some(&root));
+            }
+        }
+
+        let before = self.contexts.len();
+        self.contexts.retain(|thread, _| threads.contains(thread));
+        changed |= self.contexts.len() != before;
+
+        for &thread in threads {
+            if self.contexts.contains_key(&thread) {
+                continue;
+            }
+            let agent = agent::read_name(mem, thread, layout)
+                .map(|server| agent::identify(&config.agents, &server));
+            let project = project::read_work_dirs(mem, thread, layout)
+                .into_iter()
+                .next();
+            let file = project
+                .as_deref()
+                .filter(|_| config.project_overrides)
+                .and_then(|root| self.files.get(root));
+            tracing::info!(
+                "thread_context: thread {:04x} is {} in {}{}",
+                thread & 0xFFFF,
+                agent.as_deref().unwrap_or("an unknown agent"),
+                project
+                    .as_deref()
+                    .map_or("an unknown project".into(), |root| root.to_string_lossy()),
+                if file.is_some() {
+                    " (with .zed/yolo.json)"
+                } else {
+                    ""
+                }
+            );
+            let key = (agent.clone(), project.clone().filter(|_| file.is_some()));
+            let merged = self
+                .configs
+                .entry(key)
+                .or_insert_with(|| {
+                    Arc::new(
+                        config
+                            .for_thread(agent.as_deref(), file.as_deref())
+                            .into_owned(),
+                    )
+                })
+                .clone();
+            self.contexts.insert(
+                thread,
+                Arc::new(ThreadContext {
+                    agent,
+                    project,
+                    project_file: file.is_some(),
+                    config: merged,
+                }),
+            );
+            changed = true;
+        }
+
+        let contexts = &self.contexts;
+        self.files.retain(|root| {
+            contexts
+                .values()
+                .any(|context| context.project.as_deref() == Some(root))
+        });
+
+        if changed {
+            let map = Box::into_raw(Box::new(self.contexts.clone()));
+            let old = self.shared.map.swap(map, Ordering::SeqCst);
+            if !old.is_null() {
+                self.retired.push(old);
+            }
+        }
+        self.reclaim();
+    }
+
+    /// Free the replaced maps once no hook can be reading them.
+    fn reclaim(&mut self) {
+        if self.retired.is_empty() || self.shared.readers.load(Ordering::SeqCst) != 0 {
+            return;
+        }
+        for map in self.retired.drain(..) {
+            // SAFETY: swapped out before the reader count above was 0, so no
+            // hook holds it (`Shared::get`); from `Box::into_raw`.
+            drop(unsafe { Box::from_raw(map) });
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::YoloMode;
+    use crate::hooks::tool_call_snapshot::{StrField, VecField};
+    use crate::layouts::{ChainedStr, ChainedStrs};
+    use crate::mem::SliceMemory;
+    use crate::policy::RuleAction;
+    use std::time::SystemTime;
+
+    const THREAD: u64 = 0x1_1000_0000;
+    const OTHER: u64 = 0x1_2000_0000;
+    const NAME: u64 = 0x1_3000_0000;
+    const DIRS: u64 = 0x1_4000_0000;
+    const ROOT: u64 = 0x1_5000_0000;
+
+    fn leak(json: &str) -> &'static YoloConfig {
+        Box::leak(Box::new(serde_json::from_str(json).unwrap()))
+    }
+
+    /// Threads whose agent name (a `String`) is at +0x10.
+    fn named_threads() -> (SliceMemory, ThreadLayout) {
+        let mut mem = SliceMemory::default();
+        mem.add_words(THREAD, &[0, 0, NAME, 11]);
+        mem.add_words(OTHER, &[0, 0, NAME + 0x100, 5]);
+        mem.add(NAME, b"claude-code".to_vec());
+        mem.add(NAME + 0x100, b"codex".to_vec());
+        let layout = ThreadLayout {
+            agent_name: Some(ChainedStr {
+                deref: &[],
+                field: StrField {
+                    ptr_offset: 0x10,
+                    len_offset: 0x18,
+                    data_offset: 0,
+                    none: None,
+                },
+            }),
+            ..ThreadLayout::UNKNOWN
+        };
+        (mem, layout)
+    }
+
+    /// `THREAD` with worktree roots (a `Vec<PathBuf>`) at +0x20: `root`.
+    fn thread_in(root: &std::path::Path) -> (SliceMemory, ThreadLayout) {
+        let root = root.to_str().unwrap();
+        let mut mem = SliceMemory::default();
+        mem.add_words(THREAD, &[0, 0, 0, 0, DIRS, 1]);
+        mem.add_words(DIRS, &[0, ROOT, root.len() as u64]);
+        mem.add(ROOT, root.as_bytes().to_vec());
+        let layout = ThreadLayout {
+            work_dirs: Some(ChainedStrs {
+                deref: &[],
+                list: VecField {
+                    ptr_offset: 0x20,
+                    len_offset: 0x28,
+                    elem_size: 0x18,
+                    none: None,
+                },
+                path: StrField {
+                    ptr_offset: 8,
+                    len_offset: 16,
+                    data_offset: 0,
+                    none: None,
+                },
+            }),
+            ..ThreadLayout::UNKNOWN
+        };
+        (mem, layout)
+    }
+
+    #[test]
+    fn test_contexts_follow_threads_and_config() {
+        static SHARED: Shared = Shared::new();
+        let (mem, layout) = named_threads();
+        let config = leak(r#"{ "agents": { "claude": { "mode": "observe" } } }"#);
+        let mut contexts = Contexts::publishing_to(&SHARED);
+        assert!(SHARED.get(THREAD).is_none());
+
+        contexts.refresh(&mem, &layout, config, &[THREAD, OTHER]);
+        let claude = SHARED.get(THREAD).unwrap();
+        assert_eq!(claude.agent.as_deref(), Some("claude"));
+        assert_eq!(claude.config.mode, YoloMode::Observe);
+        let codex = SHARED.get(OTHER).unwrap();
+        assert_eq!(codex.agent.as_deref(), Some("codex"));
+        assert_eq!(codex.config.mode, YoloMode::AllowAll);
+
+        // A thread that is gone is dropped; a reload rebuilds the rest.
+        contexts.refresh(&mem, &layout, config, &[THREAD]);
+        assert!(SHARED.get(OTHER).is_none());
+        let reloaded = leak(r#"{ "agents": { "claude": { "mode": "disabled" } } }"#);
+        contexts.refresh(&mem, &layout, reloaded, &[THREAD]);
+        assert_eq!(SHARED.get(THREAD).unwrap().config.mode, YoloMode::Disabled);
+        // A context taken earlier stays valid.
+        assert_eq!(claude.config.mode, YoloMode::Observe);
+        assert!(contexts.retired.is_empty());
+    }
+
+    #[test]
+    fn test_agent_not_from_tool_calls() {
+        static SHARED: Shared = Shared::new();
+        let (mem, _) = named_threads();
+        let config = leak(
+            r#"{ "agents": { "claude": { "mode": "observe" }, "default": { "mode": "disabled" } } }"#,
+        );
+        let mut contexts = Contexts::publishing_to(&SHARED);
+
+        // Without `thread.agent_name` every thread is `default`.
+        contexts.refresh(&mem, &ThreadLayout::UNKNOWN, config, &[THREAD, OTHER]);
+        for thread in [THREAD, OTHER] {
+            let context = SHARED.get(thread).unwrap();
+            assert_eq!(context.agent, None);
+            assert_eq!(context.config.mode, YoloMode::Disabled);
+        }
+        assert!(Arc::ptr_eq(
+            &SHARED.get(THREAD).unwrap().config,
+            &SHARED.get(OTHER).unwrap().config
+        ));
+    }
+
+    #[test]
+    fn test_project_file_applied_and_reread() {
+        static SHARED: Shared = Shared::new();
+        let root = std::env::temp_dir().join(format!(
+            "zed-yolo-thread-context-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(root.join(".zed")).unwrap();
+        let path = root.join(project::PROJECT_FILE);
+        let write = |json: &str, secs: u64| {
+            std::fs::write(&path, json).unwrap();
+            let mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        };
+        let (mem, layout) = thread_in(&root);
+        let config = leak(
+            r#"{ "project_overrides": true, "policy": { "rules": [{ "action": "allow" }] } }"#,
+        );
+        let mut contexts = Contexts::publishing_to(&SHARED);
+
+        write(
+            r#"{ "policy": { "rules": [{ "action": "deny", "kind": "delete" }] } }"#,
+            1_000,
+        );
+        contexts.refresh(&mem, &layout, config, &[THREAD]);
+        let context = SHARED.get(THREAD).unwrap();
+        assert_eq!(context.project.as_deref(), Some(root.as_path()));
+        assert!(context.project_file);
+        // After the global rules.
+        let rules = &context.config.policy.rules;
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].action, RuleAction::Allow);
+        assert_eq!(rules[1].action, RuleAction::Deny);
+
+        write(
+            r#"{ "policy": { "denylist": ["terraform apply"] } }"#,
+            2_000,
+        );
+        contexts.refresh(&mem, &layout, config, &[THREAD]);
+        let context = SHARED.get(THREAD).unwrap();
+        assert_eq!(context.config.policy.rules.len(), 1);
+        assert_eq!(context.config.policy.denylist, ["terraform apply"]);
+
+        // Off by default: the project is known, its file not applied.
+        let off = leak("{}");
+        contexts.refresh(&mem, &layout, off, &[THREAD]);
+        let context = SHARED.get(THREAD).unwrap();
+        assert_eq!(context.project.as_deref(), Some(root.as_path()));
+        assert!(!context.project_file);
+        assert!(context.config.policy.denylist.is_empty());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_replaced_map_kept_while_read() {
+        static SHARED: Shared = Shared::new();
+        let (mem, layout) = named_threads();
+        let config = leak("{}");
+        let mut contexts = Contexts::publishing_to(&SHARED);
+        contexts.refresh(&mem, &layout, config, &[THREAD]);
+
+        SHARED.readers.fetch_add(1, Ordering::SeqCst);
+        contexts.refresh(&mem, &layout, config, &[THREAD, OTHER]);
+        assert_eq!(contexts.retired.len(), 1);
+        SHARED.readers.fetch_sub(1, Ordering::SeqCst);
+        contexts.refresh(&mem, &layout, config, &[THREAD, OTHER]);
+        assert!(contexts.retired.is_empty());
+    }
+}
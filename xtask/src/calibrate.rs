@@ -0,0 +1,251 @@
+//! `cargo patch calibrate` — capture a memory layout for a new Zed build.
+//!
+//! Replaces the manual recalibration in `docs/*_offset_recalibration_*.md`:
+//!
+//! 1. Build + inject the dylib (same as `cargo patch`, skip with `--no-patch`).
+//! 2. Launch Zed with `ZED_YOLO_CALIBRATE=<report>`. In that mode the dylib is
+//!    observe-only and, on the first ACP tool call, runs the layout heuristic
+//!    (`src/calibrate.rs`) and writes a report with a proposed layout entry.
+//! 3. Wait for the report, quit Zed, show the proposal and — after review —
+//!    merge it into `~/.config/dylib-hooks/{app_id}/zed-yolo-layouts.json`,
+//!    replacing any entry with the same name.
+
+use anyhow::{Context, bail};
+use serde_json::Value;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const PREVIEW_APP: &str = "/Applications/Zed Preview.app";
+const STABLE_APP: &str = "/Applications/Zed.app";
+const LAYOUTS_FILE: &str = "zed-yolo-layouts.json";
+const DEFAULT_TIMEOUT_SECS: u64 = 300;
+
+struct Options {
+    stable: bool,
+    patch: bool,
+    timeout: Duration,
+    yes: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let mut options = Options {
+            stable: false,
+            patch: true,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            yes: false,
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--stable" => options.stable = true,
+                "--no-patch" => options.patch = false,
+                "--yes" | "-y" => options.yes = true,
+                "--timeout" => {
+                    let secs = iter.next().context("--timeout needs a value in seconds")?;
+                    let secs: u64 = secs
+                        .parse()
+                        .with_context(|| format!("invalid --timeout {secs:?}"))?;
+                    options.timeout = Duration::from_secs(secs);
+                }
+                other => bail!(
+                    "unknown calibrate option {other:?} \
+                     (expected --stable, --no-patch, --timeout SECS, --yes)"
+                ),
+            }
+        }
+        Ok(options)
+    }
+
+    fn app_bundle(&self) -> &'static str {
+        if self.stable { STABLE_APP } else { PREVIEW_APP }
+    }
+
+    /// Config directory name, matching the dylib's `AppFingerprint::app_id`.
+    fn app_id(&self) -> &'static str {
+        if self.stable {
+            "zed-stable"
+        } else {
+            "zed-preview"
+        }
+    }
+}
+
+/// Run `cargo patch calibrate [--stable] [--no-patch] [--timeout SECS] [--yes]`.
+/// `args` are the arguments after `calibrate`.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let options = Options::parse(args)?;
+
+    if options.patch {
+        println!("==> Patching {}", options.app_bundle());
+        let mut patch = Command::new(std::env::current_exe()?);
+        if options.stable {
+            patch.arg("--stable");
+        }
+        let status = patch.status().context("failed to run cargo patch")?;
+        if !status.success() {
+            bail!("cargo patch failed ({status})");
+        }
+    }
+
+    let report_path =
+        std::env::temp_dir().join(format!("zed-yolo-calibrate.{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&report_path);
+
+    let report = capture(&options, &report_path)?;
+    let _ = std::fs::remove_file(&report_path);
+
+    let Some(layout) = review(&report)? else {
+        bail!("calibration did not produce a complete layout; see the hook log for details");
+    };
+
+    let db_path = layouts_path(options.app_id())?;
+    if !options.yes && !confirm(&format!("Write this layout to {}?", db_path.display()))? {
+        println!("Not written.");
+        return Ok(());
+    }
+    merge(&db_path, layout)?;
+    println!("==> Wrote {}", db_path.display());
+    println!("    Restart Zed to pick it up.");
+    Ok(())
+}
+
+/// Launch Zed in calibration mode and wait for its report.
+fn capture(options: &Options, report_path: &Path) -> anyhow::Result<Value> {
+    let exe = Path::new(options.app_bundle()).join("Contents/MacOS/zed");
+    if !exe.exists() {
+        bail!("{} not found", exe.display());
+    }
+
+    println!("==> Launching {} in calibration mode", exe.display());
+    let mut child = Command::new(&exe)
+        .env("ZED_YOLO_CALIBRATE", report_path)
+        // Hooks must be installed to see the tool call; calibration mode keeps
+        // them observe-only regardless.
+        .env("ZED_YOLO_MODE", "allow_safe")
+        .spawn()
+        .with_context(|| format!("failed to launch {}", exe.display()))?;
+
+    println!();
+    println!("    Start an external agent thread (e.g. Claude Code) and make it run");
+    println!("    a tool that asks for permission. Nothing is auto-approved in this");
+    println!("    mode — answer the dialog yourself.");
+    println!();
+    println!(
+        "    Waiting up to {}s for the report...",
+        options.timeout.as_secs()
+    );
+
+    let deadline = Instant::now() + options.timeout;
+    let report = loop {
+        if let Ok(text) = std::fs::read_to_string(report_path) {
+            break Some(text);
+        }
+        if let Some(status) = child.try_wait()? {
+            println!("    Zed exited ({status}) before a tool call was seen.");
+            break None;
+        }
+        if Instant::now() >= deadline {
+            break None;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    };
+
+    if child.try_wait()?.is_none() {
+        println!("==> Quitting Zed");
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    let Some(text) = report else {
+        bail!(
+            "no calibration report after {}s; check ~/Library/Logs/Zed/zed-yolo-hook.*.log",
+            options.timeout.as_secs()
+        );
+    };
+    serde_json::from_str(&text).context("calibration report is not valid JSON")
+}
+
+/// Print the report; returns the proposed layout entry, if complete.
+fn review(report: &Value) -> anyhow::Result<Option<Value>> {
+    let app = &report["app"];
+    println!();
+    println!(
+        "==> Zed {} (build {}, hash {})",
+        app["version"].as_str().unwrap_or("?"),
+        app["build"].as_str().unwrap_or("?"),
+        app["binary_hash"].as_str().unwrap_or("?")
+    );
+    println!(
+        "    current layout: {}",
+        report["hint"].as_str().unwrap_or("?")
+    );
+
+    let proposal = &report["proposal"];
+    if proposal.is_null() {
+        println!("    No candidate found in memory.");
+        return Ok(None);
+    }
+    println!(
+        "    proposal: {}",
+        proposal["summary"].as_str().unwrap_or("?")
+    );
+    if proposal["ambiguous"].as_bool() == Some(true) {
+        println!(
+            "    WARNING: entry size is ambiguous (few entries in the thread); \
+             re-run with a longer agent thread to confirm it."
+        );
+    }
+
+    let layout = &report["layout"];
+    if layout.is_null() {
+        println!("    Proposal is incomplete (status/respond_tx not found).");
+        return Ok(None);
+    }
+    println!();
+    println!("{}", serde_json::to_string_pretty(layout)?);
+    println!();
+    Ok(Some(layout.clone()))
+}
+
+fn confirm(question: &str) -> anyhow::Result<bool> {
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn layouts_path(app_id: &str) -> anyhow::Result<PathBuf> {
+    let home = std::env::var_os("HOME").context("HOME is not set")?;
+    Ok(PathBuf::from(home)
+        .join(".config/dylib-hooks")
+        .join(app_id)
+        .join(LAYOUTS_FILE))
+}
+
+/// Add `layout` to the database at `path`, replacing an entry with the same
+/// name. The new entry goes first so it wins over older wildcard entries.
+fn merge(path: &Path, layout: Value) -> anyhow::Result<()> {
+    let mut db = match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str::<Value>(&text)
+            .with_context(|| format!("{} is not valid JSON", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::json!({ "layouts": [] }),
+        Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+    };
+
+    let layouts = db
+        .get_mut("layouts")
+        .and_then(Value::as_array_mut)
+        .with_context(|| format!("{} has no \"layouts\" array", path.display()))?;
+    layouts.retain(|existing| existing["name"] != layout["name"]);
+    layouts.insert(0, layout);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&db)? + "\n")
+        .with_context(|| format!("cannot write {}", path.display()))
+}
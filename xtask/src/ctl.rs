@@ -0,0 +1,239 @@
+//! `cargo patch ctl` — talk to the hook inside running Zed processes.
+//!
+//! Each patched Zed process listens on
+//! `~/.config/dylib-hooks/{app_id}/zed-yolo-hook.{pid}.sock` (see the dylib's
+//! `src/control.rs`). This finds those sockets, skips ones whose process is
+//! gone, and sends line-delimited JSON requests.
+//!
+//!   cargo patch ctl status     Live mode, pause state, options, layouts
+//!   cargo patch ctl pause      Stop auto-approving (dialogs behave as stock Zed)
+//!   cargo patch ctl resume     Undo `pause`
+//!   cargo patch ctl stats      Hook counters
+//!   cargo patch ctl tail       Follow decisions as they happen (Ctrl-C to stop)
+//!
+//! Options: `--stable` / `--preview` (default: both), `--pid PID`.
+
+use anyhow::{Context, bail};
+use serde_json::{Value, json};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const SOCKET_PREFIX: &str = "zed-yolo-hook.";
+const APP_IDS: &[&str] = &["zed-preview", "zed-stable"];
+const TAIL_INTERVAL: Duration = Duration::from_millis(500);
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A running hook's control socket.
+struct Instance {
+    app_id: &'static str,
+    pid: u32,
+    path: PathBuf,
+}
+
+/// One connection to a hook.
+struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    fn connect(path: &Path) -> std::io::Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        Ok(Client {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+        })
+    }
+
+    fn request(&mut self, request: &Value) -> anyhow::Result<Value> {
+        self.writer.write_all(format!("{request}\n").as_bytes())?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("hook closed the connection");
+        }
+        let response: Value = serde_json::from_str(&line).context("invalid response")?;
+        if response["ok"] != true {
+            bail!("{}", response["error"].as_str().unwrap_or("request failed"));
+        }
+        Ok(response)
+    }
+}
+
+/// Run `cargo patch ctl <command> [options]`. `args` are the arguments after `ctl`.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let mut command = None;
+    let mut app_ids: Vec<&'static str> = Vec::new();
+    let mut pid = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--stable" => app_ids.push("zed-stable"),
+            "--preview" => app_ids.push("zed-preview"),
+            "--pid" => {
+                let value = iter.next().context("--pid needs a value")?;
+                pid = Some(value.parse::<u32>().context("invalid --pid")?);
+            }
+            other if command.is_none() && !other.starts_with('-') => command = Some(other),
+            other => bail!("unexpected argument {other:?}"),
+        }
+    }
+    if app_ids.is_empty() {
+        app_ids.extend_from_slice(APP_IDS);
+    }
+
+    let command = command.unwrap_or("status");
+    let request = match command {
+        "status" => json!({ "cmd": "status" }),
+        "pause" => json!({ "cmd": "pause" }),
+        "resume" => json!({ "cmd": "resume" }),
+        "stats" => json!({ "cmd": "stats" }),
+        "tail" => Value::Null,
+        other => {
+            bail!("unknown ctl command {other:?} (expected status, pause, resume, stats, tail)")
+        }
+    };
+
+    let instances: Vec<Instance> = discover(&app_ids)?
+        .into_iter()
+        .filter(|i| pid.is_none_or(|pid| i.pid == pid))
+        .collect();
+    if instances.is_empty() {
+        bail!(
+            "no running patched Zed found{} (is the hook loaded and not disabled?)",
+            pid.map(|p| format!(" with pid {p}")).unwrap_or_default()
+        );
+    }
+
+    if command == "tail" {
+        let [instance] = instances.as_slice() else {
+            let pids: Vec<String> = instances.iter().map(|i| i.pid.to_string()).collect();
+            bail!(
+                "several Zed processes are running ({}); pick one with --pid",
+                pids.join(", ")
+            );
+        };
+        return tail(instance);
+    }
+
+    let mut failed = false;
+    for instance in &instances {
+        println!("==> {} pid {}", instance.app_id, instance.pid);
+        match Client::connect(&instance.path)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut client| client.request(&request))
+        {
+            Ok(response) => print_fields(&response),
+            Err(e) => {
+                println!("    error: {e:#}");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        bail!("some hooks did not respond");
+    }
+    Ok(())
+}
+
+/// Control sockets of live processes. Sockets nobody listens on (Zed quit
+/// or crashed) are skipped.
+fn discover(app_ids: &[&'static str]) -> anyhow::Result<Vec<Instance>> {
+    let home = std::env::var_os("HOME").context("HOME is not set")?;
+    let mut instances = Vec::new();
+    for &app_id in app_ids {
+        let dir = PathBuf::from(&home)
+            .join(".config/dylib-hooks")
+            .join(app_id);
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Some(pid) = entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.strip_prefix(SOCKET_PREFIX))
+                .and_then(|n| n.strip_suffix(".sock"))
+                .and_then(|n| n.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            let path = entry.path();
+            if UnixStream::connect(&path).is_ok() {
+                instances.push(Instance { app_id, pid, path });
+            }
+        }
+    }
+    instances.sort_by_key(|i| (i.app_id, i.pid));
+    Ok(instances)
+}
+
+fn print_fields(response: &Value) {
+    let Some(map) = response.as_object() else {
+        return;
+    };
+    for (key, value) in map {
+        if key == "ok" {
+            continue;
+        }
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        println!("    {key:<36} {value}");
+    }
+}
+
+/// Print decisions as they are made, starting with the last few.
+fn tail(instance: &Instance) -> anyhow::Result<()> {
+    println!(
+        "==> {} pid {} — following decisions (Ctrl-C to stop)",
+        instance.app_id, instance.pid
+    );
+    let mut client = Client::connect(&instance.path)?;
+    let mut since = 0u64;
+    let mut limit = 10;
+    loop {
+        let response =
+            client.request(&json!({ "cmd": "recent", "limit": limit, "since": since }))?;
+        let records = response["records"].as_array().cloned().unwrap_or_default();
+        // Newest first on the wire; print oldest first.
+        for record in records.iter().rev() {
+            println!("{}", format_record(record));
+            since = since.max(record["seq"].as_u64().unwrap_or(since));
+        }
+        limit = 64;
+        std::thread::sleep(TAIL_INTERVAL);
+    }
+}
+
+/// `10:15:03 approved  hook   allow(allow_once)  execute "Run `ls`"  84us [s:ab12]`
+fn format_record(record: &Value) -> String {
+    let s = |key: &str| record[key].as_str().unwrap_or("-");
+    let time = s("ts").get(11..19).unwrap_or(s("ts"));
+    let option = match (record["option_id"].as_str(), record["option_kind"].as_str()) {
+        (Some(id), Some(kind)) => format!("{id}({kind})"),
+        (Some(id), None) => id.to_string(),
+        _ => "-".to_string(),
+    };
+    let mut line = format!(
+        "{time} {:<13} {:<13} {:<24} {:<8} {:?} {}us",
+        s("action"),
+        s("path"),
+        option,
+        s("kind"),
+        record["title"].as_str().unwrap_or(""),
+        record["latency_us"].as_u64().unwrap_or(0),
+    );
+    if let Some(session) = record["session"].as_str() {
+        line.push_str(&format!(" [s:{session}]"));
+    }
+    if let Some(source) = record["source"].as_str() {
+        line.push_str(&format!(" via {source}"));
+    }
+    line
+}
@@ -13,6 +13,13 @@
 //!   cargo patch config set KEY VALUE     Set a config field
 //!   cargo patch config reset             Reset config to defaults
 //!   cargo patch config path              Print config file path
+//!   cargo patch calibrate                Capture a memory layout for a new Zed build
+//!       [--stable] [--no-patch] [--timeout SECS] [--yes]
+//!   cargo patch ctl status|pause|resume|stats|tail
+//!       [--stable|--preview] [--pid PID]    Query / toggle the hook in running Zed
+
+mod calibrate;
+mod ctl;
 
 use dylib_hook_registry::{HealthCheck, HookEntry};
 use dylib_patcher::{ConfigField, HookConfigMeta, HookProject, Patcher, TargetApp};
@@ -20,6 +27,12 @@ use dylib_patcher::{ConfigField, HookConfigMeta, HookProject, Patcher, TargetApp
 fn main() -> anyhow::Result<()> {
     let args: Vec<String> = std::env::args().collect();
 
+    match args.get(1).map(String::as_str) {
+        Some("calibrate") => return calibrate::run(&args[2..]),
+        Some("ctl") => return ctl::run(&args[2..]),
+        _ => {}
+    }
+
     let config_meta = HookConfigMeta::new(
         "zed-yolo-hook.json",
         r#"{"mode":"allow_all","tool_option":"allow","plan_option":"acceptEdits","log_level":"info","retry_delay_us":1500}"#,
@@ -27,7 +40,8 @@ fn main() -> anyhow::Result<()> {
     .with_field(
         ConfigField::new("mode", "Which hooks to install")
             .with_option("allow_all", "Both ACP + native hooks (auto-approve everything)")
-            .with_option("allow_safe", "ACP hook only (native permissions follow Zed settings)")
+            .with_option("allow_safe", "ACP calls per policy; native tools only when a rule allows them")
+            .with_option("observe", "All hooks installed, log what would be approved, act on nothing")
             .with_option("disabled", "Dylib loads but installs no hooks")
             .with_default("allow_all"),
     )